@@ -29,10 +29,17 @@ impl Catalog {
     pub async fn add_definition(
         &self,
         name: String,
-        definition: Definition,
+        mut definition: Definition,
         sender: Sender<Event>,
     ) -> anyhow::Result<()> {
         let mut state = self.state.lock().await;
+        // the processed records of a join are typed by the definition it reads
+        let joined = definition
+            .joined_name()
+            .and_then(|name| state.definitions.get(name));
+        if let Some(joined) = joined.cloned() {
+            definition.set_joined(&joined)?;
+        }
         for engine in &mut state.engines {
             engine.add_definition(&definition);
         }
@@ -51,6 +58,10 @@ impl Catalog {
         self.state.lock().await.definitions.clone().into_values().collect()
     }
 
+    pub async fn definition(&self, name: &str) -> Option<Definition> {
+        self.state.lock().await.definitions.get(name).cloned()
+    }
+
     pub async fn engines(&self) -> Vec<Engine> {
         self.state.lock().await.engines.clone()
     }
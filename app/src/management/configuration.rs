@@ -1,3 +1,4 @@
+use processing::{EventTime, StateLimit, Window};
 use serde::Deserialize;
 use std::collections::HashMap;
 use util::NativeMapping;
@@ -15,14 +16,29 @@ pub struct DefinitionModel {
     pub model: Model,
     pub entity: String,
     pub filter: DefinitionFilter,
-    pub mapping: NativeMapping,
+    pub mapping: MappingModel,
     pub processing: Query,
+    pub window: Option<Window>,
+    #[serde(default)]
+    pub state: StateLimit,
+}
+
+/// The native mapping of a definition next to the event time of its records, e.g.
+/// `mapping.time = { field = "measured" }`.
+#[derive(Debug, Deserialize)]
+pub struct MappingModel {
+    #[serde(flatten)]
+    pub native: NativeMapping,
+    pub time: Option<EventTime>,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use util::RelationalMapping;
+    use processing::Lateness;
+    use util::definition::Definition;
+    use util::{InitialMeta, RelationalMapping, TimedMeta};
+    use value::Value;
 
     #[tokio::test]
     async fn relational() {
@@ -99,6 +115,23 @@ mod tests {
         let _config: NativeMapping = toml::from_str(mapping).unwrap();
     }
 
+    #[tokio::test]
+    async fn key_value() {
+        let mapping = r#"
+        [def.kv-default]
+        topic = "Key value test"
+        model = "kv"
+        entity = "devices"
+        filter.topic = "devices"
+        mapping.kv.key = {doc.key = "device"}
+        processing.sql = "SELECT * FROM $$source""#;
+
+        let config: Config = toml::from_str(mapping).unwrap();
+        let def = &config.def["kv-default"];
+        assert_eq!(def.model, Model::KeyValue);
+        assert!(matches!(def.mapping.native, NativeMapping::KeyValue(_)));
+    }
+
     #[tokio::test]
     async fn relational_full() {
         let mapping = r#"
@@ -114,4 +147,123 @@ mod tests {
 
         let _config: Config = toml::from_str(mapping).unwrap();
     }
+
+    #[tokio::test]
+    async fn window() {
+        let mapping = r#"
+        [def.relational-window]
+        topic = "Relational test"
+        model = "relational"
+        entity = "relational"
+        filter.topic = "relational"
+        mapping.relational = [
+            {name = "TEXT"}, {price = "FLOAT"}
+        ]
+        processing.sql = "SELECT name, AVG(price) FROM $$source GROUP BY name"
+        window = "HOPPING (SIZE 30 SECONDS, ADVANCE BY 10 SECONDS)""#;
+
+        let config: Config = toml::from_str(mapping).unwrap();
+        assert_eq!(
+            config.def["relational-window"].window,
+            Some(Window::Hopping {
+                size: 30_000,
+                advance: 10_000
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn plan() {
+        let mapping = r#"
+        [def.relational-plan]
+        topic = "Relational test"
+        model = "relational"
+        entity = "relational"
+        filter.topic = "relational"
+        mapping.relational = [
+            {name = "TEXT"}, {age = "INT"}
+        ]
+        processing.plan = "plans/adults.json""#;
+
+        let config: Config = toml::from_str(mapping).unwrap();
+        assert!(matches!(
+            &config.def["relational-plan"].processing,
+            Query::Plan(path) if path == "plans/adults.json"
+        ));
+    }
+
+    #[tokio::test]
+    async fn event_time() {
+        let mapping = r#"
+        [def.relational-time]
+        topic = "Relational test"
+        model = "relational"
+        entity = "relational"
+        filter.topic = "relational"
+        mapping.relational = [
+            {device = "TEXT"}, {reading = "FLOAT"}, {measured = "INT"}
+        ]
+        mapping.time = { field = "measured", delay = "30 SECONDS", late = "update" }
+        processing.sql = "SELECT device, MAX(reading) FROM $$source GROUP BY device"
+        window = "TUMBLING (SIZE 1 MINUTE)""#;
+
+        let mut config: Config = toml::from_str(mapping).unwrap();
+        assert_eq!(
+            config.def["relational-time"].mapping.time,
+            Some(EventTime {
+                field: Some("measured".to_string()),
+                delay: 30_000,
+                late: Lateness::Update,
+            })
+        );
+
+        // the mapping names the column of the tuples holding the event time
+        let def = config.def.remove("relational-time").unwrap();
+        let definition =
+            Definition::builder(def.topic, def.mapping.native, def.processing, def.model)
+                .window(def.window)
+                .time(def.mapping.time)
+                .build()
+                .await
+                .unwrap();
+        let record = Value::array(vec![Value::text("a"), Value::float(2.5), Value::int(1_500)]);
+        let meta = TimedMeta::new(1, InitialMeta::new(vec![]));
+        assert_eq!(definition.target(meta, &record).event_time, 1_500);
+    }
+
+    #[tokio::test]
+    async fn state() {
+        let mapping = r#"
+        [def.relational-top]
+        topic = "Relational test"
+        model = "relational"
+        entity = "relational"
+        filter.topic = "relational"
+        mapping.relational = [
+            {device = "TEXT"}, {reading = "FLOAT"}
+        ]
+        processing.sql = "SELECT DISTINCT device FROM $$source"
+        state = { size = 1000, within = "10 MINUTES" }
+
+        [def.relational-default]
+        topic = "Relational test"
+        model = "relational"
+        entity = "relational"
+        filter.topic = "relational"
+        mapping.relational = [{device = "TEXT"}]
+        processing.sql = "SELECT device FROM $$source""#;
+
+        let config: Config = toml::from_str(mapping).unwrap();
+        assert_eq!(
+            config.def["relational-top"].state,
+            StateLimit {
+                size: 1000,
+                within: Some(600_000),
+            }
+        );
+        assert_eq!(
+            config.def["relational-default"].state,
+            StateLimit::default()
+        );
+    }
 }
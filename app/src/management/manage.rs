@@ -1,20 +1,19 @@
 use crate::management::catalog::Catalog;
 use crate::management::configuration::Config;
-use crate::phases::nativer::Nativer;
 use crate::phases::Persister;
-use engine::EngineKind;
-use flume::{unbounded, Sender};
+use crate::phases::nativer::Nativer;
+use crate::phases::processer::Processor;
+use engine::EngineRegistry;
+use flume::{Sender, unbounded};
+use processing::FunctionRegistry;
 use std::thread;
 use tokio::runtime::Builder;
 use tokio::task::JoinSet;
 use tokio::{fs, sync};
 use tracing::{error, info};
-use util::definition::{Definition};
+use util::definition::Definition;
 use util::runtimes::Runtimes;
-use util::{
-    log_channel, Batch, Event, InitialRecord, TargetedRecord,
-};
-use crate::phases::processer::Processor;
+use util::{Batch, Event, InitialRecord, TargetedRecord, log_channel};
 
 pub struct Manager {
     catalog: Catalog,
@@ -22,6 +21,8 @@ pub struct Manager {
     runtimes: Runtimes,
     statistic_tx: Sender<Event>,
     output: sync::broadcast::Sender<Batch<TargetedRecord>>,
+    functions: FunctionRegistry,
+    engines: EngineRegistry,
 }
 
 impl Default for Manager {
@@ -49,9 +50,21 @@ impl Manager {
             runtimes: Runtimes::new(),
             statistic_tx,
             output,
+            functions: FunctionRegistry::new(),
+            engines: EngineRegistry::new(),
         }
     }
 
+    /// The functions queries of definitions can call, register them before starting.
+    pub fn functions(&self) -> &FunctionRegistry {
+        &self.functions
+    }
+
+    /// The engines `engines.toml` can configure, register them before starting.
+    pub fn engines(&self) -> &EngineRegistry {
+        &self.engines
+    }
+
     pub fn start(mut self, sink_runner: SinkRunner) -> anyhow::Result<()> {
         let ctrl_c_signal = tokio::signal::ctrl_c();
 
@@ -132,21 +145,49 @@ impl Manager {
 
         let config = Manager::load_config(file_path).await?;
 
+        let mut definitions = vec![];
         for (name, def) in config.def {
-            self.catalog
-                .add_definition(
-                    name,
-                    Definition::new(
-                        def.topic,
-                        def.filter,
-                        def.mapping,
-                        def.processing,
-                        def.model,
-                        def.entity
-                    ).await,
-                    statistic_tx.clone(),
-                )
-                .await?;
+            // an invalid definition is skipped, the others keep running
+            let builder =
+                Definition::builder(def.topic, def.mapping.native, def.processing, def.model)
+                    .filter(def.filter)
+                    .entity(def.entity)
+                    .window(def.window)
+                    .time(def.mapping.time)
+                    .state(def.state)
+                    .functions(self.functions().clone());
+            match builder.build().await {
+                Ok(definition) => definitions.push((name, definition)),
+                Err(err) => error!("Skipping invalid definition {}: {}", name, err),
+            }
+        }
+
+        // a join is typed by the definition it reads, which is therefore added before it
+        while !definitions.is_empty() {
+            let pending = definitions
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<_>>();
+            let (mut ready, waiting): (Vec<_>, Vec<_>) =
+                definitions.into_iter().partition(|(name, definition)| {
+                    definition
+                        .joined_name()
+                        .is_none_or(|joined| joined == name || !pending.iter().any(|p| p == joined))
+                });
+            definitions = waiting;
+            if ready.is_empty() {
+                // definitions which join each other are added in any order
+                ready = std::mem::take(&mut definitions);
+            }
+            for (name, definition) in ready {
+                if let Err(err) = self
+                    .catalog
+                    .add_definition(name.clone(), definition, statistic_tx.clone())
+                    .await
+                {
+                    error!("Skipping definition {}: {:#}", name, err);
+                }
+            }
         }
 
         Ok(())
@@ -209,6 +250,7 @@ impl Manager {
 
     fn init_engines(&mut self, statistic_tx: Sender<Event>) -> anyhow::Result<()> {
         let mut catalog = self.catalog.clone();
+        let registry = self.engines().clone();
 
         let (tx, rx) = unbounded();
 
@@ -221,7 +263,7 @@ impl Manager {
                 .unwrap();
 
             rt.block_on(async move {
-                let engines = EngineKind::get_all(statistic_tx.clone()).await.unwrap();
+                let engines = registry.get_all(statistic_tx.clone()).await.unwrap();
 
                 for engine in engines.into_iter() {
                     engine.start_container().await.unwrap();
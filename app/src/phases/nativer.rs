@@ -1,15 +1,15 @@
-use std::thread;
 use crate::management::catalog::Catalog;
 use engine::engine::Engine;
+use flume::unbounded;
+use processing::Scope;
+use std::thread;
 use std::time::Duration;
-use flume::{unbounded};
 use tokio::runtime::Builder;
 use tokio::task::JoinSet;
 use tokio::time::Instant;
 use tracing::{error, info};
-use processing::Scope;
 use util::definition::Stage;
-use util::{Batch, Event, Runtimes, target, TargetedRecord};
+use util::{Batch, Event, Runtimes, TargetedRecord, target};
 
 pub struct Nativer {
     catalog: Catalog,
@@ -28,12 +28,15 @@ impl Nativer {
 
         //let catalog = self.catalog.clone();
         for definition in definitions {
-
             let startup_tx = startup_tx.clone();
-            let engines = engines.clone()
+            let engines = engines
+                .clone()
                 .into_iter()
                 .filter(|e| e.model() == definition.model)
                 .collect::<Vec<Engine>>();
+            let mappers = (0..DEFINITION_THREADS)
+                .map(|_| definition.mapping.build())
+                .collect::<anyhow::Result<Vec<_>>>()?;
 
             thread::spawn(move || {
                 let rt = Builder::new_multi_thread()
@@ -43,7 +46,7 @@ impl Nativer {
                     .build()
                     .unwrap();
                 rt.block_on(async {
-                    for _ in 0..DEFINITION_THREADS {
+                    for mapper in mappers {
                         let definition = definition.clone();
                         let engines = engines.clone();
                         let mut engine = engines.into_iter().next().unwrap();
@@ -63,19 +66,17 @@ impl Nativer {
                                     })
                                 },
                                 Scope::Multi | Scope::Join => {
-                                    let tx = definition.process_full.0;
+                                    let tx = definition.process_full.0.clone();
                                     Box::new(move |records: Batch<TargetedRecord>| {
-                                        tx.send(records.iter().map(|r| r.id()).collect()).unwrap();
+                                        tx.send(records).unwrap();
                                     })
                                 }
                             };
 
-                            let rx = definition.native.1;
+                            let rx = definition.native.1.clone();
                             engine.start(&mut join_set).await.unwrap();
                             startup_tx.send(true).unwrap();
 
-                            let mapper = definition.mapping.build();
-
                             let name = format!("Nativer {} {}", engine.engine_kind, id);
 
                             let mut hb_ticker = tokio::time::interval(Duration::from_secs(5));
@@ -119,6 +120,8 @@ impl Nativer {
                                                     first: Instant::now()
                                                 });
 
+                                                definition.publish(partition_id, &mapped_data);
+
                                                 // Send original records to next phase
                                                 tx(mapped_data);
 
@@ -147,4 +150,3 @@ impl Nativer {
         Self { catalog }
     }
 }
-
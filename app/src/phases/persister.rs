@@ -18,8 +18,7 @@ use tokio::time::Instant;
 use tracing::{debug, error, info, warn};
 use util::definition::{Definition, Stage};
 use util::{
-    Batch, DefinitionId, Event, InitialRecord, PartitionId, TargetedMeta, TargetedRecord,
-    TimedRecord, WorkerId,
+    Batch, DefinitionId, Event, InitialRecord, PartitionId, TargetedRecord, TimedRecord, WorkerId,
 };
 
 pub struct Persister {
@@ -137,10 +136,8 @@ impl Persister {
 
         debug!("cost:{:?}", cost.1.engine_kind.to_string());
 
-        Ok((
-            cost.1,
-            (record.value, TargetedMeta::new(record.meta, definition.id)).into(),
-        ))
+        let meta = definition.target(record.meta, &record.value);
+        Ok((cost.1, (record.value, meta).into()))
     }
 
     pub async fn start_distributor(
@@ -3,16 +3,22 @@ use anyhow::{anyhow, bail};
 use async_trait::async_trait;
 use engine::engine::Engine;
 use flume::{Receiver, unbounded};
-use processing::{Program, Scope};
-use std::thread;
-use std::time::Duration;
+use processing::{
+    Backend, Bounded, Bounds, ColumnarProgram, Joiner, Lateness, RecordError, Schema, Scope,
+};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{iter, thread};
 use tokio::runtime::Builder;
 use tokio::sync::broadcast::Sender;
 use tokio::task::JoinSet;
 use tokio::time::Instant;
-use tracing::{error, info};
+use tracing::{debug, error, info, warn};
 use util::definition::{Definition, Stage};
-use util::{Batch, Event, Runtimes, TargetedRecord, target};
+use util::{
+    Batch, ErrorEvent, Event, PartitionId, Runtimes, TargetedMeta, TargetedRecord, WorkerId, target,
+};
+use value::Value;
 
 pub struct Processor {
     catalog: Catalog,
@@ -44,12 +50,28 @@ impl RecordProcessor for ProcessorType {
     ) -> anyhow::Result<()> {
         match self {
             ProcessorType::Tuple(t) => t.process(id, worker_id, engine, definition, outgoing).await,
+            ProcessorType::Columnar(c) => {
+                c.process(id, worker_id, engine, definition, outgoing).await
+            }
+            ProcessorType::Multi(m) => m.process(id, worker_id, engine, definition, outgoing).await,
+            ProcessorType::Join(j) => j.process(id, worker_id, engine, definition, outgoing).await,
         }
     }
 }
 
 enum ProcessorType {
-    Tuple(TupleProcessor),
+    Tuple(Box<TupleProcessor>),
+    Columnar(Box<ColumnarProcessor>),
+    Multi(Box<MultiProcessor>),
+    Join(Box<JoinProcessor>),
+}
+
+/// Multi-scope processing keeps its windows in one place, so it only gets a single worker.
+fn workers(definition: &Definition) -> u32 {
+    match definition.algebra.scope() {
+        Scope::Tuple => DEFINITIONS_THREADS,
+        Scope::Multi | Scope::Join => 1,
+    }
 }
 
 impl Processor {
@@ -57,6 +79,17 @@ impl Processor {
         Self { catalog }
     }
 
+    /// The definition named by the right side of a join.
+    async fn joined(&self, definition: &Definition) -> anyhow::Result<Definition> {
+        let name = definition
+            .joined_name()
+            .ok_or(anyhow!("Can only join with another definition"))?;
+        self.catalog
+            .definition(name)
+            .await
+            .ok_or(anyhow!("Unknown definition {} to join", name))
+    }
+
     pub async fn start(
         self,
         _rt: Runtimes,
@@ -67,15 +100,33 @@ impl Processor {
         let engines = self.catalog.engines().await;
         let mut id_counter = 0;
         let (startup_tx, startup_rx) = unbounded();
-        let total_workers = DEFINITIONS_THREADS * definitions.len() as u32;
+        let total_workers = definitions.iter().map(workers).sum::<u32>();
 
         for definition in definitions {
             let startup_tx = startup_tx.clone();
-            let engines = engines
-                .clone()
-                .into_iter()
-                .filter(|e| e.model() == definition.model)
-                .collect::<Vec<Engine>>();
+            let joined = match definition.algebra.scope() {
+                Scope::Join => {
+                    let joined = self.joined(&definition).await?;
+                    // joined records are read from where their native stage is stored
+                    let lookup = engines
+                        .iter()
+                        .find(|e| e.model() == joined.model)
+                        .cloned()
+                        .ok_or(anyhow!("No engine for joined definition {}", joined.topic))?;
+                    Some((joined, lookup))
+                }
+                _ => None,
+            };
+            // the processed rows are stored in an engine of the definition's model
+            let engine = engines
+                .iter()
+                .find(|e| e.model() == definition.model)
+                .cloned()
+                .ok_or(anyhow!(
+                    "No engine for the {:?} model of definition {}",
+                    definition.model,
+                    definition.topic
+                ))?;
             let outgoing = outgoing.clone();
 
             // Spawn a dedicated OS thread for this specific engine
@@ -87,11 +138,11 @@ impl Processor {
                     .build()
                     .unwrap();
                 rt.block_on(async {
-                    for i in 0..DEFINITIONS_THREADS {
+                    for i in 0..workers(&definition) {
                         let mut definition = definition.clone();
-                        let engines = engines.clone();
-                        let mut engine = engines.into_iter().next().unwrap();
+                        let mut engine = engine.clone();
                         let startup_tx = startup_tx.clone();
+                        let joined = joined.clone();
 
                         let outgoing = outgoing.clone();
                         let id = id_counter;
@@ -99,25 +150,39 @@ impl Processor {
                         tokio::spawn(async move {
                             let mut join_set = JoinSet::new();
 
-                            let mut strategy: ProcessorType = match definition.algebra.scope() {
-                                Scope::Tuple => ProcessorType::Tuple(TupleProcessor {
-                                    processing_engine: definition.processing(),
-                                    rx: definition.process_single.1.clone(),
-                                }),
-                                Scope::Multi => todo!("MultiProcessor implementation"),
-                                Scope::Join => todo!("JoinProcessor implementation"),
+                            let started =
+                                match strategy(&mut definition, joined, &mut join_set).await {
+                                    Ok(processor) => {
+                                        engine.start(&mut join_set).await.map(|_| processor)
+                                    }
+                                    Err(err) => Err(err),
+                                };
+                            // the processor waits for all workers, also for the failed ones
+                            let mut processor = match started {
+                                Ok(processor) => {
+                                    let _ = startup_tx.send(Ok(()));
+                                    processor
+                                }
+                                Err(err) => {
+                                    let _ = startup_tx.send(Err(anyhow!(
+                                        "Processor of definition {} failed to start: {}",
+                                        definition.id.0,
+                                        err
+                                    )));
+                                    return;
+                                }
                             };
 
-                            engine.start(&mut join_set).await.unwrap();
-                            match startup_tx.send(true) {
-                                Ok(_) => {}
-                                Err(err) => error!("{}", err),
-                            }
-
-                            strategy
+                            let definition_id = definition.id;
+                            if let Err(err) = processor
                                 .process(i as u64, id, engine, definition, outgoing)
                                 .await
-                                .unwrap();
+                            {
+                                error!(
+                                    "Processor {} of definition {} stopped: {:#}",
+                                    id, definition_id.0, err
+                                );
+                            }
                         });
                     }
                     std::future::pending::<()>().await;
@@ -126,15 +191,58 @@ impl Processor {
         }
 
         for _ in 0..total_workers {
-            startup_rx.recv()?;
+            startup_rx.recv()??;
         }
         info!("All processors started...");
         Ok(())
     }
 }
 
+/// The processor the scope of the definition needs, fails on queries it cannot evaluate.
+async fn strategy(
+    definition: &mut Definition,
+    joined: Option<(Definition, Engine)>,
+    join_set: &mut JoinSet<()>,
+) -> anyhow::Result<ProcessorType> {
+    Ok(match definition.algebra.scope() {
+        Scope::Tuple => {
+            let backend = definition.processing()?;
+            match definition.columnar(&backend) {
+                Some(program) => ProcessorType::Columnar(Box::new(ColumnarProcessor {
+                    program,
+                    schema: definition.source_schema(),
+                    fallback: backend,
+                    rx: definition.process_single.1.clone(),
+                })),
+                None => ProcessorType::Tuple(Box::new(TupleProcessor {
+                    processing_engine: backend,
+                    rx: definition.process_single.1.clone(),
+                })),
+            }
+        }
+        Scope::Multi => ProcessorType::Multi(Box::new(MultiProcessor {
+            bounded: definition.bounded()?,
+            rx: definition.process_full.1.clone(),
+        })),
+        Scope::Join => {
+            let (joined, mut lookup) =
+                joined.ok_or(anyhow!("No joined definition for {}", definition.topic))?;
+            lookup.start(join_set).await?;
+            ProcessorType::Join(Box::new(JoinProcessor {
+                joiner: definition.joiner(&joined)?,
+                rx: definition.process_full.1.clone(),
+                updates: joined.subscribe(),
+                joined,
+                lookup,
+                partitions: HashMap::new(),
+                unread: HashMap::new(),
+            }))
+        }
+    })
+}
+
 struct TupleProcessor {
-    processing_engine: Program,
+    processing_engine: Backend,
     rx: Receiver<Batch<TargetedRecord>>,
 }
 
@@ -154,8 +262,6 @@ impl RecordProcessor for TupleProcessor {
         let hb_name = name.clone();
         let id = id.into();
 
-        let definition_id = definition.id;
-
         loop {
             let mut processing_engine = self.processing_engine.clone();
             tokio::select! {
@@ -164,7 +270,7 @@ impl RecordProcessor for TupleProcessor {
                 }
 
                 res = self.rx.recv_async() => {
-                    let mut start = Instant::now();
+                    let start = Instant::now();
                     let mut records = match res {
                         Ok(r) => r,
                         Err(_) => bail!("Could not receive"), // Channel closed
@@ -186,7 +292,8 @@ impl RecordProcessor for TupleProcessor {
                         )?;
                     let meta = records.last().unwrap().meta.clone();
 
-                    let processed_data = processing_engine.collect::<Vec<_>>();
+                    let processed_data = processing_engine.by_ref().collect::<Vec<_>>();
+                    report(&engine, &definition, &records, processing_engine.take_errors());
 
                     let processed_data: Batch<_> = processed_data.into_iter()
                         .map(|d| {
@@ -196,18 +303,598 @@ impl RecordProcessor for TupleProcessor {
                             )
                         })
                         .collect();
-                    let partition_id = definition.partition_info.next(&id, &(records.len() as u64)).into();
-
                     info!("Processing of {} records took: {:?}", records.len(), start.elapsed());
-                    start = Instant::now();
+                    forward(&mut engine, &definition, &id, records, processed_data, &outgoing).await?;
+
+                    tokio::task::yield_now().await;
+                }
+            }
+        }
+    }
+}
+
+/// Routes the records the processing failed on to the error events, with the id of each.
+fn report(
+    engine: &Engine,
+    definition: &Definition,
+    records: &Batch<TargetedRecord>,
+    errors: Vec<RecordError>,
+) {
+    for error in errors {
+        let Some(record) = records.records.get(error.record) else {
+            continue;
+        };
+        warn!(
+            "Record {} of definition {} failed: {}",
+            record.meta.id, definition.id.0, error.message
+        );
+        let _ = engine.statistic_sender.send(Event::Error(ErrorEvent {
+            id: definition.id,
+            record: record.meta.id,
+            message: error.message,
+        }));
+    }
+}
+
+/// Stores the processed records, notifies the statistics and sends the original records on.
+async fn forward(
+    engine: &mut Engine,
+    definition: &Definition,
+    id: &WorkerId,
+    records: Batch<TargetedRecord>,
+    processed: Batch<TargetedRecord>,
+    outgoing: &Sender<Batch<TargetedRecord>>,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+    let partition_id = definition
+        .partition_info
+        .next(id, &(records.len() as u64))
+        .into();
+
+    // Store and notify
+    engine
+        .store(partition_id, Stage::Process, definition.id, &processed)
+        .await
+        .map_err(|e| anyhow!(e))?;
+
+    info!(
+        "Storing of {} records took:  {:?}",
+        records.len(),
+        start.elapsed()
+    );
+
+    let ids: Vec<u64> = records.records.iter().map(|r| r.meta.id).collect();
+    let _ = engine.statistic_sender.send(Event::Insert {
+        id: definition.id,
+        source: engine.id,
+        stage: Stage::Process,
+        ids,
+        first: Instant::now(),
+    });
+
+    // Send original records to next phase
+    let _ = outgoing.send(records);
+    Ok(())
+}
+
+/// Processes the records of a batch column by column, batches which do not fit the columns or
+/// fail on the way, e.g. as an integer overflows, go through the tuple backend instead.
+struct ColumnarProcessor {
+    program: ColumnarProgram,
+    schema: Schema,
+    fallback: Backend,
+    rx: Receiver<Batch<TargetedRecord>>,
+}
+
+impl ColumnarProcessor {
+    /// The processed records and the records which failed on the way.
+    fn evaluate(
+        &self,
+        records: &Batch<TargetedRecord>,
+    ) -> anyhow::Result<(Batch<TargetedRecord>, Vec<RecordError>)> {
+        let meta = records.last().unwrap().meta.clone();
+        let err = match records.columnar(&self.schema) {
+            Ok(batch) => {
+                let mut program = self.program.clone();
+                program.reset();
+                program.set_resource("$$source", iter::once(batch))?;
+                let processed = program
+                    .by_ref()
+                    .flat_map(|batch| Batch::from_columnar(&batch, &meta))
+                    .collect();
+                match program.take_error() {
+                    None => return Ok((processed, vec![])),
+                    // the tuple backend tells which records failed
+                    Some(err) => anyhow!(err),
+                }
+            }
+            Err(err) => err,
+        };
+        debug!("Processing batch record by record: {}", err);
+        let mut fallback = self.fallback.clone();
+        fallback.reset();
+        fallback.set_resource(
+            "$$source",
+            records.records.clone().into_iter().map(|d| d.value),
+        )?;
+        let processed = fallback
+            .by_ref()
+            .map(|d| target!(d, meta.clone()))
+            .collect();
+        Ok((processed, fallback.take_errors()))
+    }
+}
+
+#[async_trait]
+impl RecordProcessor for ColumnarProcessor {
+    async fn process(
+        &mut self,
+        id: u64,
+        worker_id: u64,
+        mut engine: Engine,
+        definition: Definition,
+        outgoing: Sender<Batch<TargetedRecord>>,
+    ) -> anyhow::Result<()> {
+        let name = format!("Processor {} {}", engine.engine_kind, worker_id);
+
+        let mut hb_ticker = tokio::time::interval(Duration::from_secs(5));
+        let id = id.into();
+
+        loop {
+            tokio::select! {
+                _ = hb_ticker.tick() => {
+                    let _ = engine.statistic_sender.send(Event::Heartbeat(name.clone()));
+                }
+
+                res = self.rx.recv_async() => {
+                    let start = Instant::now();
+                    let mut records = match res {
+                        Ok(r) => r,
+                        Err(_) => bail!("Could not receive"), // Channel closed
+                    };
+
+                    while let Ok(more) = self.rx.try_recv() {
+                        records.records.extend(more);
+                        if records.len() >= 100_000 { break; }
+                    }
+
+                    let (processed_data, errors) = self.evaluate(&records)?;
+                    report(&engine, &definition, &records, errors);
+
+                    info!("Columnar processing of {} records took: {:?}", records.len(), start.elapsed());
+                    forward(&mut engine, &definition, &id, records, processed_data, &outgoing).await?;
+
+                    tokio::task::yield_now().await;
+                }
+            }
+        }
+    }
+}
+
+/// Records which came too late for their windows go to the side output or are dropped.
+fn handle_late(definition: &Definition, records: Vec<TargetedRecord>) {
+    if records.is_empty() {
+        return;
+    }
+    match definition.time.as_ref().map(|t| t.late) {
+        Some(Lateness::Side) => definition.publish_late(&records.into_iter().collect()),
+        _ => debug!(
+            "Dropped {} late records of definition {}",
+            records.len(),
+            definition.id.0
+        ),
+    }
+}
+
+/// Slack given to records still on their way before an idle window is closed.
+const IDLE_DELAY_MS: i64 = 1_000;
+
+struct MultiProcessor {
+    bounded: Bounded,
+    rx: Receiver<Batch<TargetedRecord>>,
+}
 
-                    // Store and notify
-                    engine
-                        .store(partition_id, Stage::Process, definition_id, &processed_data)
-                        .await
-                        .map_err(|e| anyhow!(e))?;
+impl MultiProcessor {
+    async fn store(
+        &mut self,
+        rows: Vec<(Bounds, Value)>,
+        meta: &TargetedMeta,
+        id: &WorkerId,
+        engine: &mut Engine,
+        definition: &Definition,
+    ) -> anyhow::Result<()> {
+        for (bounds, dropped) in self.bounded.take_truncated() {
+            warn!(
+                "Window {}..{} of definition {} missed {} rows beyond its state size",
+                bounds.start, bounds.end, definition.id.0, dropped
+            );
+        }
+        for (bounds, error) in self.bounded.take_errors() {
+            let message = format!(
+                "Row {} of window {}..{} failed: {}",
+                error.record, bounds.start, bounds.end, error.message
+            );
+            warn!("{} in definition {}", message, definition.id.0);
+            let _ = engine.statistic_sender.send(Event::Error(ErrorEvent {
+                id: definition.id,
+                record: meta.id,
+                message,
+            }));
+        }
+        if rows.is_empty() {
+            return Ok(());
+        }
 
-                    info!("Storing of {} records took:  {:?}", records.len(), start.elapsed());
+        let windowed = definition.window.is_some();
+        let aggregated: Batch<_> = rows
+            .into_iter()
+            .map(|(bounds, row)| {
+                let mut meta = meta.clone();
+                if windowed {
+                    meta.timestamp = bounds.end;
+                    meta.event_time = bounds.end;
+                }
+                target!(row, meta)
+            })
+            .collect();
+
+        let partition_id = definition
+            .partition_info
+            .next(id, &(aggregated.len() as u64))
+            .into();
+        engine
+            .store(partition_id, Stage::Process, definition.id, &aggregated)
+            .await
+            .map_err(|e| anyhow!(e))
+    }
+}
+
+#[async_trait]
+impl RecordProcessor for MultiProcessor {
+    async fn process(
+        &mut self,
+        id: u64,
+        worker_id: u64,
+        mut engine: Engine,
+        definition: Definition,
+        outgoing: Sender<Batch<TargetedRecord>>,
+    ) -> anyhow::Result<()> {
+        let name = format!("Processor {} {}", engine.engine_kind, worker_id);
+
+        let mut hb_ticker = tokio::time::interval(Duration::from_secs(5));
+        let mut window_ticker = tokio::time::interval(Duration::from_secs(1));
+        let hb_name = name.clone();
+        let id = id.into();
+
+        let definition_id = definition.id;
+        let mut last_meta = TargetedMeta::default();
+
+        loop {
+            tokio::select! {
+                _ = hb_ticker.tick() => {
+                    let _ = engine.statistic_sender.send(Event::Heartbeat(hb_name.clone()));
+                }
+
+                // windows also have to close when no new records arrive, by event time only
+                // records move the watermark
+                _ = window_ticker.tick(), if definition.window.is_some() && definition.time.is_none() => {
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+                    let rows = self.bounded.advance(now - IDLE_DELAY_MS)?;
+                    self.store(rows, &last_meta, &id, &mut engine, &definition).await?;
+                }
+
+                res = self.rx.recv_async() => {
+                    let start = Instant::now();
+                    let mut records = match res {
+                        Ok(r) => r,
+                        Err(_) => bail!("Could not receive"), // Channel closed
+                    };
+
+                    while let Ok(more) = self.rx.try_recv() {
+                        records.records.extend(more);
+                        if records.len() >= 100_000 { break; }
+                    }
+
+                    let mut watermark = i64::MIN;
+                    let mut late = vec![];
+                    for record in records.iter() {
+                        watermark = watermark.max(record.meta.watermark);
+                        if !self.bounded.push(record.meta.event_time, record.value.clone())? {
+                            late.push(record.clone());
+                        }
+                    }
+                    handle_late(&definition, late);
+                    if let Some(record) = records.last() {
+                        last_meta = record.meta.clone();
+                    }
+
+                    let rows = match definition.window {
+                        Some(_) => self.bounded.advance(watermark)?,
+                        None => self.bounded.flush()?,
+                    };
+                    let aggregated = rows.len();
+                    self.store(rows, &last_meta, &id, &mut engine, &definition).await?;
+
+                    info!("Aggregating {} records into {} rows took: {:?}", records.len(), aggregated, start.elapsed());
+
+                    let ids: Vec<u64> = records.records.iter().map(|r| r.meta.id).collect();
+                    let _ = engine.statistic_sender.send(Event::Insert {
+                        id: definition_id,
+                        source: engine.id,
+                        stage: Stage::Process,
+                        ids,
+                        first: Instant::now(),
+                    });
+
+                    // Send original records to next phase
+                    let _ = outgoing.send(records);
+
+                    tokio::task::yield_now().await;
+                }
+            }
+        }
+    }
+}
+
+struct JoinProcessor {
+    joiner: Joiner,
+    rx: Receiver<Batch<TargetedRecord>>,
+    /// native records of the joined definition
+    updates: Receiver<(PartitionId, Batch<TargetedRecord>)>,
+    joined: Definition,
+    lookup: Engine,
+    /// where the indexed records of the joined definition are stored
+    partitions: HashMap<u64, PartitionId>,
+    /// indexed records of partitions the lookup engine cannot read yet, e.g. as their file is
+    /// still written
+    unread: HashMap<PartitionId, HashMap<u64, Value>>,
+}
+
+impl JoinProcessor {
+    /// Indexes the native records of the joined definition which were stored before this
+    /// processor started, e.g. in an earlier run, partition by partition until the first empty one.
+    async fn seed(&mut self) -> anyhow::Result<()> {
+        if self.joiner.is_windowed() {
+            return Ok(());
+        }
+        let mut partition_id = PartitionId(0);
+        loop {
+            let entity = self.joined.entity_name(partition_id, &Stage::Native);
+            let records = match self.lookup.scan(&Stage::Native, entity.clone()).await {
+                Ok(records) if !records.is_empty() => records,
+                Ok(_) => break,
+                Err(err) => {
+                    debug!("Stopped indexing stored records at {}: {}", entity, err);
+                    break;
+                }
+            };
+            for (id, value) in records {
+                if let Some(replaced) = self.joiner.index(id, value)? {
+                    self.partitions.remove(&replaced);
+                }
+                self.partitions.insert(id, partition_id);
+            }
+            partition_id = PartitionId(partition_id.0 + 1);
+        }
+        info!(
+            "Indexed {} stored records of {} to join",
+            self.partitions.len(),
+            self.joined.topic
+        );
+        Ok(())
+    }
+
+    /// Joins records of the joined definition with buffered ones or indexes them for lookups.
+    async fn update(
+        &mut self,
+        partition_id: PartitionId,
+        records: Batch<TargetedRecord>,
+    ) -> anyhow::Result<Vec<(Value, TargetedMeta)>> {
+        let mut rows = vec![];
+        for record in records.records {
+            if self.joiner.is_windowed() {
+                let joined = self
+                    .joiner
+                    .push_right(record.meta.event_time, record.value)?;
+                rows.extend(joined.into_iter().map(|r| (r, record.meta.clone())));
+            } else {
+                self.unread
+                    .entry(partition_id)
+                    .or_default()
+                    .insert(record.meta.id, record.value.clone());
+                if let Some(replaced) = self.joiner.index(record.meta.id, record.value)?
+                    && let Some(partition_id) = self.partitions.remove(&replaced)
+                    && let Some(unread) = self.unread.get_mut(&partition_id)
+                {
+                    unread.remove(&replaced);
+                }
+                self.partitions.insert(record.meta.id, partition_id);
+            }
+        }
+        self.forget_readable().await;
+        Ok(rows)
+    }
+
+    /// Drops the kept records of the partitions which the lookup engine can read by now.
+    async fn forget_readable(&mut self) {
+        let mut readable = vec![];
+        for partition_id in self.unread.keys() {
+            let entity = self.joined.entity_name(*partition_id, &Stage::Native);
+            if self
+                .lookup
+                .read(&Stage::Native, entity, vec![])
+                .await
+                .is_ok()
+            {
+                readable.push(*partition_id);
+            }
+        }
+        for partition_id in readable {
+            self.unread.remove(&partition_id);
+        }
+    }
+
+    async fn join(
+        &mut self,
+        records: &Batch<TargetedRecord>,
+    ) -> anyhow::Result<Vec<(Value, TargetedMeta)>> {
+        let mut rows = vec![];
+        if self.joiner.is_windowed() {
+            for record in records.iter() {
+                let joined = self
+                    .joiner
+                    .push_left(record.meta.event_time, record.value.clone())?;
+                rows.extend(joined.into_iter().map(|r| (r, record.meta.clone())));
+            }
+            return Ok(rows);
+        }
+
+        // each joined record is read once per batch, records the engine cannot read yet are kept
+        let mut read: HashMap<u64, Option<Value>> = HashMap::new();
+        for record in records.iter() {
+            let Some(id) = self.joiner.lookup(&record.value)? else {
+                continue;
+            };
+            let right = match read.get(&id) {
+                Some(right) => right.clone(),
+                None => {
+                    let right = match self.partitions.get(&id) {
+                        Some(partition_id) => match self
+                            .unread
+                            .get(partition_id)
+                            .and_then(|unread| unread.get(&id))
+                        {
+                            Some(right) => Some(right.clone()),
+                            None => {
+                                let entity = self.joined.entity_name(*partition_id, &Stage::Native);
+                                self.lookup
+                                    .read(&Stage::Native, entity, vec![Value::int(id as i64)])
+                                    .await
+                                    .map_err(|err| {
+                                        anyhow!(
+                                            "Record {} is not joined with {}: {}",
+                                            record.meta.id,
+                                            self.joined.topic,
+                                            err
+                                        )
+                                    })?
+                                    .into_iter()
+                                    .next()
+                            }
+                        },
+                        None => None,
+                    };
+                    read.insert(id, right.clone());
+                    right
+                }
+            };
+            if let Some(right) = right {
+                let joined = self.joiner.combine(record.value.clone(), right)?;
+                rows.extend(joined.into_iter().map(|r| (r, record.meta.clone())));
+            }
+        }
+        Ok(rows)
+    }
+
+    async fn store(
+        &self,
+        rows: Vec<(Value, TargetedMeta)>,
+        id: &WorkerId,
+        engine: &mut Engine,
+        definition: &Definition,
+    ) -> anyhow::Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        let joined: Batch<_> = rows
+            .into_iter()
+            .map(|(row, mut meta)| {
+                meta.definition = definition.id;
+                target!(row, meta)
+            })
+            .collect();
+
+        let partition_id = definition
+            .partition_info
+            .next(id, &(joined.len() as u64))
+            .into();
+        engine
+            .store(partition_id, Stage::Process, definition.id, &joined)
+            .await
+            .map_err(|e| anyhow!(e))
+    }
+}
+
+#[async_trait]
+impl RecordProcessor for JoinProcessor {
+    async fn process(
+        &mut self,
+        id: u64,
+        worker_id: u64,
+        mut engine: Engine,
+        definition: Definition,
+        outgoing: Sender<Batch<TargetedRecord>>,
+    ) -> anyhow::Result<()> {
+        let name = format!("Processor {} {}", engine.engine_kind, worker_id);
+
+        let mut hb_ticker = tokio::time::interval(Duration::from_secs(5));
+        let mut window_ticker = tokio::time::interval(Duration::from_secs(1));
+        let hb_name = name.clone();
+        let id = id.into();
+
+        let definition_id = definition.id;
+
+        self.seed().await?;
+
+        loop {
+            tokio::select! {
+                _ = hb_ticker.tick() => {
+                    let _ = engine.statistic_sender.send(Event::Heartbeat(hb_name.clone()));
+                }
+
+                // buffered records are dropped once they cannot be joined anymore
+                _ = window_ticker.tick(), if self.joiner.is_windowed() && definition.time.is_none() => {
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+                    self.joiner.advance(now - IDLE_DELAY_MS);
+                }
+
+                res = self.updates.recv_async() => {
+                    let (partition_id, records) = match res {
+                        Ok(r) => r,
+                        Err(_) => bail!("Could not receive"), // Channel closed
+                    };
+                    let rows = self.update(partition_id, records).await?;
+                    self.store(rows, &id, &mut engine, &definition).await?;
+                }
+
+                res = self.rx.recv_async() => {
+                    let start = Instant::now();
+                    let mut records = match res {
+                        Ok(r) => r,
+                        Err(_) => bail!("Could not receive"), // Channel closed
+                    };
+
+                    while let Ok(more) = self.rx.try_recv() {
+                        records.records.extend(more);
+                        if records.len() >= 100_000 { break; }
+                    }
+
+                    // lookups should see all joined records which arrived so far
+                    while let Ok((partition_id, updates)) = self.updates.try_recv() {
+                        let rows = self.update(partition_id, updates).await?;
+                        self.store(rows, &id, &mut engine, &definition).await?;
+                    }
+
+                    let rows = self.join(&records).await?;
+                    let joined = rows.len();
+                    if definition.time.is_some() {
+                        let watermark = records.iter().map(|r| r.meta.watermark).max();
+                        self.joiner.advance(watermark.unwrap_or(i64::MIN));
+                    }
+                    self.store(rows, &id, &mut engine, &definition).await?;
+
+                    info!("Joining {} records into {} rows took: {:?}", records.len(), joined, start.elapsed());
 
                     let ids: Vec<u64> = records.records.iter().map(|r| r.meta.id).collect();
                     let _ = engine.statistic_sender.send(Event::Insert {
@@ -230,18 +917,54 @@ impl RecordProcessor for TupleProcessor {
 
 #[cfg(test)]
 mod test {
-    use processing::{Schema, parse_sql};
+    use super::{ColumnarProcessor, JoinProcessor, Processor};
+    use crate::management::catalog::Catalog;
+    use engine::engine::Engine;
+    use engine::{ArrowFiles, Memory, StorageEngine};
+    use processing::{RecordError, Schema, parse_sql};
     use rand::{Rng, rng};
     use std::collections::HashMap;
     use std::time::Instant;
+    use util::definition::{Definition, Model, Stage};
+    use util::query::Query;
+    use util::{
+        Batch, NativeMapping, PartitionId, RelationalType, Runtimes, TargetedMeta, batch, target,
+    };
     use value::{ValType, Value};
 
+    #[tokio::test]
+    async fn missing_engine() {
+        let (statistic_tx, _statistic_rx) = flume::unbounded();
+        let catalog = Catalog::new(statistic_tx.clone());
+        let definition = Definition::builder(
+            "devices",
+            NativeMapping::doc_to_key_value("device"),
+            Query::SQL("SELECT * FROM $$source".to_string()),
+            Model::KeyValue,
+        )
+        .build()
+        .await
+        .unwrap();
+        catalog
+            .add_definition("devices".to_string(), definition, statistic_tx)
+            .await
+            .unwrap();
+
+        // no engine stores key-value pairs, which fails the start instead of its thread
+        let (outgoing, _) = tokio::sync::broadcast::channel(1);
+        let err = Processor::new(catalog)
+            .start(Runtimes::new(), outgoing)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("No engine"), "{}", err);
+    }
+
     #[test]
     fn test_tp() {
         let query = "SELECT concurrency * concurrency FROM $$source";
-        let mut alg = parse_sql(query);
+        let mut alg = parse_sql(query).unwrap();
         alg.set_schema(Schema::fixed([("concurrency".to_string(), ValType::Float)]));
-        let mut program = alg.processing();
+        let mut program = alg.processing().unwrap();
 
         let mut source = vec![];
         let mut res = vec![];
@@ -276,4 +999,242 @@ mod test {
         }
         println!("Duration: {:?}", now.elapsed());
     }
+
+    async fn definition(
+        topic: &str,
+        query: &str,
+        columns: [(&str, RelationalType); 2],
+    ) -> Definition {
+        Definition::builder(
+            topic,
+            NativeMapping::tuple_to_relational(
+                columns
+                    .into_iter()
+                    .map(|(n, t)| (n.to_string(), t))
+                    .collect(),
+            ),
+            Query::SQL(query.to_string()),
+            Model::Relational,
+        )
+        .build()
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn columnar_overflow() {
+        let mut definition = definition(
+            "sensors",
+            "SELECT name, id * 2 AS double FROM $$source",
+            [
+                ("name", RelationalType::Text),
+                ("id", RelationalType::Integer),
+            ],
+        )
+        .await;
+        let backend = definition.processing().unwrap();
+        let processor = ColumnarProcessor {
+            program: definition.columnar(&backend).unwrap(),
+            schema: definition.source_schema(),
+            fallback: backend.clone(),
+            rx: definition.process_single.1.clone(),
+        };
+
+        let records: Vec<_> = [1, i64::MAX, 3]
+            .into_iter()
+            .map(|id| Value::array(vec![Value::text("a"), Value::int(id)]))
+            .collect();
+        let (processed, errors) = processor
+            .evaluate(&Batch::new(
+                records
+                    .iter()
+                    .map(|r| target!(r.clone(), TargetedMeta::default()))
+                    .collect(),
+            ))
+            .unwrap();
+
+        // the overflowing record fails like it does in the tuple VM instead of wrapping around
+        let mut tuple = backend;
+        tuple.set_resource("$$source", records.into_iter()).unwrap();
+        let expected = tuple.by_ref().collect::<Vec<_>>();
+        let values = processed.records.into_iter().map(|r| r.value);
+        assert_eq!(values.collect::<Vec<_>>(), expected);
+        assert_eq!(expected.len(), 2);
+        let failed =
+            |errors: Vec<RecordError>| errors.into_iter().map(|e| e.record).collect::<Vec<_>>();
+        let errors = failed(errors);
+        assert_eq!(errors, failed(tuple.take_errors()));
+        assert_eq!(errors, vec![1]);
+    }
+
+    #[tokio::test]
+    async fn invalid_window() {
+        for query in [
+            "SELECT device FROM $$source ORDER BY device",
+            "SELECT device, temp, COUNT(*) FROM $$source GROUP BY device",
+        ] {
+            let definition = Definition::builder(
+                "sensors",
+                NativeMapping::tuple_to_relational(vec![
+                    ("device".to_string(), RelationalType::Integer),
+                    ("temp".to_string(), RelationalType::Float),
+                ]),
+                Query::SQL(query.to_string()),
+                Model::Relational,
+            )
+            .build()
+            .await;
+            // fails when registered, not when its workers start
+            assert!(definition.is_err(), "{}", query);
+        }
+    }
+
+    #[tokio::test]
+    async fn join_stored() {
+        let devices = definition(
+            "devices",
+            "SELECT * FROM $$source",
+            [
+                ("id", RelationalType::Integer),
+                ("location", RelationalType::Text),
+            ],
+        )
+        .await;
+        let sensors = definition(
+            "sensors",
+            "SELECT s.temp, d.location FROM $$source s JOIN $$devices d ON s.device = d.id",
+            [
+                ("device", RelationalType::Integer),
+                ("temp", RelationalType::Float),
+            ],
+        )
+        .await;
+
+        // the device was stored before the join started
+        let mut memory = Memory::new(Model::Relational);
+        memory
+            .init_entity(&devices, PartitionId(0), &Stage::Native)
+            .await
+            .unwrap();
+        memory
+            .store(
+                &Stage::Native,
+                devices.entity_name(PartitionId(0), &Stage::Native),
+                &batch![target!(
+                    Value::array(vec![Value::int(1), Value::text("hall")]),
+                    TargetedMeta {
+                        id: 3,
+                        ..TargetedMeta::default()
+                    }
+                )],
+            )
+            .await
+            .unwrap();
+
+        let (statistic_tx, _) = flume::unbounded();
+        let mut processor = JoinProcessor {
+            joiner: sensors.joiner(&devices).unwrap(),
+            rx: sensors.process_full.1.clone(),
+            updates: devices.subscribe(),
+            lookup: Engine::new(Box::new(memory), statistic_tx).await,
+            joined: devices,
+            partitions: HashMap::new(),
+            unread: HashMap::new(),
+        };
+        processor.seed().await.unwrap();
+
+        let sensor = Value::array(vec![Value::int(1), Value::float(20.5)]);
+        let rows = processor
+            .join(&batch![target!(sensor, TargetedMeta::default())])
+            .await
+            .unwrap();
+        assert_eq!(
+            rows.into_iter().map(|(row, _)| row).collect::<Vec<_>>(),
+            vec![Value::array(vec![Value::float(20.5), Value::text("hall")])]
+        );
+    }
+
+    #[tokio::test]
+    async fn join_open_file() {
+        let devices = definition(
+            "devices",
+            "SELECT * FROM $$source",
+            [
+                ("id", RelationalType::Integer),
+                ("location", RelationalType::Text),
+            ],
+        )
+        .await;
+        let sensors = definition(
+            "sensors",
+            "SELECT s.temp, d.location FROM $$source s JOIN $$devices d ON s.device = d.id",
+            [
+                ("device", RelationalType::Integer),
+                ("temp", RelationalType::Float),
+            ],
+        )
+        .await;
+
+        // the device is stored into a file which is still written
+        let directory = std::env::temp_dir().join(format!("join-open-{}", std::process::id()));
+        let mut files = ArrowFiles::new(&directory);
+        files
+            .start(&mut tokio::task::JoinSet::new(), 0.into())
+            .await
+            .unwrap();
+        files
+            .init_entity(&devices, PartitionId(0), &Stage::Native)
+            .await
+            .unwrap();
+        let device = batch![target!(
+            Value::array(vec![Value::int(1), Value::text("hall")]),
+            TargetedMeta {
+                id: 3,
+                ..TargetedMeta::default()
+            }
+        )];
+        files
+            .store(
+                &Stage::Native,
+                devices.entity_name(PartitionId(0), &Stage::Native),
+                &device,
+            )
+            .await
+            .unwrap();
+
+        let (statistic_tx, _) = flume::unbounded();
+        let mut processor = JoinProcessor {
+            joiner: sensors.joiner(&devices).unwrap(),
+            rx: sensors.process_full.1.clone(),
+            updates: devices.subscribe(),
+            lookup: Engine::new(Box::new(files), statistic_tx).await,
+            joined: devices,
+            partitions: HashMap::new(),
+            unread: HashMap::new(),
+        };
+        processor.update(PartitionId(0), device).await.unwrap();
+
+        // the device is kept until its file can be read
+        let sensor = Value::array(vec![Value::int(1), Value::float(20.5)]);
+        let rows = processor
+            .join(&batch![target!(sensor.clone(), TargetedMeta::default())])
+            .await
+            .unwrap();
+        assert_eq!(
+            rows.into_iter().map(|(row, _)| row).collect::<Vec<_>>(),
+            vec![Value::array(vec![Value::float(20.5), Value::text("hall")])]
+        );
+
+        // once the file is finished, the device is read from it again
+        processor.lookup.clone().stop().await.unwrap();
+        processor.update(PartitionId(0), batch![]).await.unwrap();
+        assert!(processor.unread.is_empty());
+        let rows = processor
+            .join(&batch![target!(sensor, TargetedMeta::default())])
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+
+        let _ = std::fs::remove_dir_all(directory);
+    }
 }
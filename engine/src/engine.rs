@@ -1,21 +1,16 @@
-use crate::connection::PostgresConnection;
-use crate::mongo::MongoDB;
-use crate::neo::Neo4j;
-use crate::postgres::Postgres;
-use derive_more::From;
+use crate::storage::StorageEngine;
 use flume::{bounded, unbounded, Receiver, Sender};
 use futures_util::future::join_all;
 use mongodb::bson::uuid;
-use serde::{Deserialize};
+use processing::Algebra;
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::ops::Mul;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::thread;
 use std::thread::JoinHandle;
 use std::time::Duration;
-use tokio::fs;
 use tokio::runtime::Builder;
 use tokio::task::JoinSet;
 use tokio::time::sleep;
@@ -38,7 +33,7 @@ pub struct Engine {
     pub ids: Vec<u64>,
     pub statistic_sender: Sender<Event>,
     pub existing_partitions: Vec<(DefinitionId, PartitionId)>,
-    pub engine_kind: EngineKind,
+    pub engine_kind: Box<dyn StorageEngine>,
     pub id: EngineId,
     pub definitions: HashMap<DefinitionId, Definition>,
     pub handles: Vec<JoinHandle<()>>,
@@ -64,12 +59,6 @@ impl Clone for Engine {
     }
 }
 
-impl AsRef<EngineKind> for Engine {
-    fn as_ref(&self) -> &EngineKind {
-        &self.engine_kind
-    }
-}
-
 impl Display for Engine {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str(format!("{}", self.engine_kind).as_str())
@@ -77,7 +66,7 @@ impl Display for Engine {
 }
 
 impl Engine {
-    pub async fn new(engine_kind: EngineKind, sender: Sender<Event>) -> Self {
+    pub async fn new(engine_kind: Box<dyn StorageEngine>, sender: Sender<Event>) -> Self {
         let buffer_in = bounded(1_000_000);
         // we move blocking before the engine, away from the other engines
         let buffer_out = bounded(200_000);
@@ -100,11 +89,7 @@ impl Engine {
     }
 
     pub async fn start_container(&self) -> anyhow::Result<()> {
-        match &self.engine_kind {
-            EngineKind::Postgres(p) => p.start_container().await,
-            EngineKind::MongoDB(m) => m.start_container().await,
-            EngineKind::Neo4j(n) => n.start_container().await,
-        }
+        self.engine_kind.start_container().await
     }
 
     pub async fn start(&mut self, join_set: &mut JoinSet<()>) -> anyhow::Result<()> {
@@ -240,11 +225,7 @@ impl Engine {
     }
 
     pub async fn stop(self) -> anyhow::Result<()> {
-        match &self.engine_kind {
-            EngineKind::Postgres(p) => p.stop().await,
-            EngineKind::MongoDB(m) => m.stop().await,
-            EngineKind::Neo4j(n) => n.stop().await,
-        }
+        self.engine_kind.stop().await
     }
 
     pub fn add_definition(&mut self, definition: &Definition) {
@@ -253,11 +234,7 @@ impl Engine {
 
     /// Mixture between current running tx, complexity of mapping (and user suggestion).
     pub fn cost(&self, value: &Value, definition: &Definition) -> f64 {
-        let cost = match &self.engine_kind {
-            EngineKind::Postgres(p) => p.cost(value),
-            EngineKind::MongoDB(m) => m.cost(value),
-            EngineKind::Neo4j(n) => n.cost(value),
-        };
+        let cost = self.engine_kind.cost(value);
 
         let pressure = self.buffer_size.load(Ordering::Relaxed) + 1;
 
@@ -271,11 +248,7 @@ impl Engine {
     }
 
     pub fn model(&self) -> Model {
-        match self.engine_kind {
-            EngineKind::Postgres(_) => Model::Relational,
-            EngineKind::MongoDB(_) => Model::Document,
-            EngineKind::Neo4j(_) => Model::Graph,
-        }
+        self.engine_kind.model()
     }
 
     pub async fn store(
@@ -304,177 +277,47 @@ impl Engine {
         }
         let entity_name = definition.entity_name(partition_id, &stage);
 
-        match &self.engine_kind {
-            EngineKind::Postgres(p) => p.store(&stage, entity_name, values).await,
-            EngineKind::MongoDB(m) => m.store(&stage, entity_name, values).await,
-            EngineKind::Neo4j(n) => n.store(&stage, entity_name, values).await,
-        }
+        self.engine_kind.store(&stage, entity_name, values).await
     }
 
-    pub async fn read(&mut self, entity: String, ids: Vec<u64>) -> anyhow::Result<Vec<Value>> {
-        match &self.engine_kind {
-            EngineKind::Postgres(p) => p.read(entity, ids).await,
-            EngineKind::MongoDB(m) => m.read(entity, ids).await,
-            EngineKind::Neo4j(n) => n.read(entity, ids).await,
-        }
-    }
-}
-
-#[derive(Clone, From, Debug, Deserialize)]
-#[serde(tag = "type")]
-#[serde(rename_all = "lowercase")]
-pub enum EngineKind {
-    Postgres(Postgres),
-    MongoDB(MongoDB),
-    Neo4j(Neo4j),
-}
-
-impl EngineKind {
-    async fn read_from_config() -> Vec<EngineKind> {
-        let content = fs::read_to_string("engines.toml").await.unwrap();
-        let map: HashMap<String, EngineKind> = toml::from_str(&content).unwrap();
-
-        map.into_values().collect()
-    }
-}
-
-impl Display for EngineKind {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            EngineKind::Postgres(_) => f.write_str("postgres"),
-            EngineKind::MongoDB(_) => f.write_str("mongodb"),
-            EngineKind::Neo4j(_) => f.write_str("neo4j"),
-        }
-    }
-}
-
-impl EngineKind {
-    pub async fn init_entity(
+    /// The stored records under the keys, which are record ids or the keys of native pairs.
+    pub async fn read(
         &mut self,
-        definition: &Definition,
-        partition_id: PartitionId,
         stage: &Stage,
-    ) -> anyhow::Result<()> {
-        match self {
-            EngineKind::Postgres(p) => p.init_entity(definition, partition_id, stage).await?,
-            EngineKind::MongoDB(m) => m.init_entity(definition, partition_id, stage).await?,
-            EngineKind::Neo4j(n) => n.init_entity(definition, partition_id).await,
-        };
-
-        Ok(())
-    }
-
-    pub async fn start(&mut self, join_set: &mut JoinSet<()>, id: EngineId) -> anyhow::Result<()> {
-        match self {
-            EngineKind::Postgres(p) => p.start(join_set, id).await?,
-            EngineKind::MongoDB(m) => m.start(id).await?,
-            EngineKind::Neo4j(n) => n.start(id).await?,
-        }
-
-        Ok(())
+        entity: String,
+        keys: Vec<Value>,
+    ) -> anyhow::Result<Vec<Value>> {
+        self.engine_kind.read(stage, entity, keys).await
     }
 
-    pub async fn get_all(statistic_tx: Sender<Event>) -> anyhow::Result<Vec<Engine>> {
-        /*let engine_kinds: Vec<EngineKind> = vec![
-            EngineKind::postgres().into(),
-            EngineKind::mongo_db().into(),
-            EngineKind::neo4j().into(),
-        ];*/
-        
-        let engine_kinds = EngineKind::read_from_config().await;
-
-        let init_futures = engine_kinds
-            .into_iter()
-            .map(|kind| Engine::new(kind, statistic_tx.clone()));
-        Ok(join_all(init_futures).await)
+    /// All stored records of the entity with their ids.
+    pub async fn scan(&self, stage: &Stage, entity: String) -> anyhow::Result<Vec<(u64, Value)>> {
+        self.engine_kind.scan(stage, entity).await
     }
 
-    pub async fn stop(self) -> anyhow::Result<()> {
-        match self {
-            EngineKind::Postgres(ref p) => p.stop().await,
-            EngineKind::MongoDB(ref m) => m.stop().await,
-            EngineKind::Neo4j(ref n) => n.stop().await,
-        }
+    /// Evaluates the algebra inside the engine over the stored records of the entity, e.g. the
+    /// Native stage of a definition.
+    pub async fn query(&self, entity: String, algebra: &Algebra) -> anyhow::Result<Vec<Value>> {
+        self.engine_kind.query(entity, algebra).await
     }
 
+    /// Reports the load of a separate connection to the engine every few seconds.
     pub async fn monitor(
-        &mut self,
+        &self,
         join_set: &mut JoinSet<()>,
         statistic_tx: Sender<Event>,
     ) -> anyhow::Result<()> {
-        let mut engine = self.clone();
+        let mut engine = self.engine_kind.clone();
         engine.start(join_set, EngineId(0)).await?;
 
         join_set.spawn(async move {
             loop {
-                match &engine {
-                    EngineKind::Postgres(p) => p.monitor(&statistic_tx).await.unwrap(),
-                    EngineKind::MongoDB(m) => m.monitor(&statistic_tx).await.unwrap(),
-                    EngineKind::Neo4j(n) => n.monitor(&statistic_tx).await.unwrap(),
-                }
+                engine.monitor(&statistic_tx).await.unwrap();
                 sleep(Duration::from_secs(5)).await;
             }
         });
         Ok(())
     }
-
-    pub fn postgres() -> Postgres {
-        Self::postgres_with_port(5432)
-    }
-
-    pub fn postgres_with_port(port: u16) -> Postgres {
-        Postgres {
-            id: None,
-            pg_id: crate::postgres::ID_BUILDER.fetch_add(1, Ordering::Relaxed),
-            name: "engine-postgres".to_string(),
-            load: Arc::new(Mutex::new(Load::Low)),
-            connector: PostgresConnection {
-                url: "localhost".to_string(),
-                port,
-                db: "postgres".to_string(),
-                user: "postgres".to_string(),
-                password: "postgres".to_string(),
-            },
-            client: None,
-            prepared_statements: Default::default(),
-            join: None,
-            deploy: true,
-        }
-    }
-
-    #[cfg(test)]
-    fn mongo_db() -> MongoDB {
-        MongoDB {
-            id: None,
-            load: Arc::new(Mutex::new(Load::Low)),
-            client: None,
-            host: "localhost".to_string(),
-            port: 27017,
-            names: Default::default(),
-            deploy: true,
-        }
-    }
-
-    #[cfg(test)]
-    pub(crate) fn neo4j() -> Neo4j {
-        Self::neo4j_with_port(7687)
-    }
-
-    #[cfg(test)]
-    pub(crate) fn neo4j_with_port(port: u16) -> Neo4j {
-        Neo4j {
-            id: None,
-            name: "neo4j-engine".to_string(),
-            load: Arc::new(Mutex::new(Load::Low)),
-            host: "localhost".to_string(),
-            port,
-            user: "neo4j".to_string(),
-            password: "neoneoneo".to_string(),
-            graph: None,
-            prepared_queries: Default::default(),
-            deploy: true,
-        }
-    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -484,65 +327,3 @@ pub enum Load {
     Middle,
     High,
 }
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[tokio::test]
-    async fn postgres() {
-        let mapping = r#"
-        [postgres]
-        type = "postgres"
-        host = "localhost"
-        port = 5432
-        db = "postgres"
-        user = "postgres"
-        password = "postgres"
-        deploy = true"#;
-
-        let engine:HashMap<String, EngineKind>  = toml::from_str(&mapping).unwrap();
-        if let Some (pg) = engine.get("postgres") {
-            assert!(matches!(pg, EngineKind::Postgres(_)))
-        }else {
-            assert!(false);
-        }
-
-    }
-
-    #[tokio::test]
-    async fn mongo() {
-        let mapping = r#"
-        [mongodb]
-        type = "mongodb"
-        host = "localhost"
-        port = 27017
-        deploy = true"#;
-
-        let engine:HashMap<String, EngineKind>  = toml::from_str(&mapping).unwrap();
-        if let Some (pg) = engine.get("mongodb") {
-            assert!(matches!(pg, EngineKind::MongoDB(_)))
-        }else {
-            assert!(false);
-        }
-    }
-
-    #[tokio::test]
-    async fn neo4j() {
-        let mapping = r#"
-        [neo4j]
-        type = "neo4j"
-        host = "localhost"
-        port = 7687
-        user = "neo4j"
-        password = "neoneoneo"
-        deploy = true"#;
-
-        let engine:HashMap<String, EngineKind>  = toml::from_str(&mapping).unwrap();
-        if let Some (neo) = engine.get("neo4j") {
-            assert!(matches!(neo, EngineKind::Neo4j(_)))
-        }else {
-            assert!(false);
-        }
-    }
-}
\ No newline at end of file
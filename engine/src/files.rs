@@ -0,0 +1,939 @@
+use crate::storage::{StorageEngine, ids};
+use anyhow::{anyhow, bail};
+use arrow_array::{
+    Array, ArrayRef, BinaryArray, BooleanArray, Float64Array, Int64Array, RecordBatch, StringArray,
+};
+use arrow_ipc::reader::FileReader;
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{DataType, Field, Schema as ArrowSchema, SchemaRef};
+use async_trait::async_trait;
+use flume::Sender;
+use processing::Schema;
+use serde::Deserialize;
+use speedy::{Readable, Writable};
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::fs::File;
+use std::io::{BufWriter, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::{JoinSet, spawn_blocking};
+use tokio::time::{Instant, sleep};
+use tracing::{debug, warn};
+use util::definition::{Definition, Model, Stage};
+use util::{
+    Batch, DefinitionId, EngineId, Event, NativeMapping, PartitionId, RelationalMapping,
+    RelationalType, TargetedRecord,
+};
+use value::Value;
+
+/// Writes every partition of a stage into its own Arrow IPC file, e.g. for DuckDB or Spark.
+///
+/// The file of a partition is finished when a copy writing it moves on to the next partition of
+/// the stage or the engine stops, only finished files can be read. Existing files are never
+/// overwritten, writing an entity again, e.g. by a copy which still writes the partition or after a
+/// restart, adds the file `<entity>.<n>.arrow`.
+#[derive(Debug)]
+pub struct ArrowFiles {
+    pub(crate) id: Option<EngineId>,
+    pub(crate) directory: PathBuf,
+    /// the partition this copy writes last for each stage of a definition with its schema
+    pub(crate) current: HashMap<(DefinitionId, Stage), (String, SchemaRef)>,
+    pub(crate) open: Arc<Mutex<OpenFiles>>,
+}
+
+impl Clone for ArrowFiles {
+    fn clone(&self) -> Self {
+        // a copy writes no partition until it initializes it itself
+        ArrowFiles {
+            id: self.id,
+            directory: self.directory.clone(),
+            current: HashMap::new(),
+            open: self.open.clone(),
+        }
+    }
+}
+
+pub(crate) struct OpenFile {
+    schema: SchemaRef,
+    writer: FileWriter<BufWriter<File>>,
+}
+
+/// The files which are still written, they are finished when the last copy of the engine is
+/// dropped.
+#[derive(Default)]
+pub(crate) struct OpenFiles(HashMap<String, OpenFile>);
+
+impl OpenFiles {
+    fn finish(&mut self, entity: &str) -> anyhow::Result<()> {
+        if let Some(mut file) = self.0.remove(entity) {
+            file.writer.finish()?;
+            debug!("Finished file of {}", entity);
+        }
+        Ok(())
+    }
+
+    fn finish_all(&mut self) -> anyhow::Result<()> {
+        let entities: Vec<_> = self.0.keys().cloned().collect();
+        for entity in entities {
+            self.finish(&entity)?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for OpenFiles {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.0.keys()).finish()
+    }
+}
+
+impl Drop for OpenFiles {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish_all() {
+            warn!("Could not finish files: {}", err)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ArrowFiles {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct RawArrowFiles {
+            directory: PathBuf,
+        }
+
+        let raw = RawArrowFiles::deserialize(deserializer)?;
+
+        Ok(ArrowFiles::new(raw.directory))
+    }
+}
+
+impl ArrowFiles {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> ArrowFiles {
+        ArrowFiles {
+            id: None,
+            directory: directory.into(),
+            current: HashMap::new(),
+            open: Default::default(),
+        }
+    }
+
+    fn path(&self, entity: &str) -> PathBuf {
+        self.directory.join(format!("{}.arrow", entity))
+    }
+
+    /// Creates the next file of the entity which does not exist yet.
+    fn create(&self, entity: &str) -> anyhow::Result<(PathBuf, File)> {
+        let mut path = self.path(entity);
+        let mut n = 0;
+        loop {
+            match File::create_new(&path) {
+                Ok(file) => return Ok((path, file)),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                    n += 1;
+                    path = self.directory.join(format!("{}.{}.arrow", entity, n));
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// All files written for the entity, in the order they were created.
+    fn paths(&self, entity: &str) -> anyhow::Result<Vec<PathBuf>> {
+        let mut paths = vec![];
+        for entry in std::fs::read_dir(&self.directory)? {
+            let name = entry?.file_name();
+            let Some(rest) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(entity))
+                .and_then(|name| name.strip_suffix(".arrow"))
+            else {
+                continue;
+            };
+            let n = match rest.strip_prefix('.') {
+                None if rest.is_empty() => 0,
+                Some(n) => match n.parse::<usize>() {
+                    Ok(n) => n,
+                    Err(_) => continue,
+                },
+                None => continue,
+            };
+            paths.push((n, self.directory.join(name)));
+        }
+        paths.sort();
+        Ok(paths.into_iter().map(|(_, path)| path).collect())
+    }
+
+    fn open(&self) -> anyhow::Result<std::sync::MutexGuard<'_, OpenFiles>> {
+        self.open.lock().map_err(|_| anyhow!("Poisoned lock"))
+    }
+
+    /// Works on the open files in the blocking pool, as writing files blocks the thread meanwhile.
+    async fn with<T, F>(&self, work: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&ArrowFiles, &mut OpenFiles) -> anyhow::Result<T> + Send + 'static,
+    {
+        let files = self.clone();
+        spawn_blocking(move || {
+            let mut open = files.open()?;
+            work(&files, &mut open)
+        })
+        .await?
+    }
+
+    /// Creates the next file of the entity and starts writing it.
+    fn begin(&self, open: &mut OpenFiles, entity: &str, schema: SchemaRef) -> anyhow::Result<()> {
+        let (path, file) = self.create(entity)?;
+        let writer = FileWriter::try_new_buffered(file, &schema)?;
+        open.0
+            .insert(entity.to_string(), OpenFile { schema, writer });
+        debug!(
+            "File {} of '{}' created on {:?}.",
+            path.display(),
+            entity,
+            self.id
+        );
+        Ok(())
+    }
+
+    pub(crate) async fn start<S: Into<EngineId>>(&mut self, id: S) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(&self.directory).await?;
+
+        let id = id.into();
+        debug!("☑️ Writing files to {} {}", self.directory.display(), id);
+        self.id = Some(id);
+        Ok(())
+    }
+
+    pub(crate) async fn stop(&self) -> anyhow::Result<()> {
+        self.with(|_, open| open.finish_all()).await
+    }
+
+    pub(crate) async fn init_entity(
+        &mut self,
+        definition: &Definition,
+        partition_id: PartitionId,
+        stage: &Stage,
+    ) -> anyhow::Result<()> {
+        let schema = match (stage, &definition.mapping) {
+            (Stage::Plain, _) => ArrowSchema::new(vec![
+                Field::new("id", DataType::Int64, false),
+                Field::new("value", DataType::Binary, false),
+            ]),
+            (Stage::Native, NativeMapping::Relational(m)) => Self::native_schema(m),
+            (Stage::Process, NativeMapping::Relational(_)) => {
+                Self::process_schema(definition.schema()?)?
+            }
+            (Stage::Native | Stage::Process, mapping) => bail!(
+                "Files only store relational definitions, {} maps to {:?}",
+                definition.topic,
+                mapping
+            ),
+            _ => return Ok(()),
+        };
+        let name = definition.entity_name(partition_id, stage);
+        let key = (definition.id, stage.clone());
+        let last = self.current.get(&key).map(|(last, _)| last.clone());
+        if last.as_ref() == Some(&name) {
+            return Ok(());
+        }
+
+        let schema = Arc::new(schema);
+        let entity = name.clone();
+        let file_schema = schema.clone();
+        self.with(move |files, open| {
+            // the partition rotated, so its file is finished and can be read, copies which still
+            // write the partition continue it in a new file
+            if let Some(last) = last {
+                open.finish(&last)?;
+            }
+            if !open.0.contains_key(&entity) {
+                files.begin(open, &entity, file_schema)?;
+            }
+            Ok(())
+        })
+        .await?;
+        self.current.insert(key, (name, schema));
+
+        Ok(())
+    }
+
+    fn native_schema(mapping: &RelationalMapping) -> ArrowSchema {
+        Self::with_id(
+            mapping
+                .get_types()
+                .into_iter()
+                .map(|(name, t)| Field::new(name, Self::arrow_type(t), true)),
+        )
+    }
+
+    fn process_schema(schema: Schema) -> anyhow::Result<ArrowSchema> {
+        let Schema::Fixed(types) = schema else {
+            bail!("Process files need a fixed schema")
+        };
+        Ok(Self::with_id(types.into_iter().map(|(name, t)| {
+            Field::new(name, Self::arrow_type(RelationalType::from(&t)), true)
+        })))
+    }
+
+    fn with_id(fields: impl Iterator<Item = Field>) -> ArrowSchema {
+        // the id allows to read the record again, e.g. for lookups
+        ArrowSchema::new(
+            [Field::new("id", DataType::Int64, false)]
+                .into_iter()
+                .chain(fields)
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    fn arrow_type(t: RelationalType) -> DataType {
+        match t {
+            RelationalType::Varchar(_) | RelationalType::Text => DataType::Utf8,
+            RelationalType::Integer => DataType::Int64,
+            RelationalType::Float => DataType::Float64,
+            RelationalType::Bool => DataType::Boolean,
+            RelationalType::Blob => DataType::Binary,
+        }
+    }
+
+    /// Appends the batch to the file of the entity as one record batch.
+    pub(crate) async fn store(
+        &self,
+        stage: &Stage,
+        entity: String,
+        values: &Batch<TargetedRecord>,
+    ) -> anyhow::Result<()> {
+        let now = Instant::now();
+
+        let rows = values
+            .iter()
+            .map(|TargetedRecord { value, meta }| {
+                let id = Value::int(meta.id as i64);
+                match (stage, value) {
+                    (Stage::Plain, value) => Ok(vec![id, value.clone()]),
+                    (Stage::Native | Stage::Process, Value::Array(a)) => {
+                        Ok([id].into_iter().chain(a.values.iter().cloned()).collect())
+                    }
+                    (Stage::Native | Stage::Process, value) => {
+                        bail!("Expected Array value for Mapped stage, got {:?}", value)
+                    }
+                    _ => bail!("Files do not store stage {:?}", stage),
+                }
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let schema = self
+            .current
+            .values()
+            .find(|(name, _)| name == &entity)
+            .map(|(_, schema)| schema.clone());
+        self.with(move |files, open| {
+            // another copy moved on and finished the file of the partition
+            if !open.0.contains_key(&entity)
+                && let Some(schema) = schema
+            {
+                files.begin(open, &entity, schema)?;
+            }
+            let file = open
+                .0
+                .get_mut(&entity)
+                .ok_or_else(|| anyhow!("File of {} is not open", entity))?;
+
+            let columns = file
+                .schema
+                .fields()
+                .iter()
+                .enumerate()
+                .map(|(i, field)| {
+                    Self::column(field.data_type(), rows.iter().map(|row| row.get(i)))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            file.writer
+                .write(&RecordBatch::try_new(file.schema.clone(), columns)?)?;
+            Ok(())
+        })
+        .await?;
+
+        debug!("wrote to file {} {:?}", values.len(), now.elapsed());
+        Ok(())
+    }
+
+    fn column<'a, I>(data_type: &DataType, values: I) -> anyhow::Result<ArrayRef>
+    where
+        I: Iterator<Item = Option<&'a Value>>,
+    {
+        let values = values.map(|v| v.filter(|v| !matches!(v, Value::Null)));
+        Ok(match data_type {
+            DataType::Int64 => Arc::new(
+                values
+                    .map(|v| v.map(|v| v.as_int().map(|i| i.0)).transpose())
+                    .collect::<anyhow::Result<Int64Array>>()?,
+            ),
+            DataType::Float64 => Arc::new(
+                values
+                    .map(|v| v.map(|v| v.as_float().map(|f| f.0.0)).transpose())
+                    .collect::<anyhow::Result<Float64Array>>()?,
+            ),
+            DataType::Utf8 => Arc::new(
+                values
+                    .map(|v| v.map(|v| v.as_text().map(|t| t.0.to_string())).transpose())
+                    .collect::<anyhow::Result<StringArray>>()?,
+            ),
+            DataType::Boolean => Arc::new(
+                values
+                    .map(|v| v.map(|v| v.as_bool().map(|b| b.0)).transpose())
+                    .collect::<anyhow::Result<BooleanArray>>()?,
+            ),
+            DataType::Binary => Arc::new(BinaryArray::from_iter(
+                values
+                    .map(|v| v.map(|v| v.write_to_vec()).transpose())
+                    .collect::<Result<Vec<_>, _>>()?,
+            )),
+            t => bail!("Cannot write column of type {}", t),
+        })
+    }
+
+    /// The records of a finished file with the ids, as they were stored.
+    pub async fn read(
+        &self,
+        stage: &Stage,
+        entity: String,
+        ids: Vec<u64>,
+    ) -> anyhow::Result<Vec<Value>> {
+        if self.open()?.0.contains_key(&entity) {
+            bail!("File of {} is still written", entity)
+        }
+        let (files, stage) = (self.clone(), stage.clone());
+        let records = spawn_blocking(move || files.records(&stage, &entity, Some(&ids))).await??;
+        Ok(records.into_iter().map(|(_, value)| value).collect())
+    }
+
+    /// All records of the finished files of the entity with their ids.
+    pub async fn scan(&self, stage: &Stage, entity: String) -> anyhow::Result<Vec<(u64, Value)>> {
+        if self.open()?.0.contains_key(&entity) {
+            bail!("File of {} is still written", entity)
+        }
+        let (files, stage) = (self.clone(), stage.clone());
+        spawn_blocking(move || files.records(&stage, &entity, None)).await?
+    }
+
+    fn records(
+        &self,
+        stage: &Stage,
+        entity: &str,
+        ids: Option<&[u64]>,
+    ) -> anyhow::Result<Vec<(u64, Value)>> {
+        let mut values = vec![];
+        for path in self.paths(entity)? {
+            values.extend(Self::read_file(stage, &path, ids)?);
+        }
+        Ok(values)
+    }
+
+    fn read_file(
+        stage: &Stage,
+        path: &Path,
+        ids: Option<&[u64]>,
+    ) -> anyhow::Result<Vec<(u64, Value)>> {
+        let reader = FileReader::try_new_buffered(File::open(path)?, None)?;
+        let mut values = vec![];
+        for batch in reader {
+            let batch = batch?;
+            let Some(id) = batch
+                .column_by_name("id")
+                .and_then(|c| c.as_any().downcast_ref::<Int64Array>())
+            else {
+                bail!("File {} has no ids", path.display())
+            };
+
+            for row in 0..batch.num_rows() {
+                let id = id.value(row) as u64;
+                if ids.is_some_and(|ids| !ids.contains(&id)) {
+                    continue;
+                }
+                // skip the id column, so we get the record as it was stored
+                let record = match stage {
+                    Stage::Plain => Self::value(batch.column(1), row)?,
+                    _ => Value::array(
+                        (1..batch.num_columns())
+                            .map(|i| Self::value(batch.column(i), row))
+                            .collect::<anyhow::Result<Vec<_>>>()?,
+                    ),
+                };
+                values.push((id, record));
+            }
+        }
+        Ok(values)
+    }
+
+    fn value(column: &ArrayRef, row: usize) -> anyhow::Result<Value> {
+        if column.is_null(row) {
+            return Ok(Value::null());
+        }
+        let any = column.as_any();
+        Ok(match column.data_type() {
+            DataType::Int64 => Value::int(any.downcast_ref::<Int64Array>().unwrap().value(row)),
+            DataType::Float64 => {
+                Value::float(any.downcast_ref::<Float64Array>().unwrap().value(row))
+            }
+            DataType::Utf8 => Value::text(any.downcast_ref::<StringArray>().unwrap().value(row)),
+            DataType::Boolean => {
+                Value::bool(any.downcast_ref::<BooleanArray>().unwrap().value(row))
+            }
+            DataType::Binary => {
+                Value::read_from_buffer(any.downcast_ref::<BinaryArray>().unwrap().value(row))?
+            }
+            t => bail!("Cannot read column of type {}", t),
+        })
+    }
+
+    pub(crate) async fn monitor(&self, statistic_tx: &Sender<Event>) -> anyhow::Result<()> {
+        loop {
+            let open = self.open()?.0.len();
+            statistic_tx
+                .send_async(Event::EngineStatus(format!("✅ Open files: {}", open)))
+                .await?;
+            sleep(Duration::from_secs(5)).await;
+        }
+    }
+}
+
+#[async_trait]
+impl StorageEngine for ArrowFiles {
+    fn kind(&self) -> &str {
+        "arrow"
+    }
+
+    fn model(&self) -> Model {
+        Model::Relational
+    }
+
+    fn clone_box(&self) -> Box<dyn StorageEngine> {
+        Box::new(self.clone())
+    }
+
+    async fn start(&mut self, _join_set: &mut JoinSet<()>, id: EngineId) -> anyhow::Result<()> {
+        ArrowFiles::start(self, id).await
+    }
+
+    async fn stop(&self) -> anyhow::Result<()> {
+        ArrowFiles::stop(self).await
+    }
+
+    async fn init_entity(
+        &mut self,
+        definition: &Definition,
+        partition_id: PartitionId,
+        stage: &Stage,
+    ) -> anyhow::Result<()> {
+        ArrowFiles::init_entity(self, definition, partition_id, stage).await
+    }
+
+    async fn store(
+        &self,
+        stage: &Stage,
+        entity: String,
+        values: &Batch<TargetedRecord>,
+    ) -> anyhow::Result<()> {
+        ArrowFiles::store(self, stage, entity, values).await
+    }
+
+    async fn read(
+        &self,
+        stage: &Stage,
+        entity: String,
+        keys: Vec<Value>,
+    ) -> anyhow::Result<Vec<Value>> {
+        ArrowFiles::read(self, stage, entity, ids(keys)?).await
+    }
+
+    async fn scan(&self, stage: &Stage, entity: String) -> anyhow::Result<Vec<(u64, Value)>> {
+        ArrowFiles::scan(self, stage, entity).await
+    }
+
+    async fn monitor(&self, statistic_tx: &Sender<Event>) -> anyhow::Result<()> {
+        ArrowFiles::monitor(self, statistic_tx).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::files::ArrowFiles;
+    use arrow_array::RecordBatch;
+    use arrow_ipc::reader::FileReader;
+    use arrow_schema::DataType;
+    use std::fs::File;
+    use std::path::PathBuf;
+    use util::definition::{Definition, Model, Stage};
+    use util::query::Query;
+    use util::{NativeMapping, PartitionId, RelationalType, TargetedMeta, batch, target};
+    use value::{Dict, Value};
+
+    fn directory(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("arrow-{}-{}", name, std::process::id()))
+    }
+
+    async fn definition() -> Definition {
+        Definition::builder(
+            "test",
+            NativeMapping::tuple_to_relational(vec![
+                ("name".to_string(), RelationalType::Text),
+                ("age".to_string(), RelationalType::Integer),
+            ]),
+            Query::SQL("SELECT name, age FROM $$source".to_string()),
+            Model::Relational,
+        )
+        .entity("users")
+        .build()
+        .await
+        .unwrap()
+    }
+
+    fn meta(id: u64) -> TargetedMeta {
+        TargetedMeta {
+            id,
+            ..TargetedMeta::default()
+        }
+    }
+
+    fn user(name: &str, age: i64) -> Value {
+        Value::array(vec![Value::text(name), Value::int(age)])
+    }
+
+    fn batches(path: PathBuf) -> Vec<RecordBatch> {
+        FileReader::try_new(File::open(path).unwrap(), None)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn rotation() {
+        let directory = directory("rotation");
+        let definition = definition().await;
+        let first = definition.entity_name(PartitionId(0), &Stage::Native);
+        let second = definition.entity_name(PartitionId(1), &Stage::Native);
+
+        let mut files = ArrowFiles::new(&directory);
+        files.start(0).await.unwrap();
+        files
+            .init_entity(&definition, PartitionId(0), &Stage::Native)
+            .await
+            .unwrap();
+        files
+            .store(
+                &Stage::Native,
+                first.clone(),
+                &batch![
+                    target!(user("Alice", 30), meta(1)),
+                    target!(user("Bob", 25), meta(2))
+                ],
+            )
+            .await
+            .unwrap();
+        files
+            .store(
+                &Stage::Native,
+                first.clone(),
+                &batch![target!(user("Carol", 41), meta(3))],
+            )
+            .await
+            .unwrap();
+        assert!(
+            files
+                .read(&Stage::Native, first.clone(), vec![2])
+                .await
+                .is_err()
+        );
+
+        files
+            .init_entity(&definition, PartitionId(1), &Stage::Native)
+            .await
+            .unwrap();
+        files
+            .store(
+                &Stage::Native,
+                second.clone(),
+                &batch![target!(user("Dave", 19), meta(4))],
+            )
+            .await
+            .unwrap();
+
+        // the first partition was finished by the rotation
+        let written = batches(files.path(&first));
+        assert_eq!(written.len(), 2);
+        assert_eq!(
+            written[0]
+                .schema()
+                .fields()
+                .iter()
+                .map(|f| f.name().as_str())
+                .collect::<Vec<_>>(),
+            vec!["id", "name", "age"]
+        );
+        assert_eq!(
+            files
+                .read(&Stage::Native, first.clone(), vec![2, 3])
+                .await
+                .unwrap(),
+            vec![user("Bob", 25), user("Carol", 41)]
+        );
+        assert_eq!(
+            files.scan(&Stage::Native, first).await.unwrap()[0],
+            (1, user("Alice", 30))
+        );
+
+        files.stop().await.unwrap();
+        assert_eq!(
+            files.read(&Stage::Native, second, vec![4]).await.unwrap(),
+            vec![user("Dave", 19)]
+        );
+
+        let _ = std::fs::remove_dir_all(directory);
+    }
+
+    #[tokio::test]
+    async fn plain_and_process() {
+        let directory = directory("stages");
+        let definition = definition().await;
+        let plain = definition.entity_name(PartitionId(0), &Stage::Plain);
+        let process = definition.entity_name(PartitionId(0), &Stage::Process);
+
+        let mut files = ArrowFiles::new(&directory);
+        files.start(0).await.unwrap();
+        for stage in [Stage::Plain, Stage::Process] {
+            files
+                .init_entity(&definition, PartitionId(0), &stage)
+                .await
+                .unwrap();
+        }
+
+        let doc = Value::from(Dict::from(vec![("name", Value::text("Alice"))]));
+        files
+            .store(
+                &Stage::Plain,
+                plain.clone(),
+                &batch![target!(doc.clone(), meta(7))],
+            )
+            .await
+            .unwrap();
+        files
+            .store(
+                &Stage::Process,
+                process.clone(),
+                &batch![
+                    target!(user("Alice", 30), meta(7)),
+                    target!(Value::array(vec![Value::null(), Value::int(3)]), meta(8))
+                ],
+            )
+            .await
+            .unwrap();
+        assert!(
+            files
+                .store(
+                    &Stage::Process,
+                    process.clone(),
+                    &batch![target!(Value::text("Alice"), meta(9))],
+                )
+                .await
+                .is_err()
+        );
+
+        // dropping the last copy finishes the files too
+        drop(files);
+
+        let files = ArrowFiles::new(&directory);
+        assert_eq!(
+            files.read(&Stage::Plain, plain, vec![7]).await.unwrap(),
+            vec![doc]
+        );
+        let written = batches(files.path(&process));
+        assert_eq!(written[0].num_rows(), 2);
+        assert_eq!(written[0].column(1).null_count(), 1);
+        assert_eq!(
+            files
+                .read(&Stage::Process, process.clone(), vec![8])
+                .await
+                .unwrap(),
+            vec![Value::array(vec![Value::null(), Value::int(3)])]
+        );
+        assert_eq!(
+            files.scan(&Stage::Process, process).await.unwrap()[0],
+            (7, user("Alice", 30))
+        );
+
+        let _ = std::fs::remove_dir_all(directory);
+    }
+
+    #[tokio::test]
+    async fn process_any() {
+        let directory = directory("any");
+        // the branches differ in their type, so the column holds any value
+        let definition = Definition::builder(
+            "test",
+            NativeMapping::tuple_to_relational(vec![
+                ("name".to_string(), RelationalType::Text),
+                ("age".to_string(), RelationalType::Integer),
+            ]),
+            Query::SQL(
+                "SELECT name, CASE WHEN age > 26 THEN age ELSE name END AS label FROM $$source"
+                    .to_string(),
+            ),
+            Model::Relational,
+        )
+        .entity("users")
+        .build()
+        .await
+        .unwrap();
+        let process = definition.entity_name(PartitionId(0), &Stage::Process);
+
+        let mut files = ArrowFiles::new(&directory);
+        files.start(0).await.unwrap();
+        files
+            .init_entity(&definition, PartitionId(0), &Stage::Process)
+            .await
+            .unwrap();
+        let doc = Value::from(Dict::from(vec![("name", Value::text("Bob"))]));
+        files
+            .store(
+                &Stage::Process,
+                process.clone(),
+                &batch![
+                    target!(user("Alice", 30), meta(1)),
+                    target!(Value::array(vec![Value::text("Bob"), doc.clone()]), meta(2))
+                ],
+            )
+            .await
+            .unwrap();
+        drop(files);
+
+        let written = batches(ArrowFiles::new(&directory).path(&process));
+        let label = written[0].column(2);
+        assert_eq!(label.data_type(), &DataType::Binary);
+        assert_eq!(ArrowFiles::value(label, 0).unwrap(), Value::int(30));
+        assert_eq!(ArrowFiles::value(label, 1).unwrap(), doc);
+
+        let _ = std::fs::remove_dir_all(directory);
+    }
+
+    #[tokio::test]
+    async fn two_copies() {
+        let directory = directory("copies");
+        let definition = definition().await;
+        let first = definition.entity_name(PartitionId(0), &Stage::Native);
+
+        let mut files = ArrowFiles::new(&directory);
+        files.start(0).await.unwrap();
+        let mut other = files.clone();
+        for copy in [&mut files, &mut other] {
+            copy.init_entity(&definition, PartitionId(0), &Stage::Native)
+                .await
+                .unwrap();
+        }
+
+        // one copy rotates and finishes the file, though the other one still writes the partition
+        files
+            .init_entity(&definition, PartitionId(1), &Stage::Native)
+            .await
+            .unwrap();
+        assert!(
+            files
+                .read(&Stage::Native, first.clone(), vec![1])
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        other
+            .store(
+                &Stage::Native,
+                first.clone(),
+                &batch![target!(user("Alice", 30), meta(1))],
+            )
+            .await
+            .unwrap();
+        assert!(
+            files
+                .read(&Stage::Native, first.clone(), vec![1])
+                .await
+                .is_err()
+        );
+
+        other
+            .init_entity(&definition, PartitionId(1), &Stage::Native)
+            .await
+            .unwrap();
+        // the other copy continued the partition in a second file
+        assert_eq!(files.paths(&first).unwrap().len(), 2);
+        assert_eq!(
+            files.read(&Stage::Native, first, vec![1]).await.unwrap(),
+            vec![user("Alice", 30)]
+        );
+
+        let _ = std::fs::remove_dir_all(directory);
+    }
+
+    #[tokio::test]
+    async fn restart() {
+        let directory = directory("restart");
+        let definition = definition().await;
+        let first = definition.entity_name(PartitionId(0), &Stage::Native);
+
+        for (name, id) in [("Alice", 1), ("Bob", 2)] {
+            let mut files = ArrowFiles::new(&directory);
+            files.start(0).await.unwrap();
+            files
+                .init_entity(&definition, PartitionId(0), &Stage::Native)
+                .await
+                .unwrap();
+            files
+                .store(
+                    &Stage::Native,
+                    first.clone(),
+                    &batch![target!(user(name, 30), meta(id))],
+                )
+                .await
+                .unwrap();
+            files.stop().await.unwrap();
+        }
+
+        // the second run adds a file instead of overwriting the finished one
+        let files = ArrowFiles::new(&directory);
+        assert_eq!(files.paths(&first).unwrap().len(), 2);
+        assert_eq!(
+            files.read(&Stage::Native, first, vec![1, 2]).await.unwrap(),
+            vec![user("Alice", 30), user("Bob", 30)]
+        );
+
+        let _ = std::fs::remove_dir_all(directory);
+    }
+
+    #[tokio::test]
+    async fn not_relational() {
+        let directory = directory("document");
+        let mut definition = definition().await;
+        definition.mapping = NativeMapping::document();
+
+        let mut files = ArrowFiles::new(&directory);
+        files.start(0).await.unwrap();
+        assert!(
+            files
+                .init_entity(&definition, PartitionId(0), &Stage::Plain)
+                .await
+                .is_ok()
+        );
+        assert!(
+            files
+                .init_entity(&definition, PartitionId(0), &Stage::Native)
+                .await
+                .is_err()
+        );
+
+        let _ = std::fs::remove_dir_all(directory);
+    }
+}
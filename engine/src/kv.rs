@@ -0,0 +1,450 @@
+use crate::storage::StorageEngine;
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use flume::Sender;
+use redb::{Database, ReadableDatabase, ReadableTableMetadata, TableDefinition};
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio::time::{Instant, sleep};
+use tracing::{debug, warn};
+use util::definition::{Definition, Model, Stage};
+use util::{Batch, EngineId, Event, NativeMapping, PartitionId, TargetedRecord};
+use value::Value;
+
+/// A redb database file storing key-value pairs.
+///
+/// Native records are the pairs of a `KeyValueMapping` and replace the earlier value of their
+/// key, plain records are stored under their id and processed rows under ascending numbers.
+#[derive(Debug, Clone)]
+pub struct Redb {
+    pub(crate) id: Option<EngineId>,
+    pub(crate) path: PathBuf,
+    // a file can only be opened once, so all copies share the database
+    pub(crate) database: Arc<Mutex<Option<Arc<Database>>>>,
+}
+
+impl<'de> Deserialize<'de> for Redb {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct RawRedb {
+            path: PathBuf,
+        }
+
+        let raw = RawRedb::deserialize(deserializer)?;
+
+        Ok(Redb::new(raw.path))
+    }
+}
+
+impl Redb {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Redb {
+        Redb {
+            id: None,
+            path: path.into(),
+            database: Default::default(),
+        }
+    }
+
+    pub(crate) async fn start<S: Into<EngineId>>(&mut self, id: S) -> anyhow::Result<()> {
+        let mut database = self.database.lock().map_err(|_| anyhow!("Poisoned lock"))?;
+        if database.is_none() {
+            if let Some(parent) = self.path.parent()
+                && !parent.as_os_str().is_empty()
+            {
+                std::fs::create_dir_all(parent)?;
+            }
+            *database = Some(Arc::new(Database::create(&self.path)?));
+        }
+
+        let id = id.into();
+        debug!("☑️ Opened redb database {} {}", self.path.display(), id);
+        self.id = Some(id);
+
+        Ok(())
+    }
+
+    fn database(&self) -> anyhow::Result<Arc<Database>> {
+        self.database
+            .lock()
+            .map_err(|_| anyhow!("Poisoned lock"))?
+            .clone()
+            .ok_or_else(|| anyhow!("redb database {} is not open", self.path.display()))
+    }
+
+    fn table(entity: &str) -> TableDefinition<'_, Value, Value> {
+        TableDefinition::new(entity)
+    }
+
+    pub(crate) async fn init_entity(
+        &self,
+        definition: &Definition,
+        partition_id: PartitionId,
+        stage: &Stage,
+    ) -> anyhow::Result<()> {
+        if !matches!(stage, Stage::Plain)
+            && !matches!(definition.mapping, NativeMapping::KeyValue(_))
+        {
+            return Ok(());
+        }
+        let name = definition.entity_name(partition_id, stage);
+
+        // opening the table in a write transaction creates it, so it can be read before any store
+        let transaction = self.database()?.begin_write()?;
+        transaction.open_table(Self::table(&name))?;
+        transaction.commit()?;
+        debug!("Table '{}' ensured to exist on {:?}.", name, self.id);
+
+        Ok(())
+    }
+
+    /// Inserts the batch in one transaction, native records which are no key-value pair or miss
+    /// their key are skipped.
+    pub(crate) async fn store(
+        &self,
+        stage: &Stage,
+        entity: String,
+        values: &Batch<TargetedRecord>,
+    ) -> anyhow::Result<()> {
+        let now = Instant::now();
+
+        let transaction = self.database()?.begin_write()?;
+        {
+            let mut table = transaction.open_table(Self::table(&entity))?;
+            // processed rows share the meta of their batch and are only appended
+            let mut row = table.len()? as i64;
+            for TargetedRecord { value, meta } in values.iter() {
+                match stage {
+                    Stage::Plain => {
+                        table.insert(Value::int(meta.id as i64), value)?;
+                    }
+                    Stage::Process => {
+                        table.insert(Value::int(row), value)?;
+                        row += 1;
+                    }
+                    Stage::Native => match value {
+                        Value::Array(a) if a.values.len() == 2 && a.values[0] != Value::Null => {
+                            table.insert(&a.values[0], &a.values[1])?;
+                        }
+                        _ => warn!("Skipped record {} without key: {:?}", meta.id, value),
+                    },
+                    _ => bail!("redb does not store stage {:?}", stage),
+                }
+            }
+        }
+        transaction.commit()?;
+
+        debug!("inserted in redb {} {:?}", values.len(), now.elapsed());
+        Ok(())
+    }
+
+    /// The values stored under the keys, keys without a value are skipped. Plain records are
+    /// stored under their id.
+    pub async fn read(&self, entity: String, keys: Vec<Value>) -> anyhow::Result<Vec<Value>> {
+        let transaction = self.database()?.begin_read()?;
+        let table = transaction.open_table(Self::table(&entity))?;
+
+        let mut values = vec![];
+        for key in keys {
+            if let Some(value) = table.get(key)? {
+                values.push(value.value());
+            }
+        }
+        Ok(values)
+    }
+
+    pub(crate) async fn monitor(&self, statistic_tx: &Sender<Event>) -> anyhow::Result<()> {
+        loop {
+            let size = tokio::fs::metadata(&self.path).await?.len();
+            statistic_tx
+                .send_async(Event::EngineStatus(format!(
+                    "✅ redb size: {:.2} MiB",
+                    size as f64 / (1024.0 * 1024.0)
+                )))
+                .await?;
+            sleep(Duration::from_secs(5)).await;
+        }
+    }
+}
+
+#[async_trait]
+impl StorageEngine for Redb {
+    fn kind(&self) -> &str {
+        "redb"
+    }
+
+    fn model(&self) -> Model {
+        Model::KeyValue
+    }
+
+    fn clone_box(&self) -> Box<dyn StorageEngine> {
+        Box::new(self.clone())
+    }
+
+    async fn start(&mut self, _join_set: &mut JoinSet<()>, id: EngineId) -> anyhow::Result<()> {
+        Redb::start(self, id).await
+    }
+
+    async fn stop(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn init_entity(
+        &mut self,
+        definition: &Definition,
+        partition_id: PartitionId,
+        stage: &Stage,
+    ) -> anyhow::Result<()> {
+        Redb::init_entity(self, definition, partition_id, stage).await
+    }
+
+    async fn store(
+        &self,
+        stage: &Stage,
+        entity: String,
+        values: &Batch<TargetedRecord>,
+    ) -> anyhow::Result<()> {
+        Redb::store(self, stage, entity, values).await
+    }
+
+    async fn read(
+        &self,
+        _stage: &Stage,
+        entity: String,
+        keys: Vec<Value>,
+    ) -> anyhow::Result<Vec<Value>> {
+        Redb::read(self, entity, keys).await
+    }
+
+    async fn monitor(&self, statistic_tx: &Sender<Event>) -> anyhow::Result<()> {
+        Redb::monitor(self, statistic_tx).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::kv::Redb;
+    use crate::storage::StorageEngine;
+    use std::path::PathBuf;
+    use util::definition::{Definition, Model, Stage};
+    use util::query::Query;
+    use util::{NativeMapping, PartitionId, TargetedMeta, batch, target};
+    use value::{Dict, Value};
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("redb-{}-{}/engine.redb", name, std::process::id()))
+    }
+
+    async fn definition(mapping: NativeMapping) -> Definition {
+        Definition::builder(
+            "test",
+            mapping,
+            Query::SQL("SELECT * FROM $$source".to_string()),
+            Model::KeyValue,
+        )
+        .entity("devices")
+        .build()
+        .await
+        .unwrap()
+    }
+
+    fn meta(id: u64) -> TargetedMeta {
+        TargetedMeta {
+            id,
+            ..TargetedMeta::default()
+        }
+    }
+
+    fn reading(device: &str, temperature: i64) -> Value {
+        Value::from(Dict::from(vec![
+            ("device", Value::text(device)),
+            ("temperature", Value::int(temperature)),
+        ]))
+    }
+
+    #[tokio::test]
+    async fn latest_per_key() {
+        let path = path("latest");
+        let definition = definition(NativeMapping::doc_to_key_value("device")).await;
+        let entity = definition.entity_name(PartitionId(0), &Stage::Native);
+        let mapper = definition.mapping.build().unwrap();
+
+        let mut redb = Redb::new(&path);
+        redb.start(0).await.unwrap();
+        redb.init_entity(&definition, PartitionId(0), &Stage::Native)
+            .await
+            .unwrap();
+
+        let records = [reading("a", 20), reading("b", 15), reading("a", 22)];
+        let batch = records
+            .iter()
+            .enumerate()
+            .map(|(i, r)| target!(mapper(r.clone()), meta(i as u64)))
+            .collect();
+        redb.store(&Stage::Native, entity.clone(), &batch)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            redb.read(
+                entity.clone(),
+                vec![Value::text("a"), Value::text("c"), Value::text("b")]
+            )
+            .await
+            .unwrap(),
+            vec![reading("a", 22), reading("b", 15)]
+        );
+
+        // another copy shares the opened file, which is read by key through the engine
+        let mut other = redb.clone();
+        other.start(1).await.unwrap();
+        let other: Box<dyn StorageEngine> = Box::new(other);
+        assert_eq!(
+            other
+                .read(&Stage::Native, entity, vec![Value::text("b")])
+                .await
+                .unwrap(),
+            vec![reading("b", 15)]
+        );
+
+        drop(other);
+        drop(redb);
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn plain_by_id() {
+        let path = path("plain");
+        let definition = definition(NativeMapping::doc_to_key_value("device")).await;
+        let entity = definition.entity_name(PartitionId(0), &Stage::Plain);
+
+        let mut redb = Redb::new(&path);
+        redb.start(0).await.unwrap();
+        redb.init_entity(&definition, PartitionId(0), &Stage::Plain)
+            .await
+            .unwrap();
+        assert!(
+            redb.read(entity.clone(), vec![Value::int(4)])
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        redb.store(
+            &Stage::Plain,
+            entity.clone(),
+            &batch![target!(reading("a", 20), meta(4))],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            redb.read(entity, vec![Value::int(4)]).await.unwrap(),
+            vec![reading("a", 20)]
+        );
+
+        drop(redb);
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn no_pair() {
+        let path = path("pair");
+        let definition = definition(NativeMapping::doc_to_key_value("device")).await;
+        let entity = definition.entity_name(PartitionId(0), &Stage::Native);
+        let mapper = definition.mapping.build().unwrap();
+
+        let mut redb = Redb::new(&path);
+        redb.start(0).await.unwrap();
+        redb.init_entity(&definition, PartitionId(0), &Stage::Native)
+            .await
+            .unwrap();
+
+        // a document without the key is skipped, the rest of the batch is stored
+        let missing = Value::from(Dict::from(vec![("temperature", Value::int(3))]));
+        redb.store(
+            &Stage::Native,
+            entity.clone(),
+            &batch![
+                target!(mapper(missing), meta(0)),
+                target!(mapper(reading("a", 20)), meta(1))
+            ],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            redb.read(entity, vec![Value::text("a"), Value::null()])
+                .await
+                .unwrap(),
+            vec![reading("a", 20)]
+        );
+
+        drop(redb);
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn process_rows() {
+        let path = path("process");
+        let definition = definition(NativeMapping::doc_to_key_value("device")).await;
+        let entity = definition.entity_name(PartitionId(0), &Stage::Process);
+
+        let mut redb = Redb::new(&path);
+        redb.start(0).await.unwrap();
+        redb.init_entity(&definition, PartitionId(0), &Stage::Process)
+            .await
+            .unwrap();
+
+        // the rows of a batch carry the meta of its last record
+        for batch in [[("a", 1), ("b", 2)], [("c", 3), ("d", 4)]] {
+            let rows = batch
+                .iter()
+                .map(|(device, temperature)| target!(reading(device, *temperature), meta(9)))
+                .collect();
+            redb.store(&Stage::Process, entity.clone(), &rows)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            redb.read(entity, (0..5).map(Value::int).collect())
+                .await
+                .unwrap(),
+            vec![
+                reading("a", 1),
+                reading("b", 2),
+                reading("c", 3),
+                reading("d", 4)
+            ]
+        );
+
+        drop(redb);
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn array_mapping() {
+        let mapping: NativeMapping = toml::from_str(
+            r#"
+        [kv]
+        key = {index = 0}
+        value = {index = 2}"#,
+        )
+        .unwrap();
+        let mapper = mapping.build().unwrap();
+
+        assert_eq!(
+            mapper(Value::array(vec![
+                Value::text("a"),
+                Value::int(1),
+                Value::float(2.5)
+            ])),
+            Value::array(vec![Value::text("a"), Value::float(2.5)])
+        );
+        // documents have no entries to pick
+        assert_eq!(mapper(reading("a", 20)), Value::null());
+    }
+}
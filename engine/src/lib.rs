@@ -1,7 +1,19 @@
 mod connection;
 pub mod engine;
+mod files;
+mod kv;
+mod memory;
 mod mongo;
 mod neo;
 mod postgres;
+mod sqlite;
+mod storage;
 
-pub use engine::EngineKind;
+pub use files::ArrowFiles;
+pub use kv::Redb;
+pub use memory::Memory;
+pub use mongo::MongoDB;
+pub use neo::Neo4j;
+pub use postgres::Postgres;
+pub use sqlite::Sqlite;
+pub use storage::{EngineRegistry, StorageEngine};
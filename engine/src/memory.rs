@@ -0,0 +1,374 @@
+use crate::storage::{StorageEngine, ids};
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use flume::Sender;
+use processing::duration;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio::time::sleep;
+use tracing::debug;
+use util::definition::{Definition, Model, Stage};
+use util::{Batch, EngineId, Event, PartitionId, TargetedRecord};
+use value::Value;
+
+/// The stored records of each stage of an entity.
+type Entities = HashMap<(String, Stage), Vec<TargetedRecord>>;
+
+/// Keeps the stored records in memory, so pipelines run without any database.
+///
+/// It presents itself as the configured model, e.g.
+/// `{ type = "memory", model = "relational", latency = "5 MILLISECONDS", fail_every = 10 }`.
+/// All copies share the stored records.
+#[derive(Debug, Clone)]
+pub struct Memory {
+    pub(crate) id: Option<EngineId>,
+    pub(crate) model: Model,
+    /// how long each store and read takes, in ms
+    pub(crate) latency: i64,
+    /// every n-th store fails without storing anything
+    pub(crate) fail_every: Option<u64>,
+    pub(crate) stores: Arc<AtomicU64>,
+    pub(crate) entities: Arc<RwLock<Entities>>,
+}
+
+impl<'de> Deserialize<'de> for Memory {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct RawMemory {
+            model: Model,
+            #[serde(default, with = "duration")]
+            latency: i64,
+            fail_every: Option<u64>,
+        }
+
+        let raw = RawMemory::deserialize(deserializer)?;
+        if raw.fail_every == Some(0) {
+            return Err(serde::de::Error::custom(
+                "fail_every needs to be at least 1",
+            ));
+        }
+
+        Ok(Memory {
+            latency: raw.latency,
+            fail_every: raw.fail_every,
+            ..Memory::new(raw.model)
+        })
+    }
+}
+
+impl Memory {
+    pub fn new(model: Model) -> Memory {
+        Memory {
+            id: None,
+            model,
+            latency: 0,
+            fail_every: None,
+            stores: Default::default(),
+            entities: Default::default(),
+        }
+    }
+
+    /// The records stored so far for the stage of the entity.
+    pub fn records(&self, entity: &str, stage: &Stage) -> Vec<TargetedRecord> {
+        self.entities
+            .read()
+            .unwrap()
+            .get(&(entity.to_string(), stage.clone()))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    async fn delay(&self) {
+        if self.latency > 0 {
+            sleep(Duration::from_millis(self.latency as u64)).await;
+        }
+    }
+
+    pub(crate) async fn init_entity(
+        &self,
+        definition: &Definition,
+        partition_id: PartitionId,
+        stage: &Stage,
+    ) -> anyhow::Result<()> {
+        let name = definition.entity_name(partition_id, stage);
+        self.entities
+            .write()
+            .map_err(|_| anyhow!("Poisoned lock"))?
+            .entry((name, stage.clone()))
+            .or_default();
+        Ok(())
+    }
+
+    pub(crate) async fn store(
+        &self,
+        stage: &Stage,
+        entity: String,
+        values: &Batch<TargetedRecord>,
+    ) -> anyhow::Result<()> {
+        self.delay().await;
+
+        let count = self.stores.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(every) = self.fail_every
+            && count.is_multiple_of(every)
+        {
+            bail!("Injected failure of store {} into {}", count, entity)
+        }
+
+        let mut entities = self
+            .entities
+            .write()
+            .map_err(|_| anyhow!("Poisoned lock"))?;
+        let Some(records) = entities.get_mut(&(entity.clone(), stage.clone())) else {
+            bail!("Entity {} of stage {:?} not found", entity, stage)
+        };
+        records.extend(values.iter().cloned());
+
+        debug!("inserted in memory {} into {}", values.len(), entity);
+        Ok(())
+    }
+
+    pub async fn read(
+        &self,
+        stage: &Stage,
+        entity: String,
+        ids: Vec<u64>,
+    ) -> anyhow::Result<Vec<Value>> {
+        self.delay().await;
+
+        let entities = self.entities.read().map_err(|_| anyhow!("Poisoned lock"))?;
+        Ok(entities
+            .get(&(entity, stage.clone()))
+            .into_iter()
+            .flatten()
+            .filter(|record| ids.contains(&record.meta.id))
+            .map(|record| record.value.clone())
+            .collect())
+    }
+
+    pub async fn scan(&self, stage: &Stage, entity: String) -> anyhow::Result<Vec<(u64, Value)>> {
+        self.delay().await;
+
+        let entities = self.entities.read().map_err(|_| anyhow!("Poisoned lock"))?;
+        Ok(entities
+            .get(&(entity, stage.clone()))
+            .into_iter()
+            .flatten()
+            .map(|record| (record.meta.id, record.value.clone()))
+            .collect())
+    }
+
+    pub(crate) async fn monitor(&self, statistic_tx: &Sender<Event>) -> anyhow::Result<()> {
+        loop {
+            let count: usize = self
+                .entities
+                .read()
+                .map_err(|_| anyhow!("Poisoned lock"))?
+                .values()
+                .map(Vec::len)
+                .sum();
+            statistic_tx
+                .send_async(Event::EngineStatus(format!(
+                    "✅ Records in memory: {}",
+                    count
+                )))
+                .await?;
+            sleep(Duration::from_secs(5)).await;
+        }
+    }
+}
+
+#[async_trait]
+impl StorageEngine for Memory {
+    fn kind(&self) -> &str {
+        "memory"
+    }
+
+    fn model(&self) -> Model {
+        self.model.clone()
+    }
+
+    fn clone_box(&self) -> Box<dyn StorageEngine> {
+        Box::new(self.clone())
+    }
+
+    async fn start(&mut self, _join_set: &mut JoinSet<()>, id: EngineId) -> anyhow::Result<()> {
+        self.id = Some(id);
+        Ok(())
+    }
+
+    async fn stop(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn init_entity(
+        &mut self,
+        definition: &Definition,
+        partition_id: PartitionId,
+        stage: &Stage,
+    ) -> anyhow::Result<()> {
+        Memory::init_entity(self, definition, partition_id, stage).await
+    }
+
+    async fn store(
+        &self,
+        stage: &Stage,
+        entity: String,
+        values: &Batch<TargetedRecord>,
+    ) -> anyhow::Result<()> {
+        Memory::store(self, stage, entity, values).await
+    }
+
+    async fn read(
+        &self,
+        stage: &Stage,
+        entity: String,
+        keys: Vec<Value>,
+    ) -> anyhow::Result<Vec<Value>> {
+        Memory::read(self, stage, entity, ids(keys)?).await
+    }
+
+    async fn scan(&self, stage: &Stage, entity: String) -> anyhow::Result<Vec<(u64, Value)>> {
+        Memory::scan(self, stage, entity).await
+    }
+
+    async fn monitor(&self, statistic_tx: &Sender<Event>) -> anyhow::Result<()> {
+        Memory::monitor(self, statistic_tx).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::Memory;
+    use std::time::Duration;
+    use tokio::time::Instant;
+    use util::definition::{Definition, Model, Stage};
+    use util::query::Query;
+    use util::{NativeMapping, PartitionId, TargetedMeta, batch, target};
+    use value::Value;
+
+    async fn definition() -> Definition {
+        Definition::builder(
+            "test",
+            NativeMapping::document(),
+            Query::SQL("SELECT * FROM $$source".to_string()),
+            Model::Document,
+        )
+        .entity("docs")
+        .build()
+        .await
+        .unwrap()
+    }
+
+    fn meta(id: u64) -> TargetedMeta {
+        TargetedMeta {
+            id,
+            ..TargetedMeta::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn store_read() {
+        let definition = definition().await;
+        let entity = definition.entity_name(PartitionId(0), &Stage::Plain);
+        let memory = Memory::new(Model::Document);
+        memory
+            .init_entity(&definition, PartitionId(0), &Stage::Plain)
+            .await
+            .unwrap();
+
+        // copies, like the ones of the workers, store into the same records
+        let other = memory.clone();
+        other
+            .store(
+                &Stage::Plain,
+                entity.clone(),
+                &batch![
+                    target!(Value::text("a"), meta(1)),
+                    target!(Value::text("b"), meta(2))
+                ],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            memory
+                .read(&Stage::Plain, entity.clone(), vec![2, 3])
+                .await
+                .unwrap(),
+            vec![Value::text("b")]
+        );
+        assert_eq!(
+            memory.scan(&Stage::Plain, entity.clone()).await.unwrap()[1],
+            (2, Value::text("b"))
+        );
+        assert_eq!(memory.records(&entity, &Stage::Plain).len(), 2);
+        assert!(memory.records(&entity, &Stage::Native).is_empty());
+    }
+
+    #[tokio::test]
+    async fn unknown_entity() {
+        let memory = Memory::new(Model::Graph);
+        assert!(
+            memory
+                .store(
+                    &Stage::Native,
+                    "nodes".to_string(),
+                    &batch![target!(Value::text("a"), meta(1))],
+                )
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn failures() {
+        let definition = definition().await;
+        let entity = definition.entity_name(PartitionId(0), &Stage::Plain);
+        let mut memory = Memory::new(Model::Document);
+        memory.fail_every = Some(2);
+        memory
+            .init_entity(&definition, PartitionId(0), &Stage::Plain)
+            .await
+            .unwrap();
+
+        let mut stored = vec![];
+        for id in 0..4 {
+            let result = memory
+                .store(
+                    &Stage::Plain,
+                    entity.clone(),
+                    &batch![target!(Value::int(id as i64), meta(id))],
+                )
+                .await;
+            stored.push(result.is_ok());
+        }
+        assert_eq!(stored, vec![true, false, true, false]);
+        assert_eq!(
+            memory
+                .read(&Stage::Plain, entity, vec![0, 1, 2, 3])
+                .await
+                .unwrap(),
+            vec![Value::int(0), Value::int(2)]
+        );
+    }
+
+    #[tokio::test]
+    async fn latency() {
+        let mut memory = Memory::new(Model::Document);
+        memory.latency = 20;
+
+        let now = Instant::now();
+        memory
+            .read(&Stage::Plain, "docs".to_string(), vec![1])
+            .await
+            .unwrap();
+        assert!(now.elapsed() >= Duration::from_millis(20));
+    }
+}
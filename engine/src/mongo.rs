@@ -1,19 +1,24 @@
 use crate::engine::Load;
+use crate::storage::{StorageEngine, ids};
 use anyhow::{bail, Context};
+use async_trait::async_trait;
 use flume::Sender;
 use futures_util::StreamExt;
-use mongodb::bson::doc;
+use mongodb::bson;
+use mongodb::bson::{doc, Bson, Document};
 use mongodb::options::{ClientOptions, ServerApi, ServerApiVersion};
 use mongodb::{Client, Cursor};
+use processing::{to_pipeline, Algebra};
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::error::Error;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use tokio::task::JoinSet;
 use tokio::time::{sleep, timeout, Instant};
 use tracing::{debug, error, info};
 use util::container::Mapping;
-use util::definition::{Definition, Stage};
+use util::definition::{Definition, Model, Stage};
 use util::Event::EngineStatus;
 use util::{container, Batch, NativeMapping, EngineId, Event, PartitionId, TargetedRecord};
 use value::Value;
@@ -170,13 +175,65 @@ impl MongoDB {
                 let mut values = vec![];
 
                 while let Some(Ok(value)) = res.next().await {
-                    values.push(value);
+                    // documents hold the stored record next to its id
+                    match value {
+                        Value::Dict(d) => values.push(d.get("value").cloned().unwrap_or_default()),
+                        value => values.push(value),
+                    }
                 }
                 Ok(values)
             }
         }
     }
 
+    /// All documents of the collection with their ids.
+    pub async fn scan(&self, entity: String) -> anyhow::Result<Vec<(u64, Value)>> {
+        let client = self.client.as_ref().context("No client")?;
+        let mut res: Cursor<Value> = client
+            .database("public")
+            .collection(&entity)
+            .find(doc! {})
+            .await?;
+
+        let mut values = vec![];
+        while let Some(value) = res.next().await {
+            // documents hold the stored record next to its id
+            match value? {
+                Value::Dict(d) => {
+                    let id = d.get("id").context("Document without id")?.as_int()?.0 as u64;
+                    values.push((id, d.get("value").cloned().unwrap_or_default()));
+                }
+                value => bail!("Unexpected document {:?} in {}", value, entity),
+            }
+        }
+        Ok(values)
+    }
+
+    /// Evaluates the algebra as aggregation over the stored records of the collection.
+    pub async fn query(&self, entity: String, algebra: &Algebra) -> anyhow::Result<Vec<Value>> {
+        let client = self.client.as_ref().context("No client")?;
+        // documents hold the stored record next to its id
+        let mut pipeline = vec![doc! {"$replaceRoot": {"newRoot": "$value"}}];
+        for stage in to_pipeline(algebra)? {
+            match bson::to_bson(&stage)? {
+                Bson::Document(stage) => pipeline.push(stage),
+                stage => bail!("Invalid stage {}", stage),
+            }
+        }
+        let mut res = client
+            .database("public")
+            .collection::<Document>(&entity)
+            .aggregate(pipeline)
+            .with_type::<Value>()
+            .await?;
+
+        let mut values = vec![];
+        while let Some(value) = res.next().await {
+            values.push(value?);
+        }
+        Ok(values)
+    }
+
     pub(crate) async fn monitor(&self, statistic_tx: &Sender<Event>) -> anyhow::Result<()> {
         loop {
             match self.measure_opcounters(statistic_tx).await {
@@ -299,3 +356,73 @@ impl MongoDB {
         Ok(())
     }
 }
+
+#[async_trait]
+impl StorageEngine for MongoDB {
+    fn kind(&self) -> &str {
+        "mongodb"
+    }
+
+    fn model(&self) -> Model {
+        Model::Document
+    }
+
+    fn clone_box(&self) -> Box<dyn StorageEngine> {
+        Box::new(self.clone())
+    }
+
+    async fn start_container(&self) -> anyhow::Result<()> {
+        MongoDB::start_container(self).await
+    }
+
+    async fn start(&mut self, _join_set: &mut JoinSet<()>, id: EngineId) -> anyhow::Result<()> {
+        MongoDB::start(self, id).await
+    }
+
+    async fn stop(&self) -> anyhow::Result<()> {
+        MongoDB::stop(self).await
+    }
+
+    async fn init_entity(
+        &mut self,
+        definition: &Definition,
+        partition_id: PartitionId,
+        stage: &Stage,
+    ) -> anyhow::Result<()> {
+        MongoDB::init_entity(self, definition, partition_id, stage).await
+    }
+
+    async fn store(
+        &self,
+        stage: &Stage,
+        entity: String,
+        values: &Batch<TargetedRecord>,
+    ) -> anyhow::Result<()> {
+        MongoDB::store(self, stage, entity, values).await
+    }
+
+    async fn read(
+        &self,
+        _stage: &Stage,
+        entity: String,
+        keys: Vec<Value>,
+    ) -> anyhow::Result<Vec<Value>> {
+        MongoDB::read(self, entity, ids(keys)?).await
+    }
+
+    async fn scan(&self, _stage: &Stage, entity: String) -> anyhow::Result<Vec<(u64, Value)>> {
+        MongoDB::scan(self, entity).await
+    }
+
+    async fn query(&self, entity: String, algebra: &Algebra) -> anyhow::Result<Vec<Value>> {
+        MongoDB::query(self, entity, algebra).await
+    }
+
+    fn cost(&self, value: &Value) -> f64 {
+        MongoDB::cost(self, value)
+    }
+
+    async fn monitor(&self, statistic_tx: &Sender<Event>) -> anyhow::Result<()> {
+        MongoDB::monitor(self, statistic_tx).await
+    }
+}
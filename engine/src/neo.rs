@@ -1,8 +1,11 @@
 use crate::engine::Load;
+use crate::storage::{StorageEngine, ids};
 use anyhow::{anyhow, bail};
+use async_trait::async_trait;
 use flume::Sender;
 use mongodb::bson::uuid;
 use neo4rs::{ConfigBuilder, Graph, query};
+use processing::{Algebra, to_cypher};
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use reqwest::Client;
 use serde::Deserialize;
@@ -12,11 +15,12 @@ use std::error::Error;
 use std::fmt::Debug;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use tokio::task::JoinSet;
 use tokio::time::{Instant, sleep};
 use tracing::{debug, info};
 use util::Event::EngineStatus;
 use util::container::Mapping;
-use util::definition::{Definition, Stage};
+use util::definition::{Definition, Model, Stage};
 use util::{Batch, EngineId, Event, NativeMapping, PartitionId, TargetedRecord, container};
 use value::Value;
 
@@ -104,6 +108,22 @@ struct TxMetrics {
 }
 
 impl Neo4j {
+    #[cfg(test)]
+    pub(crate) fn with_port(port: u16) -> Neo4j {
+        Neo4j {
+            id: None,
+            name: "neo4j-engine".to_string(),
+            load: Arc::new(Mutex::new(Load::Low)),
+            host: "localhost".to_string(),
+            port,
+            user: "neo4j".to_string(),
+            password: "neoneoneo".to_string(),
+            graph: None,
+            prepared_queries: Default::default(),
+            deploy: true,
+        }
+    }
+
     pub(crate) async fn start<S: Into<EngineId>>(&mut self, id: S) -> anyhow::Result<()> {
         let config = ConfigBuilder::default()
             .uri(format!("{}:{}", self.host, self.port))
@@ -281,6 +301,22 @@ impl Neo4j {
         }
     }
 
+    /// Evaluates the algebra as query over the nodes of the entity.
+    pub async fn query(&self, entity: String, algebra: &Algebra) -> anyhow::Result<Vec<Value>> {
+        let Some(g) = &self.graph else {
+            bail!("No graph")
+        };
+        let cypher_query = to_cypher(algebra, &format!("db_{}", entity))?;
+        debug!("pushed down {}", cypher_query);
+
+        let mut res = g.execute_read(query(&cypher_query)).await?;
+        let mut values = vec![];
+        while let Some(value) = res.next().await? {
+            values.push(Value::from(value))
+        }
+        Ok(values)
+    }
+
     async fn check_throughput(
         &self,
         statistic_tx: &Sender<Event>,
@@ -385,13 +421,80 @@ fn to_primitive(value: &Value) -> Value {
     }
 }
 
+#[async_trait]
+impl StorageEngine for Neo4j {
+    fn kind(&self) -> &str {
+        "neo4j"
+    }
+
+    fn model(&self) -> Model {
+        Model::Graph
+    }
+
+    fn clone_box(&self) -> Box<dyn StorageEngine> {
+        Box::new(self.clone())
+    }
+
+    async fn start_container(&self) -> anyhow::Result<()> {
+        Neo4j::start_container(self).await
+    }
+
+    async fn start(&mut self, _join_set: &mut JoinSet<()>, id: EngineId) -> anyhow::Result<()> {
+        Neo4j::start(self, id).await
+    }
+
+    async fn stop(&self) -> anyhow::Result<()> {
+        Neo4j::stop(self).await
+    }
+
+    async fn init_entity(
+        &mut self,
+        definition: &Definition,
+        partition_id: PartitionId,
+        _stage: &Stage,
+    ) -> anyhow::Result<()> {
+        Neo4j::init_entity(self, definition, partition_id).await;
+        Ok(())
+    }
+
+    async fn store(
+        &self,
+        stage: &Stage,
+        entity: String,
+        values: &Batch<TargetedRecord>,
+    ) -> anyhow::Result<()> {
+        Neo4j::store(self, stage, entity, values).await
+    }
+
+    async fn read(
+        &self,
+        _stage: &Stage,
+        entity: String,
+        keys: Vec<Value>,
+    ) -> anyhow::Result<Vec<Value>> {
+        Neo4j::read(self, entity, ids(keys)?).await
+    }
+
+    async fn query(&self, entity: String, algebra: &Algebra) -> anyhow::Result<Vec<Value>> {
+        Neo4j::query(self, entity, algebra).await
+    }
+
+    fn cost(&self, value: &Value) -> f64 {
+        Neo4j::cost(self, value)
+    }
+
+    async fn monitor(&self, statistic_tx: &Sender<Event>) -> anyhow::Result<()> {
+        Neo4j::monitor(self, statistic_tx).await
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::EngineKind;
+    use crate::neo::Neo4j;
     use neo4rs::{BoltInteger, BoltMap, BoltString, BoltType, query};
     use std::collections::HashMap;
     use std::vec;
-    use util::definition::{Definition, DefinitionFilter, Model, Stage};
+    use util::definition::{Definition, Model, Stage};
     use util::query::Query;
     use util::{NativeMapping, PartitionId, TargetedMeta, batch, target};
     use value::{Dict, Value};
@@ -399,19 +502,20 @@ mod tests {
     //#[tokio::test]
     //#[traced_test]
     async fn test_insert() {
-        let mut neo = EngineKind::neo4j_with_port(7688);
+        let mut neo = Neo4j::with_port(7688);
         neo.start_container().await.unwrap();
         neo.start(0).await.unwrap();
 
-        let definition = Definition::new(
+        let definition = Definition::builder(
             "test",
-            DefinitionFilter::AllMatch,
             NativeMapping::doc_to_graph(),
-            Query::Cypher("None".to_string()),
+            Query::Cypher("MATCH (n) RETURN n".to_string()),
             Model::Document,
-            "users".to_string(),
         )
-        .await;
+        .entity("users")
+        .build()
+        .await
+        .unwrap();
         neo.init_entity(&definition, PartitionId(0)).await;
 
         neo.store(
@@ -451,19 +555,20 @@ mod tests {
     //#[tokio::test]
     //#[traced_test]
     async fn test_insert_node() {
-        let mut neo = EngineKind::neo4j();
+        let mut neo = Neo4j::with_port(7687);
         neo.start_container().await.unwrap();
         neo.start(0).await.unwrap();
 
-        let definition = Definition::new(
+        let definition = Definition::builder(
             "test",
-            DefinitionFilter::AllMatch,
             NativeMapping::doc_to_graph(),
-            Query::Cypher("None".to_string()),
+            Query::Cypher("MATCH (n) RETURN n".to_string()),
             Model::Document,
-            "users".to_string(),
         )
-        .await;
+        .entity("users")
+        .build()
+        .await
+        .unwrap();
         neo.init_entity(&definition, PartitionId(0)).await;
 
         match neo.graph {
@@ -1,6 +1,8 @@
 use crate::connection::PostgresConnection;
 use crate::engine::Load;
+use crate::storage::{StorageEngine, ids};
 use anyhow::{anyhow, bail};
+use async_trait::async_trait;
 use flume::Sender;
 use pin_utils::pin_mut;
 use rayon::iter::IntoParallelRefIterator;
@@ -15,11 +17,11 @@ use tokio::task::JoinSet;
 use tokio::time::{sleep, timeout, Instant};
 use tokio_postgres::binary_copy::BinaryCopyInWriter;
 use tokio_postgres::types::{ToSql, Type};
-use tokio_postgres::{Client, Statement};
+use tokio_postgres::{Client, Row, Statement};
 use tracing::{debug, info};
-use processing::{Algebra, Schema};
+use processing::{to_sql, Algebra, Schema};
 use util::container::Mapping;
-use util::definition::{Definition, Stage};
+use util::definition::{Definition, Model, Stage};
 use util::{
     container, Batch, NativeMapping, EngineId, Event, PartitionId, RelationalMapping,
     RelationalType, TargetedRecord,
@@ -111,6 +113,28 @@ impl Drop for Postgres {
 }
 
 impl Postgres {
+    /// A local Postgres deployed as container, with the default credentials.
+    #[cfg(test)]
+    pub(crate) fn with_port(port: u16) -> Postgres {
+        Postgres {
+            id: None,
+            pg_id: ID_BUILDER.fetch_add(1, Ordering::Relaxed),
+            name: "engine-postgres".to_string(),
+            load: Arc::new(Mutex::new(Load::Low)),
+            connector: PostgresConnection {
+                url: "localhost".to_string(),
+                port,
+                db: "postgres".to_string(),
+                user: "postgres".to_string(),
+                password: "postgres".to_string(),
+            },
+            client: None,
+            prepared_statements: Default::default(),
+            join: None,
+            deploy: true,
+        }
+    }
+
     pub(crate) async fn start<S: Into<EngineId>>(
         &mut self,
         join_set: &mut JoinSet<()>,
@@ -176,25 +200,100 @@ impl Postgres {
         Ok(())
     }
 
-    pub async fn read(&self, entity: String, ids: Vec<u64>) -> anyhow::Result<Vec<Value>> {
-        match &self.client {
-            None => bail!("Could not create postgres database"),
-            Some(client) => {
-                let insert_query = format!("SELECT * FROM {} WHERE id IN($1)", entity);
-                let statement = client.prepare(&insert_query).await?;
-
-                let res = client
-                    .query(
-                        &statement,
-                        &[&ids.into_iter().map(Value::from).collect::<Vec<_>>()],
-                    )
-                    .await?
-                    .into_iter()
-                    .map(Value::from)
-                    .collect::<Vec<_>>();
-                Ok(res)
-            }
+    pub async fn read(
+        &self,
+        stage: &Stage,
+        entity: String,
+        ids: Vec<u64>,
+    ) -> anyhow::Result<Vec<Value>> {
+        let Some(client) = &self.client else {
+            bail!("Could not create postgres database")
+        };
+        let read_query = format!(
+            "SELECT id, {} FROM {} WHERE id = ANY($1)",
+            Self::record_columns(client, &entity).await?.join(", "),
+            entity
+        );
+        let statement = client.prepare(&read_query).await?;
+
+        let ids = ids.into_iter().map(|id| id as i64).collect::<Vec<_>>();
+        client
+            .query(&statement, &[&ids])
+            .await?
+            .into_iter()
+            .map(|row| Self::record(stage, row).map(|(_, record)| record))
+            .collect()
+    }
+
+    /// All rows of the table with their ids.
+    pub async fn scan(&self, stage: &Stage, entity: String) -> anyhow::Result<Vec<(u64, Value)>> {
+        let Some(client) = &self.client else {
+            bail!("Could not create postgres database")
+        };
+        let scan_query = format!(
+            "SELECT id, {} FROM {} WHERE id IS NOT NULL",
+            Self::record_columns(client, &entity).await?.join(", "),
+            entity
+        );
+        client
+            .query(&scan_query, &[])
+            .await?
+            .into_iter()
+            .map(|row| Self::record(stage, row))
+            .collect()
+    }
+
+    /// The columns which hold the records of the table, in the order they were stored.
+    ///
+    /// Tables of earlier runs got their `id` column appended, so they are looked up by name.
+    async fn record_columns(client: &Client, entity: &str) -> anyhow::Result<Vec<String>> {
+        let columns = client
+            .query(
+                "SELECT attname::text FROM pg_attribute
+                WHERE attrelid = $1::text::regclass AND attnum > 0 AND NOT attisdropped
+                ORDER BY attnum",
+                &[&entity],
+            )
+            .await?
+            .into_iter()
+            .map(|row| row.get::<_, String>(0))
+            .collect::<Vec<_>>();
+        // e.g. process tables, their rows are no records of the definition
+        if !columns.iter().any(|c| c == "id") {
+            bail!("Table {} stores no record ids", entity)
         }
+        Ok(columns
+            .into_iter()
+            .filter(|c| c != "_id" && c != "id")
+            .collect())
+    }
+
+    /// The id and the record as it was stored of a row selected with its id first, plain rows
+    /// hold the whole record in their value column.
+    fn record(stage: &Stage, row: Row) -> anyhow::Result<(u64, Value)> {
+        let id = row.try_get::<_, i64>(0)? as u64;
+        if matches!(stage, Stage::Plain) {
+            return Ok((id, row.try_get::<_, Option<Value>>(1)?.unwrap_or_default()));
+        }
+        let record = (1..row.len())
+            .map(|i| Ok(row.try_get::<_, Option<Value>>(i)?.unwrap_or_default()))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok((id, Value::array(record)))
+    }
+
+    /// Evaluates the algebra as query over the table with the planner of postgres.
+    pub async fn query(&self, entity: String, algebra: &Algebra) -> anyhow::Result<Vec<Value>> {
+        let Some(client) = &self.client else {
+            bail!("Could not create postgres database")
+        };
+        let query = to_sql(algebra, &entity)?;
+        debug!("pushed down {}", query);
+        Ok(client
+            .query(&query, &[])
+            .await?
+            .into_iter()
+            .map(Value::from)
+            .collect())
     }
 
     pub(crate) async fn monitor(&self, statistic_tx: &Sender<Event>) -> anyhow::Result<()> {
@@ -259,7 +358,7 @@ impl Postgres {
             && let NativeMapping::Relational(_) = &definition.mapping
         {
             let name = definition.entity_name(partition_id, &Stage::Process);
-            self.create_table_process(name.as_str(),Algebra::from(definition.processing.clone()).schema()).await?;
+            self.create_table_process(name.as_str(), definition.schema()?).await?;
         }
 
         Ok(())
@@ -278,6 +377,7 @@ impl Postgres {
                 );
 
                 client.execute(&create_table_query, &[]).await?;
+                Self::add_ids(client, name).await?;
                 debug!(
                     "Table '{}' ensured to exist on {:?} pg_id {}.",
                     name, self.id, self.pg_id
@@ -294,6 +394,17 @@ impl Postgres {
         Ok(())
     }
 
+    /// Tables of earlier runs were created without the ids of their records.
+    async fn add_ids(client: &Client, name: &str) -> anyhow::Result<()> {
+        client
+            .execute(
+                &format!("ALTER TABLE {} ADD COLUMN IF NOT EXISTS id BIGINT", name),
+                &[],
+            )
+            .await?;
+        Ok(())
+    }
+
     async fn create_table_native(
         &mut self,
         name: &str,
@@ -307,11 +418,12 @@ impl Postgres {
                 let create_table_query = format!(
                     "CREATE TABLE IF NOT EXISTS {} (
                     _id SERIAL PRIMARY KEY,
+                    id BIGINT,
                     {})",
                     name,
                     types
                         .iter()
-                        .map(|(name, t)| format!("{} {}", name, t))
+                        .map(|(name, t)| format!("{} {}", name, Self::pg_column(t)))
                         .collect::<Vec<_>>()
                         .join(",\n")
                 );
@@ -319,9 +431,10 @@ impl Postgres {
                 //info!("{}", create_table_query);
 
                 client.execute(&create_table_query, &[]).await?;
+                Self::add_ids(client, name).await?;
                 //info!("Table '{}' ensured to exist.", name);
                 let copy_query = format!(
-                    "COPY {} ({}) FROM STDIN BINARY",
+                    "COPY {} (id, {}) FROM STDIN BINARY",
                     name,
                     types
                         .iter()
@@ -335,7 +448,10 @@ impl Postgres {
                     (name.to_string(), Stage::Native),
                     (
                         statement,
-                        types.into_iter().map(|(_, t)| Self::pg_type(t)).collect(),
+                        [Type::INT8]
+                            .into_iter()
+                            .chain(types.into_iter().map(|(_, t)| Self::pg_type(t)))
+                            .collect(),
                     ),
                 );
             }
@@ -349,11 +465,13 @@ impl Postgres {
         name: &str,
         schema: Schema,
     ) -> anyhow::Result<()> {
-        let types: Vec<_> = if let Schema::Fixed(types) = schema {
-            types.into_iter().map(|(n, t)| (n, RelationalType::from(&t))).collect()
-        }else {
-            todo!()
+        let Schema::Fixed(types) = schema else {
+            bail!("Process table {} needs a fixed schema", name)
         };
+        let types: Vec<_> = types
+            .into_iter()
+            .map(|(n, t)| (n, RelationalType::from(&t)))
+            .collect();
 
         match &self.client {
             None => bail!("Could not create postgres database"),
@@ -365,7 +483,7 @@ impl Postgres {
                     name,
                     types
                         .iter()
-                        .map(|(name, t)| format!("{} {}", name, t))
+                        .map(|(name, t)| format!("{} {}", name, Self::pg_column(t)))
                         .collect::<Vec<_>>()
                         .join(",\n")
                 );
@@ -465,10 +583,11 @@ impl Postgres {
                     }
                     Stage::Native => {
                         if let Value::Array(a) = value {
-                            let row_params: Vec<&(dyn ToSql + Sync)> = a
-                                .values
-                                .par_iter()
-                                .map(|v| v as &(dyn ToSql + Sync))
+                            // the id allows to read the record again, e.g. for lookups
+                            let id_val = meta.id as i64;
+                            let row_params: Vec<&(dyn ToSql + Sync)> = [&id_val as &(dyn ToSql + Sync)]
+                                .into_iter()
+                                .chain(a.values.iter().map(|v| v as &(dyn ToSql + Sync)))
                                 .collect();
 
                             writer.as_mut().write(&row_params).await?;
@@ -523,13 +642,92 @@ impl Postgres {
             RelationalType::Float => Type::FLOAT4,
             RelationalType::Bool => Type::BOOL,
             RelationalType::Text => Type::TEXT,
+            RelationalType::Blob => Type::BYTEA,
         }
     }
+
+    /// The type of the column, postgres calls blobs BYTEA.
+    fn pg_column(t: &RelationalType) -> String {
+        match t {
+            RelationalType::Blob => "BYTEA".to_string(),
+            t => t.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl StorageEngine for Postgres {
+    fn kind(&self) -> &str {
+        "postgres"
+    }
+
+    fn model(&self) -> Model {
+        Model::Relational
+    }
+
+    fn clone_box(&self) -> Box<dyn StorageEngine> {
+        Box::new(self.clone())
+    }
+
+    async fn start_container(&self) -> anyhow::Result<()> {
+        Postgres::start_container(self).await
+    }
+
+    async fn start(&mut self, join_set: &mut JoinSet<()>, id: EngineId) -> anyhow::Result<()> {
+        Postgres::start(self, join_set, id).await
+    }
+
+    async fn stop(&self) -> anyhow::Result<()> {
+        Postgres::stop(self).await
+    }
+
+    async fn init_entity(
+        &mut self,
+        definition: &Definition,
+        partition_id: PartitionId,
+        stage: &Stage,
+    ) -> anyhow::Result<()> {
+        Postgres::init_entity(self, definition, partition_id, stage).await
+    }
+
+    async fn store(
+        &self,
+        stage: &Stage,
+        entity: String,
+        values: &Batch<TargetedRecord>,
+    ) -> anyhow::Result<()> {
+        Postgres::store(self, stage, entity, values).await
+    }
+
+    async fn read(
+        &self,
+        stage: &Stage,
+        entity: String,
+        keys: Vec<Value>,
+    ) -> anyhow::Result<Vec<Value>> {
+        Postgres::read(self, stage, entity, ids(keys)?).await
+    }
+
+    async fn scan(&self, stage: &Stage, entity: String) -> anyhow::Result<Vec<(u64, Value)>> {
+        Postgres::scan(self, stage, entity).await
+    }
+
+    async fn query(&self, entity: String, algebra: &Algebra) -> anyhow::Result<Vec<Value>> {
+        Postgres::query(self, entity, algebra).await
+    }
+
+    fn cost(&self, value: &Value) -> f64 {
+        Postgres::cost(self, value)
+    }
+
+    async fn monitor(&self, statistic_tx: &Sender<Event>) -> anyhow::Result<()> {
+        Postgres::monitor(self, statistic_tx).await
+    }
 }
 
 #[cfg(test)]
 pub mod tests {
-    use crate::EngineKind;
+    use crate::postgres::Postgres;
     use tokio::task::JoinSet;
     use tracing_test::traced_test;
     use util::definition::Stage;
@@ -541,7 +739,7 @@ pub mod tests {
     #[tokio::test]
     #[traced_test]
     pub async fn test_postgres() {
-        let mut pg = EngineKind::postgres();
+        let mut pg = Postgres::with_port(5432);
         let mut join_set = JoinSet::new();
         pg.start_container().await.unwrap();
         pg.start(&mut join_set, 0).await.unwrap();
@@ -561,7 +759,7 @@ pub mod tests {
     //#[tokio::test]
     //#[traced_test]
     pub async fn test_postgres_mapped() {
-        let mut pg = EngineKind::postgres_with_port(5433);
+        let mut pg = Postgres::with_port(5433);
         pg.start_container().await.unwrap();
         let mut join_set = JoinSet::new();
         pg.start(&mut join_set, 0).await.unwrap();
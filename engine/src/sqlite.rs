@@ -0,0 +1,875 @@
+use crate::storage::{StorageEngine, ids};
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use flume::Sender;
+use indexmap::IndexMap;
+use processing::{Algebra, Schema, to_sql};
+use rusqlite::types::{ToSql, ToSqlOutput, Value as SqlValue};
+use rusqlite::{Connection, params_from_iter};
+use serde::Deserialize;
+use speedy::Writable;
+use std::collections::HashMap;
+use std::iter::once;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::{JoinSet, spawn_blocking};
+use tokio::time::{Instant, sleep};
+use tracing::{debug, info};
+use util::definition::{Definition, Model, Stage};
+use util::{Batch, EngineId, Event, NativeMapping, PartitionId, RelationalType, TargetedRecord};
+use value::{ValType, Value};
+
+/// A SQLite database file, which needs no container to run.
+#[derive(Debug)]
+pub struct Sqlite {
+    pub(crate) id: Option<EngineId>,
+    pub(crate) path: PathBuf,
+    pub(crate) connection: Option<Arc<Mutex<Connection>>>,
+    /// the insert statements of the tables with the types of their columns
+    pub(crate) inserts: HashMap<(String, Stage), (String, Vec<RelationalType>)>,
+}
+
+impl Clone for Sqlite {
+    fn clone(&self) -> Self {
+        Self {
+            id: None,
+            path: self.path.clone(),
+            connection: None,
+            inserts: Default::default(),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Sqlite {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct RawSqlite {
+            path: PathBuf,
+        }
+
+        let raw = RawSqlite::deserialize(deserializer)?;
+
+        Ok(Sqlite::new(raw.path))
+    }
+}
+
+impl Drop for Sqlite {
+    fn drop(&mut self) {
+        if self.connection.is_some() {
+            info!("Dropping SQLite {:?}", self.id)
+        }
+    }
+}
+
+impl Sqlite {
+    /// An engine storing into the database file, `:memory:` keeps each started copy in memory.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Sqlite {
+        Sqlite {
+            id: None,
+            path: path.into(),
+            connection: None,
+            inserts: HashMap::new(),
+        }
+    }
+
+    pub(crate) async fn start<S: Into<EngineId>>(&mut self, id: S) -> anyhow::Result<()> {
+        if let Some(parent) = self.path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let path = self.path.clone();
+        let connection = spawn_blocking(move || -> anyhow::Result<Connection> {
+            let connection = Connection::open(path)?;
+            // every started copy writes over its own connection, so they wait for each other's locks
+            connection.pragma_update(None, "journal_mode", "WAL")?;
+            connection.busy_timeout(Duration::from_secs(5))?;
+            Ok(connection)
+        })
+        .await??;
+
+        let id = id.into();
+        debug!("☑️ Opened SQLite database {} {}", self.path.display(), id);
+        self.id = Some(id);
+        self.connection = Some(Arc::new(Mutex::new(connection)));
+
+        Ok(())
+    }
+
+    /// Works on the connection in the blocking pool, as SQLite blocks its thread meanwhile.
+    async fn with<T, F>(&self, work: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> anyhow::Result<T> + Send + 'static,
+    {
+        let Some(connection) = self.connection.clone() else {
+            bail!("SQLite database {} is not open", self.path.display())
+        };
+        spawn_blocking(move || {
+            let mut connection = connection.lock().map_err(|_| anyhow!("Poisoned lock"))?;
+            work(&mut connection)
+        })
+        .await?
+    }
+
+    /// Inserts the batch in one transaction, either all records are stored or none.
+    pub(crate) async fn store(
+        &self,
+        stage: &Stage,
+        entity: String,
+        values: &Batch<TargetedRecord>,
+    ) -> anyhow::Result<()> {
+        let now = Instant::now();
+        let (insert, types) = self
+            .inserts
+            .get(&(entity.clone(), stage.clone()))
+            .ok_or(anyhow!("Statement not found"))?
+            .clone();
+
+        let rows = values
+            .iter()
+            .map(|TargetedRecord { value, meta }| {
+                let record = match (stage, value) {
+                    (Stage::Plain, value) => std::slice::from_ref(value),
+                    (Stage::Native | Stage::Process, Value::Array(a)) => a.values.as_slice(),
+                    (Stage::Native | Stage::Process, value) => {
+                        bail!("Expected Array value for Mapped stage, got {:?}", value)
+                    }
+                    _ => bail!("SQLite does not store stage {:?}", stage),
+                };
+                if record.len() != types.len() {
+                    bail!("Expected {} values, got {:?}", types.len(), value)
+                }
+                let record = record.iter().zip(&types).map(|(v, t)| Self::param(v, t));
+                match stage {
+                    Stage::Process => record.collect(),
+                    // the id allows to read the record again, e.g. for lookups
+                    _ => once(Ok(SqlValue::Integer(meta.id as i64)))
+                        .chain(record)
+                        .collect(),
+                }
+            })
+            .collect::<anyhow::Result<Vec<Vec<_>>>>()?;
+
+        self.with(move |connection| {
+            let transaction = connection.transaction()?;
+            {
+                let mut statement = transaction.prepare_cached(&insert)?;
+                for row in rows {
+                    statement.execute(params_from_iter(row))?;
+                }
+            }
+            transaction.commit()?;
+            Ok(())
+        })
+        .await?;
+
+        debug!("inserted in sqlite {} {:?}", values.len(), now.elapsed());
+        Ok(())
+    }
+
+    /// The value as it is stored in a column of the type, blobs hold any value serialized.
+    fn param(value: &Value, column: &RelationalType) -> anyhow::Result<SqlValue> {
+        Ok(match (value, column) {
+            (Value::Null, _) => SqlValue::Null,
+            (value, RelationalType::Blob) => SqlValue::Blob(value.write_to_vec()?),
+            (value, _) => match value.to_sql()? {
+                ToSqlOutput::Owned(value) => value,
+                ToSqlOutput::Borrowed(value) => value.try_into()?,
+                output => bail!("Unexpected SQLite value {:?}", output),
+            },
+        })
+    }
+
+    pub async fn read(
+        &self,
+        stage: &Stage,
+        entity: String,
+        ids: Vec<u64>,
+    ) -> anyhow::Result<Vec<Value>> {
+        let stage = stage.clone();
+        self.with(move |connection| {
+            let columns = Self::record_columns(connection, &entity)?;
+            let read_query = format!(
+                "SELECT id, {} FROM {} WHERE id IN ({})",
+                columns.keys().cloned().collect::<Vec<_>>().join(", "),
+                entity,
+                vec!["?"; ids.len()].join(", ")
+            );
+            let mut statement = connection.prepare(&read_query)?;
+
+            let ids = ids.into_iter().map(|id| id as i64);
+            let rows = statement
+                .query_map(params_from_iter(ids), |row| {
+                    Self::record(&stage, &columns, row).map(|(_, record)| record)
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(rows)
+        })
+        .await
+    }
+
+    /// All rows of the table with their ids.
+    pub async fn scan(&self, stage: &Stage, entity: String) -> anyhow::Result<Vec<(u64, Value)>> {
+        let stage = stage.clone();
+        self.with(move |connection| {
+            let columns = Self::record_columns(connection, &entity)?;
+            let mut statement = connection.prepare(&format!(
+                "SELECT id, {} FROM {} WHERE id IS NOT NULL",
+                columns.keys().cloned().collect::<Vec<_>>().join(", "),
+                entity
+            ))?;
+
+            let rows = statement
+                .query_map([], |row| Self::record(&stage, &columns, row))?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(rows)
+        })
+        .await
+    }
+
+    /// The columns of the table with the types they were created with.
+    fn table_columns(
+        connection: &Connection,
+        entity: &str,
+    ) -> anyhow::Result<IndexMap<String, ValType>> {
+        let mut statement =
+            connection.prepare("SELECT name, type FROM pragma_table_info(?) ORDER BY cid")?;
+        let columns = statement
+            .query_map([entity], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        columns
+            .into_iter()
+            .map(|(name, declared)| Ok((name, declared.parse::<RelationalType>()?.into())))
+            .collect()
+    }
+
+    /// The columns which hold the records of the table, in the order they were stored.
+    ///
+    /// Tables of earlier runs got their `id` column appended, so they are looked up by name.
+    fn record_columns(
+        connection: &Connection,
+        entity: &str,
+    ) -> anyhow::Result<IndexMap<String, ValType>> {
+        let mut columns = Self::table_columns(connection, entity)?;
+        // e.g. process tables, their rows are no records of the definition
+        if columns.shift_remove("id").is_none() {
+            bail!("Table {} stores no record ids", entity)
+        }
+        columns.shift_remove("_id");
+        Ok(columns)
+    }
+
+    /// The id and the record as it was stored of a row selected with its id first, plain rows
+    /// hold the whole record in their value column.
+    fn record(
+        stage: &Stage,
+        columns: &IndexMap<String, ValType>,
+        row: &rusqlite::Row<'_>,
+    ) -> rusqlite::Result<(u64, Value)> {
+        let id = row.get::<_, i64>(0)? as u64;
+        if matches!(stage, Stage::Plain) {
+            return Ok((id, row.get::<_, Value>(1)?));
+        }
+        columns
+            .values()
+            .enumerate()
+            .map(|(i, t)| Ok(Self::typed(row.get::<_, Value>(i + 1)?, t)))
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map(|values| (id, Value::array(values)))
+    }
+
+    /// SQLite stores booleans, times and dates as integers, they get their type back from the
+    /// column they were selected from.
+    fn typed(value: Value, column: &ValType) -> Value {
+        match (value, column) {
+            (Value::Int(i), ValType::Bool) => Value::bool(i.0 != 0),
+            (Value::Int(i), ValType::Time) => Value::time(i.0, 0),
+            (Value::Int(i), ValType::Date) => Value::date(i.0),
+            (value, _) => value,
+        }
+    }
+
+    /// Evaluates the algebra as query over the table with the planner of SQLite.
+    pub async fn query(&self, entity: String, algebra: &Algebra) -> anyhow::Result<Vec<Value>> {
+        let mut algebra = algebra.clone();
+        self.with(move |connection| {
+            // the wildcard stands for the columns of the records, not the ids of their rows
+            let mut columns = Self::table_columns(connection, &entity)?;
+            columns.shift_remove("_id");
+            columns.shift_remove("id");
+            algebra.set_schema(Schema::Fixed(columns));
+            let types = match algebra.schema()? {
+                Schema::Fixed(fields) => fields.into_values().collect(),
+                Schema::Dynamic => vec![],
+            };
+
+            let query = to_sql(&algebra, &entity)?;
+            debug!("pushed down {}", query);
+
+            let mut statement = connection.prepare(&query)?;
+            let count = statement.column_count();
+            let rows = statement
+                .query_map([], |row| {
+                    let mut values = (0..count)
+                        .map(|i| {
+                            let column = types.get(i).unwrap_or(&ValType::Any);
+                            Ok(Self::typed(row.get::<_, Value>(i)?, column))
+                        })
+                        .collect::<rusqlite::Result<Vec<_>>>()?;
+                    Ok(match values.len() {
+                        1 => values.pop().unwrap(),
+                        _ => Value::array(values),
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(rows)
+        })
+        .await
+    }
+
+    pub(crate) async fn monitor(&self, statistic_tx: &Sender<Event>) -> anyhow::Result<()> {
+        loop {
+            let size: i64 = self
+                .with(|connection| {
+                    Ok(connection.query_row(
+                        "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+                        [],
+                        |row| row.get(0),
+                    )?)
+                })
+                .await?;
+            statistic_tx
+                .send_async(Event::EngineStatus(format!(
+                    "✅ SQLite size: {:.2} MiB",
+                    size as f64 / (1024.0 * 1024.0)
+                )))
+                .await?;
+            sleep(Duration::from_secs(5)).await;
+        }
+    }
+
+    pub async fn init_entity(
+        &mut self,
+        definition: &Definition,
+        partition_id: PartitionId,
+        stage: &Stage,
+    ) -> anyhow::Result<()> {
+        let name = definition.entity_name(partition_id, stage);
+        let types = match (stage, &definition.mapping) {
+            (Stage::Plain, _) => vec![("value".to_string(), RelationalType::Blob)],
+            (Stage::Native, NativeMapping::Relational(m)) => m.get_types(),
+            (Stage::Process, NativeMapping::Relational(_)) => {
+                let Schema::Fixed(types) = definition.schema()? else {
+                    bail!("Process table {} needs a fixed schema", name)
+                };
+                types
+                    .into_iter()
+                    .map(|(n, t)| (n, RelationalType::from(&t)))
+                    .collect()
+            }
+            _ => return Ok(()),
+        };
+        // processed rows are no records of the definition, so they have no ids
+        let ids = !matches!(stage, Stage::Process);
+
+        let create = format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                _id INTEGER PRIMARY KEY,{}
+                {})",
+            name,
+            if ids {
+                "\n                id BIGINT,"
+            } else {
+                ""
+            },
+            Self::columns(&types)
+        );
+        let table = name.clone();
+        self.with(move |connection| {
+            connection.execute(&create, [])?;
+            if ids {
+                Self::add_ids(connection, &table)?;
+            }
+            Ok(())
+        })
+        .await?;
+        debug!("Table '{}' ensured to exist on {:?}.", name, self.id);
+
+        let insert = match ids {
+            true => format!(
+                "INSERT INTO {} (id, {}) VALUES (?, {})",
+                name,
+                Self::names(&types),
+                vec!["?"; types.len()].join(", ")
+            ),
+            false => format!(
+                "INSERT INTO {} ({}) VALUES ({})",
+                name,
+                Self::names(&types),
+                vec!["?"; types.len()].join(", ")
+            ),
+        };
+        self.inserts.insert(
+            (name, stage.clone()),
+            (insert, types.into_iter().map(|(_, t)| t).collect()),
+        );
+        Ok(())
+    }
+
+    /// Tables of earlier runs were created without the ids of their records.
+    fn add_ids(connection: &Connection, name: &str) -> anyhow::Result<()> {
+        if !Self::table_columns(connection, name)?.contains_key("id") {
+            connection.execute(&format!("ALTER TABLE {} ADD COLUMN id BIGINT", name), [])?;
+        }
+        Ok(())
+    }
+
+    fn columns(types: &[(String, RelationalType)]) -> String {
+        types
+            .iter()
+            .map(|(name, t)| format!("{} {}", name, t))
+            .collect::<Vec<_>>()
+            .join(",\n")
+    }
+
+    fn names(types: &[(String, RelationalType)]) -> String {
+        types
+            .iter()
+            .map(|(n, _)| n.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[async_trait]
+impl StorageEngine for Sqlite {
+    fn kind(&self) -> &str {
+        "sqlite"
+    }
+
+    fn model(&self) -> Model {
+        Model::Relational
+    }
+
+    fn clone_box(&self) -> Box<dyn StorageEngine> {
+        Box::new(self.clone())
+    }
+
+    async fn start(&mut self, _join_set: &mut JoinSet<()>, id: EngineId) -> anyhow::Result<()> {
+        Sqlite::start(self, id).await
+    }
+
+    async fn stop(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn init_entity(
+        &mut self,
+        definition: &Definition,
+        partition_id: PartitionId,
+        stage: &Stage,
+    ) -> anyhow::Result<()> {
+        Sqlite::init_entity(self, definition, partition_id, stage).await
+    }
+
+    async fn store(
+        &self,
+        stage: &Stage,
+        entity: String,
+        values: &Batch<TargetedRecord>,
+    ) -> anyhow::Result<()> {
+        Sqlite::store(self, stage, entity, values).await
+    }
+
+    async fn read(
+        &self,
+        stage: &Stage,
+        entity: String,
+        keys: Vec<Value>,
+    ) -> anyhow::Result<Vec<Value>> {
+        Sqlite::read(self, stage, entity, ids(keys)?).await
+    }
+
+    async fn scan(&self, stage: &Stage, entity: String) -> anyhow::Result<Vec<(u64, Value)>> {
+        Sqlite::scan(self, stage, entity).await
+    }
+
+    async fn query(&self, entity: String, algebra: &Algebra) -> anyhow::Result<Vec<Value>> {
+        Sqlite::query(self, entity, algebra).await
+    }
+
+    async fn monitor(&self, statistic_tx: &Sender<Event>) -> anyhow::Result<()> {
+        Sqlite::monitor(self, statistic_tx).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sqlite::Sqlite;
+    use processing::parse_sql;
+    use util::definition::{Definition, Model, Stage};
+    use util::query::Query;
+    use util::{NativeMapping, PartitionId, RelationalType, TargetedMeta, batch, target};
+    use value::{Dict, Value};
+
+    async fn definition() -> Definition {
+        Definition::builder(
+            "test",
+            NativeMapping::tuple_to_relational(vec![
+                ("name".to_string(), RelationalType::Text),
+                ("age".to_string(), RelationalType::Integer),
+            ]),
+            Query::SQL("SELECT name, age FROM $$source".to_string()),
+            Model::Relational,
+        )
+        .entity("users")
+        .build()
+        .await
+        .unwrap()
+    }
+
+    async fn started(definition: &Definition) -> Sqlite {
+        let mut sqlite = Sqlite::new(":memory:");
+        sqlite.start(0).await.unwrap();
+        for stage in [Stage::Plain, Stage::Native, Stage::Process] {
+            sqlite
+                .init_entity(definition, PartitionId(0), &stage)
+                .await
+                .unwrap();
+        }
+        sqlite
+    }
+
+    fn meta(id: u64) -> TargetedMeta {
+        TargetedMeta {
+            id,
+            ..TargetedMeta::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn plain() {
+        let definition = definition().await;
+        let sqlite = started(&definition).await;
+        let entity = definition.entity_name(PartitionId(0), &Stage::Plain);
+
+        let doc = Value::from(Dict::from(vec![("name", Value::text("Alice"))]));
+        sqlite
+            .store(
+                &Stage::Plain,
+                entity.clone(),
+                &batch![
+                    target!(doc.clone(), meta(1)),
+                    target!(Value::int(3), meta(2))
+                ],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            sqlite.read(&Stage::Plain, entity, vec![1]).await.unwrap(),
+            vec![doc]
+        );
+    }
+
+    #[tokio::test]
+    async fn native() {
+        let definition = definition().await;
+        let sqlite = started(&definition).await;
+        let entity = definition.entity_name(PartitionId(0), &Stage::Native);
+
+        sqlite
+            .store(
+                &Stage::Native,
+                entity.clone(),
+                &batch![
+                    target!(
+                        Value::array(vec![Value::text("Alice"), Value::int(30)]),
+                        meta(1)
+                    ),
+                    target!(
+                        Value::array(vec![Value::text("Bob"), Value::int(25)]),
+                        meta(2)
+                    )
+                ],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            sqlite
+                .read(&Stage::Native, entity.clone(), vec![2])
+                .await
+                .unwrap(),
+            vec![Value::array(vec![Value::text("Bob"), Value::int(25)])]
+        );
+
+        let algebra = parse_sql("SELECT name FROM $$source WHERE age > 26").unwrap();
+        assert_eq!(
+            sqlite.query(entity, &algebra).await.unwrap(),
+            vec![Value::text("Alice")]
+        );
+    }
+
+    #[tokio::test]
+    async fn rolled_back() {
+        let definition = definition().await;
+        let sqlite = started(&definition).await;
+        let entity = definition.entity_name(PartitionId(0), &Stage::Native);
+
+        // the second record is no row, so the whole batch is not stored
+        assert!(
+            sqlite
+                .store(
+                    &Stage::Native,
+                    entity.clone(),
+                    &batch![
+                        target!(
+                            Value::array(vec![Value::text("Alice"), Value::int(30)]),
+                            meta(1)
+                        ),
+                        target!(Value::text("Bob"), meta(2))
+                    ],
+                )
+                .await
+                .is_err()
+        );
+        assert!(
+            sqlite
+                .read(&Stage::Native, entity, vec![1, 2])
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn process() {
+        let definition = definition().await;
+        let sqlite = started(&definition).await;
+        let entity = definition.entity_name(PartitionId(0), &Stage::Process);
+
+        sqlite
+            .store(
+                &Stage::Process,
+                entity.clone(),
+                &batch![target!(
+                    Value::array(vec![Value::text("Alice"), Value::int(30)]),
+                    meta(1)
+                )],
+            )
+            .await
+            .unwrap();
+
+        let algebra = parse_sql("SELECT * FROM $$source").unwrap();
+        assert_eq!(
+            sqlite.query(entity.clone(), &algebra).await.unwrap(),
+            vec![Value::array(vec![Value::text("Alice"), Value::int(30)])]
+        );
+        // processed rows are no records with ids
+        assert!(
+            sqlite
+                .read(&Stage::Process, entity.clone(), vec![1])
+                .await
+                .is_err()
+        );
+        assert!(sqlite.scan(&Stage::Process, entity).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn earlier_layout() {
+        let definition = definition().await;
+        let entity = definition.entity_name(PartitionId(0), &Stage::Native);
+        let mut sqlite = Sqlite::new(":memory:");
+        sqlite.start(0).await.unwrap();
+
+        // a table of a run before the records had ids
+        for statement in [
+            "CREATE TABLE {} (_id INTEGER PRIMARY KEY, name TEXT, age INTEGER)",
+            "INSERT INTO {} (name, age) VALUES ('Carol', 41)",
+        ] {
+            let statement = statement.replace("{}", &entity);
+            sqlite
+                .with(move |connection| Ok(connection.execute(&statement, [])?))
+                .await
+                .unwrap();
+        }
+        sqlite
+            .init_entity(&definition, PartitionId(0), &Stage::Native)
+            .await
+            .unwrap();
+
+        let alice = Value::array(vec![Value::text("Alice"), Value::int(30)]);
+        sqlite
+            .store(
+                &Stage::Native,
+                entity.clone(),
+                &batch![target!(alice.clone(), meta(1))],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            sqlite
+                .read(&Stage::Native, entity.clone(), vec![1])
+                .await
+                .unwrap(),
+            vec![alice.clone()]
+        );
+        // the rows stored without ids cannot be read again
+        assert_eq!(
+            sqlite.scan(&Stage::Native, entity).await.unwrap(),
+            vec![(1, alice)]
+        );
+    }
+
+    #[tokio::test]
+    async fn process_any() {
+        // the branches differ in their type, so the column holds any value
+        let definition = Definition::builder(
+            "test",
+            NativeMapping::tuple_to_relational(vec![
+                ("name".to_string(), RelationalType::Text),
+                ("age".to_string(), RelationalType::Integer),
+            ]),
+            Query::SQL(
+                "SELECT name, CASE WHEN age > 26 THEN age ELSE name END AS label FROM $$source"
+                    .to_string(),
+            ),
+            Model::Relational,
+        )
+        .entity("users")
+        .build()
+        .await
+        .unwrap();
+        let sqlite = started(&definition).await;
+        let entity = definition.entity_name(PartitionId(0), &Stage::Process);
+
+        let doc = Value::from(Dict::from(vec![("name", Value::text("Bob"))]));
+        sqlite
+            .store(
+                &Stage::Process,
+                entity.clone(),
+                &batch![
+                    target!(
+                        Value::array(vec![Value::text("Alice"), Value::int(30)]),
+                        meta(1)
+                    ),
+                    target!(Value::array(vec![Value::text("Bob"), doc.clone()]), meta(2))
+                ],
+            )
+            .await
+            .unwrap();
+
+        let algebra = parse_sql("SELECT * FROM $$source").unwrap();
+        assert_eq!(
+            sqlite.query(entity, &algebra).await.unwrap(),
+            vec![
+                Value::array(vec![Value::text("Alice"), Value::int(30)]),
+                Value::array(vec![Value::text("Bob"), doc])
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn typed() {
+        let definition = Definition::builder(
+            "test",
+            NativeMapping::tuple_to_relational(vec![
+                ("active".to_string(), RelationalType::Bool),
+                ("seen".to_string(), RelationalType::Blob),
+            ]),
+            Query::SQL("SELECT active, seen FROM $$source".to_string()),
+            Model::Relational,
+        )
+        .entity("users")
+        .build()
+        .await
+        .unwrap();
+        let sqlite = started(&definition).await;
+        let entity = definition.entity_name(PartitionId(0), &Stage::Native);
+
+        // booleans are integers in SQLite and blobs hold any value serialized
+        let record = Value::array(vec![Value::bool(true), Value::time(1_000, 0)]);
+        sqlite
+            .store(
+                &Stage::Native,
+                entity.clone(),
+                &batch![target!(record.clone(), meta(1))],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            sqlite
+                .read(&Stage::Native, entity.clone(), vec![1])
+                .await
+                .unwrap(),
+            vec![record.clone()]
+        );
+        assert_eq!(
+            sqlite.scan(&Stage::Native, entity.clone()).await.unwrap(),
+            vec![(1, record.clone())]
+        );
+        let algebra = parse_sql("SELECT * FROM $$source WHERE active").unwrap();
+        assert_eq!(sqlite.query(entity, &algebra).await.unwrap(), vec![record]);
+    }
+
+    #[tokio::test]
+    async fn file() {
+        let path = std::env::temp_dir().join(format!("sqlite-{}/engine.db", std::process::id()));
+        let definition = definition().await;
+        let entity = definition.entity_name(PartitionId(0), &Stage::Native);
+
+        let mut sqlite = Sqlite::new(&path);
+        sqlite.start(0).await.unwrap();
+        sqlite
+            .init_entity(&definition, PartitionId(0), &Stage::Native)
+            .await
+            .unwrap();
+        sqlite
+            .store(
+                &Stage::Native,
+                entity.clone(),
+                &batch![target!(
+                    Value::array(vec![Value::text("Alice"), Value::int(30)]),
+                    meta(7)
+                )],
+            )
+            .await
+            .unwrap();
+
+        // a second copy, like the one of another worker, sees the stored records
+        let mut other = sqlite.clone();
+        other.start(1).await.unwrap();
+        assert_eq!(
+            other
+                .read(&Stage::Native, entity, vec![7])
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        drop(other);
+        drop(sqlite);
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn not_started() {
+        let sqlite = Sqlite::new(":memory:");
+        assert!(
+            sqlite
+                .read(&Stage::Native, "users".to_string(), vec![1])
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,503 @@
+use crate::engine::Engine;
+use crate::files::ArrowFiles;
+use crate::kv::Redb;
+use crate::memory::Memory;
+use crate::mongo::MongoDB;
+use crate::neo::Neo4j;
+use crate::postgres::Postgres;
+use crate::sqlite::Sqlite;
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use flume::Sender;
+use futures_util::future::join_all;
+use processing::Algebra;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use tokio::fs;
+use tokio::task::JoinSet;
+use util::definition::{Definition, Model, Stage};
+use util::{Batch, EngineId, Event, PartitionId, TargetedRecord};
+use value::Value;
+
+/// A database the records of definitions are stored in.
+///
+/// An engine is configured in `engines.toml` under the `type` it was registered with in the
+/// [`EngineRegistry`] and started once per worker which stores into it.
+#[async_trait]
+pub trait StorageEngine: Debug + Send + Sync {
+    /// The `type` of the engine, e.g. `postgres`.
+    fn kind(&self) -> &str;
+
+    /// The data model native records are stored in.
+    fn model(&self) -> Model;
+
+    /// An unstarted copy with the same configuration.
+    fn clone_box(&self) -> Box<dyn StorageEngine>;
+
+    /// Deploys the database, e.g. as container, if the configuration asks for it.
+    async fn start_container(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Connects to the database.
+    async fn start(&mut self, join_set: &mut JoinSet<()>, id: EngineId) -> anyhow::Result<()>;
+
+    async fn stop(&self) -> anyhow::Result<()>;
+
+    /// Creates the entity a stage of the definition stores its records of the partition in.
+    async fn init_entity(
+        &mut self,
+        definition: &Definition,
+        partition_id: PartitionId,
+        stage: &Stage,
+    ) -> anyhow::Result<()>;
+
+    async fn store(
+        &self,
+        stage: &Stage,
+        entity: String,
+        values: &Batch<TargetedRecord>,
+    ) -> anyhow::Result<()>;
+
+    /// The stored records of the entity under the keys, as they were stored. The keys are the
+    /// record ids, or the keys of the native pairs of a key-value entity.
+    async fn read(
+        &self,
+        stage: &Stage,
+        entity: String,
+        keys: Vec<Value>,
+    ) -> anyhow::Result<Vec<Value>>;
+
+    /// All stored records of the entity with their ids, e.g. to index them for lookups.
+    async fn scan(&self, _stage: &Stage, _entity: String) -> anyhow::Result<Vec<(u64, Value)>> {
+        bail!("{} cannot scan entities", self.kind())
+    }
+
+    /// Evaluates the algebra inside the database over the stored records of the entity.
+    async fn query(&self, _entity: String, _algebra: &Algebra) -> anyhow::Result<Vec<Value>> {
+        bail!("{} cannot evaluate queries", self.kind())
+    }
+
+    /// How expensive storing the value is, regardless of the current load.
+    fn cost(&self, _value: &Value) -> f64 {
+        1.0
+    }
+
+    /// Reports the load of the database to the statistics until it fails.
+    async fn monitor(&self, statistic_tx: &Sender<Event>) -> anyhow::Result<()>;
+}
+
+impl Clone for Box<dyn StorageEngine> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+impl Display for dyn StorageEngine {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.kind())
+    }
+}
+
+/// The record ids of read keys, for engines which store records by id.
+pub(crate) fn ids(keys: Vec<Value>) -> anyhow::Result<Vec<u64>> {
+    keys.into_iter()
+        .map(|key| match key {
+            Value::Int(id) if id.0 >= 0 => Ok(id.0 as u64),
+            key => bail!("Expected a record id, got {:?}", key),
+        })
+        .collect()
+}
+
+type Factory = Arc<dyn Fn(toml::Value) -> anyhow::Result<Box<dyn StorageEngine>> + Send + Sync>;
+
+/// The storage engines `engines.toml` may configure by their `type`, clones share their
+/// registrations.
+///
+/// Postgres, MongoDB, Neo4j, SQLite, redb, Arrow files and the in-memory engine are registered from
+/// the start, other engines are registered before the engines are read.
+#[derive(Clone)]
+pub struct EngineRegistry {
+    factories: Arc<RwLock<HashMap<String, Factory>>>,
+}
+
+impl Default for EngineRegistry {
+    fn default() -> Self {
+        let registry = EngineRegistry {
+            factories: Default::default(),
+        };
+        registry.register::<Postgres>("postgres").unwrap();
+        registry.register::<MongoDB>("mongodb").unwrap();
+        registry.register::<Neo4j>("neo4j").unwrap();
+        registry.register::<Sqlite>("sqlite").unwrap();
+        registry.register::<Redb>("redb").unwrap();
+        registry.register::<ArrowFiles>("arrow").unwrap();
+        registry.register::<Memory>("memory").unwrap();
+        registry
+    }
+}
+
+impl EngineRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the engine under the type, which its configuration is deserialized from.
+    pub fn register<E>(&self, kind: &str) -> anyhow::Result<()>
+    where
+        E: StorageEngine + DeserializeOwned + 'static,
+    {
+        let mut factories = self.factories.write().unwrap();
+        if factories.contains_key(kind) {
+            bail!("Engine type {} is already registered", kind)
+        }
+        factories.insert(
+            kind.to_string(),
+            Arc::new(|config| Ok(Box::new(config.try_into::<E>()?))),
+        );
+        Ok(())
+    }
+
+    /// Builds the engine the configuration describes by its `type`.
+    pub fn build(&self, mut config: toml::Table) -> anyhow::Result<Box<dyn StorageEngine>> {
+        let kind = match config.remove("type") {
+            Some(toml::Value::String(kind)) => kind,
+            _ => bail!("Engine configuration needs a type"),
+        };
+        let factory = self
+            .factories
+            .read()
+            .unwrap()
+            .get(&kind)
+            .cloned()
+            .ok_or_else(|| anyhow!("Unknown engine type {}", kind))?;
+        factory(toml::Value::Table(config))
+    }
+
+    /// The engines of a configuration like `engines.toml`, with one table per engine.
+    pub fn parse(&self, content: &str) -> anyhow::Result<Vec<Box<dyn StorageEngine>>> {
+        let tables: HashMap<String, toml::Table> = toml::from_str(content)?;
+        tables
+            .into_iter()
+            .map(|(name, config)| {
+                self.build(config)
+                    .map_err(|e| anyhow!("Invalid engine {}: {}", name, e))
+            })
+            .collect()
+    }
+
+    pub async fn read<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> anyhow::Result<Vec<Box<dyn StorageEngine>>> {
+        self.parse(&fs::read_to_string(path).await?)
+    }
+
+    /// The engines of `engines.toml`, ready to be started.
+    pub async fn get_all(&self, statistic_tx: Sender<Event>) -> anyhow::Result<Vec<Engine>> {
+        let kinds = self.read("engines.toml").await?;
+        let init_futures = kinds
+            .into_iter()
+            .map(|kind| Engine::new(kind, statistic_tx.clone()));
+        Ok(join_all(init_futures).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use processing::parse_sql;
+    use serde::Deserialize;
+    use util::query::Query;
+    use util::{NativeMapping, RelationalType, TargetedMeta, batch, target};
+    use value::Dict;
+
+    #[derive(Clone, Debug, Deserialize)]
+    struct Dummy {
+        path: String,
+    }
+
+    #[async_trait]
+    impl StorageEngine for Dummy {
+        fn kind(&self) -> &str {
+            "dummy"
+        }
+
+        fn model(&self) -> Model {
+            Model::Document
+        }
+
+        fn clone_box(&self) -> Box<dyn StorageEngine> {
+            Box::new(self.clone())
+        }
+
+        async fn start(&mut self, _: &mut JoinSet<()>, _: EngineId) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn stop(&self) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn init_entity(
+            &mut self,
+            _: &Definition,
+            _: PartitionId,
+            _: &Stage,
+        ) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn store(
+            &self,
+            _: &Stage,
+            _: String,
+            _: &Batch<TargetedRecord>,
+        ) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn read(&self, _: &Stage, _: String, _: Vec<Value>) -> anyhow::Result<Vec<Value>> {
+            Ok(vec![Value::text(&self.path)])
+        }
+
+        async fn monitor(&self, _: &Sender<Event>) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn parse_one(content: &str) -> Box<dyn StorageEngine> {
+        let mut engines = EngineRegistry::new().parse(content).unwrap();
+        assert_eq!(engines.len(), 1);
+        engines.remove(0)
+    }
+
+    #[test]
+    fn postgres() {
+        let engine = parse_one(
+            r#"
+        [postgres]
+        type = "postgres"
+        host = "localhost"
+        port = 5432
+        db = "postgres"
+        user = "postgres"
+        password = "postgres"
+        deploy = true"#,
+        );
+        assert_eq!(engine.kind(), "postgres");
+        assert_eq!(engine.model(), Model::Relational);
+    }
+
+    #[test]
+    fn mongo() {
+        let engine = parse_one(
+            r#"
+        [mongodb]
+        type = "mongodb"
+        host = "localhost"
+        port = 27017
+        deploy = true"#,
+        );
+        assert_eq!(engine.kind(), "mongodb");
+        assert_eq!(engine.model(), Model::Document);
+    }
+
+    #[test]
+    fn neo4j() {
+        let engine = parse_one(
+            r#"
+        [neo4j]
+        type = "neo4j"
+        host = "localhost"
+        port = 7687
+        user = "neo4j"
+        password = "neoneoneo"
+        deploy = true"#,
+        );
+        assert_eq!(engine.kind(), "neo4j");
+        assert_eq!(engine.model(), Model::Graph);
+    }
+
+    #[test]
+    fn sqlite() {
+        let engine = parse_one(
+            r#"
+        [sqlite]
+        type = "sqlite"
+        path = "sqlite/engine.db""#,
+        );
+        assert_eq!(engine.kind(), "sqlite");
+        assert_eq!(engine.model(), Model::Relational);
+    }
+
+    #[test]
+    fn redb() {
+        let engine = parse_one(
+            r#"
+        [redb]
+        type = "redb"
+        path = "redb/engine.redb""#,
+        );
+        assert_eq!(engine.kind(), "redb");
+        assert_eq!(engine.model(), Model::KeyValue);
+    }
+
+    #[test]
+    fn arrow() {
+        let engine = parse_one(
+            r#"
+        [cold]
+        type = "arrow"
+        directory = "cold""#,
+        );
+        assert_eq!(engine.kind(), "arrow");
+        assert_eq!(engine.model(), Model::Relational);
+    }
+
+    #[test]
+    fn memory() {
+        let engine = parse_one(
+            r#"
+        [memory]
+        type = "memory"
+        model = "relational"
+        latency = "5 MILLISECONDS"
+        fail_every = 10"#,
+        );
+        assert_eq!(engine.kind(), "memory");
+        assert_eq!(engine.model(), Model::Relational);
+
+        assert!(
+            EngineRegistry::new()
+                .parse(
+                    r#"
+        [memory]
+        type = "memory"
+        model = "graph"
+        fail_every = 0"#,
+                )
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn unknown() {
+        let err = EngineRegistry::new()
+            .parse(
+                r#"
+        [cassandra]
+        type = "cassandra"
+        host = "localhost""#,
+            )
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("Unknown engine type cassandra"),
+            "{}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn custom() {
+        let registry = EngineRegistry::new();
+        registry.register::<Dummy>("dummy").unwrap();
+
+        let engines = registry
+            .clone()
+            .parse(
+                r#"
+        [first]
+        type = "dummy"
+        path = "/tmp/dummy""#,
+            )
+            .unwrap();
+        assert_eq!(engines[0].kind(), "dummy");
+        assert_eq!(engines[0].to_string(), "dummy");
+        assert_eq!(
+            engines[0]
+                .read(&Stage::Native, "entity".to_string(), vec![])
+                .await
+                .unwrap(),
+            vec![Value::text("/tmp/dummy")]
+        );
+        assert!(
+            engines[0]
+                .query(
+                    "entity".to_string(),
+                    &parse_sql("SELECT * FROM entity").unwrap()
+                )
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn plain_records() {
+        let definition = Definition::builder(
+            "test",
+            NativeMapping::tuple_to_relational(vec![("name".to_string(), RelationalType::Text)]),
+            Query::SQL("SELECT name FROM $$source".to_string()),
+            Model::Relational,
+        )
+        .entity("plain")
+        .build()
+        .await
+        .unwrap();
+        let entity = definition.entity_name(PartitionId(0), &Stage::Plain);
+        let directory = std::env::temp_dir().join(format!("plain-{}", std::process::id()));
+
+        let doc = Value::from(Dict::from(vec![("name", Value::text("Alice"))]));
+        let meta = TargetedMeta {
+            id: 1,
+            ..TargetedMeta::default()
+        };
+        let engines: Vec<Box<dyn StorageEngine>> = vec![
+            Box::new(Memory::new(Model::Document)),
+            Box::new(Sqlite::new(":memory:")),
+            Box::new(Redb::new(directory.join("engine.redb"))),
+            Box::new(ArrowFiles::new(directory.join("files"))),
+        ];
+        let mut join_set = JoinSet::new();
+        // every engine returns the plain record as it was stored
+        for mut engine in engines {
+            engine.start(&mut join_set, EngineId(0)).await.unwrap();
+            engine
+                .init_entity(&definition, PartitionId(0), &Stage::Plain)
+                .await
+                .unwrap();
+            engine
+                .store(
+                    &Stage::Plain,
+                    entity.clone(),
+                    &batch![target!(doc.clone(), meta.clone())],
+                )
+                .await
+                .unwrap();
+            // files are only read once they are finished
+            engine.stop().await.unwrap();
+            assert_eq!(
+                engine
+                    .read(&Stage::Plain, entity.clone(), vec![Value::int(1)])
+                    .await
+                    .unwrap(),
+                vec![doc.clone()],
+                "{}",
+                engine.kind()
+            );
+        }
+
+        let _ = std::fs::remove_dir_all(directory);
+    }
+
+    #[test]
+    fn duplicate() {
+        let registry = EngineRegistry::new();
+        registry.register::<Dummy>("dummy").unwrap();
+        assert!(registry.register::<Dummy>("dummy").is_err());
+        assert!(registry.register::<Postgres>("postgres").is_err());
+    }
+}
@@ -0,0 +1,830 @@
+use crate::expression::Expression;
+use crate::operator::Operator;
+use crate::time::Lateness;
+use crate::tuple::program::compare;
+use crate::window::{Bounds, Window};
+use crate::{Algebra, Filter, Program, Project, RecordError, Scan, Schema};
+use anyhow::bail;
+use indexmap::IndexMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::iter;
+use value::{ValType, Value};
+
+const GROUPS: &str = "$$groups";
+
+/// Bounds of the single window used when a definition has no window clause.
+const ALL: Bounds = Bounds {
+    start: i64::MIN,
+    end: i64::MAX,
+};
+
+/// Accumulators of the aggregate calls per group key.
+type Groups = IndexMap<Vec<Value>, Vec<Accumulator>>;
+
+/// Incrementally evaluates an [`Aggregate`](crate::Aggregate) per window and group.
+///
+/// The input rows are reduced to their group keys and aggregate arguments by the tuple VM,
+/// accumulated here and turned into the final rows by a second program once a window closes.
+pub struct Aggregator {
+    window: Option<Window>,
+    keys: usize,
+    functions: Vec<Operator>,
+    input: Program,
+    output: Box<Program>,
+    groups: BTreeMap<Bounds, Groups>,
+    /// rows which can still fall into a new sliding window
+    history: Vec<(i64, Vec<Value>, Vec<Value>)>,
+    watermark: i64,
+    lateness: Lateness,
+    /// emitted windows which late records may still update
+    closed: BTreeMap<Bounds, Groups>,
+    /// closed windows which got late records since they were emitted
+    updated: BTreeSet<Bounds>,
+    /// rows of emitted windows which failed the output, e.g. its `HAVING`
+    errors: Vec<(Bounds, RecordError)>,
+}
+
+impl Aggregator {
+    pub fn new(algebra: &Algebra, window: Option<Window>) -> anyhow::Result<Self> {
+        let collected;
+        let algebra = match algebra {
+            Algebra::Collect(c) => {
+                collected = Algebra::Aggregate(c.aggregate());
+                &collected
+            }
+            algebra => algebra,
+        };
+        let Some(aggregate) = algebra.aggregation() else {
+            bail!("Expected an aggregation as last operator");
+        };
+        if aggregate.keys.iter().any(|k| !k.aggregates().is_empty()) {
+            bail!("Aggregate functions are not allowed in GROUP BY");
+        }
+
+        let calls = aggregate.calls();
+        let mut functions = vec![];
+        for call in &calls {
+            if let Expression::Call {
+                operator,
+                expressions,
+            } = call
+            {
+                if expressions.iter().any(|e| !e.aggregates().is_empty()) {
+                    bail!("Aggregate functions cannot be nested");
+                }
+                functions.push(operator.clone());
+            }
+        }
+
+        // the output works on [key_0, .., key_n, aggregate_0, .., aggregate_m]
+        let mut fields = IndexMap::new();
+        for i in 0..aggregate.keys.len() {
+            fields.insert(format!("$key{}", i), ValType::Any);
+        }
+        for i in 0..calls.len() {
+            fields.insert(format!("$agg{}", i), ValType::Any);
+        }
+
+        let mut counter = 0;
+        let mut expressions = IndexMap::new();
+        for (name, expression) in &aggregate.expressions {
+            expressions.insert(
+                name.clone(),
+                Self::rewrite(expression, &aggregate.keys, &mut counter)?,
+            );
+        }
+
+        let grouped = Algebra::Project(Project {
+            expressions,
+            input: Box::new(Algebra::Scan(Scan {
+                source: GROUPS.to_string(),
+                schema: Schema::Fixed(fields),
+            })),
+        });
+        let output = Box::new(Program::try_from(&Self::above(algebra, grouped))?);
+
+        Ok(Aggregator {
+            window,
+            keys: aggregate.keys.len(),
+            functions,
+            input: Algebra::Aggregate(aggregate.clone()).processing()?,
+            output,
+            groups: BTreeMap::new(),
+            history: vec![],
+            watermark: i64::MIN,
+            lateness: Lateness::Drop,
+            closed: BTreeMap::new(),
+            updated: BTreeSet::new(),
+            errors: vec![],
+        })
+    }
+
+    /// With [`Lateness::Update`] closed windows are kept one more window size, late records
+    /// update them and they are emitted again.
+    pub fn set_lateness(&mut self, lateness: Lateness) {
+        self.lateness = lateness;
+    }
+
+    /// The filters and projections above the aggregation, e.g. of `HAVING`, over the grouped
+    /// rows.
+    fn above(algebra: &Algebra, grouped: Algebra) -> Algebra {
+        match algebra {
+            Algebra::Project(p) => Algebra::Project(Project {
+                expressions: p.expressions.clone(),
+                input: Box::new(Self::above(&p.input, grouped)),
+            }),
+            Algebra::Filter(f) => Algebra::Filter(Filter {
+                predicate: f.predicate.clone(),
+                input: Box::new(Self::above(&f.input, grouped)),
+            }),
+            _ => grouped,
+        }
+    }
+
+    /// Replaces group keys and aggregate calls with the fields of the grouped row.
+    fn rewrite(
+        expression: &Expression,
+        keys: &[Expression],
+        counter: &mut usize,
+    ) -> anyhow::Result<Expression> {
+        if let Some(i) = keys.iter().position(|k| k == expression) {
+            return Ok(Expression::Field(format!("$key{}", i)));
+        }
+        match expression {
+            Expression::Call { operator, .. } if operator.is_aggregate() => {
+                let field = Expression::Field(format!("$agg{}", counter));
+                *counter += 1;
+                Ok(field)
+            }
+            Expression::Call {
+                operator,
+                expressions,
+            } => Ok(Expression::Call {
+                operator: operator.clone(),
+                expressions: expressions
+                    .iter()
+                    .map(|e| Self::rewrite(e, keys, counter))
+                    .collect::<anyhow::Result<_>>()?,
+            }),
+            Expression::Field(name) => {
+                bail!(
+                    "{} must appear in GROUP BY or be used in an aggregate function",
+                    name
+                )
+            }
+            e => Ok(e.clone()),
+        }
+    }
+
+    /// Adds a record with the given timestamp to all windows it belongs to.
+    /// Returns false for late records, which only belong to closed windows and are dropped.
+    pub fn push(&mut self, timestamp: i64, value: Value) -> anyhow::Result<bool> {
+        if self.is_late(timestamp) {
+            return Ok(false);
+        }
+        self.input.reset();
+        self.input.set_resource("$$source", iter::once(value))?;
+
+        while let Some(row) = self.input.next() {
+            let Value::Array(row) = row else {
+                bail!("Expected a row, got {:?}", row);
+            };
+            let mut keys = row.values;
+            let args = keys.split_off(self.keys);
+            self.accumulate(timestamp, keys, args);
+        }
+        Ok(true)
+    }
+
+    /// Whether all windows of the timestamp are closed and none of them can be updated.
+    fn is_late(&self, timestamp: i64) -> bool {
+        let Some(window) = &self.window else {
+            return false;
+        };
+        let open = window
+            .assign(timestamp)
+            .iter()
+            .any(|b| b.end > self.watermark);
+        !open && !self.closed.keys().any(|b| b.contains(timestamp))
+    }
+
+    /// Adds the row to the kept closed windows it falls into.
+    fn update(&mut self, timestamp: i64, keys: &[Value], args: &[Value]) {
+        let functions = &self.functions;
+        for (bounds, group) in self
+            .closed
+            .iter_mut()
+            .filter(|(b, _)| b.contains(timestamp))
+        {
+            let accumulators = group
+                .entry(keys.to_vec())
+                .or_insert_with(|| functions.iter().map(Accumulator::new).collect());
+            Accumulator::add_all(accumulators, args);
+            self.updated.insert(*bounds);
+        }
+    }
+
+    fn accumulate(&mut self, timestamp: i64, keys: Vec<Value>, args: Vec<Value>) {
+        self.update(timestamp, &keys, &args);
+
+        let functions = &self.functions;
+        let accumulators = || functions.iter().map(Accumulator::new).collect::<Vec<_>>();
+
+        match &self.window {
+            None => {
+                let group = self.groups.entry(ALL).or_default();
+                Accumulator::add_all(group.entry(keys).or_insert_with(accumulators), &args);
+            }
+            Some(window @ Window::Sliding { .. }) => {
+                let bounds = window.assign(timestamp)[0];
+                if bounds.end <= self.watermark {
+                    return;
+                }
+
+                // a new window starts with all earlier rows it reaches back to
+                let group = self.groups.entry(bounds).or_default();
+                if !group.contains_key(&keys) {
+                    let mut seeded = accumulators();
+                    for (_, _, args) in self
+                        .history
+                        .iter()
+                        .filter(|(t, k, _)| k == &keys && bounds.contains(*t))
+                    {
+                        Accumulator::add_all(&mut seeded, args);
+                    }
+                    group.insert(keys.clone(), seeded);
+                }
+
+                for (_, group) in self
+                    .groups
+                    .iter_mut()
+                    .filter(|(b, _)| b.contains(timestamp))
+                {
+                    if let Some(accumulators) = group.get_mut(&keys) {
+                        Accumulator::add_all(accumulators, &args);
+                    }
+                }
+                self.history.push((timestamp, keys, args));
+            }
+            Some(window) => {
+                for bounds in window.assign(timestamp) {
+                    if bounds.end <= self.watermark {
+                        continue;
+                    }
+                    let group = self.groups.entry(bounds).or_default();
+                    Accumulator::add_all(
+                        group.entry(keys.clone()).or_insert_with(accumulators),
+                        &args,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Closes all windows which end at or before the watermark and returns their rows.
+    pub fn advance(&mut self, watermark: i64) -> anyhow::Result<Vec<(Bounds, Value)>> {
+        self.watermark = self.watermark.max(watermark);
+        if let Some(window) = &self.window {
+            let size = window.size();
+            let watermark = self.watermark;
+            self.history
+                .retain(|(t, _, _)| *t > watermark.saturating_sub(size));
+        }
+
+        let closed = self
+            .groups
+            .keys()
+            .filter(|b| b.end <= self.watermark)
+            .copied()
+            .collect::<Vec<_>>();
+
+        // updated windows are emitted again, the newly closed ones for the first time
+        let mut windows = std::mem::take(&mut self.updated)
+            .into_iter()
+            .filter_map(|b| Some((b, self.closed.get(&b)?.clone())))
+            .collect::<Vec<_>>();
+        for bounds in closed {
+            windows.push((bounds, self.groups.remove(&bounds).unwrap_or_default()));
+        }
+
+        if let (Lateness::Update, Some(window)) = (self.lateness, &self.window) {
+            let kept = self.watermark.saturating_sub(window.size());
+            self.closed.retain(|b, _| b.end > kept);
+            self.closed
+                .extend(windows.iter().filter(|(b, _)| b.end > kept).cloned());
+        }
+        self.emit(windows)
+    }
+
+    /// Closes all open windows.
+    pub fn flush(&mut self) -> anyhow::Result<Vec<(Bounds, Value)>> {
+        let windows = std::mem::take(&mut self.groups).into_iter().collect();
+        self.emit(windows)
+    }
+
+    /// The rows of the emitted windows which failed evaluation, by their position in the window.
+    pub fn take_errors(&mut self) -> Vec<(Bounds, RecordError)> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Evaluates the output per window, as `HAVING` may drop some of its rows.
+    fn emit(&mut self, windows: Vec<(Bounds, Groups)>) -> anyhow::Result<Vec<(Bounds, Value)>> {
+        let mut emitted = vec![];
+        for (bounds, groups) in windows {
+            if groups.is_empty() {
+                continue;
+            }
+            // groups whose aggregates failed are reported instead of evaluated
+            let mut rows = vec![];
+            let mut positions = vec![];
+            for (position, (mut keys, accumulators)) in groups.into_iter().enumerate() {
+                if let Some(message) = accumulators.iter().find_map(Accumulator::failure) {
+                    let error = RecordError {
+                        record: position,
+                        message,
+                    };
+                    self.errors.push((bounds, error));
+                    continue;
+                }
+                keys.extend(accumulators.iter().map(|a| a.result()));
+                rows.push(Value::array(keys));
+                positions.push(position);
+            }
+            self.output.reset();
+            self.output.set_resource(GROUPS, rows.into_iter())?;
+            emitted.extend(self.output.by_ref().map(|row| (bounds, row)));
+            let errors = self.output.take_errors();
+            self.errors.extend(errors.into_iter().map(|mut e| {
+                e.record = positions[e.record];
+                (bounds, e)
+            }));
+        }
+        Ok(emitted)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Accumulator {
+    Count(i64),
+    Sum(Value),
+    Avg(f64, i64),
+    Min(Value),
+    Max(Value),
+    Collect(Vec<Value>),
+    /// an aggregate which cannot be computed anymore, e.g. as its sum overflowed
+    Failed(String),
+}
+
+impl Accumulator {
+    fn new(operator: &Operator) -> Self {
+        match operator {
+            Operator::Count => Accumulator::Count(0),
+            Operator::Sum => Accumulator::Sum(Value::null()),
+            Operator::Avg => Accumulator::Avg(0.0, 0),
+            Operator::Min => Accumulator::Min(Value::null()),
+            Operator::Max => Accumulator::Max(Value::null()),
+            Operator::Collect => Accumulator::Collect(vec![]),
+            op => panic!("{:?} is no aggregate function", op),
+        }
+    }
+
+    fn add_all(accumulators: &mut [Accumulator], args: &[Value]) {
+        for (accumulator, arg) in accumulators.iter_mut().zip(args) {
+            accumulator.add(arg);
+        }
+    }
+
+    /// Nulls are ignored by all aggregate functions.
+    fn add(&mut self, value: &Value) {
+        if matches!(value, Value::Null) {
+            return;
+        }
+        match self {
+            Accumulator::Count(count) => *count += 1,
+            Accumulator::Sum(Value::Null) => *self = Accumulator::Sum(value.clone()),
+            Accumulator::Sum(sum) => match checked_add(sum, value) {
+                Ok(total) => *sum = total,
+                Err(message) => *self = Accumulator::Failed(message),
+            },
+            Accumulator::Avg(sum, count) => {
+                if let Ok(v) = value.as_float() {
+                    *sum += v.0.0;
+                    *count += 1;
+                }
+            }
+            Accumulator::Min(min) => {
+                if matches!(min, Value::Null) || compare(value, min) == Some(Ordering::Less) {
+                    *min = value.clone();
+                }
+            }
+            Accumulator::Max(max) => {
+                if matches!(max, Value::Null) || compare(value, max) == Some(Ordering::Greater) {
+                    *max = value.clone();
+                }
+            }
+            Accumulator::Collect(values) => values.push(value.clone()),
+            Accumulator::Failed(_) => {}
+        }
+    }
+
+    fn failure(&self) -> Option<String> {
+        match self {
+            Accumulator::Failed(message) => Some(message.clone()),
+            _ => None,
+        }
+    }
+
+    fn result(&self) -> Value {
+        match self {
+            Accumulator::Count(count) => Value::int(*count),
+            Accumulator::Avg(_, 0) => Value::null(),
+            Accumulator::Avg(sum, count) => Value::float(*sum / *count as f64),
+            Accumulator::Sum(v) | Accumulator::Min(v) | Accumulator::Max(v) => v.clone(),
+            Accumulator::Collect(values) => Value::array(values.clone()),
+            Accumulator::Failed(_) => Value::null(),
+        }
+    }
+}
+
+/// Adds to a sum, integers which overflow fail it instead of wrapping around.
+fn checked_add(sum: &Value, value: &Value) -> Result<Value, String> {
+    match (sum, value) {
+        (Value::Int(a), Value::Int(b)) => {
+            a.0.checked_add(b.0)
+                .map(Value::int)
+                .ok_or_else(|| format!("Integer overflow in SUM of {} and {}", a.0, b.0))
+        }
+        _ => Ok(sum + value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_sql;
+    use value::Dict;
+
+    fn aggregator(query: &str, window: Option<Window>) -> Aggregator {
+        let mut algebra = parse_sql(query).unwrap();
+        algebra.set_schema(Schema::fixed([
+            ("name".to_string(), ValType::Text),
+            ("price".to_string(), ValType::Float),
+        ]));
+        Aggregator::new(&algebra, window).unwrap()
+    }
+
+    fn row(name: &str, price: f64) -> Value {
+        Value::array([Value::text(name), Value::float(price)])
+    }
+
+    #[test]
+    fn tumbling() {
+        let mut aggregator = aggregator(
+            "SELECT name, COUNT(*), SUM(price), AVG(price), MIN(price), MAX(price) FROM $$source GROUP BY name",
+            Some(Window::Tumbling { size: 10 }),
+        );
+        aggregator.push(1, row("a", 1.0)).unwrap();
+        aggregator.push(2, row("b", 2.0)).unwrap();
+        aggregator.push(5, row("a", 3.0)).unwrap();
+        aggregator.push(12, row("a", 4.0)).unwrap();
+
+        // nothing is complete yet
+        assert!(aggregator.advance(9).unwrap().is_empty());
+
+        let rows = aggregator.advance(10).unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (
+                    Bounds { start: 0, end: 10 },
+                    Value::array([
+                        Value::text("a"),
+                        Value::int(2),
+                        Value::float(4.0),
+                        Value::float(2.0),
+                        Value::float(1.0),
+                        Value::float(3.0),
+                    ])
+                ),
+                (
+                    Bounds { start: 0, end: 10 },
+                    Value::array([
+                        Value::text("b"),
+                        Value::int(1),
+                        Value::float(2.0),
+                        Value::float(2.0),
+                        Value::float(2.0),
+                        Value::float(2.0),
+                    ])
+                ),
+            ]
+        );
+
+        // late for the closed window
+        aggregator.push(3, row("a", 10.0)).unwrap();
+        let rows = aggregator.advance(20).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(
+            rows[0].1,
+            Value::array([
+                Value::text("a"),
+                Value::int(1),
+                Value::float(4.0),
+                Value::float(4.0),
+                Value::float(4.0),
+                Value::float(4.0),
+            ])
+        );
+    }
+
+    #[test]
+    fn having() {
+        let window = Some(Window::Tumbling { size: 10 });
+        let mut selected = aggregator(
+            "SELECT name, COUNT(*) AS amount FROM $$source GROUP BY name HAVING COUNT(*) > 1",
+            window.clone(),
+        );
+        // only reads fields which are not selected
+        let mut hidden = aggregator(
+            "SELECT name FROM $$source GROUP BY name HAVING SUM(price) >= 4 AND name <> 'c'",
+            window,
+        );
+        for aggregator in [&mut selected, &mut hidden] {
+            for (t, name, price) in [
+                (1, "a", 1.0),
+                (2, "b", 2.0),
+                (3, "a", 3.0),
+                (4, "c", 5.0),
+                (12, "b", 1.0),
+                (13, "b", 4.0),
+            ] {
+                aggregator.push(t, row(name, price)).unwrap();
+            }
+        }
+
+        assert_eq!(
+            selected.advance(20).unwrap(),
+            vec![
+                (
+                    Bounds { start: 0, end: 10 },
+                    Value::array([Value::text("a"), Value::int(2)])
+                ),
+                (
+                    Bounds { start: 10, end: 20 },
+                    Value::array([Value::text("b"), Value::int(2)])
+                ),
+            ]
+        );
+        assert_eq!(
+            hidden.advance(20).unwrap(),
+            vec![
+                (
+                    Bounds { start: 0, end: 10 },
+                    Value::array([Value::text("a")])
+                ),
+                (
+                    Bounds { start: 10, end: 20 },
+                    Value::array([Value::text("b")])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_rows() {
+        let mut aggregator = aggregator(
+            "SELECT name, SUM(price) FROM $$source GROUP BY name HAVING COUNT(*) * 9223372036854775807 > 0",
+            Some(Window::Tumbling { size: 10 }),
+        );
+        for (t, name) in [(1, "a"), (2, "b"), (3, "b"), (12, "a")] {
+            aggregator.push(t, row(name, 1.0)).unwrap();
+        }
+
+        // the overflowing group is reported with its window, the later window keeps its bounds
+        assert_eq!(
+            aggregator.advance(20).unwrap(),
+            vec![
+                (
+                    Bounds { start: 0, end: 10 },
+                    Value::array([Value::text("a"), Value::float(1.0)])
+                ),
+                (
+                    Bounds { start: 10, end: 20 },
+                    Value::array([Value::text("a"), Value::float(1.0)])
+                ),
+            ]
+        );
+        let errors = aggregator.take_errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, Bounds { start: 0, end: 10 });
+        assert_eq!(errors[0].1.record, 1);
+        assert!(aggregator.take_errors().is_empty());
+    }
+
+    #[test]
+    fn sum_overflow() {
+        let mut algebra =
+            parse_sql("SELECT name, SUM(amount) FROM $$source GROUP BY name").unwrap();
+        algebra.set_schema(Schema::fixed([
+            ("name".to_string(), ValType::Text),
+            ("amount".to_string(), ValType::Integer),
+        ]));
+        let mut aggregator = Aggregator::new(&algebra, None).unwrap();
+        for (name, amount) in [("a", i64::MAX), ("a", 1), ("a", 1), ("b", 2)] {
+            let row = Value::array([Value::text(name), Value::int(amount)]);
+            aggregator.push(0, row).unwrap();
+        }
+
+        // the overflowing sum fails its group instead of starting over
+        assert_eq!(
+            aggregator.flush().unwrap(),
+            vec![(ALL, Value::array([Value::text("b"), Value::int(2)]))]
+        );
+        let errors = aggregator.take_errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].1.record, 0);
+        assert!(errors[0].1.message.contains("overflow"));
+    }
+
+    #[test]
+    fn late_records() {
+        let query = "SELECT name, COUNT(*) FROM $$source GROUP BY name";
+        let window = Some(Window::Tumbling { size: 10 });
+
+        let mut dropping = aggregator(query, window.clone());
+        assert!(dropping.push(1, row("a", 1.0)).unwrap());
+        assert_eq!(dropping.advance(10).unwrap().len(), 1);
+        assert!(!dropping.push(2, row("a", 1.0)).unwrap());
+        assert!(dropping.advance(20).unwrap().is_empty());
+
+        let mut updating = aggregator(query, window);
+        updating.set_lateness(Lateness::Update);
+        updating.push(1, row("a", 1.0)).unwrap();
+        updating.push(12, row("a", 1.0)).unwrap();
+        assert_eq!(
+            updating.advance(10).unwrap(),
+            vec![(
+                Bounds { start: 0, end: 10 },
+                Value::array([Value::text("a"), Value::int(1)])
+            )]
+        );
+
+        // the closed window is emitted again with the late records
+        assert!(updating.push(2, row("a", 1.0)).unwrap());
+        assert!(updating.push(3, row("b", 1.0)).unwrap());
+        assert_eq!(
+            updating.advance(10).unwrap(),
+            vec![
+                (
+                    Bounds { start: 0, end: 10 },
+                    Value::array([Value::text("a"), Value::int(2)])
+                ),
+                (
+                    Bounds { start: 0, end: 10 },
+                    Value::array([Value::text("b"), Value::int(1)])
+                ),
+            ]
+        );
+
+        // it is kept one more window size
+        assert_eq!(updating.advance(20).unwrap().len(), 1);
+        assert!(!updating.push(4, row("a", 1.0)).unwrap());
+        assert!(updating.advance(25).unwrap().is_empty());
+    }
+
+    #[test]
+    fn hopping() {
+        let mut aggregator = aggregator(
+            "SELECT COUNT(*) * 10 FROM $$source WHERE price > 1",
+            Some(Window::Hopping {
+                size: 20,
+                advance: 10,
+            }),
+        );
+        aggregator.push(5, row("a", 2.0)).unwrap();
+        aggregator.push(15, row("a", 2.0)).unwrap();
+        aggregator.push(16, row("a", 0.5)).unwrap();
+
+        let rows = aggregator.advance(30).unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (
+                    Bounds {
+                        start: -10,
+                        end: 10
+                    },
+                    Value::array([Value::int(10)])
+                ),
+                (Bounds { start: 0, end: 20 }, Value::array([Value::int(20)])),
+                (
+                    Bounds { start: 10, end: 30 },
+                    Value::array([Value::int(10)])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn sliding() {
+        let mut aggregator = aggregator(
+            "SELECT name, SUM(price) FROM $$source GROUP BY name",
+            Some(Window::Sliding { size: 10 }),
+        );
+        aggregator.push(1, row("a", 1.0)).unwrap();
+        aggregator.push(5, row("a", 2.0)).unwrap();
+        aggregator.push(12, row("a", 4.0)).unwrap();
+
+        let sums = aggregator
+            .advance(20)
+            .unwrap()
+            .into_iter()
+            .map(|(_, row)| row.as_array().unwrap().values[1].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sums,
+            vec![Value::float(1.0), Value::float(3.0), Value::float(6.0)]
+        );
+    }
+
+    #[test]
+    fn without_window() {
+        let mut aggregator = aggregator("SELECT COUNT(name), MAX(price) FROM $$source", None);
+        aggregator.push(1, row("a", 1.0)).unwrap();
+        aggregator
+            .push(2, Value::array([Value::null(), Value::float(5.0)]))
+            .unwrap();
+
+        let rows = aggregator.flush().unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].1, Value::array([Value::int(1), Value::float(5.0)]));
+        assert!(aggregator.flush().unwrap().is_empty());
+    }
+
+    #[test]
+    fn ungrouped_field() {
+        let mut algebra = parse_sql("SELECT name, COUNT(*) FROM $$source").unwrap();
+        algebra.set_schema(Schema::fixed([("name".to_string(), ValType::Text)]));
+        assert!(Aggregator::new(&algebra, None).is_err());
+    }
+
+    #[test]
+    fn collect() {
+        let schema = Schema::fixed([
+            ("name".to_string(), ValType::Text),
+            ("price".to_string(), ValType::Float),
+        ]);
+        let mut aggregator =
+            Aggregator::new(&Algebra::collect(Algebra::scan("$$source", schema)), None).unwrap();
+        aggregator.push(1, row("a", 1.0)).unwrap();
+        aggregator.push(2, row("b", 2.0)).unwrap();
+
+        let rows = aggregator.flush().unwrap();
+        assert_eq!(
+            rows[0].1,
+            Value::array([Value::array([row("a", 1.0), row("b", 2.0)])])
+        );
+        // each batch is collected on its own
+        aggregator.push(3, row("c", 3.0)).unwrap();
+        assert_eq!(
+            aggregator.flush().unwrap()[0].1,
+            Value::array([Value::array([row("c", 3.0)])])
+        );
+    }
+
+    #[test]
+    fn collect_documents() {
+        // documents compare in the order of their keys
+        let document = |name: &str, tags: Value| {
+            Value::Dict(Box::new(Dict::from(vec![
+                ("name", Value::text(name)),
+                ("tags", tags),
+            ])))
+        };
+        let unwound = Algebra::unwind(
+            Algebra::scan("$$source", Schema::Dynamic),
+            "tags",
+            Operator::Explode,
+        );
+        let mut aggregator = Aggregator::new(&Algebra::collect(unwound), None).unwrap();
+        aggregator
+            .push(
+                1,
+                document("a", Value::array([Value::text("x"), Value::text("y")])),
+            )
+            .unwrap();
+        aggregator
+            .push(2, document("b", Value::array(Vec::<Value>::new())))
+            .unwrap();
+
+        let rows = aggregator.flush().unwrap();
+        assert_eq!(
+            rows[0].1,
+            Value::array([Value::array([
+                document("a", Value::text("x")),
+                document("a", Value::text("y")),
+            ])])
+        );
+    }
+}
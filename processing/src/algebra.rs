@@ -1,19 +1,26 @@
 use crate::expression::Expression;
 use crate::language::Sql;
 use crate::operator::Operator;
+use crate::optimizer::optimize;
 use crate::tuple::program::Program;
+use anyhow::{anyhow, bail};
 use indexmap::IndexMap;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::cmp;
 use value::ValType;
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Algebra {
     Scan(Scan),
     Project(Project),
     Filter(Filter),
     Collect(Collect),
     Unwind(Unwind),
+    Aggregate(Aggregate),
+    Join(Join),
+    Sort(Sort),
+    Limit(Limit),
+    Distinct(Distinct),
     Todo(String),
 }
 
@@ -30,7 +37,13 @@ impl Algebra {
     pub fn scope(&self) -> Scope {
         match self {
             Algebra::Scan(_) | Algebra::Todo(_) => Scope::Tuple,
-            Algebra::Collect(_) | Algebra::Unwind(_) => Scope::Multi,
+            // every element becomes a record of its own
+            Algebra::Unwind(u) => u.input.scope(),
+            Algebra::Collect(_)
+            | Algebra::Aggregate(_)
+            | Algebra::Sort(_)
+            | Algebra::Limit(_)
+            | Algebra::Distinct(_) => Scope::Multi,
             Algebra::Project(p) => {
                 let expr_max = p
                     .expressions
@@ -41,6 +54,7 @@ impl Algebra {
                 cmp::max(p.input.scope(), expr_max)
             }
             Algebra::Filter(f) => cmp::max(f.input.scope(), f.predicate.scope()),
+            Algebra::Join(_) => Scope::Join,
         }
     }
 
@@ -56,10 +70,34 @@ impl Algebra {
             Algebra::Filter(f) => &mut f.input,
             Algebra::Collect(c) => &mut c.input,
             Algebra::Unwind(u) => &mut u.input,
+            Algebra::Aggregate(a) => &mut a.input,
+            Algebra::Sort(s) => &mut s.input,
+            Algebra::Limit(l) => &mut l.input,
+            Algebra::Distinct(d) => &mut d.input,
+            // the left side is the stream of the definition itself
+            Algebra::Join(j) => &mut j.left,
             Algebra::Todo(_) => return,
         };
         input.set_schema(s);
     }
+
+    /// Sets the schema of the right side of the join, which reads the records of another
+    /// definition.
+    pub fn set_joined_schema(&mut self, s: Schema) {
+        let input = match self {
+            Algebra::Join(j) => return j.right.set_schema(s),
+            Algebra::Project(p) => &mut p.input,
+            Algebra::Filter(f) => &mut f.input,
+            Algebra::Collect(c) => &mut c.input,
+            Algebra::Unwind(u) => &mut u.input,
+            Algebra::Aggregate(a) => &mut a.input,
+            Algebra::Sort(s) => &mut s.input,
+            Algebra::Limit(l) => &mut l.input,
+            Algebra::Distinct(d) => &mut d.input,
+            Algebra::Scan(_) | Algebra::Todo(_) => return,
+        };
+        input.set_joined_schema(s);
+    }
 }
 
 impl Algebra {
@@ -99,16 +137,124 @@ impl Algebra {
         })
     }
 
-    pub fn schema(&self) -> Schema {
-        Schema::Fixed(IndexMap::from([("price".to_string(), ValType::Float)]))
+    #[cfg(test)]
+    pub(crate) fn collect(child: Algebra) -> Self {
+        Algebra::Collect(Collect {
+            input: Box::new(child),
+        })
+    }
+
+    /// The schema of the records this algebra produces, typed against the schema of its scans.
+    pub fn schema(&self) -> anyhow::Result<Schema> {
+        match self {
+            Algebra::Scan(s) => Ok(s.schema.clone()),
+            Algebra::Todo(_) => Ok(Schema::Dynamic),
+            Algebra::Filter(f) => {
+                let schema = f.input.schema()?;
+                predicate(&f.predicate, &schema)?;
+                Ok(schema)
+            }
+            Algebra::Project(p) => {
+                let input = p.input.schema()?;
+                let expressions = p.resolve(&input);
+                // fields are only added to a document whose fields are unknown
+                if expressions.values().any(|e| e == &Expression::Wildcard) {
+                    return Ok(Schema::Dynamic);
+                }
+                Ok(Schema::Fixed(typed(&expressions, &input)?))
+            }
+            Algebra::Aggregate(a) => {
+                let input = a.input.schema()?;
+                for key in &a.keys {
+                    key.val_type(&input)?;
+                }
+                Ok(Schema::Fixed(typed(&a.expressions, &input)?))
+            }
+            Algebra::Unwind(u) => {
+                let Schema::Fixed(mut fields) = u.input.schema()? else {
+                    return Ok(Schema::Dynamic);
+                };
+                match fields.get(&u.key) {
+                    Some(ValType::Array | ValType::Any) => {}
+                    Some(t) => bail!("Cannot unwind {} of type {}", u.key, t.dump("")),
+                    None => bail!("Unknown field {}", u.key),
+                }
+                // the elements of the array are not typed
+                fields.insert(u.key.clone(), ValType::Any);
+                Ok(Schema::Fixed(fields))
+            }
+            Algebra::Sort(s) => {
+                let input = s.input.schema()?;
+                for key in &s.keys {
+                    key.expression.val_type(&input)?;
+                }
+                Ok(input)
+            }
+            Algebra::Limit(l) => l.input.schema(),
+            Algebra::Distinct(d) => d.input.schema(),
+            Algebra::Collect(c) => {
+                c.input.schema()?;
+                Ok(Schema::fixed([("values".to_string(), ValType::Array)]))
+            }
+            Algebra::Join(j) => {
+                let schema = match (j.left.schema()?, j.right.schema()?) {
+                    (Schema::Fixed(left), Schema::Fixed(right)) => {
+                        let qualify = |alias: &str, fields: IndexMap<String, ValType>| {
+                            fields
+                                .into_iter()
+                                .map(|(n, t)| (format!("{}.{}", alias, n), t))
+                                .collect::<Vec<_>>()
+                        };
+                        let mut fields = IndexMap::new();
+                        fields.extend(qualify(&j.left_alias, left));
+                        fields.extend(qualify(&j.right_alias, right));
+                        Schema::Fixed(fields)
+                    }
+                    _ => Schema::Dynamic,
+                };
+                predicate(&j.on, &schema)?;
+                Ok(schema)
+            }
+        }
+    }
+
+    pub fn processing(&self) -> anyhow::Result<Program> {
+        Program::try_from(&self.optimized())
+    }
+
+    /// An equivalent algebra which is cheaper to evaluate.
+    pub fn optimized(&self) -> Algebra {
+        optimize(self.clone())
+    }
+
+    /// The aggregation below the projections and filters of `HAVING`, if there is one.
+    pub(crate) fn aggregation(&self) -> Option<&Aggregate> {
+        match self {
+            Algebra::Aggregate(a) => Some(a),
+            Algebra::Project(p) => p.input.aggregation(),
+            Algebra::Filter(f) => f.input.aggregation(),
+            _ => None,
+        }
     }
 
-    pub fn processing(&self) -> Program {
-        Program::from(self)
+    /// The join below the other operators, if there is one.
+    pub fn join(&self) -> Option<&Join> {
+        match self {
+            Algebra::Join(j) => Some(j),
+            Algebra::Project(p) => p.input.join(),
+            Algebra::Filter(f) => f.input.join(),
+            Algebra::Collect(c) => c.input.join(),
+            Algebra::Unwind(u) => u.input.join(),
+            Algebra::Aggregate(a) => a.input.join(),
+            Algebra::Sort(s) => s.input.join(),
+            Algebra::Limit(l) => l.input.join(),
+            Algebra::Distinct(d) => d.input.join(),
+            Algebra::Scan(_) | Algebra::Todo(_) => None,
+        }
     }
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Schema {
     Dynamic,
     Fixed(IndexMap<String, ValType>),
@@ -122,7 +268,24 @@ impl Schema {
     pub fn len(&self) -> usize {
         match self {
             Schema::Dynamic => 1,
-            Schema::Fixed(f) => f.len()
+            Schema::Fixed(f) => f.len(),
+        }
+    }
+
+    /// The type of a field, which may also be referenced without the qualifier of a join.
+    pub fn field_type(&self, name: &str) -> anyhow::Result<ValType> {
+        let Schema::Fixed(fields) = self else {
+            return Ok(ValType::Any);
+        };
+        if let Some(t) = fields.get(name) {
+            return Ok(t.clone());
+        }
+        let suffix = format!(".{}", name);
+        let mut matches = fields.iter().filter(|(n, _)| n.ends_with(&suffix));
+        match (matches.next(), matches.next()) {
+            (Some((_, t)), None) => Ok(t.clone()),
+            (Some(_), Some(_)) => bail!("Field {} is ambiguous", name),
+            (None, _) => Err(anyhow!("Unknown field {}", name)),
         }
     }
 
@@ -134,74 +297,316 @@ impl Schema {
     }
 }
 
-impl Sql for Algebra {
-    fn sql(&self) -> String {
-        match self {
-            Algebra::Scan(s) => format!("FROM {}", s.source),
-            Algebra::Project(p) => p.sql(),
-            Algebra::Filter(f) => f.sql(),
-            Algebra::Todo(_) => panic!(),
-            Algebra::Collect(_) => panic!(),
-            Algebra::Unwind(_) => panic!(),
+/// Types the named expressions of a projection, excluded fields are left out.
+fn typed(
+    expressions: &IndexMap<String, Expression>,
+    input: &Schema,
+) -> anyhow::Result<IndexMap<String, ValType>> {
+    let mut fields = IndexMap::new();
+    for (name, expression) in expressions {
+        if let Expression::Exclude(_) = expression {
+            continue;
         }
+        fields.insert(name.clone(), expression.val_type(input)?);
+    }
+    Ok(fields)
+}
+
+fn predicate(predicate: &Expression, input: &Schema) -> anyhow::Result<()> {
+    match predicate.val_type(input)? {
+        ValType::Bool | ValType::Any | ValType::Null => Ok(()),
+        t => bail!(
+            "{} is no predicate, it has type {}",
+            predicate.sql(),
+            t.dump("")
+        ),
     }
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Scan {
     pub source: String,
     pub schema: Schema,
 }
 
-#[derive(Clone, Debug, Serialize)]
+/// Gathers all records of the input into a single array per window.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Collect {
-    input: Box<Algebra>,
+    pub(crate) input: Box<Algebra>,
 }
 
-#[derive(Clone, Debug, Serialize)]
+impl Collect {
+    /// The aggregation without groups which collects the whole records.
+    pub(crate) fn aggregate(&self) -> Aggregate {
+        Aggregate {
+            keys: vec![],
+            expressions: IndexMap::from([(
+                "values".to_string(),
+                Expression::call(Operator::Collect, vec![Expression::Wildcard]),
+            )]),
+            input: self.input.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Unwind {
     pub(crate) input: Box<Algebra>,
     pub(crate) key: String,
     pub(crate) func: Operator,
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Project {
     pub expressions: IndexMap<String, Expression>,
     pub input: Box<Algebra>,
 }
 
-impl Sql for Project {
-    fn sql(&self) -> String {
-        let select = format!(
-            "SELECT {}",
-            self.expressions
-                .iter()
-                .map(|(_, e)| e.sql())
-                .collect::<Vec<_>>()
-                .join(", ")
-        );
-        let child = self.input.sql();
-        format!("{} {}", select, child)
+impl Project {
+    /// Replaces the wildcard with the fields of a fixed input and drops the excluded fields.
+    pub(crate) fn resolve(&self, input: &Schema) -> IndexMap<String, Expression> {
+        let Schema::Fixed(fields) = input else {
+            return self.expressions.clone();
+        };
+        let mut resolved = IndexMap::new();
+        for (name, expression) in &self.expressions {
+            match expression {
+                Expression::Wildcard => resolved.extend(
+                    fields
+                        .keys()
+                        .map(|k| (k.clone(), Expression::Field(k.clone()))),
+                ),
+                Expression::Exclude(field) => {
+                    resolved.shift_remove(field);
+                }
+                e => {
+                    resolved.insert(name.clone(), e.clone());
+                }
+            }
+        }
+        resolved
     }
 }
 
-#[derive(Clone, Debug, Serialize)]
-pub struct Filter {
-    pub predicate: Expression,
+/// Groups the input by its keys and evaluates the expressions, which may contain
+/// aggregate calls, once per group.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Aggregate {
+    pub keys: Vec<Expression>,
+    pub expressions: IndexMap<String, Expression>,
     pub input: Box<Algebra>,
 }
 
-impl Sql for Filter {
-    fn sql(&self) -> String {
-        format!(" WHERE {}", self.predicate.sql())
+impl Aggregate {
+    /// All aggregate calls of the expressions, in order of appearance.
+    pub(crate) fn calls(&self) -> Vec<&Expression> {
+        self.expressions
+            .values()
+            .flat_map(|e| e.aggregates())
+            .collect()
     }
 }
 
+/// Combines the records of the left input with the matching ones of the right input.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Join {
+    pub left: Box<Algebra>,
+    pub right: Box<Algebra>,
+    /// qualifiers of the fields of each side, e.g. `a` in `a.id`
+    pub left_alias: String,
+    pub right_alias: String,
+    pub on: Expression,
+}
+
+impl Join {
+    /// The source the right side reads from, which names another definition.
+    pub fn right_source(&self) -> Option<&str> {
+        match self.right.as_ref() {
+            Algebra::Scan(s) => Some(&s.source),
+            _ => None,
+        }
+    }
+}
+
+/// Orders the records of the input, evaluated per window like an aggregation.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Sort {
+    pub keys: Vec<SortKey>,
+    pub input: Box<Algebra>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SortKey {
+    pub expression: Expression,
+    pub descending: bool,
+}
+
+/// Keeps the first records of the input.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Limit {
+    pub limit: usize,
+    pub input: Box<Algebra>,
+}
+
+/// Keeps the first of equal records, evaluated per window like an aggregation.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Distinct {
+    pub input: Box<Algebra>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Filter {
+    pub predicate: Expression,
+    pub input: Box<Algebra>,
+}
+
 #[cfg(test)]
 mod test {
+    use crate::expression::Expression;
     use crate::language::{Sql, parse_sql};
+    use crate::{Algebra, Schema};
+    use indexmap::IndexMap;
     use tracing::debug;
+    use value::ValType;
+
+    fn schema(query: &str) -> anyhow::Result<Schema> {
+        let mut algebra = parse_sql(query).unwrap();
+        algebra.set_schema(Schema::fixed([
+            ("name".to_string(), ValType::Text),
+            ("age".to_string(), ValType::Integer),
+            ("price".to_string(), ValType::Float),
+        ]));
+        algebra.schema()
+    }
+
+    fn fields(schema: Schema) -> IndexMap<String, ValType> {
+        match schema {
+            Schema::Fixed(fields) => fields,
+            Schema::Dynamic => panic!("expected a fixed schema"),
+        }
+    }
+
+    #[test]
+    fn infer_project() {
+        let fields = fields(
+            schema("SELECT name, age * 2, age * price AS total, age > 3 FROM $$source").unwrap(),
+        );
+        assert_eq!(
+            fields.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("field0".to_string(), ValType::Text),
+                ("field1".to_string(), ValType::Integer),
+                ("total".to_string(), ValType::Float),
+                ("field3".to_string(), ValType::Bool),
+            ]
+        );
+
+        let schema = schema("SELECT * FROM $$source WHERE age > 3").unwrap();
+        assert_eq!(schema.len(), 3);
+    }
+
+    #[test]
+    fn infer_aggregate() {
+        let fields = fields(
+            schema(
+                "SELECT name, COUNT(*), SUM(age), AVG(age), MAX(price) FROM $$source GROUP BY name",
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            fields.into_values().collect::<Vec<_>>(),
+            vec![
+                ValType::Text,
+                ValType::Integer,
+                ValType::Integer,
+                ValType::Float,
+                ValType::Float,
+            ]
+        );
+    }
+
+    #[test]
+    fn order_limit_distinct() {
+        let algebra = parse_sql(
+            "SELECT DISTINCT name, price FROM $$source ORDER BY price DESC, name LIMIT 10",
+        )
+        .unwrap();
+        let Algebra::Limit(limit) = algebra else {
+            panic!("expected a limit");
+        };
+        assert_eq!(limit.limit, 10);
+        let Algebra::Sort(sort) = *limit.input else {
+            panic!("expected a sort");
+        };
+        assert_eq!(
+            sort.keys
+                .iter()
+                .map(|k| (k.expression.clone(), k.descending))
+                .collect::<Vec<_>>(),
+            vec![
+                (Expression::field("field1"), true),
+                (Expression::field("field0"), false)
+            ]
+        );
+        assert!(matches!(*sort.input, Algebra::Distinct(_)));
+
+        let fields = fields(schema("SELECT * FROM $$source ORDER BY age LIMIT 1").unwrap());
+        assert_eq!(fields.len(), 3);
+        // keys which are not selected cannot be read
+        assert!(schema("SELECT name FROM $$source ORDER BY age").is_err());
+
+        assert!(parse_sql("SELECT name FROM $$source LIMIT 1 OFFSET 2").is_err());
+        assert!(parse_sql("SELECT name FROM $$source LIMIT -1").is_err());
+        assert!(parse_sql("SELECT DISTINCT ON (name) name FROM $$source").is_err());
+        assert!(parse_sql("SELECT name FROM $$source HAVING name = 'a'").is_err());
+    }
+
+    #[test]
+    fn type_errors() {
+        assert!(schema("SELECT name + age FROM $$source").is_err());
+        assert!(schema("SELECT name FROM $$source WHERE age + 1").is_err());
+        assert!(schema("SELECT unknown FROM $$source").is_err());
+        assert!(schema("SELECT SUM(name) FROM $$source").is_err());
+        assert!(schema("SELECT name + name FROM $$source").is_ok());
+    }
+
+    #[test]
+    fn infer_join() {
+        let mut algebra =
+            parse_sql("SELECT s.name, d.location FROM $$source s JOIN $$devices d ON s.age = d.id")
+                .unwrap();
+        algebra.set_schema(Schema::fixed([
+            ("name".to_string(), ValType::Text),
+            ("age".to_string(), ValType::Integer),
+        ]));
+        let join = |algebra: &Algebra| Algebra::Join(algebra.join().unwrap().clone()).schema();
+        // the joined definition is unknown
+        assert!(matches!(join(&algebra).unwrap(), Schema::Dynamic));
+
+        algebra.set_joined_schema(Schema::fixed([
+            ("id".to_string(), ValType::Integer),
+            ("location".to_string(), ValType::Text),
+        ]));
+        assert_eq!(fields(join(&algebra).unwrap()).len(), 4);
+        assert_eq!(
+            fields(algebra.schema().unwrap())
+                .into_values()
+                .collect::<Vec<_>>(),
+            vec![ValType::Text, ValType::Text]
+        );
+
+        // the join is typed below an aggregation as well
+        let mut algebra = parse_sql(
+            "SELECT d.location, COUNT(*) FROM $$source s JOIN $$devices d ON s.age = d.id GROUP BY d.location",
+        )
+        .unwrap();
+        algebra.set_joined_schema(Schema::fixed([
+            ("id".to_string(), ValType::Integer),
+            ("location".to_string(), ValType::Text),
+        ]));
+        let right = algebra.join().unwrap().right.schema().unwrap();
+        assert_eq!(fields(right).len(), 2);
+    }
 
     #[test]
     // SELECT Istream(auction, DOLTOEUR(price), bidder, datetime) FROM bid [ROWS UNBOUNDED]
@@ -209,7 +614,7 @@ mod test {
     fn nexmark_q1_sql() {
         let q1_sql = "SELECT auction, price * 1.1, bidder, datetime FROM $$source";
 
-        let algebra = parse_sql(q1_sql);
+        let algebra = parse_sql(q1_sql).unwrap();
 
         let sql = algebra.sql();
         debug!("{:?}", sql);
@@ -235,7 +640,7 @@ mod test {
     fn nexmark_q2_sql() {
         let q1_sql = "SELECT auction, price * 1.1, bidder, datetime FROM $$source";
 
-        let algebra = parse_sql(q1_sql);
+        let algebra = parse_sql(q1_sql).unwrap();
 
         let sql = algebra.sql();
         debug!("{:?}", sql);
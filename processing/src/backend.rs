@@ -0,0 +1,58 @@
+use crate::{Algebra, JitProgram, Program, RecordError};
+use value::Value;
+
+/// Evaluates tuple-scope algebra, natively where the fixed schema allows it and with the tuple VM
+/// otherwise.
+#[derive(Clone)]
+pub enum Backend {
+    Vm(Program),
+    Jit(JitProgram),
+}
+
+impl Backend {
+    /// Compiles the optimized algebra with the fastest backend which supports it.
+    pub fn new(algebra: &Algebra) -> anyhow::Result<Self> {
+        let optimized = algebra.optimized();
+        Ok(match JitProgram::new(&optimized) {
+            Some(jit) => Backend::Jit(jit),
+            None => Backend::Vm(Program::try_from(&optimized)?),
+        })
+    }
+
+    pub fn set_resource<S: AsRef<str>>(
+        &mut self,
+        name: S,
+        iter: impl Iterator<Item = Value> + Send + Sync + 'static,
+    ) -> anyhow::Result<()> {
+        match self {
+            Backend::Vm(program) => program.set_resource(name, iter),
+            Backend::Jit(program) => program.set_resource(name, iter),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        match self {
+            Backend::Vm(program) => program.reset(),
+            Backend::Jit(program) => program.reset(),
+        }
+    }
+
+    /// The records which failed since the last call.
+    pub fn take_errors(&mut self) -> Vec<RecordError> {
+        match self {
+            Backend::Vm(program) => program.take_errors(),
+            Backend::Jit(program) => program.take_errors(),
+        }
+    }
+}
+
+impl Iterator for Backend {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Backend::Vm(program) => program.next(),
+            Backend::Jit(program) => program.next(),
+        }
+    }
+}
@@ -0,0 +1,645 @@
+use crate::aggregate::Aggregator;
+use crate::time::Lateness;
+use crate::tuple::program::compare;
+use crate::window::{Bounds, Window};
+use crate::{Algebra, Program, Project, RecordError, Scan, Scope};
+use anyhow::bail;
+use indexmap::{IndexMap, IndexSet};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::iter;
+use value::Value;
+
+const ROWS: &str = "$$rows";
+
+/// Bounds of the single window used when a definition has no window clause.
+const ALL: Bounds = Bounds {
+    start: i64::MIN,
+    end: i64::MAX,
+};
+
+/// How much state `ORDER BY`, `LIMIT` and `DISTINCT` may keep.
+///
+/// Written like `state = { size = 1000, within = "10 MINUTES" }`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateLimit {
+    /// records kept per window, further ones are dropped
+    #[serde(default = "default_size")]
+    pub size: usize,
+    /// how long `DISTINCT` without a window remembers a record in ms, without it records are
+    /// only forgotten once the state size is reached
+    #[serde(default, with = "within")]
+    pub within: Option<i64>,
+}
+
+fn default_size() -> usize {
+    10_000
+}
+
+impl Default for StateLimit {
+    fn default() -> Self {
+        StateLimit {
+            size: default_size(),
+            within: None,
+        }
+    }
+}
+
+/// An operator of the query above its aggregation or tuple part.
+enum Op {
+    Distinct,
+    Sort {
+        /// computes the sort keys of a row
+        keys: Box<Program>,
+        descending: Vec<bool>,
+    },
+    Limit(usize),
+}
+
+enum Inner {
+    Aggregated(Box<Aggregator>),
+    Rows {
+        program: Box<Program>,
+        windows: BTreeMap<Bounds, Vec<Value>>,
+    },
+}
+
+/// Evaluates a Multi-scope query per window with bounded state.
+///
+/// `ORDER BY`, `LIMIT` and `DISTINCT` on top of the query are applied to the rows of each closed
+/// window, the rows below come from an [`Aggregator`] or from the tuple VM. Rows of open windows
+/// are reduced while they arrive, a top-n only keeps n rows, so sorting them needs a `LIMIT`
+/// within the state size. Without a window they apply to each batch, while `DISTINCT` remembers
+/// the records it saw for the configured time.
+pub struct Bounded {
+    window: Option<Window>,
+    state: StateLimit,
+    /// applied bottom-up
+    ops: Vec<Op>,
+    inner: Inner,
+    watermark: i64,
+    /// the latest timestamp pushed
+    latest: i64,
+    /// records `DISTINCT` without a window emitted, with their timestamp
+    seen: IndexMap<Value, i64>,
+    /// rows dropped beyond the state size per open window
+    dropped: BTreeMap<Bounds, usize>,
+    /// closed windows which missed rows, with how many
+    truncated: Vec<(Bounds, usize)>,
+}
+
+impl Bounded {
+    pub fn new(
+        algebra: &Algebra,
+        window: Option<Window>,
+        state: StateLimit,
+    ) -> anyhow::Result<Self> {
+        let mut ops = vec![];
+        let mut current = algebra;
+        let algebra = loop {
+            current = match current {
+                Algebra::Distinct(d) => {
+                    ops.push(Op::Distinct);
+                    &d.input
+                }
+                Algebra::Sort(s) => {
+                    let expressions = s
+                        .keys
+                        .iter()
+                        .enumerate()
+                        .map(|(i, k)| (format!("$sort{}", i), k.expression.clone()))
+                        .collect();
+                    let keys = Box::new(Program::try_from(&Algebra::Project(Project {
+                        expressions,
+                        input: Box::new(Algebra::Scan(Scan {
+                            source: ROWS.to_string(),
+                            schema: s.input.schema()?,
+                        })),
+                    }))?);
+                    let descending = s.keys.iter().map(|k| k.descending).collect();
+                    ops.push(Op::Sort { keys, descending });
+                    &s.input
+                }
+                Algebra::Limit(l) => {
+                    ops.push(Op::Limit(l.limit));
+                    &l.input
+                }
+                algebra => break algebra,
+            };
+        };
+        ops.reverse();
+
+        let inner = match algebra {
+            algebra
+                if algebra.aggregation().is_some() || matches!(algebra, Algebra::Collect(_)) =>
+            {
+                Inner::Aggregated(Box::new(Aggregator::new(algebra, window.clone())?))
+            }
+            _ if ops.is_empty() => bail!("Expected an aggregation as last operator"),
+            _ if matches!(window, Some(Window::Sliding { .. })) => {
+                bail!("Sliding windows are only supported for aggregations")
+            }
+            algebra => {
+                if algebra.scope() != Scope::Tuple {
+                    bail!("ORDER BY, LIMIT and DISTINCT have to follow the other operators");
+                }
+                // the rows of a window are reduced to the state size while they arrive
+                for (i, op) in ops.iter().enumerate() {
+                    match (op, ops.get(i + 1)) {
+                        (Op::Sort { .. }, Some(Op::Limit(_))) => {}
+                        (Op::Sort { .. }, _) => bail!("ORDER BY of records needs a LIMIT"),
+                        (Op::Limit(limit), _) if *limit > state.size => {
+                            bail!("LIMIT {} exceeds the state size of {}", limit, state.size)
+                        }
+                        _ => {}
+                    }
+                }
+                Inner::Rows {
+                    program: Box::new(algebra.processing()?),
+                    windows: BTreeMap::new(),
+                }
+            }
+        };
+
+        Ok(Bounded {
+            window,
+            state,
+            ops,
+            inner,
+            watermark: i64::MIN,
+            latest: i64::MIN,
+            seen: IndexMap::new(),
+            dropped: BTreeMap::new(),
+            truncated: vec![],
+        })
+    }
+
+    /// Forwarded to the aggregation, rows of closed windows are never updated.
+    pub fn set_lateness(&mut self, lateness: Lateness) -> anyhow::Result<()> {
+        match &mut self.inner {
+            Inner::Aggregated(aggregator) => aggregator.set_lateness(lateness),
+            Inner::Rows { .. } if lateness == Lateness::Update => {
+                bail!("Late records only update aggregations, not the rows of windows")
+            }
+            Inner::Rows { .. } => {}
+        }
+        Ok(())
+    }
+
+    /// Adds a record with the given timestamp to all windows it belongs to.
+    /// Returns false for late records, which only belong to closed windows.
+    pub fn push(&mut self, timestamp: i64, value: Value) -> anyhow::Result<bool> {
+        self.latest = self.latest.max(timestamp);
+        let (program, windows) = match &mut self.inner {
+            Inner::Aggregated(aggregator) => return aggregator.push(timestamp, value),
+            Inner::Rows { program, windows } => (program, windows),
+        };
+
+        let bounds = match &self.window {
+            Some(window) => window
+                .assign(timestamp)
+                .into_iter()
+                .filter(|b| b.end > self.watermark)
+                .collect(),
+            None => vec![ALL],
+        };
+        if bounds.is_empty() {
+            return Ok(false);
+        }
+
+        program.reset();
+        program.set_resource("$$source", iter::once(value))?;
+        let produced = program.by_ref().collect::<Vec<_>>();
+        for bounds in bounds {
+            let rows = windows.entry(bounds).or_default();
+            rows.extend(produced.iter().cloned());
+            if rows.len() > self.state.size {
+                let dropped = Self::reduce(&mut self.ops, rows, self.state.size)?;
+                if dropped > 0 {
+                    *self.dropped.entry(bounds).or_default() += dropped;
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Applies the operators which give the same result on part of the rows as on all of them,
+    /// then drops the rows beyond the state size and returns how many.
+    fn reduce(ops: &mut [Op], rows: &mut Vec<Value>, size: usize) -> anyhow::Result<usize> {
+        let (distinct, rest) = match ops {
+            [Op::Distinct, rest @ ..] => (true, rest),
+            rest => (false, rest),
+        };
+        if distinct {
+            *rows = dedup(std::mem::take(rows));
+        }
+        match rest {
+            [Op::Sort { keys, descending }, Op::Limit(limit), ..] => {
+                *rows = sort(keys, descending, std::mem::take(rows))?;
+                rows.truncate(*limit);
+            }
+            [Op::Limit(limit), ..] => rows.truncate(*limit),
+            _ => {}
+        }
+
+        let dropped = rows.len().saturating_sub(size);
+        rows.truncate(size);
+        Ok(dropped)
+    }
+
+    /// The closed windows which missed rows beyond the state size, e.g. of `DISTINCT` with more
+    /// distinct records than the state keeps.
+    pub fn take_truncated(&mut self) -> Vec<(Bounds, usize)> {
+        std::mem::take(&mut self.truncated)
+    }
+
+    /// The aggregated rows of the closed windows which failed evaluation and were dropped.
+    pub fn take_errors(&mut self) -> Vec<(Bounds, RecordError)> {
+        match &mut self.inner {
+            Inner::Aggregated(aggregator) => aggregator.take_errors(),
+            Inner::Rows { .. } => vec![],
+        }
+    }
+
+    /// Closes all windows which end at or before the watermark and returns their rows.
+    pub fn advance(&mut self, watermark: i64) -> anyhow::Result<Vec<(Bounds, Value)>> {
+        self.watermark = self.watermark.max(watermark);
+        let windows = match &mut self.inner {
+            Inner::Aggregated(aggregator) => group(aggregator.advance(watermark)?),
+            Inner::Rows { windows, .. } => {
+                let closed = windows
+                    .keys()
+                    .filter(|b| b.end <= self.watermark)
+                    .copied()
+                    .collect::<Vec<_>>();
+                closed
+                    .into_iter()
+                    .filter_map(|b| Some((b, windows.remove(&b)?)))
+                    .collect()
+            }
+        };
+        self.emit(windows)
+    }
+
+    /// Closes all open windows.
+    pub fn flush(&mut self) -> anyhow::Result<Vec<(Bounds, Value)>> {
+        let windows = match &mut self.inner {
+            Inner::Aggregated(aggregator) => group(aggregator.flush()?),
+            Inner::Rows { windows, .. } => std::mem::take(windows).into_iter().collect(),
+        };
+        self.emit(windows)
+    }
+
+    fn emit(&mut self, windows: Vec<(Bounds, Vec<Value>)>) -> anyhow::Result<Vec<(Bounds, Value)>> {
+        let mut emitted = vec![];
+        for (bounds, mut rows) in windows {
+            if let Some(dropped) = self.dropped.remove(&bounds) {
+                self.truncated.push((bounds, dropped));
+            }
+            for op in &mut self.ops {
+                rows = match op {
+                    Op::Distinct if self.window.is_none() => {
+                        unseen(&mut self.seen, &self.state, self.latest, rows)
+                    }
+                    Op::Distinct => dedup(rows),
+                    Op::Sort { keys, descending } => sort(keys, descending, rows)?,
+                    Op::Limit(limit) => rows.into_iter().take(*limit).collect(),
+                };
+            }
+            emitted.extend(rows.into_iter().map(|row| (bounds, row)));
+        }
+        Ok(emitted)
+    }
+}
+
+/// The rows which were not emitted within the configured time, the remembered ones are capped
+/// by the state size.
+fn unseen(
+    seen: &mut IndexMap<Value, i64>,
+    state: &StateLimit,
+    latest: i64,
+    rows: Vec<Value>,
+) -> Vec<Value> {
+    if let Some(within) = state.within {
+        let oldest = latest.saturating_sub(within);
+        seen.retain(|_, t| *t > oldest);
+    }
+    let mut unseen = vec![];
+    for row in rows {
+        if !seen.contains_key(&row) {
+            seen.insert(row.clone(), latest);
+            unseen.push(row);
+        }
+    }
+    let excess = seen.len().saturating_sub(state.size);
+    seen.drain(..excess);
+    unseen
+}
+
+/// The rows of each window, aggregated rows arrive window by window.
+fn group(rows: Vec<(Bounds, Value)>) -> Vec<(Bounds, Vec<Value>)> {
+    let mut windows: IndexMap<Bounds, Vec<Value>> = IndexMap::new();
+    for (bounds, row) in rows {
+        windows.entry(bounds).or_default().push(row);
+    }
+    windows.into_iter().collect()
+}
+
+/// Keeps the first of equal rows.
+fn dedup(rows: Vec<Value>) -> Vec<Value> {
+    rows.into_iter()
+        .collect::<IndexSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// Sorts stable by the keys, rows whose keys cannot be compared keep their order.
+fn sort(keys: &mut Program, descending: &[bool], rows: Vec<Value>) -> anyhow::Result<Vec<Value>> {
+    if rows.len() < 2 {
+        return Ok(rows);
+    }
+    keys.reset();
+    keys.set_resource(ROWS, rows.clone().into_iter())?;
+    let keys = keys
+        .by_ref()
+        .map(|k| match k {
+            Value::Array(a) => a.values,
+            Value::Dict(d) => (0..descending.len())
+                .map(|i| {
+                    d.get(format!("$sort{}", i))
+                        .cloned()
+                        .unwrap_or(Value::null())
+                })
+                .collect(),
+            k => vec![k],
+        })
+        .collect::<Vec<_>>();
+    if keys.len() != rows.len() {
+        bail!("Could not compute the sort keys of all rows");
+    }
+    let mut keyed = keys.into_iter().zip(rows).collect::<Vec<_>>();
+
+    keyed.sort_by(|(a, _), (b, _)| {
+        for ((a, b), descending) in a.iter().zip(b).zip(descending) {
+            let ordering = compare(a, b).unwrap_or(Ordering::Equal);
+            let ordering = if *descending {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    });
+    Ok(keyed.into_iter().map(|(_, row)| row).collect())
+}
+
+/// The remembered time is written like a window duration, e.g. `10 MINUTES`.
+mod within {
+    use super::*;
+    use crate::time::duration;
+
+    pub fn serialize<S: Serializer>(
+        within: &Option<i64>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match within {
+            Some(within) => duration::serialize(within, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<i64>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(within) => duration::parse(&within)
+                .map(Some)
+                .map_err(serde::de::Error::custom),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Schema, parse_sql};
+    use value::ValType;
+
+    fn bounded(query: &str, window: Option<Window>, state: StateLimit) -> anyhow::Result<Bounded> {
+        let mut algebra = parse_sql(query).unwrap();
+        algebra.set_schema(Schema::fixed([
+            ("device".to_string(), ValType::Text),
+            ("reading".to_string(), ValType::Float),
+        ]));
+        Bounded::new(&algebra, window, state)
+    }
+
+    fn row(device: &str, reading: f64) -> Value {
+        Value::array([Value::text(device), Value::float(reading)])
+    }
+
+    fn devices(rows: Vec<(Bounds, Value)>) -> Vec<(i64, String)> {
+        rows.into_iter()
+            .map(|(b, row)| {
+                let row = row.as_array().unwrap();
+                (b.end, row.values[0].as_text().unwrap().0.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn top_n() {
+        let mut top = bounded(
+            "SELECT device, reading FROM $$source ORDER BY reading DESC LIMIT 2",
+            Some(Window::Tumbling { size: 10 }),
+            StateLimit {
+                size: 2,
+                within: None,
+            },
+        )
+        .unwrap();
+        for (t, device, reading) in [
+            (1, "a", 1.0),
+            (2, "b", 5.0),
+            (3, "c", 3.0),
+            (4, "d", 4.0),
+            (12, "e", 0.5),
+        ] {
+            assert!(top.push(t, row(device, reading)).unwrap());
+        }
+
+        assert_eq!(
+            devices(top.advance(10).unwrap()),
+            vec![(10, "b".to_string()), (10, "d".to_string())]
+        );
+        assert!(!top.push(5, row("f", 9.0)).unwrap());
+        assert_eq!(
+            devices(top.advance(20).unwrap()),
+            vec![(20, "e".to_string())]
+        );
+    }
+
+    #[test]
+    fn top_n_groups() {
+        let mut top = bounded(
+            "SELECT device, MAX(reading) AS highest FROM $$source GROUP BY device ORDER BY highest DESC LIMIT 1",
+            Some(Window::Tumbling { size: 10 }),
+            StateLimit::default(),
+        )
+        .unwrap();
+        for (device, reading) in [("a", 3.0), ("b", 2.0), ("a", 1.0), ("c", 2.5)] {
+            top.push(1, row(device, reading)).unwrap();
+        }
+
+        let rows = top.advance(10).unwrap();
+        assert_eq!(
+            rows.into_iter().map(|(_, row)| row).collect::<Vec<_>>(),
+            vec![row("a", 3.0)]
+        );
+    }
+
+    #[test]
+    fn distinct() {
+        let mut distinct = bounded(
+            "SELECT DISTINCT device FROM $$source",
+            Some(Window::Tumbling { size: 10 }),
+            StateLimit::default(),
+        )
+        .unwrap();
+        for (t, device) in [(1, "a"), (2, "b"), (3, "a"), (11, "a")] {
+            distinct.push(t, row(device, 0.0)).unwrap();
+        }
+        assert_eq!(
+            devices(distinct.flush().unwrap()),
+            vec![
+                (10, "a".to_string()),
+                (10, "b".to_string()),
+                (20, "a".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn distinct_within() {
+        let mut distinct = bounded(
+            "SELECT DISTINCT device FROM $$source",
+            None,
+            StateLimit {
+                size: 10,
+                within: Some(100),
+            },
+        )
+        .unwrap();
+        let mut batch = |records: &[(i64, &str)]| {
+            for (t, device) in records {
+                distinct.push(*t, row(device, 0.0)).unwrap();
+            }
+            devices(distinct.flush().unwrap())
+                .into_iter()
+                .map(|(_, d)| d)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(batch(&[(1, "a"), (2, "b"), (3, "a")]), vec!["a", "b"]);
+        assert_eq!(batch(&[(50, "a"), (60, "c")]), vec!["c"]);
+        // a is forgotten 100 ms after it was seen
+        assert_eq!(batch(&[(150, "a"), (151, "c")]), vec!["a"]);
+    }
+
+    #[test]
+    fn state_size() {
+        let state = StateLimit {
+            size: 2,
+            within: None,
+        };
+        // all rows of a window would have to be kept
+        assert!(
+            bounded(
+                "SELECT device FROM $$source ORDER BY device",
+                None,
+                state.clone()
+            )
+            .is_err()
+        );
+        assert!(bounded("SELECT device FROM $$source LIMIT 3", None, state.clone()).is_err());
+
+        let mut sorted = bounded(
+            "SELECT device FROM $$source ORDER BY device LIMIT 2",
+            None,
+            state.clone(),
+        )
+        .unwrap();
+        for device in ["c", "b", "a"] {
+            sorted.push(0, row(device, 0.0)).unwrap();
+        }
+        let devices = devices(sorted.flush().unwrap());
+        assert_eq!(
+            devices.into_iter().map(|(_, d)| d).collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+        assert!(sorted.take_truncated().is_empty());
+
+        // more distinct rows than the state keeps
+        let mut distinct = bounded(
+            "SELECT DISTINCT device FROM $$source",
+            Some(Window::Tumbling { size: 10 }),
+            state,
+        )
+        .unwrap();
+        for device in ["c", "b", "c", "a"] {
+            distinct.push(1, row(device, 0.0)).unwrap();
+        }
+        assert_eq!(distinct.advance(10).unwrap().len(), 2);
+        assert_eq!(
+            distinct.take_truncated(),
+            vec![(Bounds { start: 0, end: 10 }, 1)]
+        );
+        assert!(distinct.take_truncated().is_empty());
+    }
+
+    #[test]
+    fn unsupported() {
+        assert!(
+            bounded(
+                "SELECT device FROM $$source LIMIT 3",
+                Some(Window::Sliding { size: 10 }),
+                StateLimit::default()
+            )
+            .is_err()
+        );
+        assert!(bounded("SELECT device FROM $$source", None, StateLimit::default()).is_err());
+
+        let mut limited = bounded(
+            "SELECT device FROM $$source LIMIT 3",
+            Some(Window::Tumbling { size: 10 }),
+            StateLimit::default(),
+        )
+        .unwrap();
+        assert!(limited.set_lateness(Lateness::Side).is_ok());
+        assert!(limited.set_lateness(Lateness::Update).is_err());
+    }
+
+    #[test]
+    fn parse_state() {
+        let state: StateLimit =
+            serde_json::from_str(r#"{"size": 100, "within": "10 minutes"}"#).unwrap();
+        assert_eq!(
+            state,
+            StateLimit {
+                size: 100,
+                within: Some(600_000),
+            }
+        );
+        let json = serde_json::to_string(&state).unwrap();
+        assert_eq!(serde_json::from_str::<StateLimit>(&json).unwrap(), state);
+        assert_eq!(
+            serde_json::from_str::<StateLimit>("{}").unwrap(),
+            StateLimit::default()
+        );
+    }
+}
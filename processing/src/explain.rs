@@ -0,0 +1,187 @@
+use crate::expression::Expression;
+use crate::language::Sql;
+use crate::{Algebra, Schema};
+use indexmap::IndexMap;
+use std::fmt::Write;
+
+impl Algebra {
+    /// Describes what the algebra becomes: the logical and the optimized plan with the schema
+    /// of each operator and the instructions of the tuple VM.
+    pub fn explain(&self) -> String {
+        let optimized = self.optimized();
+
+        let mut out = String::from("Logical plan:\n");
+        tree(self, 1, &mut out);
+        out.push_str("Optimized plan:\n");
+        tree(&optimized, 1, &mut out);
+        out.push_str("Instructions:\n");
+        match compiled(&optimized) {
+            true => match optimized.processing() {
+                Ok(program) => out.push_str(&program.listing()),
+                Err(err) => writeln!(out, "  none, {}", err).unwrap(),
+            },
+            false => out.push_str("  none, the plan is evaluated per window or by the Joiner\n"),
+        }
+        out
+    }
+}
+
+/// Whether the tuple VM evaluates the algebra, which happens up to the aggregation.
+fn compiled(algebra: &Algebra) -> bool {
+    match algebra {
+        Algebra::Scan(_) | Algebra::Todo(_) => true,
+        Algebra::Project(p) => compiled(&p.input),
+        Algebra::Filter(f) => compiled(&f.input),
+        Algebra::Unwind(u) => compiled(&u.input),
+        Algebra::Aggregate(a) => compiled(&a.input),
+        Algebra::Collect(_)
+        | Algebra::Join(_)
+        | Algebra::Sort(_)
+        | Algebra::Limit(_)
+        | Algebra::Distinct(_) => false,
+    }
+}
+
+fn tree(algebra: &Algebra, depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    let schema = match algebra.schema() {
+        Ok(Schema::Fixed(fields)) => format!(
+            "({})",
+            fields
+                .iter()
+                .map(|(name, t)| format!("{}: {}", name, t.dump("")))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Ok(Schema::Dynamic) => "(dynamic)".to_string(),
+        Err(err) => format!("(invalid: {})", err),
+    };
+
+    let (label, inputs) = match algebra {
+        Algebra::Scan(s) => (format!("Scan {}", s.source), vec![]),
+        Algebra::Todo(t) => (format!("Todo {}", t), vec![]),
+        Algebra::Project(p) => (format!("Project {}", named(&p.expressions)), vec![&p.input]),
+        Algebra::Filter(f) => (format!("Filter {}", f.predicate.sql()), vec![&f.input]),
+        Algebra::Unwind(u) => (format!("Unwind {}", u.key), vec![&u.input]),
+        Algebra::Collect(c) => ("Collect".to_string(), vec![&c.input]),
+        Algebra::Aggregate(a) => (
+            format!(
+                "Aggregate {} BY [{}]",
+                named(&a.expressions),
+                a.keys
+                    .iter()
+                    .map(|k| k.sql())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            vec![&a.input],
+        ),
+        Algebra::Join(j) => (
+            format!("Join {} {} ON {}", j.left_alias, j.right_alias, j.on.sql()),
+            vec![&j.left, &j.right],
+        ),
+        Algebra::Sort(s) => (
+            format!(
+                "Sort {}",
+                s.keys
+                    .iter()
+                    .map(|k| match k.descending {
+                        true => format!("{} DESC", k.expression.sql()),
+                        false => k.expression.sql(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            vec![&s.input],
+        ),
+        Algebra::Limit(l) => (format!("Limit {}", l.limit), vec![&l.input]),
+        Algebra::Distinct(d) => ("Distinct".to_string(), vec![&d.input]),
+    };
+
+    let _ = writeln!(out, "{}{} {}", indent, label, schema);
+    for input in inputs {
+        tree(input, depth + 1, out);
+    }
+}
+
+fn named(expressions: &IndexMap<String, Expression>) -> String {
+    expressions
+        .iter()
+        .map(|(name, e)| match e {
+            Expression::Wildcard => "*".to_string(),
+            Expression::Exclude(field) => format!("-{}", field),
+            e => format!("{} := {}", name, e.sql()),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Schema, parse_mql, parse_sql};
+    use value::ValType;
+
+    fn schema() -> Schema {
+        Schema::fixed([
+            ("id".to_string(), ValType::Integer),
+            ("price".to_string(), ValType::Float),
+        ])
+    }
+
+    #[test]
+    fn explain() {
+        let mut algebra =
+            parse_sql("SELECT price * 2 AS total FROM $$source WHERE id > 1 + 1").unwrap();
+        algebra.set_schema(schema());
+
+        assert_eq!(
+            algebra.explain(),
+            "Logical plan:
+  Project total := price * 2 (total: FLOAT)
+    Filter id > 1 + 1 (id: INT, price: FLOAT)
+      Scan $$source (id: INT, price: FLOAT)
+Optimized plan:
+  Project total := price * 2 (total: FLOAT)
+    Filter id > 2 (id: INT, price: FLOAT)
+      Scan $$source (id: INT, price: FLOAT)
+Instructions:
+  slots: 0 id, 1 price
+  resources: 0 $$source
+     0  NextTuple { resource_id: 0 }
+     1  Flatten
+     2  LoadField(0)
+     3  PushConst(0)  ; 2
+     4  Greater
+     5  JumpIfFalse { target: 0 }
+     6  LoadField(1)
+     7  PushConst(1)  ; 2
+     8  Multiply
+     9  Replace(1)
+    10  Yield(1)
+    11  Jump { target: 0 }
+"
+        );
+    }
+
+    #[test]
+    fn explain_invalid() {
+        let mut algebra =
+            parse_mql("db.$$source.aggregate([{$unset: \"id\"}, {$sort: {price: -1}}])").unwrap();
+        algebra.set_schema(schema());
+        let explain = algebra.explain();
+        assert!(
+            explain.contains("Sort price DESC (price: FLOAT)"),
+            "{}",
+            explain
+        );
+        assert!(
+            explain.contains("Project *, -id (price: FLOAT)"),
+            "{}",
+            explain
+        );
+        assert!(explain.ends_with("evaluated per window or by the Joiner\n"));
+
+        algebra.set_schema(Schema::fixed([("id".to_string(), ValType::Text)]));
+        assert!(algebra.explain().contains("(invalid: Unknown field price)"));
+    }
+}
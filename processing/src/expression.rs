@@ -1,16 +1,25 @@
+use crate::Schema;
 use crate::algebra::Scope;
-use crate::language::Sql;
+use crate::function::Function;
+use crate::language::{Language, QueryError, Sql};
 use crate::operator::Operator;
-use mongodb::bson::Bson;
-use serde::Serialize;
-use sqlparser::ast::{BinaryOperator, Expr, SelectItem};
+use crate::udf::FunctionRegistry;
+use anyhow::anyhow;
+use mongodb::bson::{Bson, Document};
+use serde::{Deserialize, Serialize};
+use sqlparser::ast::{
+    AccessExpr, CeilFloorKind, DateTimeField, Expr, FunctionArg, FunctionArgExpr,
+    FunctionArguments, SelectItem, Spanned, Subscript, UnaryOperator,
+};
 use std::{cmp, vec};
-use value::Value;
+use value::{ValType, Value};
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Expression {
     Field(String),
     Literal(Value),
+    /// all fields of the input, e.g. to add fields to them
+    Wildcard,
     Exclude(String),
     Call {
         operator: Operator,
@@ -33,7 +42,7 @@ impl Expression {
                     .map(|e| e.scope())
                     .fold(Scope::Tuple, cmp::max),
             ),
-            Expression::Exclude(_) => Scope::Tuple,
+            Expression::Exclude(_) | Expression::Wildcard => Scope::Tuple,
         }
     }
 
@@ -45,69 +54,528 @@ impl Expression {
         Self::Field(name.to_string())
     }
 
-    fn build_call(left: &Box<Expr>, op: &BinaryOperator, right: &Box<Expr>) -> Expression {
+    pub(crate) fn call(operator: Operator, expressions: Vec<Expression>) -> Self {
         Expression::Call {
-            operator: Operator::from(op),
-            expressions: vec![
-                Expression::from(left.clone()),
-                Expression::from(right.clone()),
-            ],
+            operator,
+            expressions,
         }
     }
-}
 
-impl From<&SelectItem> for Expression {
-    fn from(value: &SelectItem) -> Self {
-        if let SelectItem::UnnamedExpr(f) = value {
-            if let Expr::Identifier(i) = f {
-                Expression::Field(i.value.clone())
-            } else if let Expr::BinaryOp { left, op, right } = f {
-                Self::build_call(left, op, right)
-            } else {
-                panic!("Expected identifier, found {:?}", f);
-            }
-        } else {
-            todo!()
+    /// Follows the keys from the container, e.g. `items[2].price`, missing ones are null.
+    pub(crate) fn path(container: Expression, keys: Vec<Expression>) -> Self {
+        match container {
+            _ if keys.is_empty() => container,
+            // a path into a path is one longer path
+            Expression::Call {
+                operator: Operator::Index,
+                mut expressions,
+            } => {
+                expressions.extend(keys);
+                Expression::call(Operator::Index, expressions)
+            }
+            container => Expression::call(Operator::Index, [vec![container], keys].concat()),
+        }
+    }
+
+    /// A dotted name like `a.b.c`, the first part is the field and the others are keys in it.
+    pub(crate) fn dotted(name: &str) -> Self {
+        let mut parts = name.split('.');
+        let field = Expression::field(parts.next().unwrap_or_default());
+        Expression::path(
+            field,
+            parts
+                .map(|key| Expression::Literal(Value::text(key)))
+                .collect(),
+        )
+    }
+
+    pub(crate) fn function(function: Function, expressions: Vec<Expression>) -> Self {
+        Expression::call(Operator::Function(function), expressions)
+    }
+
+    /// Chains a binary operator over all expressions, e.g. `a AND b AND c`.
+    pub(crate) fn fold(operator: Operator, expressions: Vec<Expression>) -> Self {
+        expressions
+            .into_iter()
+            .reduce(|l, r| Expression::call(operator.clone(), vec![l, r]))
+            .unwrap_or(Expression::Literal(Value::null()))
+    }
+
+    /// Splits a predicate into its top-level `AND` terms, so each one can be checked on its own.
+    pub(crate) fn conjuncts(&self) -> Vec<&Expression> {
+        match self {
+            Expression::Call {
+                operator: Operator::And,
+                expressions,
+            } => expressions.iter().flat_map(|e| e.conjuncts()).collect(),
+            e => vec![e],
+        }
+    }
+
+    /// The type this expression evaluates to for records of the schema.
+    pub(crate) fn val_type(&self, schema: &Schema) -> anyhow::Result<ValType> {
+        match self {
+            Expression::Field(name) => schema.field_type(name),
+            Expression::Literal(value) => Ok(value.type_()),
+            Expression::Exclude(_) | Expression::Wildcard => Ok(ValType::Any),
+            Expression::Call {
+                operator,
+                expressions,
+            } => {
+                let args = expressions
+                    .iter()
+                    .map(|e| e.val_type(schema))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                operator
+                    .output_type(&args)
+                    .map_err(|e| anyhow!("{} in {}", e, self.sql()))
+            }
         }
     }
-}
 
-impl From<Box<Expr>> for Expression {
-    fn from(value: Box<Expr>) -> Self {
-        match *value {
+    /// All aggregate calls in this expression, in order of appearance.
+    pub(crate) fn aggregates(&self) -> Vec<&Expression> {
+        match self {
+            Expression::Call { operator, .. } if operator.is_aggregate() => vec![self],
+            Expression::Call { expressions, .. } => {
+                expressions.iter().flat_map(|e| e.aggregates()).collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Lowers a parsed SQL expression, `query` is the text it was parsed from.
+    pub(crate) fn from_sql(
+        expr: &Expr,
+        query: &str,
+        functions: &FunctionRegistry,
+    ) -> Result<Self, QueryError> {
+        let unsupported = |what: String| QueryError::sql(query, expr.span(), what);
+        let lower = |e: &Expr| Expression::from_sql(e, query, functions);
+        Ok(match expr {
             Expr::Identifier(i) => Expression::Field(i.value.clone()),
-            Expr::Value(v) => match v.value {
-                sqlparser::ast::Value::Number(i, _) => {
-                    Expression::Literal(Value::float(i.parse().unwrap()))
+            Expr::CompoundIdentifier(parts) => Expression::path(
+                Expression::field(&parts[0].value),
+                parts[1..]
+                    .iter()
+                    .map(|p| Expression::Literal(Value::text(&p.value)))
+                    .collect(),
+            ),
+            // `items[2].price` and `payload['key']`, arrays start at 0 like in the other languages
+            Expr::CompoundFieldAccess { root, access_chain } => {
+                let mut keys = vec![];
+                for access in access_chain {
+                    keys.push(match access {
+                        AccessExpr::Dot(Expr::Identifier(key)) => {
+                            Expression::Literal(Value::text(&key.value))
+                        }
+                        AccessExpr::Subscript(Subscript::Index { index }) => lower(index)?,
+                        access => Err(unsupported(format!("Unsupported access {}", access)))?,
+                    });
+                }
+                Expression::path(lower(root)?, keys)
+            }
+            Expr::Value(v) => match &v.value {
+                sqlparser::ast::Value::Number(i, _) => match i.parse::<i64>() {
+                    Ok(i) => Expression::Literal(Value::int(i)),
+                    Err(_) => Expression::Literal(Value::float(
+                        i.parse()
+                            .map_err(|_| unsupported(format!("Invalid number {}", i)))?,
+                    )),
+                },
+                sqlparser::ast::Value::SingleQuotedString(s)
+                | sqlparser::ast::Value::DoubleQuotedString(s) => {
+                    Expression::Literal(Value::text(s))
                 }
-                e => todo!("{:?}", e),
+                sqlparser::ast::Value::Boolean(b) => Expression::Literal(Value::bool(*b)),
+                sqlparser::ast::Value::Null => Expression::Literal(Value::null()),
+                v => Err(unsupported(format!("Unsupported value {}", v)))?,
             },
-            Expr::BinaryOp { left, right, op } => Self::build_call(&left, &op, &right),
-            e => todo!("{:?}", e),
+            Expr::BinaryOp { left, right, op } => Expression::call(
+                Operator::try_from(op).map_err(unsupported)?,
+                vec![lower(left)?, lower(right)?],
+            ),
+            Expr::Nested(e) => lower(e)?,
+            Expr::UnaryOp {
+                op: UnaryOperator::Not,
+                expr,
+            } => Expression::call(Operator::Not, vec![lower(expr)?]),
+            Expr::UnaryOp {
+                op: UnaryOperator::Minus,
+                expr,
+            } => Expression::call(
+                Operator::Minus,
+                vec![Expression::Literal(Value::int(0)), lower(expr)?],
+            ),
+            Expr::IsNull(e) => Expression::call(Operator::IsNull, vec![lower(e)?]),
+            Expr::IsNotNull(e) => Expression::call(
+                Operator::Not,
+                vec![Expression::call(Operator::IsNull, vec![lower(e)?])],
+            ),
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let mut expressions = vec![lower(expr)?];
+                for e in list {
+                    expressions.push(lower(e)?);
+                }
+                let call = Expression::call(Operator::In, expressions);
+                if *negated {
+                    Expression::call(Operator::Not, vec![call])
+                } else {
+                    call
+                }
+            }
+            Expr::Function(f) => {
+                let name = f.name.to_string();
+                let operator = Operator::aggregate(&name)
+                    .or_else(|| Function::lookup(&name).map(Operator::Function))
+                    .or_else(|| functions.lookup(&name).map(Operator::Udf))
+                    .ok_or_else(|| unsupported(format!("Unsupported function {}", name)))?;
+                let args = match &f.args {
+                    FunctionArguments::List(list) => list.args.as_slice(),
+                    _ => &[],
+                };
+                let mut expressions = vec![];
+                for arg in args {
+                    match arg {
+                        FunctionArg::Unnamed(FunctionArgExpr::Expr(e)) => {
+                            expressions.push(lower(e)?)
+                        }
+                        // COUNT(*)
+                        FunctionArg::Unnamed(FunctionArgExpr::Wildcard) => {}
+                        a => Err(unsupported(format!("Unsupported argument {}", a)))?,
+                    }
+                }
+                match &operator {
+                    Operator::Function(f) => f.check(expressions.len()).map_err(unsupported)?,
+                    Operator::Udf(f) => f.check(expressions.len()).map_err(unsupported)?,
+                    _ => {}
+                }
+                Expression::call(operator, expressions)
+            }
+            Expr::Substring {
+                expr,
+                substring_from,
+                substring_for,
+                ..
+            } => {
+                let mut expressions = vec![lower(expr)?];
+                expressions.push(match substring_from {
+                    Some(from) => lower(from)?,
+                    None => Expression::Literal(Value::int(1)),
+                });
+                if let Some(length) = substring_for {
+                    expressions.push(lower(length)?);
+                }
+                Expression::function(Function::Substring, expressions)
+            }
+            Expr::Trim {
+                expr,
+                trim_where: None,
+                trim_what: None,
+                trim_characters: None,
+            } => Expression::function(Function::Trim, vec![lower(expr)?]),
+            Expr::Cast {
+                expr, data_type, ..
+            } => {
+                let name = data_type.to_string();
+                // the length of VARCHAR(10) and the precision of DECIMAL(5, 2) do not matter
+                let val_type = match name.split('(').next().unwrap_or_default().trim() {
+                    "INT" | "INTEGER" | "BIGINT" | "SMALLINT" => ValType::Integer,
+                    "FLOAT" | "DOUBLE" | "DOUBLE PRECISION" | "REAL" | "DECIMAL" | "NUMERIC" => {
+                        ValType::Float
+                    }
+                    "TEXT" | "VARCHAR" | "STRING" | "CHAR" => ValType::Text,
+                    "BOOL" | "BOOLEAN" => ValType::Bool,
+                    "DATE" => ValType::Date,
+                    "TIMESTAMP" | "DATETIME" | "TIME" => ValType::Time,
+                    _ => Err(unsupported(format!("Unsupported type {}", name)))?,
+                };
+                Expression::function(Function::Cast(val_type), vec![lower(expr)?])
+            }
+            Expr::Extract { field, expr, .. } => {
+                let function = Function::lookup(&field.to_string())
+                    .filter(|f| {
+                        matches!(
+                            f,
+                            Function::Year
+                                | Function::Month
+                                | Function::Day
+                                | Function::Hour
+                                | Function::Minute
+                                | Function::Second
+                        )
+                    })
+                    .ok_or_else(|| unsupported(format!("Unsupported field {}", field)))?;
+                Expression::function(function, vec![lower(expr)?])
+            }
+            Expr::Ceil {
+                expr,
+                field: CeilFloorKind::DateTimeField(DateTimeField::NoDateTime),
+            } => Expression::function(Function::Ceil, vec![lower(expr)?]),
+            Expr::Floor {
+                expr,
+                field: CeilFloorKind::DateTimeField(DateTimeField::NoDateTime),
+            } => Expression::function(Function::Floor, vec![lower(expr)?]),
+            Expr::Like {
+                negated,
+                any: false,
+                expr,
+                pattern,
+                escape_char: None,
+            } => {
+                let like =
+                    Expression::function(Function::Like, vec![lower(expr)?, lower(pattern)?]);
+                if *negated {
+                    Expression::call(Operator::Not, vec![like])
+                } else {
+                    like
+                }
+            }
+            Expr::RLike {
+                negated,
+                expr,
+                pattern,
+                ..
+            } => {
+                let regex =
+                    Expression::function(Function::Regex, vec![lower(expr)?, lower(pattern)?]);
+                if *negated {
+                    Expression::call(Operator::Not, vec![regex])
+                } else {
+                    regex
+                }
+            }
+            Expr::Case {
+                operand,
+                conditions,
+                else_result,
+                ..
+            } => {
+                let mut case = match else_result {
+                    Some(e) => lower(e)?,
+                    None => Expression::Literal(Value::null()),
+                };
+                // the first matching condition wins, so they are nested from the last one
+                for when in conditions.iter().rev() {
+                    let condition = match operand {
+                        Some(operand) => Expression::call(
+                            Operator::Equal,
+                            vec![lower(operand)?, lower(&when.condition)?],
+                        ),
+                        None => lower(&when.condition)?,
+                    };
+                    case = Expression::call(
+                        Operator::Cond,
+                        vec![condition, lower(&when.result)?, case],
+                    );
+                }
+                case
+            }
+            e => Err(unsupported(format!("Unsupported expression {}", e)))?,
+        })
+    }
+
+    pub(crate) fn from_select_item(
+        item: &SelectItem,
+        query: &str,
+        functions: &FunctionRegistry,
+    ) -> Result<Self, QueryError> {
+        match item {
+            SelectItem::UnnamedExpr(f) | SelectItem::ExprWithAlias { expr: f, .. } => {
+                Expression::from_sql(f, query, functions)
+            }
+            item => Err(QueryError::sql(
+                query,
+                item.span(),
+                format!("Unsupported projection {}", item),
+            )),
         }
     }
 }
 
-impl From<(&str, &Bson)> for Expression {
-    fn from(value: (&str, &Bson)) -> Self {
-        if let Some(num) = value.1.as_i64() {
-            if num == 1 {
-                Expression::Field(value.0.to_string())
-            } else {
-                Expression::Exclude(value.0.to_string())
-            }
-        } else if let Some(field) = value.1.as_str() {
-            Expression::Field(field.to_string())
-        } else {
-            Expression::from(value.1)
+impl Expression {
+    /// A field of a `$project` stage, `1` keeps the field, `0` removes it.
+    pub(crate) fn from_mql_field(
+        name: &str,
+        value: &Bson,
+        query: &str,
+        functions: &FunctionRegistry,
+    ) -> Result<Self, QueryError> {
+        Ok(match value {
+            Bson::Int32(1) | Bson::Int64(1) | Bson::Boolean(true) => Expression::dotted(name),
+            Bson::Int32(0) | Bson::Int64(0) | Bson::Boolean(false) => {
+                Expression::Exclude(name.to_string())
+            }
+            bson => Expression::from_mql(bson, query, functions)?,
+        })
+    }
+
+    /// An aggregation expression like `{$multiply: ["$price", 1.1]}`.
+    pub(crate) fn from_mql(
+        value: &Bson,
+        query: &str,
+        functions: &FunctionRegistry,
+    ) -> Result<Self, QueryError> {
+        match value {
+            // "$$" marks variables like $$ROOT
+            Bson::String(s) if s.starts_with('$') && !s.starts_with("$$") => {
+                Ok(Expression::dotted(&s[1..]))
+            }
+            Bson::String(s) if s == "$$ROOT" => Ok(Expression::Wildcard),
+            Bson::Document(d) if d.len() == 1 && d.keys().all(|k| k.starts_with('$')) => {
+                let (name, args) = d.iter().next().unwrap();
+                mql_call(name, args, query, functions)
+            }
+            bson => Ok(Expression::Literal(literal(bson))),
         }
     }
 }
 
-impl From<&Bson> for Expression {
-    fn from(_: &Bson) -> Self {
-        todo!()
+pub(crate) fn literal(bson: &Bson) -> Value {
+    match bson {
+        Bson::Array(a) => Value::array(a.iter().map(literal).collect::<Vec<_>>()),
+        bson => Value::from(bson),
+    }
+}
+
+fn mql_call(
+    name: &str,
+    args: &Bson,
+    query: &str,
+    functions: &FunctionRegistry,
+) -> Result<Expression, QueryError> {
+    let lower = |b: &Bson| Expression::from_mql(b, query, functions);
+    let error = |message: String| QueryError::locate(Language::Mql, query, name, message);
+    // operators with named arguments
+    let named = |d: &Document, keys: &[&str]| {
+        keys.iter()
+            .map(|k| lower(d.get(k).unwrap_or(&Bson::Null)))
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let mut args = match args {
+        // the value is not evaluated, e.g. a text like "$name"
+        args if name == "$literal" => return Ok(Expression::Literal(literal(args))),
+        Bson::Array(a) => a.iter().map(lower).collect::<Result<Vec<_>, _>>()?,
+        Bson::Document(d) if name == "$getField" => {
+            return Ok(match (d.get("input"), d.get("field")) {
+                // without an input the field is one of the record, dots are part of its name
+                (None, Some(Bson::String(field))) => Expression::field(field),
+                (None, _) => Expression::path(Expression::Wildcard, named(d, &["field"])?),
+                (Some(input), _) => Expression::path(lower(input)?, named(d, &["field"])?),
+            });
+        }
+        Bson::Document(d) if name == "$cond" => named(d, &["if", "then", "else"])?,
+        Bson::Document(d) if name == "$trim" => named(d, &["input"])?,
+        Bson::Document(d) if name == "$regexMatch" => named(d, &["input", "regex"])?,
+        Bson::Document(d) if name == "$switch" => {
+            let mut switch = lower(d.get("default").unwrap_or(&Bson::Null))?;
+            let branches = d
+                .get_array("branches")
+                .map_err(|_| error("$switch expects an array of branches".to_string()))?;
+            // the first matching branch wins, so they are nested from the last one
+            for branch in branches.iter().rev() {
+                let branch = branch
+                    .as_document()
+                    .ok_or_else(|| error(format!("Invalid branch {}", branch)))?;
+                let mut args = named(branch, &["case", "then"])?;
+                args.push(switch);
+                switch = Expression::call(Operator::Cond, args);
+            }
+            return Ok(switch);
+        }
+        arg => vec![lower(arg)?],
+    };
+
+    let operator = match name {
+        "$add" => return Ok(Expression::fold(Operator::Add, args)),
+        "$multiply" => return Ok(Expression::fold(Operator::Multiply, args)),
+        "$and" => return Ok(Expression::fold(Operator::And, args)),
+        "$or" => return Ok(Expression::fold(Operator::Or, args)),
+        "$subtract" => Operator::Minus,
+        "$gt" => Operator::Gt,
+        "$gte" => Operator::Gte,
+        "$lt" => Operator::Lt,
+        "$lte" => Operator::Lte,
+        // unlike comparisons in SQL, null equals null and missing values
+        "$eq" | "$ne"
+            if args
+                .iter()
+                .any(|a| a == &Expression::Literal(Value::null())) =>
+        {
+            args.retain(|a| a != &Expression::Literal(Value::null()));
+            let value = args.pop().unwrap_or(Expression::Literal(Value::null()));
+            let is_null = Expression::call(Operator::IsNull, vec![value]);
+            return Ok(match name {
+                "$eq" => is_null,
+                _ => Expression::call(Operator::Not, vec![is_null]),
+            });
+        }
+        "$eq" => Operator::Equal,
+        "$ne" => Operator::NotEqual,
+        "$not" => Operator::Not,
+        "$cond" => Operator::Cond,
+        "$concat" => Operator::Concat,
+        "$divide" => Operator::Divide,
+        "$mod" => Operator::Function(Function::Mod),
+        "$pow" => Operator::Function(Function::Pow),
+        "$abs" => Operator::Function(Function::Abs),
+        "$round" => Operator::Function(Function::Round),
+        "$floor" => Operator::Function(Function::Floor),
+        "$ceil" => Operator::Function(Function::Ceil),
+        "$toLower" => Operator::Function(Function::Lower),
+        "$toUpper" => Operator::Function(Function::Upper),
+        "$trim" => Operator::Function(Function::Trim),
+        "$strLenCP" | "$size" => Operator::Function(Function::Length),
+        "$regexMatch" => Operator::Function(Function::Regex),
+        "$ifNull" => Operator::Function(Function::Coalesce),
+        "$substrCP" => {
+            // the start is 0-based
+            if let Some(start) = args.get_mut(1) {
+                *start = Expression::call(
+                    Operator::Add,
+                    vec![start.clone(), Expression::Literal(Value::int(1))],
+                );
+            }
+            Operator::Function(Function::Substring)
+        }
+        "$toInt" | "$toLong" => Operator::Function(Function::Cast(ValType::Integer)),
+        "$toDouble" | "$toDecimal" => Operator::Function(Function::Cast(ValType::Float)),
+        "$toString" => Operator::Function(Function::Cast(ValType::Text)),
+        "$toBool" => Operator::Function(Function::Cast(ValType::Bool)),
+        "$toDate" => Operator::Function(Function::Cast(ValType::Time)),
+        "$year" => Operator::Function(Function::Year),
+        "$month" => Operator::Function(Function::Month),
+        "$dayOfMonth" => Operator::Function(Function::Day),
+        "$hour" => Operator::Function(Function::Hour),
+        "$minute" => Operator::Function(Function::Minute),
+        "$second" => Operator::Function(Function::Second),
+        "$in" => {
+            // {$in: ["$name", ["a", "b"]]} compares with each item of the array
+            if let Some(Expression::Literal(Value::Array(list))) = args.pop() {
+                args.extend(list.values.into_iter().map(Expression::Literal));
+            }
+            Operator::In
+        }
+        "$sum" if args == [Expression::Literal(Value::int(1))] => {
+            return Ok(Expression::call(Operator::Count, vec![]));
+        }
+        "$count" => return Ok(Expression::call(Operator::Count, vec![])),
+        "$push" => Operator::Collect,
+        "$arrayElemAt" => Operator::Index,
+        name => Operator::aggregate(name.trim_start_matches('$'))
+            .or_else(|| functions.lookup(&name[1..]).map(Operator::Udf))
+            .ok_or_else(|| error(format!("Unsupported operator {}", name)))?,
+    };
+    match &operator {
+        Operator::Function(f) => f.check(args.len()).map_err(error)?,
+        Operator::Udf(f) => f.check(args.len()).map_err(error)?,
+        _ => {}
     }
+    Ok(Expression::call(operator, args))
 }
 
 impl Sql for Expression {
@@ -119,6 +587,7 @@ impl Sql for Expression {
                 operator,
                 expressions,
             } => operator.sql(expressions.clone()),
+            Expression::Wildcard => "*".to_string(),
             Expression::Exclude(_) => unreachable!(),
         }
     }
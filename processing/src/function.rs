@@ -0,0 +1,509 @@
+use chrono::{DateTime, Datelike, NaiveDate, Timelike, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use value::{ValType, Value};
+
+/// Scalar functions which the query languages share, they are evaluated per record.
+///
+/// Functions return null if an argument is null, except `Coalesce` and `NullIf` which exist to
+/// handle nulls, and if they cannot be applied to the values, e.g. a cast of `"a"` to an integer.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Function {
+    Lower,
+    Upper,
+    /// text, 1-based start and optional length in characters
+    Substring,
+    Trim,
+    /// characters of a text or items of an array
+    Length,
+    /// text and SQL pattern with `%` and `_`
+    Like,
+    /// text and regular expression which matches anywhere in the text
+    Regex,
+    Abs,
+    /// number and optional digits after the point
+    Round,
+    Floor,
+    Ceil,
+    Mod,
+    Pow,
+    /// the first argument which is not null
+    Coalesce,
+    /// null if both arguments are equal, otherwise the first
+    NullIf,
+    Cast(ValType),
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+}
+
+impl Function {
+    /// The function with the name in SQL or Cypher, names are case-insensitive.
+    pub(crate) fn lookup(name: &str) -> Option<Function> {
+        Some(match name.to_lowercase().as_str() {
+            "lower" | "lcase" | "tolower" => Function::Lower,
+            "upper" | "ucase" | "toupper" => Function::Upper,
+            "substring" | "substr" => Function::Substring,
+            "trim" => Function::Trim,
+            "length" | "char_length" | "character_length" | "size" => Function::Length,
+            "like" => Function::Like,
+            "regexp_like" => Function::Regex,
+            "abs" => Function::Abs,
+            "round" => Function::Round,
+            "floor" => Function::Floor,
+            "ceil" | "ceiling" => Function::Ceil,
+            "mod" => Function::Mod,
+            "pow" | "power" => Function::Pow,
+            "coalesce" | "ifnull" => Function::Coalesce,
+            "nullif" => Function::NullIf,
+            "tointeger" => Function::Cast(ValType::Integer),
+            "tofloat" => Function::Cast(ValType::Float),
+            "tostring" => Function::Cast(ValType::Text),
+            "toboolean" => Function::Cast(ValType::Bool),
+            "year" => Function::Year,
+            "month" => Function::Month,
+            "day" | "dayofmonth" => Function::Day,
+            "hour" => Function::Hour,
+            "minute" => Function::Minute,
+            "second" => Function::Second,
+            _ => return None,
+        })
+    }
+
+    /// How many arguments the function takes at least and at most.
+    pub(crate) fn arity(&self) -> (usize, usize) {
+        match self {
+            Function::Substring => (2, 3),
+            Function::Round => (1, 2),
+            Function::Like | Function::Regex | Function::Mod | Function::Pow => (2, 2),
+            Function::NullIf => (2, 2),
+            Function::Coalesce => (1, usize::MAX),
+            _ => (1, 1),
+        }
+    }
+
+    /// Checks the number of arguments, the error names the function like SQL does.
+    pub(crate) fn check(&self, args: usize) -> Result<(), String> {
+        let (min, max) = self.arity();
+        if args < min || args > max {
+            return Err(format!(
+                "{} does not take {} argument{}",
+                self.name(),
+                args,
+                if args == 1 { "" } else { "s" }
+            ));
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Function::Lower => "LOWER",
+            Function::Upper => "UPPER",
+            Function::Substring => "SUBSTRING",
+            Function::Trim => "TRIM",
+            Function::Length => "LENGTH",
+            Function::Like => "LIKE",
+            Function::Regex => "REGEXP_LIKE",
+            Function::Abs => "ABS",
+            Function::Round => "ROUND",
+            Function::Floor => "FLOOR",
+            Function::Ceil => "CEIL",
+            Function::Mod => "MOD",
+            Function::Pow => "POWER",
+            Function::Coalesce => "COALESCE",
+            Function::NullIf => "NULLIF",
+            Function::Cast(_) => "CAST",
+            Function::Year => "YEAR",
+            Function::Month => "MONTH",
+            Function::Day => "DAY",
+            Function::Hour => "HOUR",
+            Function::Minute => "MINUTE",
+            Function::Second => "SECOND",
+        }
+    }
+
+    pub(crate) fn sql(&self, args: Vec<String>) -> String {
+        match self {
+            Function::Like => format!("{} LIKE {}", args[0], args[1]),
+            Function::Cast(t) => format!("CAST({} AS {})", args[0], t.dump("")),
+            Function::Year
+            | Function::Month
+            | Function::Day
+            | Function::Hour
+            | Function::Minute
+            | Function::Second => format!("EXTRACT({} FROM {})", self.name(), args[0]),
+            _ => format!("{}({})", self.name(), args.join(", ")),
+        }
+    }
+
+    /// The type of the result for arguments of the given types, `None` if they do not fit.
+    pub(crate) fn output_type(&self, args: &[ValType]) -> Option<ValType> {
+        self.check(args.len()).ok()?;
+        let all = |allowed: &[ValType]| {
+            args.iter()
+                .all(|a| matches!(a, ValType::Any | ValType::Null) || allowed.contains(a))
+        };
+        let text = [ValType::Text];
+        let number = [ValType::Integer, ValType::Float];
+        let time = [ValType::Time, ValType::Date];
+
+        match self {
+            Function::Lower | Function::Upper | Function::Trim => {
+                all(&text).then_some(ValType::Text)
+            }
+            Function::Substring => (all(&[ValType::Text, ValType::Integer])
+                && !matches!(args.first(), Some(ValType::Integer)))
+            .then_some(ValType::Text),
+            Function::Length => match args.first()? {
+                ValType::Text | ValType::Array | ValType::Any | ValType::Null => {
+                    Some(ValType::Integer)
+                }
+                _ => None,
+            },
+            Function::Like | Function::Regex => all(&text).then_some(ValType::Bool),
+            Function::Abs | Function::Floor | Function::Ceil | Function::Round => {
+                // the digits of ROUND do not change the type
+                match args.first()? {
+                    t if all(&number) => Some(t.clone()),
+                    _ => None,
+                }
+            }
+            Function::Mod => match (&args[0], &args[1]) {
+                (ValType::Integer, ValType::Integer) => Some(ValType::Integer),
+                (ValType::Any, _) | (_, ValType::Any) if all(&number) => Some(ValType::Any),
+                _ if all(&number) => Some(ValType::Float),
+                _ => None,
+            },
+            Function::Pow => all(&number).then_some(ValType::Float),
+            Function::Coalesce => {
+                let mut types = args.iter().filter(|t| **t != ValType::Null);
+                match types.next() {
+                    None => Some(ValType::Null),
+                    Some(first) if types.all(|t| t == first) => Some(first.clone()),
+                    Some(_) => Some(ValType::Any),
+                }
+            }
+            Function::NullIf => args.first().cloned(),
+            Function::Cast(t) => Some(t.clone()),
+            Function::Year | Function::Month | Function::Day => {
+                all(&time).then_some(ValType::Integer)
+            }
+            Function::Hour | Function::Minute | Function::Second => {
+                all(&[ValType::Time]).then_some(ValType::Integer)
+            }
+        }
+    }
+
+    /// Applies the function to the values of its arguments.
+    pub(crate) fn evaluate(&self, args: &[Value]) -> Value {
+        match self {
+            Function::Coalesce => {
+                return args
+                    .iter()
+                    .find(|a| !matches!(a, Value::Null))
+                    .cloned()
+                    .unwrap_or(Value::Null);
+            }
+            Function::NullIf => {
+                return match (&args[0], &args[1]) {
+                    (l, r) if l == r => Value::null(),
+                    (l, _) => l.clone(),
+                };
+            }
+            _ if args.iter().any(|a| matches!(a, Value::Null)) => return Value::null(),
+            _ => {}
+        }
+
+        self.apply(args).unwrap_or(Value::Null)
+    }
+
+    fn apply(&self, args: &[Value]) -> Option<Value> {
+        let text = |i: usize| match &args[i] {
+            Value::Text(t) => Some(t.0.as_str()),
+            _ => None,
+        };
+        let int = |i: usize| match &args[i] {
+            Value::Int(i) => Some(i.0),
+            _ => None,
+        };
+
+        Some(match self {
+            Function::Lower => Value::text(text(0)?.to_lowercase()),
+            Function::Upper => Value::text(text(0)?.to_uppercase()),
+            Function::Trim => Value::text(text(0)?.trim()),
+            Function::Substring => {
+                let chars = text(0)?.chars().collect::<Vec<_>>();
+                // positions before the first character still count towards the length
+                let from = int(1)? - 1;
+                let to = match args.get(2) {
+                    Some(_) if int(2)? < 0 => return None,
+                    Some(_) => from.saturating_add(int(2)?),
+                    None => chars.len() as i64,
+                };
+                let clamp = |i: i64| i.clamp(0, chars.len() as i64) as usize;
+                Value::text(
+                    chars[clamp(from)..clamp(to).max(clamp(from))]
+                        .iter()
+                        .collect::<String>(),
+                )
+            }
+            Function::Length => match &args[0] {
+                Value::Text(t) => Value::int(t.0.chars().count() as i64),
+                Value::Array(a) => Value::int(a.values.len() as i64),
+                _ => return None,
+            },
+            Function::Like => Value::bool(matches(&like(text(1)?), text(0)?)?),
+            Function::Regex => Value::bool(matches(text(1)?, text(0)?)?),
+            Function::Abs => match &args[0] {
+                Value::Int(i) => Value::int(i.0.checked_abs()?),
+                Value::Float(f) => Value::float(f.0.0.abs()),
+                _ => return None,
+            },
+            Function::Floor | Function::Ceil | Function::Round => {
+                let digits = match args.get(1) {
+                    Some(_) => int(1)?,
+                    None => 0,
+                };
+                match &args[0] {
+                    Value::Int(i) if digits >= 0 => Value::int(i.0),
+                    Value::Float(f) => {
+                        let scale = 10f64.powi(digits as i32);
+                        let scaled = f.0.0 * scale;
+                        Value::float(
+                            match self {
+                                Function::Floor => scaled.floor(),
+                                Function::Ceil => scaled.ceil(),
+                                _ => scaled.round(),
+                            } / scale,
+                        )
+                    }
+                    _ => return None,
+                }
+            }
+            Function::Mod => match (&args[0], &args[1]) {
+                (Value::Int(l), Value::Int(r)) => Value::int(l.0.checked_rem(r.0)?),
+                (l, r) => Value::float(number(l)? % number(r)?),
+            },
+            Function::Pow => Value::float(number(&args[0])?.powf(number(&args[1])?)),
+            Function::Cast(t) => cast(&args[0], t)?,
+            Function::Year | Function::Month | Function::Day => {
+                let date = match &args[0] {
+                    Value::Time(t) => DateTime::<Utc>::from_timestamp_millis(t.ms)?.date_naive(),
+                    Value::Date(d) => {
+                        epoch().checked_add_signed(chrono::Duration::try_days(d.0)?)?
+                    }
+                    _ => return None,
+                };
+                Value::int(match self {
+                    Function::Year => date.year() as i64,
+                    Function::Month => date.month() as i64,
+                    _ => date.day() as i64,
+                })
+            }
+            Function::Hour | Function::Minute | Function::Second => {
+                let Value::Time(t) = &args[0] else {
+                    return None;
+                };
+                let time = DateTime::<Utc>::from_timestamp_millis(t.ms)?;
+                Value::int(match self {
+                    Function::Hour => time.hour(),
+                    Function::Minute => time.minute(),
+                    _ => time.second(),
+                } as i64)
+            }
+            Function::Coalesce | Function::NullIf => unreachable!(),
+        })
+    }
+}
+
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(_) | Value::Float(_) => value.as_float().ok().map(|f| f.0.0),
+        _ => None,
+    }
+}
+
+fn epoch() -> NaiveDate {
+    NaiveDate::from_ymd_opt(1970, 1, 1).unwrap()
+}
+
+/// Converts the value with the `as_*` helpers, text is parsed strictly so invalid input is null.
+pub(crate) fn cast(value: &Value, to: &ValType) -> Option<Value> {
+    Some(match (to, value) {
+        (ValType::Integer, Value::Float(f)) if !f.0.0.is_finite() => return None,
+        (ValType::Integer, Value::Text(t)) => match t.0.trim().parse::<i64>() {
+            Ok(i) => Value::int(i),
+            Err(_) => Value::int(t.0.trim().parse::<f64>().ok()? as i64),
+        },
+        (ValType::Integer, v) => Value::Int(v.as_int().ok()?),
+        (ValType::Float, Value::Text(t)) => Value::float(t.0.trim().parse().ok()?),
+        (ValType::Float, v) => Value::Float(v.as_float().ok()?),
+        (ValType::Bool, Value::Text(t)) => match t.0.trim().to_lowercase().as_str() {
+            "true" | "1" => Value::bool(true),
+            "false" | "0" => Value::bool(false),
+            _ => return None,
+        },
+        (ValType::Bool, v) => Value::Bool(v.as_bool().ok()?),
+        (ValType::Text, Value::Text(_)) => value.clone(),
+        (ValType::Text, v) => Value::text(v.to_string()),
+        (ValType::Time, Value::Text(t)) => {
+            Value::Time(t.0.trim().parse::<DateTime<Utc>>().ok()?.into())
+        }
+        (ValType::Time, Value::Date(d)) => Value::time(d.0.checked_mul(86_400_000)?, 0),
+        (ValType::Time, v @ (Value::Int(_) | Value::Time(_))) => Value::Time(v.as_time().ok()?),
+        (ValType::Date, Value::Text(t)) => {
+            let date = NaiveDate::parse_from_str(t.0.trim(), "%Y-%m-%d").ok()?;
+            Value::date((date - epoch()).num_days())
+        }
+        (ValType::Date, Value::Time(t)) => Value::date(t.ms.div_euclid(86_400_000)),
+        (ValType::Date, v @ (Value::Int(_) | Value::Date(_))) => Value::Date(v.as_date().ok()?),
+        (ValType::Any, v) => v.clone(),
+        _ => return None,
+    })
+}
+
+/// The regular expression of a SQL pattern, `%` matches any text and `_` any character.
+fn like(pattern: &str) -> String {
+    let mut regex = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '%' => regex.push_str(".*"),
+            '_' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+thread_local! {
+    // patterns are mostly literals of the query, so they are compiled once per thread
+    static REGEXES: RefCell<HashMap<String, Option<Regex>>> = RefCell::new(HashMap::new());
+}
+
+/// Whether the regular expression matches the text, `None` if it is invalid.
+fn matches(pattern: &str, text: &str) -> Option<bool> {
+    REGEXES.with(|regexes| {
+        let mut regexes = regexes.borrow_mut();
+        if regexes.len() > 1024 {
+            regexes.clear();
+        }
+        regexes
+            .entry(pattern.to_string())
+            .or_insert_with(|| Regex::new(&format!("(?s){}", pattern)).ok())
+            .as_ref()
+            .map(|r| r.is_match(text))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::function::Function;
+    use value::{ValType, Value};
+
+    fn eval(function: Function, args: Vec<Value>) -> Value {
+        function.evaluate(&args)
+    }
+
+    #[test]
+    fn strings() {
+        let text = || Value::text("Hello World");
+        assert_eq!(
+            eval(Function::Lower, vec![text()]),
+            Value::text("hello world")
+        );
+        assert_eq!(
+            eval(Function::Substring, vec![text(), Value::int(7)]),
+            Value::text("World")
+        );
+        assert_eq!(
+            eval(
+                Function::Substring,
+                vec![text(), Value::int(0), Value::int(3)]
+            ),
+            Value::text("He")
+        );
+        assert_eq!(
+            eval(Function::Like, vec![text(), Value::text("H_llo%")]),
+            Value::bool(true)
+        );
+        assert_eq!(
+            eval(Function::Like, vec![text(), Value::text("World%")]),
+            Value::bool(false)
+        );
+        assert_eq!(
+            eval(Function::Regex, vec![text(), Value::text("o W")]),
+            Value::bool(true)
+        );
+        assert_eq!(
+            eval(Function::Length, vec![Value::text("äöü")]),
+            Value::int(3)
+        );
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(eval(Function::Abs, vec![Value::int(-3)]), Value::int(3));
+        assert_eq!(
+            eval(Function::Round, vec![Value::float(2.456), Value::int(2)]),
+            Value::float(2.46)
+        );
+        assert_eq!(
+            eval(Function::Floor, vec![Value::float(-1.5)]),
+            Value::float(-2.0)
+        );
+        assert_eq!(
+            eval(Function::Mod, vec![Value::int(7), Value::int(3)]),
+            Value::int(1)
+        );
+        assert_eq!(
+            eval(Function::Mod, vec![Value::int(7), Value::int(0)]),
+            Value::null()
+        );
+        assert_eq!(
+            eval(Function::Pow, vec![Value::int(2), Value::int(10)]),
+            Value::float(1024.0)
+        );
+    }
+
+    #[test]
+    fn nulls_and_casts() {
+        assert_eq!(eval(Function::Upper, vec![Value::null()]), Value::null());
+        assert_eq!(
+            eval(Function::Coalesce, vec![Value::null(), Value::int(2)]),
+            Value::int(2)
+        );
+        assert_eq!(
+            eval(Function::NullIf, vec![Value::int(2), Value::int(2)]),
+            Value::null()
+        );
+        let cast = |t: ValType, v: Value| eval(Function::Cast(t), vec![v]);
+        assert_eq!(cast(ValType::Integer, Value::text(" 42 ")), Value::int(42));
+        assert_eq!(cast(ValType::Integer, Value::text("a")), Value::null());
+        assert_eq!(cast(ValType::Text, Value::float(1.5)), Value::text("1.5"));
+        assert_eq!(
+            cast(ValType::Date, Value::text("2024-02-29")),
+            Value::date(19782)
+        );
+        assert_eq!(cast(ValType::Date, Value::text("yesterday")), Value::null());
+    }
+
+    #[test]
+    fn dates() {
+        let time = Value::time(1_709_216_130_000, 0); // 2024-02-29 14:15:30
+        assert_eq!(eval(Function::Year, vec![time.clone()]), Value::int(2024));
+        assert_eq!(eval(Function::Month, vec![time.clone()]), Value::int(2));
+        assert_eq!(eval(Function::Hour, vec![time.clone()]), Value::int(14));
+        assert_eq!(eval(Function::Second, vec![time]), Value::int(30));
+        assert_eq!(
+            eval(Function::Day, vec![Value::date(19782)]),
+            Value::int(29)
+        );
+    }
+}
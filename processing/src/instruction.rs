@@ -1,17 +1,38 @@
+use crate::function::Function;
+
 #[derive(Clone, Debug)]
 pub enum Instruction {
     // Scalar Ops
     LoadField(usize), // load value from record
     StoreField(usize),
+    Replace(usize), // arg = how many values to pop from the stack to form the new record
     PushConst(usize),
     Add,
     Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
     Equal,
-    Index,
+    NotEqual,
+    And,
+    Or,
+    Not,
+    IsNull,
+    In(usize),   // arg = how many list items to compare the value below them with
+    Path(usize), // arg = how many keys to follow from the value below them, missing ones are null
+    SetKey,      // sets the key below the topmost value in the document below it
     Minus,
     Multiply,
     Divide,
     Length,
+    Cond, // picks the second or third value below depending on the first
+    Concat(usize),
+    Array(usize), // arg = how many values to pop from the stack to form the array
+    HasLabel,
+    StartNode,
+    EndNode,
+    Call(Function, usize), // arg = how many arguments to pop from the stack
+    CallUdf(usize, usize), // function of the program and how many arguments to pop
 
     // Flatten
     Flatten,
@@ -1,7 +1,11 @@
-use crate::jit::value::{Expr, VarId};
+use crate::expression::Expression;
+use crate::operator::Operator;
+use crate::{Algebra, Scan, Schema};
 use cranelift::prelude::*;
 use cranelift_jit::{JITBuilder, JITModule};
 use cranelift_module::Module;
+use std::mem;
+use value::ValType;
 
 pub struct QueryCompiler {
     builder_context: FunctionBuilderContext,
@@ -20,527 +24,347 @@ impl QueryCompiler {
         }
     }
 
-    // Example: Compiling a simple arithmetic expression from the tree
-    fn compile_expr(&self, builder: &mut FunctionBuilder, expr: Expr) -> Value {
-        match expr {
-            Expr::Literal(val) => {
-                let int = self.module.target_config().pointer_type();
-                builder.ins().iconst(int, i64::from(val))
-            },
-            Expr::Add(lhs, rhs) => {
-                let l = self.compile_expr(builder, *lhs);
-                let r = self.compile_expr(builder, *rhs);
-                builder.ins().iadd(l, r)
-            },
-            Expr::Col(VarId(id)) => {
-                // Map VarId to a pre-defined Cranelift Stack Slot or Parameter
-                builder.use_var(Variable::from_u32(id))
-            }
+    /// Compiles a chain of projections and filters over a scan of numeric fields into a function
+    /// which reads one record from its input slots and writes the result into its output slots.
+    /// Returns `None` if the algebra uses anything which has no native counterpart.
+    pub(crate) fn compile_algebra(mut self, algebra: &Algebra) -> Option<Compiled> {
+        let inputs = scanned(algebra)?;
+
+        let pointer = self.module.target_config().pointer_type();
+        let mut sig = self.module.make_signature();
+        sig.params.push(AbiParam::new(pointer));
+        sig.params.push(AbiParam::new(pointer));
+        sig.returns.push(AbiParam::new(types::I64));
+        self.ctx.func.signature = sig;
+
+        let mut builder = FunctionBuilder::new(&mut self.ctx.func, &mut self.builder_context);
+        let entry = builder.create_block();
+        let reject = builder.create_block();
+        let fallback = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        builder.seal_block(entry);
+        let input = builder.block_params(entry)[0];
+        let output = builder.block_params(entry)[1];
+
+        let fields = lower(&mut builder, algebra, input, reject, fallback)?;
+        for (i, (_, field)) in fields.iter().enumerate() {
+            let slot = match field.native {
+                Native::Bool => builder.ins().uextend(types::I64, field.value),
+                Native::Int | Native::Float => field.value,
+            };
+            builder
+                .ins()
+                .store(MemFlags::trusted(), slot, output, (i * 8) as i32);
         }
+        let pass = builder.ins().iconst(types::I64, 1);
+        builder.ins().return_(&[pass]);
+
+        builder.switch_to_block(reject);
+        builder.seal_block(reject);
+        let drop = builder.ins().iconst(types::I64, 0);
+        builder.ins().return_(&[drop]);
+
+        builder.switch_to_block(fallback);
+        builder.seal_block(fallback);
+        let unsupported = builder.ins().iconst(types::I64, 2);
+        builder.ins().return_(&[unsupported]);
+        builder.finalize();
+
+        let id = self
+            .module
+            .declare_anonymous_function(&self.ctx.func.signature)
+            .ok()?;
+        self.module.define_function(id, &mut self.ctx).ok()?;
+        self.module.clear_context(&mut self.ctx);
+        self.module.finalize_definitions().ok()?;
+        let code = self.module.get_finalized_function(id);
+
+        Some(Compiled {
+            // SAFETY: the function was built with the signature of `NativeFn` on the host's
+            // default calling convention
+            function: unsafe { mem::transmute::<*const u8, NativeFn>(code) },
+            inputs,
+            outputs: fields.into_iter().map(|(_, f)| f.native).collect(),
+            _module: self.module,
+        })
     }
 }
 
+type NativeFn = unsafe extern "C" fn(*const i64, *mut i64) -> i64;
 
-pub struct  SIMDQueryCompiler {
-    builder_context: FunctionBuilderContext,
-    ctx: codegen::Context,
-    module: JITModule,
+/// How a value is kept in its 8-byte slot: integers as they are, floats as their bits and
+/// booleans as 0 or 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Native {
+    Int,
+    Float,
+    Bool,
 }
 
-impl SIMDQueryCompiler {
-
-    pub fn new() -> Self {
-        let mut flag_builder = settings::builder();
-        //flag_builder.set("enable_simd", "true").unwrap();
-        flag_builder.set("opt_level", "speed").unwrap();
-        //flag_builder.set("enable_simd", "true").unwrap();
-
-        let mut isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
-            panic!("host machine is unsupported: {}", msg);
-        });
-        isa_builder.enable("has_avx2").unwrap();
-        isa_builder.enable("has_avx").unwrap();
-        isa_builder.enable("has_sse41").unwrap();
-        let isa = isa_builder.finish(settings::Flags::new(flag_builder)).unwrap();
-
-        let builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
-        let mut module = JITModule::new(builder);
-        Self {
-            builder_context: FunctionBuilderContext::new(),
-            ctx: module.make_context(),
-            module,
+impl Native {
+    fn of(val_type: &ValType) -> Option<Self> {
+        match val_type {
+            ValType::Integer => Some(Native::Int),
+            ValType::Float => Some(Native::Float),
+            ValType::Bool => Some(Native::Bool),
+            _ => None,
         }
-
     }
+}
 
-    fn compile_simd_expr(builder: &mut FunctionBuilder, base_ptr: Value, expr: Expr) -> Value {
-        // We use I64X2 for 2-lane SIMD (128-bit)
-        let simd_type = types::I64X2;
-
-        match expr {
-            Expr::Literal(val) => {
-
-                // To load a constant into SIMD, we broadcast the scalar to all lanes
-                let scalar = builder.ins().iconst(types::I64, val);
-                builder.ins().splat(simd_type, scalar)
-            }
-            Expr::Add(lhs, rhs) => {
-                let l = Self::compile_simd_expr(builder, base_ptr, *lhs);
-                let r = Self::compile_simd_expr(builder, base_ptr, *rhs);
+/// A natively compiled algebra with the layout of the records it reads and writes.
+pub(crate) struct Compiled {
+    function: NativeFn,
+    pub(crate) inputs: Vec<(String, Native)>,
+    pub(crate) outputs: Vec<Native>,
+    // owns the memory the function lives in
+    _module: JITModule,
+}
 
-                builder.ins().iadd(l, r)
-            },
-            Expr::Col(VarId(id)) => {
-                // Calculate offset: VarId * size_of(i64)
-                // For simplicity, let's assume VarId is the byte offset here
-                let offset = id as i32;
-                let mut flags = MemFlags::new();
-                flags.set_notrap(); // Safer for debugging alignment issues
-                builder.ins().load(simd_type, flags, base_ptr, offset)
-            }
+// SAFETY: the module is only kept to own the finalized code, which is immutable and can be
+// called from any thread
+unsafe impl Send for Compiled {}
+unsafe impl Sync for Compiled {}
+
+impl Compiled {
+    /// Evaluates one record, returns whether it passed all filters or `None` if the record has
+    /// to be evaluated by the tuple VM, e.g. as an integer overflows.
+    pub(crate) fn call(&self, input: &[i64], output: &mut [i64]) -> Option<bool> {
+        assert!(input.len() >= self.inputs.len() && output.len() >= self.outputs.len());
+        // SAFETY: the function reads exactly one slot per input field and writes one per output
+        match unsafe { (self.function)(input.as_ptr(), output.as_mut_ptr()) } {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
         }
     }
+}
 
-    fn compile_simd_expr_wide(
-        builder: &mut FunctionBuilder,
-        base_ptr: Value,
-        expr: Expr,
-        dtype: ScalarType, // Pass the expected type here
-        lanes: u16
-    ) -> WideValue {
-        // We use I64X2 for 2-lane SIMD (128-bit)
-        let simd_type = get_simd_type(dtype, lanes);
-
-        match expr {
-            Expr::Literal(val) => {
-                let scalar = builder.ins().iconst(types::I64, val);
-                let vec = builder.ins().splat(simd_type, scalar);
-
-                let vec = builder.ins().splat(simd_type, scalar);
-                WideValue { low: vec, high: vec }
-            }
-            Expr::Add(lhs, rhs) => {
-                let l = Self::compile_simd_expr_wide(builder, base_ptr, *lhs, dtype.clone(), lanes);
-                let r = Self::compile_simd_expr_wide(builder, base_ptr, *rhs, dtype, lanes);
+#[derive(Clone, Copy)]
+struct Typed {
+    value: Value,
+    native: Native,
+}
 
-                match dtype {
-                    ScalarType::I64 => WideValue {
-                        low: builder.ins().iadd(l.low, r.low),
-                        high: builder.ins().iadd(l.high, r.high),
-                    },
-                    ScalarType::F64 => WideValue {
-                        low: builder.ins().fadd(l.low, r.low),
-                        high: builder.ins().fadd(l.high, r.high),
-                    },
-                    _ => todo!()
-                }
-            },
-            Expr::Col(VarId(id)) => {
-                let offset_low = id as i32;
-                let offset_high = offset_low + 16; // 16 bytes = 128 bits
+/// The fields of the scan below the projections and filters, if they all have a native type.
+fn scanned(algebra: &Algebra) -> Option<Vec<(String, Native)>> {
+    match algebra {
+        Algebra::Scan(Scan {
+            schema: Schema::Fixed(fields),
+            ..
+        }) => fields
+            .iter()
+            .map(|(name, t)| Some((name.clone(), Native::of(t)?)))
+            .collect(),
+        Algebra::Project(p) => scanned(&p.input),
+        Algebra::Filter(f) => scanned(&f.input),
+        _ => None,
+    }
+}
 
-                WideValue {
-                    low: builder.ins().load(simd_type, MemFlags::trusted(), base_ptr, offset_low),
-                    high: builder.ins().load(simd_type, MemFlags::trusted(), base_ptr, offset_high),
+fn lower(
+    builder: &mut FunctionBuilder,
+    algebra: &Algebra,
+    input: Value,
+    reject: Block,
+    fallback: Block,
+) -> Option<Vec<(String, Typed)>> {
+    match algebra {
+        Algebra::Scan(_) => scanned(algebra)?
+            .into_iter()
+            .enumerate()
+            .map(|(i, (name, native))| {
+                let (flags, offset) = (MemFlags::trusted(), (i * 8) as i32);
+                let value = match native {
+                    Native::Int => builder.ins().load(types::I64, flags, input, offset),
+                    Native::Float => builder.ins().load(types::F64, flags, input, offset),
+                    Native::Bool => {
+                        let slot = builder.ins().load(types::I64, flags, input, offset);
+                        builder.ins().icmp_imm(IntCC::NotEqual, slot, 0)
+                    }
+                };
+                Some((name, Typed { value, native }))
+            })
+            .collect(),
+        Algebra::Filter(f) => {
+            let fields = lower(builder, &f.input, input, reject, fallback)?;
+            for conjunct in f.predicate.conjuncts() {
+                let predicate = lower_expr(builder, conjunct, &fields, fallback)?;
+                if predicate.native != Native::Bool {
+                    return None;
                 }
+                let next = builder.create_block();
+                builder.ins().brif(predicate.value, next, &[], reject, &[]);
+                builder.switch_to_block(next);
+                builder.seal_block(next);
             }
+            Some(fields)
+        }
+        Algebra::Project(p) => {
+            let schema = p.input.schema().ok()?;
+            let fields = lower(builder, &p.input, input, reject, fallback)?;
+            p.resolve(&schema)
+                .into_iter()
+                .map(|(name, e)| Some((name, lower_expr(builder, &e, &fields, fallback)?)))
+                .collect()
         }
+        _ => None,
     }
+}
 
-    fn compile_simd_loop(
-        builder: &mut FunctionBuilder,
-        base_ptr: Value,
-        expr: Expr,
-        dtype: ScalarType, // Pass the expected type here
-        lanes: u16
-    ) -> WideValue {
-        // We use I64X2 for 2-lane SIMD (128-bit)
-        let simd_type = get_simd_type(dtype, lanes);
-
-        match expr {
-            Expr::Literal(val) => {
-                // Handle float vs int literals
-                let scalar = match dtype {
-                    ScalarType::F64 => builder.ins().f64const(val as f64),
-                    _ => builder.ins().iconst(types::I64, val as i64),
-                };
-                let vec = builder.ins().splat(simd_type, scalar);
-                WideValue { low: vec, high: vec }
-            }
-            Expr::Add(lhs, rhs) => {
-                let l = Self::compile_simd_loop(builder, base_ptr, *lhs, dtype.clone(), lanes);
-                let r = Self::compile_simd_loop(builder, base_ptr, *rhs, dtype, lanes);
-
-                match dtype {
-                    ScalarType::I64 => WideValue {
-                        low: builder.ins().iadd(l.low, r.low),
-                        high: builder.ins().iadd(l.high, r.high),
-                    },
-                    ScalarType::F64 => WideValue {
-                        low: builder.ins().fadd(l.low, r.low),
-                        high: builder.ins().fadd(l.high, r.high),
-                    },
-                    _ => todo!()
+/// Lowers the expression, integer overflows jump to `fallback` so that the tuple VM reports them.
+fn lower_expr(
+    builder: &mut FunctionBuilder,
+    expression: &Expression,
+    fields: &[(String, Typed)],
+    fallback: Block,
+) -> Option<Typed> {
+    let (operator, expressions) = match expression {
+        Expression::Field(name) => {
+            return fields.iter().find(|(n, _)| n == name).map(|(_, t)| *t);
+        }
+        Expression::Literal(literal) => {
+            return match literal {
+                value::Value::Int(i) => Some(Typed {
+                    value: builder.ins().iconst(types::I64, i.0),
+                    native: Native::Int,
+                }),
+                value::Value::Float(f) => Some(Typed {
+                    value: builder.ins().f64const(f.0.0),
+                    native: Native::Float,
+                }),
+                value::Value::Bool(b) => Some(Typed {
+                    value: builder.ins().iconst(types::I8, b.0 as i64),
+                    native: Native::Bool,
+                }),
+                _ => None,
+            };
+        }
+        Expression::Call {
+            operator,
+            expressions,
+        } => (operator, expressions),
+        Expression::Wildcard | Expression::Exclude(_) => return None,
+    };
+    let args = expressions
+        .iter()
+        .map(|e| lower_expr(builder, e, fields, fallback))
+        .collect::<Option<Vec<_>>>()?;
+
+    let bool = |value| Some(Typed { value, native: Native::Bool });
+    match (operator, args.as_slice()) {
+        (Operator::Add | Operator::Minus | Operator::Multiply, [l, r]) => {
+            match (l.native, r.native) {
+                (Native::Int, Native::Int) => {
+                    let (value, overflow) = match operator {
+                        Operator::Add => builder.ins().sadd_overflow(l.value, r.value),
+                        Operator::Minus => builder.ins().ssub_overflow(l.value, r.value),
+                        _ => builder.ins().smul_overflow(l.value, r.value),
+                    };
+                    let next = builder.create_block();
+                    builder.ins().brif(overflow, fallback, &[], next, &[]);
+                    builder.switch_to_block(next);
+                    builder.seal_block(next);
+                    Some(Typed {
+                        value,
+                        native: Native::Int,
+                    })
                 }
-            },
-            Expr::Col(VarId(_id)) => {
-                // In a loop, the base_ptr is already incremented to the correct row.
-                // Low part: first 128 bits (offset 0)
-                // High part: second 128 bits (offset 16 bytes)
-                WideValue {
-                    low: builder.ins().load(simd_type, MemFlags::trusted(), base_ptr, 0),
-                    high: builder.ins().load(simd_type, MemFlags::trusted(), base_ptr, 16),
+                _ => {
+                    let (l, r) = (float(builder, *l)?, float(builder, *r)?);
+                    Some(Typed {
+                        value: match operator {
+                            Operator::Add => builder.ins().fadd(l, r),
+                            Operator::Minus => builder.ins().fsub(l, r),
+                            _ => builder.ins().fmul(l, r),
+                        },
+                        native: Native::Float,
+                    })
                 }
             }
         }
-    }
-
-}
-
-
-struct WideValue {
-    low: Value,
-    high: Value,
-}
-
-#[derive(Debug, Copy, Clone)]
-enum ScalarType {
-    I32,
-    I64,
-    F64,
-}
-
-// Map your type to a Cranelift SIMD type
-fn get_simd_type(scalar: ScalarType, lanes: u16) -> Type {
-    match (scalar, lanes) {
-        (ScalarType::I64, 2) => types::I64X2,
-        (ScalarType::I32, 4) => types::I32X4,
-        (ScalarType::F64, 2) => types::F64X2,
-        _ => todo!("Unsupported SIMD width"),
+        (
+            Operator::Equal
+            | Operator::NotEqual
+            | Operator::Gt
+            | Operator::Gte
+            | Operator::Lt
+            | Operator::Lte,
+            [l, r],
+        ) => bool(compare(builder, operator, *l, *r)?),
+        (Operator::In, [l, candidates @ ..]) if !candidates.is_empty() => {
+            let mut any = compare(builder, &Operator::Equal, *l, candidates[0])?;
+            for candidate in &candidates[1..] {
+                let equal = compare(builder, &Operator::Equal, *l, *candidate)?;
+                any = builder.ins().bor(any, equal);
+            }
+            bool(any)
+        }
+        (Operator::And, [l, r]) if l.native == Native::Bool && r.native == Native::Bool => {
+            bool(builder.ins().band(l.value, r.value))
+        }
+        (Operator::Or, [l, r]) if l.native == Native::Bool && r.native == Native::Bool => {
+            bool(builder.ins().bor(l.value, r.value))
+        }
+        (Operator::Not, [v]) if v.native == Native::Bool => {
+            bool(builder.ins().icmp_imm(IntCC::Equal, v.value, 0))
+        }
+        // fields of native types are never null
+        (Operator::IsNull, [_]) => bool(builder.ins().iconst(types::I8, 0)),
+        (Operator::Cond, [c, t, e]) if c.native == Native::Bool && t.native == e.native => {
+            Some(Typed {
+                value: builder.ins().select(c.value, t.value, e.value),
+                native: t.native,
+            })
+        }
+        _ => None,
     }
 }
 
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use cranelift_jit::{JITBuilder, JITModule};
-    use cranelift_module::{Linkage, Module};
-    use std::mem;
-    use std::time::Instant;
-    use cranelift_codegen::ir::BlockArg;
-
-    #[test]
-    fn test_jit_add_expression() {
-        // 1. Setup JIT Module
-        let builder = JITBuilder::new(cranelift_module::default_libcall_names()).unwrap();
-        let mut module = JITModule::new(builder);
-        let mut ctx = module.make_context();
-        let mut func_ctx = FunctionBuilderContext::new();
-
-        // 2. Define Function Signature: fn(i64) -> i64
-        // This represents a query taking one column value as input
-        let mut sig = module.make_signature();
-        sig.params.push(AbiParam::new(types::I64));
-        sig.returns.push(AbiParam::new(types::I64));
-        ctx.func.signature = sig;
-
-        // 3. Build the Function Body
-        let mut builder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
-        let entry_block = builder.create_block();
-        builder.append_block_params_for_function_params(entry_block);
-        builder.switch_to_block(entry_block);
-        builder.seal_block(entry_block);
-
-        // Map our Newtype VarId(0) to the first function parameter
-        let var0 = builder.declare_var(types::I64);
-        let param0 = builder.block_params(entry_block)[0];
-        builder.def_var(var0, param0);
-
-        // Define Algebra: (Column 0 + 10)
-        let expr = Expr::Add(
-            Box::new(Expr::Col(VarId(0))),
-            Box::new(Expr::Literal(10))
-        );
-
-        // Compile the expression using your logic
-        let mut compiler = QueryCompiler::new();
-        let result = compiler.compile_expr(&mut builder, expr);
-
-        builder.ins().return_(&[result]);
-        builder.finalize();
-
-        // 4. Compile to Machine Code
-        let id = compiler.module.declare_function("query_fn", Linkage::Export, &ctx.func.signature).unwrap();
-        compiler.module.define_function(id, &mut ctx).unwrap();
-        compiler.module.finalize_definitions().unwrap();
-
-        // 5. Execute
-        let code = compiler.module.get_finalized_function(id);
-        let ptr = unsafe { mem::transmute::<*const u8, fn(i64) -> i64>(code) };
-
-        assert_eq!(ptr(5), 15);  // 5 + 10 = 15
-        assert_eq!(ptr(100), 110);
-    }
-
-    #[test]
-    fn test_jit_simd_add() {
-        // 1. Setup JIT Module
-        //let builder = JITBuilder::new(cranelift_module::default_libcall_names()).unwrap();
-        //let mut module = JITModule::new(builder);
-        let mut compiler = SIMDQueryCompiler::new();
-
-        //let mut ctx = module.make_context();
-        //let mut func_ctx = FunctionBuilderContext::new();
-
-        // 2. Define Function Signature: fn(i64) -> i64
-        // This represents a query taking one column value as input
-        let mut sig = compiler.module.make_signature();
-
-        // Use the HOST calling convention (SystemV for Linux/Mac, WindowsFastcall for Windows)
-        //sig.call_conv = CallConv::triple_default(&HOST);
-
-        // Ensure the pointer is the correct width for the architecture
-        let ptr_type = compiler.module.target_config().pointer_type();
-
-        // Parameter 0: The Input Pointer (Scalar i64)
-        sig.params.push(AbiParam::new(types::I64));
-        // Parameter 1: The Output Pointer (Scalar i64)
-        sig.params.push(AbiParam::new(types::I64));
-        compiler.ctx.func.signature = sig;
-
-        // 3. Build the Function Body
-        let mut builder = FunctionBuilder::new(&mut compiler.ctx.func, &mut compiler.builder_context);
-        let entry_block = builder.create_block();
-        builder.append_block_params_for_function_params(entry_block);
-        builder.switch_to_block(entry_block);
-        builder.seal_block(entry_block);
-
-        let input_ptr = builder.block_params(entry_block)[0];
-        let output_ptr = builder.block_params(entry_block)[1];
-
-        // Define Algebra: (Column 0 + 10)
-        let expr = Expr::Add(
-            Box::new(Expr::Col(VarId(0))),
-            Box::new(Expr::Literal(10))
-        );
-
-        // Compile the expression using your logic
-        let result = SIMDQueryCompiler::compile_simd_expr(&mut builder, input_ptr, expr);
-
-        builder.ins().store(MemFlags::trusted(), result, output_ptr, 0);
-        builder.ins().return_(&[]);
-        builder.finalize();
-
-        // 4. Compile to Machine Code
-        let id = compiler.module.declare_function("query_fn", Linkage::Export, &compiler.ctx.func.signature).unwrap();
-
-        println!("{}", compiler.ctx.func.display());
-
-        compiler.module.define_function(id, &mut compiler.ctx).unwrap();
-        compiler.module.finalize_definitions().unwrap();
-
-        // 5. Execute
-        // Define a type for our 128-bit SIMD vector (2 x i64)
-        type JitFunc = unsafe extern "system" fn(*const i64, *mut i64);
-        let code = compiler.module.get_finalized_function(id);
-        let ptr: JitFunc = unsafe { mem::transmute(code) };
-
-        let input_vec: Vec<i64> = (0..4).collect();
-        let mut output_vec: Vec<i64> = vec![0; 4];
-
-        let now = Instant::now();
-        unsafe {
-            // Pass the pointer to the heap-allocated data
-            ptr(input_vec.as_ptr(), output_vec.as_mut_ptr());
+/// Compares two values like the tuple VM does, numbers by their numeric value regardless of their
+/// type and booleans only for equality.
+fn compare(
+    builder: &mut FunctionBuilder,
+    operator: &Operator,
+    l: Typed,
+    r: Typed,
+) -> Option<Value> {
+    let int = match operator {
+        Operator::Equal => IntCC::Equal,
+        Operator::NotEqual => IntCC::NotEqual,
+        Operator::Gt => IntCC::SignedGreaterThan,
+        Operator::Gte => IntCC::SignedGreaterThanOrEqual,
+        Operator::Lt => IntCC::SignedLessThan,
+        Operator::Lte => IntCC::SignedLessThanOrEqual,
+        _ => return None,
+    };
+    match (l.native, r.native) {
+        (Native::Int, Native::Int) => Some(builder.ins().icmp(int, l.value, r.value)),
+        (Native::Bool, Native::Bool) if matches!(int, IntCC::Equal | IntCC::NotEqual) => {
+            Some(builder.ins().icmp(int, l.value, r.value))
         }
-
-        println!("duration: {:?}", now.elapsed());
-        assert_eq!(output_vec[0], 10);  // 0 + 10
-        assert_eq!(output_vec[1], 11); // 1 + 10
-    }
-
-    #[test]
-    fn test_jit_simd_add_wide() {
-        // 1. Setup JIT Module
-        let mut compiler = SIMDQueryCompiler::new();
-
-        let mut sig = compiler.module.make_signature();
-
-        // Ensure the pointer is the correct width for the architecture
-        let ptr_type = compiler.module.target_config().pointer_type();
-
-        sig.params.push(AbiParam::new(ptr_type));
-        sig.params.push(AbiParam::new(ptr_type));
-        compiler.ctx.func.signature = sig;
-
-        // 3. Build the Function Body
-        let mut builder = FunctionBuilder::new(&mut compiler.ctx.func, &mut compiler.builder_context);
-        let entry_block = builder.create_block();
-        builder.append_block_params_for_function_params(entry_block);
-        builder.switch_to_block(entry_block);
-        builder.seal_block(entry_block);
-
-        let input_ptr = builder.block_params(entry_block)[0];
-        let output_ptr = builder.block_params(entry_block)[1];
-
-        // Define Algebra: (Column 0 + 10)
-        let expr = Expr::Add(
-            Box::new(Expr::Col(VarId(0))),
-            Box::new(Expr::Literal(10))
-        );
-
-        // Compile the expression using your logic
-        let result = SIMDQueryCompiler::compile_simd_expr_wide(&mut builder, input_ptr, expr, ScalarType::I64, 2);
-        builder.ins().store(MemFlags::trusted(), result.low, output_ptr, 0);
-        builder.ins().store(MemFlags::trusted(), result.high, output_ptr, 16);
-
-        builder.ins().return_(&[]);
-        builder.finalize();
-
-        // 4. Compile to Machine Code
-        let id = compiler.module.declare_function("query_fn", Linkage::Export, &compiler.ctx.func.signature).unwrap();
-
-        println!("{}", compiler.ctx.func.display());
-
-        compiler.module.define_function(id, &mut compiler.ctx).unwrap();
-        compiler.module.finalize_definitions().unwrap();
-
-        // 5. Execute
-        // Define a type for our 128-bit SIMD vector (2 x i64)
-        type JitFunc = unsafe extern "system" fn(*const i64, *mut i64);
-        let code = compiler.module.get_finalized_function(id);
-        let ptr: JitFunc = unsafe { mem::transmute(code) };
-
-        // 3. Execution
-        let input_vec: Vec<i64> = vec![0, 1, 2, 3]; // 4 elements = 256 bits
-        let mut output_vec: Vec<i64> = vec![0; 4];
-
-        let now = Instant::now();
-        unsafe {
-            // Note: ensure input_vec is 16-byte aligned (standard Vec usually is)
-            ptr(input_vec.as_ptr(), output_vec.as_mut_ptr());
+        (Native::Bool, _) | (_, Native::Bool) => None,
+        _ => {
+            let cc = match operator {
+                Operator::Equal => FloatCC::Equal,
+                Operator::NotEqual => FloatCC::NotEqual,
+                Operator::Gt => FloatCC::GreaterThan,
+                Operator::Gte => FloatCC::GreaterThanOrEqual,
+                Operator::Lt => FloatCC::LessThan,
+                _ => FloatCC::LessThanOrEqual,
+            };
+            let (l, r) = (float(builder, l)?, float(builder, r)?);
+            Some(builder.ins().fcmp(cc, l, r))
         }
-        println!("duration: {:?}", now.elapsed());
-
-        assert_eq!(output_vec[0], 10);
-        assert_eq!(output_vec[1], 11);
-        assert_eq!(output_vec[2], 12);
-        assert_eq!(output_vec[3], 13);
     }
+}
 
-    #[test]
-    fn test_jit_simd_add_loop() {
-        // Define Algebra: (Column 0 + 10)
-        let expr = Expr::Add(
-            Box::new(Expr::Col(VarId(0))),
-            Box::new(Expr::Literal(10))
-        );
-
-        // 1. Setup JIT Module
-        let mut compiler = SIMDQueryCompiler::new();
-
-        let mut sig = compiler.module.make_signature();
-
-        // Ensure the pointer is the correct width for the architecture
-        let ptr_type = compiler.module.target_config().pointer_type();
-
-        // Param 0: Input Pointer, Param 1: Output Pointer, Param 2: Num Blocks (i64)
-        sig.params.push(AbiParam::new(ptr_type));
-        sig.params.push(AbiParam::new(ptr_type));
-        sig.params.push(AbiParam::new(types::I64));
-        compiler.ctx.func.signature = sig;
-
-        // 3. Build the Function Body
-        let mut builder = FunctionBuilder::new(&mut compiler.ctx.func, &mut compiler.builder_context);
-        let entry_block = builder.create_block();
-        builder.append_block_params_for_function_params(entry_block);
-        builder.switch_to_block(entry_block);
-        builder.seal_block(entry_block);
-
-        let input_ptr = builder.block_params(entry_block)[0];
-        let output_ptr = builder.block_params(entry_block)[1];
-        let num_blocks = builder.block_params(entry_block)[2];
-
-        let header_block = builder.create_block();
-        builder.append_block_param(header_block, types::I64);
-
-        let loop_block = builder.create_block();
-        let exit_block = builder.create_block();
-
-        // Initial index = 0
-        let i0 = builder.ins().iconst(types::I64, 0);
-        builder.ins().jump(header_block, &[BlockArg::Value(i0)]);
-
-        // Start Loop
-        builder.switch_to_block(header_block);
-        let i = builder.block_params(header_block)[0];
-
-        // 1. Check condition: if i >= num_blocks, jump to exit
-        let cmp = builder.ins().icmp(IntCC::SignedLessThan, i, num_blocks);
-        builder.ins().brif(cmp, loop_block, &[], exit_block, &[]); // Note: This logic varies by version;
-        // typically: brif(cond, body_block, args, exit_block, args)
-
-        builder.switch_to_block(loop_block);
-
-        // --- Loop Body ---
-        // Calculate byte offset: i * 32 (because 1 WideValue = 4 x i64 = 32 bytes)
-        let thirty_two = builder.ins().iconst(types::I64, 32);
-        let byte_offset = builder.ins().imul(i, thirty_two);
-
-        // Adjust the base pointers for this iteration
-        let current_input = builder.ins().iadd(input_ptr, byte_offset);
-        let current_output = builder.ins().iadd(output_ptr, byte_offset);
-
-        // Compile your expression logic
-        let result = SIMDQueryCompiler::compile_simd_loop(&mut builder, current_input, expr, ScalarType::I64, 2);
-
-        // Store results
-        builder.ins().store(MemFlags::trusted(), result.low, current_output, 0);
-        builder.ins().store(MemFlags::trusted(), result.high, current_output, 16);
-
-        // Increment i and jump back
-        let next_i = builder.ins().iadd_imm(i, 1);
-        builder.ins().jump(header_block, &[BlockArg::Value(next_i)]);
-
-        builder.seal_block(header_block);
-
-        // Finalize Loop
-        builder.seal_block(loop_block);
-        builder.switch_to_block(exit_block);
-        builder.ins().return_(&[]);
-        builder.seal_block(exit_block);
-        builder.finalize();
-
-        // 4. Compile to Machine Code
-        let id = compiler.module.declare_function("query_fn", Linkage::Export, &compiler.ctx.func.signature).unwrap();
-
-        println!("{}", compiler.ctx.func.display());
-
-        compiler.module.define_function(id, &mut compiler.ctx).unwrap();
-        compiler.module.finalize_definitions().unwrap();
-
-        // 5. Execute
-        // Define a type for our 128-bit SIMD vector (2 x i64)
-        type JitFunc = unsafe extern "system" fn(*const i64, *mut i64, i64);
-        let code = compiler.module.get_finalized_function(id);
-        let ptr: JitFunc = unsafe { mem::transmute(code) };
-
-        // Execution
-        let count = 10_000;
-        let input_vec: Vec<i64> = (0..count).collect(); // 12 elements = 3 blocks
-        let mut output_vec: Vec<i64> = vec![0; count as usize];
-        let num_blocks = (input_vec.len() / 4) as i64;
-
-        let now = Instant::now();
-        unsafe {
-            ptr(input_vec.as_ptr(), output_vec.as_mut_ptr(), num_blocks);
-        }
-        println!("duration: {:?}", now.elapsed());
-
-        assert_eq!(output_vec[0], 10);
-        assert_eq!(output_vec[500], 510);
-        assert_eq!(output_vec[9999], 10_009);
+/// Promotes an integer to a float, like mixed arithmetic does in the tuple VM.
+fn float(builder: &mut FunctionBuilder, typed: Typed) -> Option<Value> {
+    match typed.native {
+        Native::Float => Some(typed.value),
+        Native::Int => Some(builder.ins().fcvt_from_sint(types::F64, typed.value)),
+        Native::Bool => None,
     }
-
-}
\ No newline at end of file
+}
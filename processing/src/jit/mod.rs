@@ -1,2 +1,4 @@
-mod value;
-mod builder;
\ No newline at end of file
+mod builder;
+mod program;
+
+pub use program::JitProgram;
@@ -0,0 +1,286 @@
+use crate::jit::builder::{Compiled, Native, QueryCompiler};
+use crate::{Algebra, Program, RecordError};
+use std::iter;
+use std::sync::Arc;
+use value::Value;
+
+/// Evaluates projections and filters over numeric records with natively compiled code.
+///
+/// Records which do not match the fixed schema the code was compiled for, e.g. an integer in a
+/// float field or a missing field, or which overflow an integer are evaluated by the tuple VM
+/// instead, so both give the same results and errors.
+pub struct JitProgram {
+    compiled: Arc<Compiled>,
+    source: String,
+    fallback: Program,
+    resource: Option<Box<dyn Iterator<Item = Value> + Send + Sync + 'static>>,
+    input: Vec<i64>,
+    output: Vec<i64>,
+    /// records taken from the resource since the last reset
+    read: usize,
+    errors: Vec<RecordError>,
+}
+
+impl Clone for JitProgram {
+    fn clone(&self) -> Self {
+        Self {
+            compiled: self.compiled.clone(),
+            source: self.source.clone(),
+            fallback: self.fallback.clone(),
+            resource: None,
+            input: self.input.clone(),
+            output: self.output.clone(),
+            read: 0,
+            errors: vec![],
+        }
+    }
+}
+
+impl JitProgram {
+    /// Compiles the algebra, returns `None` if it cannot be evaluated natively.
+    pub fn new(algebra: &Algebra) -> Option<Self> {
+        let compiled = QueryCompiler::new().compile_algebra(algebra)?;
+        let source = source(algebra)?;
+        Some(Self {
+            input: vec![0; compiled.inputs.len()],
+            output: vec![0; compiled.outputs.len()],
+            compiled: Arc::new(compiled),
+            source,
+            fallback: Program::try_from(algebra).ok()?,
+            resource: None,
+            read: 0,
+            errors: vec![],
+        })
+    }
+
+    pub fn set_resource<S: AsRef<str>>(
+        &mut self,
+        name: S,
+        iter: impl Iterator<Item = Value> + Send + Sync + 'static,
+    ) -> anyhow::Result<()> {
+        if name.as_ref() != self.source {
+            anyhow::bail!("No named resource in compiler")
+        }
+        self.resource = Some(Box::new(iter));
+        Ok(())
+    }
+
+    pub fn reset(&mut self) {
+        self.fallback.reset();
+        self.read = 0;
+        self.errors.clear();
+    }
+
+    /// The records the tuple VM failed to evaluate since the last call.
+    pub fn take_errors(&mut self) -> Vec<RecordError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Writes the record into the input slots, fails if it does not match the compiled schema.
+    fn encode(&mut self, record: &Value) -> bool {
+        let inputs = &self.compiled.inputs;
+        let values = match record {
+            Value::Array(a) if a.values.len() == inputs.len() => a.values.iter().collect(),
+            Value::Dict(d) => match inputs
+                .iter()
+                .map(|(name, _)| d.get(name))
+                .collect::<Option<Vec<_>>>()
+            {
+                Some(values) => values,
+                None => return false,
+            },
+            _ => return false,
+        };
+
+        for ((slot, (_, native)), value) in self.input.iter_mut().zip(inputs).zip(values) {
+            *slot = match (native, value) {
+                (Native::Int, Value::Int(i)) => i.0,
+                (Native::Float, Value::Float(f)) => f.0.0.to_bits() as i64,
+                (Native::Bool, Value::Bool(b)) => b.0 as i64,
+                _ => return false,
+            };
+        }
+        true
+    }
+
+    fn decode(&self) -> Value {
+        Value::array(
+            self.compiled
+                .outputs
+                .iter()
+                .zip(&self.output)
+                .map(|(native, slot)| match native {
+                    Native::Int => Value::int(*slot),
+                    Native::Float => Value::float(f64::from_bits(*slot as u64)),
+                    Native::Bool => Value::bool(*slot != 0),
+                })
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    fn evaluate_fallback(&mut self, record: Value) -> Option<Value> {
+        self.fallback.reset();
+        self.fallback
+            .set_resource(&self.source, iter::once(record))
+            .ok()?;
+        let row = self.fallback.next();
+        // the VM only saw this record
+        let record = self.read - 1;
+        self.errors.extend(
+            self.fallback
+                .take_errors()
+                .into_iter()
+                .map(|error| RecordError { record, ..error }),
+        );
+        row
+    }
+}
+
+fn source(algebra: &Algebra) -> Option<String> {
+    match algebra {
+        Algebra::Scan(s) => Some(s.source.clone()),
+        Algebra::Project(p) => source(&p.input),
+        Algebra::Filter(f) => source(&f.input),
+        _ => None,
+    }
+}
+
+impl Iterator for JitProgram {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let record = self.resource.as_mut()?.next()?;
+            self.read += 1;
+            let passed = match self.encode(&record) {
+                true => self.compiled.call(&self.input, &mut self.output),
+                false => None,
+            };
+            match passed {
+                Some(true) => return Some(self.decode()),
+                Some(false) => {}
+                None => {
+                    if let Some(row) = self.evaluate_fallback(record) {
+                        return Some(row);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Algebra, JitProgram, Schema, parse_sql};
+    use std::collections::HashMap;
+    use value::{ValType, Value};
+
+    fn algebra(query: &str) -> Algebra {
+        let mut algebra = parse_sql(query).unwrap();
+        algebra.set_schema(Schema::fixed([
+            ("id".to_string(), ValType::Integer),
+            ("price".to_string(), ValType::Float),
+            ("paid".to_string(), ValType::Bool),
+        ]));
+        algebra.optimized()
+    }
+
+    fn records() -> Vec<Value> {
+        (0..20)
+            .map(|i| {
+                Value::array(vec![
+                    Value::int(i),
+                    Value::float(i as f64 * 1.5),
+                    Value::bool(i % 3 == 0),
+                ])
+            })
+            .collect()
+    }
+
+    fn check(query: &str, records: Vec<Value>) {
+        let algebra = algebra(query);
+        let mut jit = JitProgram::new(&algebra).unwrap_or_else(|| panic!("{} not compiled", query));
+        jit.set_resource("$$source", records.clone().into_iter())
+            .unwrap();
+        let mut vm = algebra.processing().unwrap();
+        vm.set_resource("$$source", records.into_iter()).unwrap();
+
+        assert_eq!(
+            jit.by_ref().collect::<Vec<_>>(),
+            vm.by_ref().collect::<Vec<_>>(),
+            "{}",
+            query
+        );
+        assert_eq!(jit.take_errors(), vm.take_errors(), "{}", query);
+    }
+
+    #[test]
+    fn same_as_vm() {
+        for query in [
+            "SELECT * FROM $$source",
+            "SELECT id + 1 AS next, price * 2 AS double FROM $$source WHERE id > 4",
+            "SELECT id * price AS total FROM $$source WHERE paid AND price <= 21.0",
+            "SELECT id FROM $$source WHERE id IN (1, 2, 9) OR NOT paid",
+            "SELECT id = 3 AS three, price + id AS sum FROM $$source WHERE id <> 3",
+            "SELECT id - 10 AS off FROM $$source WHERE id IS NULL OR id >= 10",
+        ] {
+            check(query, records());
+        }
+    }
+
+    #[test]
+    fn fallback() {
+        // an integer in the float field and a dict
+        let mut records = records();
+        records.push(Value::array(vec![
+            Value::int(30),
+            Value::int(2),
+            Value::bool(true),
+        ]));
+        records.push(Value::dict(HashMap::from([
+            ("paid".to_string(), Value::bool(false)),
+            ("id".to_string(), Value::int(31)),
+            ("price".to_string(), Value::float(3.0)),
+        ])));
+        check(
+            "SELECT id, price * 2 AS double FROM $$source WHERE id > 15",
+            records,
+        );
+    }
+
+    #[test]
+    fn same_errors_as_vm() {
+        let records = vec![
+            Value::array(vec![
+                Value::int(i64::MAX),
+                Value::float(i64::MAX as f64),
+                Value::bool(true),
+            ]),
+            Value::array(vec![Value::int(3), Value::float(3.0), Value::bool(false)]),
+            Value::array(vec![Value::int(-2), Value::float(2.5), Value::bool(true)]),
+            Value::array(vec![
+                Value::int(i64::MIN),
+                Value::float(0.0),
+                Value::bool(true),
+            ]),
+        ];
+        for query in [
+            "SELECT id = price AS same, id <> price AS different FROM $$source",
+            "SELECT id + 1 AS next FROM $$source",
+            "SELECT id - 1 AS previous FROM $$source WHERE paid",
+            "SELECT id * 4611686018427387904 AS big FROM $$source WHERE id = price",
+        ] {
+            check(query, records.clone());
+        }
+    }
+
+    #[test]
+    fn not_compiled() {
+        assert!(JitProgram::new(&algebra("SELECT COUNT(*) AS c FROM $$source")).is_none());
+        assert!(JitProgram::new(&algebra("SELECT id FROM $$source WHERE id = 'a'")).is_none());
+
+        let mut dynamic = parse_sql("SELECT id + 1 AS next FROM $$source").unwrap();
+        dynamic.set_schema(Schema::Dynamic);
+        assert!(JitProgram::new(&dynamic).is_none());
+    }
+}
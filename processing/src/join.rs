@@ -0,0 +1,456 @@
+use crate::expression::Expression;
+use crate::language::Sql;
+use crate::operator::Operator;
+use crate::window::Window;
+use crate::{Algebra, Join, Program, Project, Scan, Schema};
+use anyhow::bail;
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::iter;
+use value::{ValType, Value};
+
+const LEFT: &str = "$$left";
+const RIGHT: &str = "$$right";
+const JOINED: &str = "$$joined";
+
+type Rows = HashMap<Vec<Value>, Vec<(i64, Vec<Value>)>>;
+
+/// Evaluates an equi-join between the stream of a definition (left) and another definition (right).
+///
+/// With a window, records of both sides are buffered and paired when they share a window.
+/// Without one, the right side is a table: only the id of its latest record per key is kept,
+/// the record itself is read from the engine when a left record needs it.
+pub struct Joiner {
+    window: Option<Window>,
+    right_source: String,
+    left_fields: IndexMap<String, ValType>,
+    right_fields: IndexMap<String, ValType>,
+    left_key: Program,
+    right_key: Program,
+    output: Box<Program>,
+    left: Rows,
+    right: Rows,
+    index: HashMap<Vec<Value>, u64>,
+    watermark: i64,
+}
+
+impl Joiner {
+    pub fn new(
+        algebra: &Algebra,
+        left: Schema,
+        right: Schema,
+        window: Option<Window>,
+    ) -> anyhow::Result<Self> {
+        let mut output = algebra.clone();
+        let join = Self::take_join(&mut output)?;
+        let Some(right_source) = join.right_source() else {
+            bail!("Can only join with another definition");
+        };
+
+        let (Schema::Fixed(left_fields), Schema::Fixed(right_fields)) = (left, right) else {
+            bail!("Joins need a fixed schema on both sides");
+        };
+        let qualify = |alias: &str, fields: &IndexMap<String, ValType>| {
+            fields
+                .iter()
+                .map(|(n, t)| (format!("{}.{}", alias, n), t.clone()))
+                .collect::<IndexMap<_, _>>()
+        };
+        let left_schema = qualify(&join.left_alias, &left_fields);
+        let right_schema = qualify(&join.right_alias, &right_fields);
+        let mut joined = left_schema.clone();
+        joined.extend(right_schema.clone());
+        let names = joined.keys().cloned().collect::<Vec<_>>();
+
+        Self::resolve_algebra(&mut output, &names)?;
+        output.set_schema(Schema::Fixed(joined));
+
+        let mut left_keys = IndexMap::new();
+        let mut right_keys = IndexMap::new();
+        let on = Self::resolve(&join.on, &names)?;
+        for (i, conjunct) in on.conjuncts().into_iter().enumerate() {
+            let Expression::Call {
+                operator: Operator::Equal,
+                expressions,
+            } = conjunct
+            else {
+                bail!(
+                    "Only equalities are supported in ON, got {}",
+                    conjunct.sql()
+                );
+            };
+            let (a, b) = (&expressions[0], &expressions[1]);
+            let (l, r) = match (
+                Self::refers_to(a, &left_schema),
+                Self::refers_to(b, &right_schema),
+            ) {
+                (true, true) => (a, b),
+                _ if Self::refers_to(b, &left_schema) && Self::refers_to(a, &right_schema) => {
+                    (b, a)
+                }
+                _ => bail!("{} does not compare both sides of the join", conjunct.sql()),
+            };
+            left_keys.insert(format!("key{}", i), l.clone());
+            right_keys.insert(format!("key{}", i), r.clone());
+        }
+
+        let key_program = |source: &str, schema: IndexMap<String, ValType>, keys| {
+            Program::try_from(&Algebra::Project(Project {
+                expressions: keys,
+                input: Box::new(Algebra::Scan(Scan {
+                    source: source.to_string(),
+                    schema: Schema::Fixed(schema),
+                })),
+            }))
+        };
+
+        Ok(Joiner {
+            window,
+            right_source: right_source.to_string(),
+            left_fields,
+            right_fields,
+            left_key: key_program(LEFT, left_schema, left_keys)?,
+            right_key: key_program(RIGHT, right_schema, right_keys)?,
+            output: Box::new(Program::try_from(&output)?),
+            left: HashMap::new(),
+            right: HashMap::new(),
+            index: HashMap::new(),
+            watermark: i64::MIN,
+        })
+    }
+
+    /// Replaces the join with a scan over the joined rows.
+    fn take_join(algebra: &mut Algebra) -> anyhow::Result<Join> {
+        match algebra {
+            Algebra::Join(_) => {
+                let scan = Algebra::Scan(Scan {
+                    source: JOINED.to_string(),
+                    schema: Schema::Dynamic,
+                });
+                let Algebra::Join(join) = std::mem::replace(algebra, scan) else {
+                    unreachable!()
+                };
+                Ok(join)
+            }
+            Algebra::Project(p) => Self::take_join(&mut p.input),
+            Algebra::Filter(f) => Self::take_join(&mut f.input),
+            _ => bail!("Joins can only be followed by projections and filters"),
+        }
+    }
+
+    fn resolve_algebra(algebra: &mut Algebra, names: &[String]) -> anyhow::Result<()> {
+        match algebra {
+            Algebra::Project(p) => {
+                for expression in p.expressions.values_mut() {
+                    *expression = Self::resolve(expression, names)?;
+                }
+                Self::resolve_algebra(&mut p.input, names)
+            }
+            Algebra::Filter(f) => {
+                f.predicate = Self::resolve(&f.predicate, names)?;
+                Self::resolve_algebra(&mut f.input, names)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Qualifies fields which are unique to one side, `temp` becomes `s.temp`.
+    fn resolve(expression: &Expression, names: &[String]) -> anyhow::Result<Expression> {
+        match expression {
+            Expression::Field(name) if !names.contains(name) => {
+                let suffix = format!(".{}", name);
+                let candidates = names
+                    .iter()
+                    .filter(|n| n.ends_with(&suffix))
+                    .collect::<Vec<_>>();
+                match candidates.as_slice() {
+                    [field] => Ok(Expression::Field(field.to_string())),
+                    [] => bail!("Unknown field {} in join", name),
+                    _ => bail!("Field {} is ambiguous in join", name),
+                }
+            }
+            Expression::Call {
+                operator,
+                expressions,
+            } => Ok(Expression::Call {
+                operator: operator.clone(),
+                expressions: expressions
+                    .iter()
+                    .map(|e| Self::resolve(e, names))
+                    .collect::<anyhow::Result<_>>()?,
+            }),
+            e => Ok(e.clone()),
+        }
+    }
+
+    fn refers_to(expression: &Expression, schema: &IndexMap<String, ValType>) -> bool {
+        match expression {
+            Expression::Field(name) => schema.contains_key(name),
+            Expression::Call { expressions, .. } => {
+                expressions.iter().all(|e| Self::refers_to(e, schema))
+            }
+            _ => true,
+        }
+    }
+
+    /// The source of the right side, which names the joined definition.
+    pub fn right_source(&self) -> &str {
+        &self.right_source
+    }
+
+    pub fn is_windowed(&self) -> bool {
+        self.window.is_some()
+    }
+
+    /// Adds a record of the definition itself and returns the joined rows it completes.
+    pub fn push_left(&mut self, timestamp: i64, value: Value) -> anyhow::Result<Vec<Value>> {
+        self.push(true, timestamp, value)
+    }
+
+    /// Adds a record of the joined definition and returns the joined rows it completes.
+    pub fn push_right(&mut self, timestamp: i64, value: Value) -> anyhow::Result<Vec<Value>> {
+        self.push(false, timestamp, value)
+    }
+
+    fn push(&mut self, left: bool, timestamp: i64, value: Value) -> anyhow::Result<Vec<Value>> {
+        let Some(window) = &self.window else {
+            bail!("Streams can only be joined within a window");
+        };
+        if window.last_end(timestamp) <= self.watermark {
+            return Ok(vec![]);
+        }
+
+        let (fields, program, source, own, other) = if left {
+            (
+                &self.left_fields,
+                &mut self.left_key,
+                LEFT,
+                &mut self.left,
+                &self.right,
+            )
+        } else {
+            (
+                &self.right_fields,
+                &mut self.right_key,
+                RIGHT,
+                &mut self.right,
+                &self.left,
+            )
+        };
+
+        let row = flatten(value, fields);
+        let Some(key) = key(program, source, &row)? else {
+            return Ok(vec![]);
+        };
+
+        let rows = other
+            .get(&key)
+            .into_iter()
+            .flatten()
+            .filter(|(t, _)| window.shares(timestamp, *t))
+            .map(|(_, other)| match left {
+                true => [row.clone(), other.clone()].concat(),
+                false => [other.clone(), row.clone()].concat(),
+            })
+            .collect();
+        own.entry(key).or_default().push((timestamp, row));
+
+        self.emit(rows)
+    }
+
+    /// Drops all buffered records which cannot share a window with records at or after the watermark.
+    pub fn advance(&mut self, watermark: i64) {
+        self.watermark = self.watermark.max(watermark);
+        let Some(window) = &self.window else {
+            return;
+        };
+        for rows in [&mut self.left, &mut self.right] {
+            rows.retain(|_, records| {
+                records.retain(|(t, _)| window.last_end(*t) > self.watermark);
+                !records.is_empty()
+            });
+        }
+    }
+
+    /// Remembers a record of the joined definition as the current one for its key,
+    /// returns the id of the record it replaces.
+    pub fn index(&mut self, id: u64, value: Value) -> anyhow::Result<Option<u64>> {
+        let row = flatten(value, &self.right_fields);
+        Ok(key(&mut self.right_key, RIGHT, &row)?.and_then(|key| self.index.insert(key, id)))
+    }
+
+    /// The id of the joined record matching a record of the definition itself.
+    pub fn lookup(&mut self, value: &Value) -> anyhow::Result<Option<u64>> {
+        let row = flatten(value.clone(), &self.left_fields);
+        Ok(key(&mut self.left_key, LEFT, &row)?.and_then(|key| self.index.get(&key).copied()))
+    }
+
+    /// Joins a record of the definition itself with the record read for its lookup.
+    pub fn combine(&mut self, left: Value, right: Value) -> anyhow::Result<Vec<Value>> {
+        let mut row = flatten(left, &self.left_fields);
+        row.extend(flatten(right, &self.right_fields));
+        self.emit(vec![row])
+    }
+
+    fn emit(&mut self, rows: Vec<Vec<Value>>) -> anyhow::Result<Vec<Value>> {
+        if rows.is_empty() {
+            return Ok(vec![]);
+        }
+        self.output.reset();
+        self.output
+            .set_resource(JOINED, rows.into_iter().map(Value::array))?;
+        Ok(self.output.by_ref().collect())
+    }
+}
+
+/// Brings a record into the field order of its schema.
+fn flatten(value: Value, fields: &IndexMap<String, ValType>) -> Vec<Value> {
+    match value {
+        Value::Array(a) => a.values,
+        Value::Dict(d) => fields
+            .keys()
+            .map(|k| d.get(k).cloned().unwrap_or(Value::Null))
+            .collect(),
+        v => vec![v],
+    }
+}
+
+/// The join key of a row, nulls never match anything.
+fn key(program: &mut Program, source: &str, row: &[Value]) -> anyhow::Result<Option<Vec<Value>>> {
+    program.reset();
+    program.set_resource(source, iter::once(Value::array(row.to_vec())))?;
+    match program.next() {
+        Some(Value::Array(key)) if !key.values.contains(&Value::Null) => Ok(Some(key.values)),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_sql;
+
+    fn joiner(query: &str, window: Option<Window>) -> Joiner {
+        let algebra = parse_sql(query).unwrap();
+        Joiner::new(
+            &algebra,
+            Schema::fixed([
+                ("device".to_string(), ValType::Integer),
+                ("temp".to_string(), ValType::Float),
+            ]),
+            Schema::fixed([
+                ("id".to_string(), ValType::Integer),
+                ("location".to_string(), ValType::Text),
+            ]),
+            window,
+        )
+        .unwrap()
+    }
+
+    fn sensor(device: i64, temp: f64) -> Value {
+        Value::array([Value::int(device), Value::float(temp)])
+    }
+
+    fn device(id: i64, location: &str) -> Value {
+        Value::array([Value::int(id), Value::text(location)])
+    }
+
+    #[test]
+    fn windowed() {
+        let mut joiner = joiner(
+            "SELECT s.temp, d.location FROM $$source s JOIN $$devices d ON s.device = d.id",
+            Some(Window::Tumbling { size: 10 }),
+        );
+        assert_eq!(joiner.right_source(), "$$devices");
+
+        assert!(joiner.push_right(1, device(1, "hall")).unwrap().is_empty());
+        assert_eq!(
+            joiner.push_left(2, sensor(1, 20.5)).unwrap(),
+            vec![Value::array([Value::float(20.5), Value::text("hall")])]
+        );
+        // different window
+        assert!(joiner.push_left(12, sensor(1, 21.0)).unwrap().is_empty());
+        // different key
+        assert!(joiner.push_left(3, sensor(2, 19.0)).unwrap().is_empty());
+
+        // the left record arrived first
+        assert_eq!(
+            joiner.push_right(13, device(1, "roof")).unwrap(),
+            vec![Value::array([Value::float(21.0), Value::text("roof")])]
+        );
+
+        joiner.advance(20);
+        assert!(joiner.left.is_empty() && joiner.right.is_empty());
+        assert!(joiner.push_left(15, sensor(1, 22.0)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unqualified_fields() {
+        let mut joiner = joiner(
+            "SELECT temp, location FROM $$a JOIN $$b ON device = id WHERE temp > 20",
+            Some(Window::Sliding { size: 5 }),
+        );
+        joiner.push_right(10, device(1, "hall")).unwrap();
+        assert!(joiner.push_left(12, sensor(1, 18.0)).unwrap().is_empty());
+        assert!(joiner.push_left(15, sensor(1, 25.0)).unwrap().is_empty());
+        assert_eq!(
+            joiner.push_left(14, sensor(1, 25.0)).unwrap(),
+            vec![Value::array([Value::float(25.0), Value::text("hall")])]
+        );
+    }
+
+    #[test]
+    fn lookup() {
+        let mut joiner = joiner("SELECT * FROM $$a JOIN $$b ON a.device = b.id", None);
+        assert!(!joiner.is_windowed());
+
+        assert_eq!(joiner.index(7, device(1, "hall")).unwrap(), None);
+        assert_eq!(joiner.index(8, device(1, "roof")).unwrap(), Some(7));
+
+        assert_eq!(joiner.lookup(&sensor(1, 20.0)).unwrap(), Some(8));
+        assert_eq!(joiner.lookup(&sensor(2, 20.0)).unwrap(), None);
+
+        assert_eq!(
+            joiner.combine(sensor(1, 20.0), device(1, "roof")).unwrap(),
+            vec![Value::array([
+                Value::int(1),
+                Value::float(20.0),
+                Value::int(1),
+                Value::text("roof"),
+            ])]
+        );
+    }
+
+    #[test]
+    fn qualified_paths() {
+        // `d.location` is the field of the joined source, `city` a key in it
+        let Algebra::Project(project) =
+            parse_sql("SELECT d.location.city FROM $$a s JOIN $$b d ON s.device = d.id").unwrap()
+        else {
+            panic!("expected a projection")
+        };
+        assert_eq!(
+            project.expressions["field0"],
+            Expression::path(
+                Expression::field("d.location"),
+                vec![Expression::Literal(Value::text("city"))]
+            )
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        let algebra = parse_sql("SELECT * FROM $$a JOIN $$b ON a.device > b.id").unwrap();
+        let fixed = || Schema::fixed([("device".to_string(), ValType::Integer)]);
+        assert!(
+            Joiner::new(
+                &algebra,
+                fixed(),
+                Schema::fixed([("id".to_string(), ValType::Integer)]),
+                None
+            )
+            .is_err()
+        );
+        assert!(Joiner::new(&algebra, fixed(), Schema::Dynamic, None).is_err());
+    }
+}
@@ -1,24 +1,71 @@
 use crate::expression::Expression;
-use crate::{Algebra, Project, Scan, Schema};
-use anyhow::anyhow;
+use crate::function::Function;
+use crate::language::{Language, QueryError};
+use crate::operator::Operator;
+use crate::udf::FunctionRegistry;
+use crate::{Aggregate, Algebra, Filter, Limit, Project, Scan, Schema, Sort, SortKey, Unwind};
 use indexmap::IndexMap;
-use nom::IResult;
-use nom::Parser;
 use nom::branch::alt;
-use nom::bytes::complete::tag;
-use nom::character::complete::{alpha1, alphanumeric1, multispace1};
-use nom::combinator::recognize;
-use nom::multi::many0;
-use nom::sequence::{delimited, pair};
+use nom::bytes::complete::{tag, tag_no_case, take_till};
+use nom::character::complete::{alpha1, alphanumeric1, char, digit1, multispace0, satisfy};
+use nom::combinator::{map, map_res, not, opt, recognize, value};
+use nom::error::{Error, ErrorKind};
+use nom::multi::{many0, many1, separated_list0, separated_list1};
+use nom::number::complete::recognize_float;
+use nom::sequence::{delimited, pair, preceded, terminated};
+use nom::{IResult, Parser};
+use std::collections::HashMap;
+use value::Value;
 
+/// A `MATCH … [WHERE …] [UNWIND … AS …] RETURN … [ORDER BY …] [LIMIT …]` query.
 #[derive(Debug, PartialEq)]
 pub struct MatchQuery {
-    pub src: String,          // e.g., "source"
-    pub label: String,        // e.g., "Person"
-    pub alias: String,        // e.g., "n"
-    pub return_field: String, // e.g., "age"
+    /// the definition the records come from, e.g. `$$source`
+    pub src: String,
+    pub pattern: Pattern,
+    pub predicate: Option<Term>,
+    /// lists whose elements become records of their own, bound to the variable
+    pub unwinds: Vec<(Term, String)>,
+    /// returned terms with their optional `AS` alias
+    pub returns: Vec<(Term, Option<String>)>,
+    /// terms with whether they are sorted descending
+    pub order: Vec<(Term, bool)>,
+    pub limit: Option<usize>,
 }
 
+/// What a single record is matched against, nodes match `Value::Node` records,
+/// relationships match `Value::Edge` records.
+#[derive(Debug, PartialEq)]
+pub enum Pattern {
+    Node(Element),
+    /// `(a)-[r]->(b)`, a reversed `(b)<-[r]-(a)` is stored in the same direction
+    Relationship {
+        start: Element,
+        relationship: Element,
+        end: Element,
+    },
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Element {
+    pub variable: Option<String>,
+    /// each group needs to match, `:A|B` matches either label of its group
+    pub labels: Vec<Vec<String>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Term {
+    Literal(Value),
+    Variable(String),
+    /// `n.age`
+    Property(String, String),
+    /// a function like `count(*)`, which has no arguments
+    Call(String, Vec<Term>),
+    Op(Operator, Vec<Term>),
+}
+
+type Res<'a, O> = IResult<&'a str, O>;
+
 // Parser for identifiers (n, source, Person, age)
 fn identifier(input: &str) -> IResult<&str, &str> {
     recognize(pair(
@@ -28,73 +75,771 @@ fn identifier(input: &str) -> IResult<&str, &str> {
     .parse(input)
 }
 
-// Parses: (n:$$source:Person)
-fn parse_pattern(input: &str) -> IResult<&str, (&str, &str, &str)> {
-    delimited(
-        tag("("),
+fn name(input: &str) -> Res<'_, String> {
+    map(preceded(multispace0, identifier), str::to_string).parse(input)
+}
+
+fn token<'a>(t: &'static str) -> impl Parser<&'a str, Output = &'a str, Error = Error<&'a str>> {
+    preceded(multispace0, tag(t))
+}
+
+/// Case-insensitive keyword, which is not the start of a longer identifier (OR in ORDER).
+fn keyword<'a>(
+    word: &'static str,
+) -> impl Parser<&'a str, Output = &'a str, Error = Error<&'a str>> {
+    preceded(
+        multispace0,
+        terminated(
+            tag_no_case(word),
+            not(satisfy(|c: char| {
+                c.is_alphanumeric() || c == '_' || c == '$'
+            })),
+        ),
+    )
+}
+
+// Parses: (n:$$source:Person) or [r:KNOWS|LIKES]
+fn element(input: &str) -> Res<'_, Element> {
+    map(
         (
-            identifier, // "n"
-            tag(":"),   // ":$$"
-            identifier, // "source"
-            tag(":"),   // ":"
-            identifier, // "Person"
+            opt(name),
+            many0(preceded(token(":"), separated_list1(token("|"), name))),
         ),
-        tag(")"),
+        |(variable, labels)| Element { variable, labels },
     )
     .parse(input)
-    .map(|(next, (alias, _, src, _, label))| (next, (alias, src, label)))
 }
 
-impl From<MatchQuery> for Algebra {
-    fn from(m: MatchQuery) -> Self {
-        let scan = Algebra::Scan(Scan {
-            source: m.src,
-            schema: Schema::Dynamic,
+fn node(input: &str) -> Res<'_, Element> {
+    delimited(token("("), element, token(")")).parse(input)
+}
+
+fn parse_pattern(input: &str) -> Res<'_, Pattern> {
+    let (input, start) = node(input)?;
+    let (input, relationship) = opt(alt((
+        map(
+            (token("-["), element, token("]->"), node),
+            |(_, relationship, _, end)| (relationship, end, false),
+        ),
+        map(
+            (token("<-["), element, token("]-"), node),
+            |(_, relationship, _, end)| (relationship, end, true),
+        ),
+    )))
+    .parse(input)?;
+
+    let pattern = match relationship {
+        None => Pattern::Node(start),
+        Some((relationship, end, false)) => Pattern::Relationship {
+            start,
+            relationship,
+            end,
+        },
+        Some((relationship, start_node, true)) => Pattern::Relationship {
+            start: start_node,
+            relationship,
+            end: start,
+        },
+    };
+    Ok((input, pattern))
+}
+
+fn expression(input: &str) -> Res<'_, Term> {
+    let (input, first) = conjunction(input)?;
+    let (input, rest) = many0(preceded(keyword("OR"), conjunction)).parse(input)?;
+    Ok((input, chain(Operator::Or, first, rest)))
+}
+
+fn conjunction(input: &str) -> Res<'_, Term> {
+    let (input, first) = negation(input)?;
+    let (input, rest) = many0(preceded(keyword("AND"), negation)).parse(input)?;
+    Ok((input, chain(Operator::And, first, rest)))
+}
+
+fn chain(operator: Operator, first: Term, rest: Vec<Term>) -> Term {
+    rest.into_iter().fold(first, |left, right| {
+        Term::Op(operator.clone(), vec![left, right])
+    })
+}
+
+fn negation(input: &str) -> Res<'_, Term> {
+    alt((
+        map(preceded(keyword("NOT"), negation), |t| {
+            Term::Op(Operator::Not, vec![t])
+        }),
+        comparison,
+    ))
+    .parse(input)
+}
+
+fn comparison(input: &str) -> Res<'_, Term> {
+    let (input, left) = additive(input)?;
+
+    if let Ok((input, negated)) = (keyword("IS"), opt(keyword("NOT")), keyword("NULL")).parse(input)
+    {
+        let is_null = Term::Op(Operator::IsNull, vec![left]);
+        return Ok(match negated.1 {
+            Some(_) => (input, Term::Op(Operator::Not, vec![is_null])),
+            None => (input, is_null),
         });
-        Algebra::Project(Project {
-            expressions: IndexMap::from([(
-                m.return_field.to_string(),
-                Expression::field(&m.return_field),
-            )]),
-            input: Box::new(scan),
-        })
     }
+    if let Ok((input, items)) = preceded(keyword("IN"), list).parse(input) {
+        return Ok((input, Term::Op(Operator::In, [vec![left], items].concat())));
+    }
+
+    let (input, right) = opt(pair(
+        alt((
+            value(Operator::Function(Function::Regex), token("=~")),
+            value(Operator::NotEqual, token("<>")),
+            value(Operator::Lte, token("<=")),
+            value(Operator::Gte, token(">=")),
+            value(Operator::Equal, token("=")),
+            value(Operator::Lt, token("<")),
+            value(Operator::Gt, token(">")),
+        )),
+        additive,
+    ))
+    .parse(input)?;
+    Ok(match right {
+        None => (input, left),
+        Some((operator, right)) => (input, Term::Op(operator, vec![left, right])),
+    })
 }
 
-fn parse_cypher_query(input: &str) -> IResult<&str, MatchQuery> {
-    let (input, _) = tag("MATCH")(input)?;
-    let (input, _) = multispace1(input)?;
+fn additive(input: &str) -> Res<'_, Term> {
+    let (input, first) = multiplicative(input)?;
+    let (input, rest) = many0(pair(
+        alt((
+            value(Operator::Add, token("+")),
+            value(Operator::Minus, token("-")),
+        )),
+        multiplicative,
+    ))
+    .parse(input)?;
+    Ok((
+        input,
+        rest.into_iter().fold(first, |left, (operator, right)| {
+            Term::Op(operator, vec![left, right])
+        }),
+    ))
+}
+
+fn multiplicative(input: &str) -> Res<'_, Term> {
+    let (input, first) = unary(input)?;
+    let (input, rest) = many0(pair(
+        alt((
+            value(Operator::Multiply, token("*")),
+            value(Operator::Divide, token("/")),
+            value(Operator::Function(Function::Mod), token("%")),
+        )),
+        unary,
+    ))
+    .parse(input)?;
+    Ok((
+        input,
+        rest.into_iter().fold(first, |left, (operator, right)| {
+            Term::Op(operator, vec![left, right])
+        }),
+    ))
+}
+
+fn unary(input: &str) -> Res<'_, Term> {
+    alt((
+        map(preceded(token("-"), unary), |t| match t {
+            Term::Literal(Value::Int(i)) => Term::Literal(Value::int(-i.0)),
+            Term::Literal(Value::Float(f)) => Term::Literal(Value::float(-f.0.0)),
+            t => Term::Op(Operator::Minus, vec![Term::Literal(Value::int(0)), t]),
+        }),
+        atom,
+    ))
+    .parse(input)
+}
+
+fn atom(input: &str) -> Res<'_, Term> {
+    alt((
+        map(number, Term::Literal),
+        map(string, |s| Term::Literal(Value::text(&s))),
+        value(Term::Literal(Value::bool(true)), keyword("TRUE")),
+        value(Term::Literal(Value::bool(false)), keyword("FALSE")),
+        value(Term::Literal(Value::null()), keyword("NULL")),
+        delimited(token("("), expression, token(")")),
+        case,
+        map(
+            pair(
+                name,
+                delimited(
+                    token("("),
+                    alt((
+                        value(vec![], token("*")),
+                        separated_list0(token(","), expression),
+                    )),
+                    token(")"),
+                ),
+            ),
+            |(function, args)| Term::Call(function, args),
+        ),
+        // `n.props.x` follows the keys into the property
+        map(
+            (name, many1(preceded(char('.'), identifier))),
+            |(variable, mut keys)| {
+                let property = Term::Property(variable, keys.remove(0).to_string());
+                match keys.is_empty() {
+                    true => property,
+                    false => Term::Op(
+                        Operator::Index,
+                        [property]
+                            .into_iter()
+                            .chain(keys.into_iter().map(|k| Term::Literal(Value::text(k))))
+                            .collect(),
+                    ),
+                }
+            },
+        ),
+        map(name, Term::Variable),
+    ))
+    .parse(input)
+}
+
+/// `CASE [x] WHEN … THEN … [ELSE …] END`, with an operand the conditions are compared with it.
+fn case(input: &str) -> Res<'_, Term> {
+    let (input, _) = keyword("CASE").parse(input)?;
+    let (input, operand) = opt(preceded(not(keyword("WHEN")), expression)).parse(input)?;
+    let (input, branches) = many1(pair(
+        preceded(keyword("WHEN"), expression),
+        preceded(keyword("THEN"), expression),
+    ))
+    .parse(input)?;
+    let (input, otherwise) = opt(preceded(keyword("ELSE"), expression)).parse(input)?;
+    let (input, _) = keyword("END").parse(input)?;
+
+    let case = branches.into_iter().rev().fold(
+        otherwise.unwrap_or(Term::Literal(Value::null())),
+        |otherwise, (condition, then)| {
+            let condition = match &operand {
+                Some(operand) => Term::Op(Operator::Equal, vec![operand.clone(), condition]),
+                None => condition,
+            };
+            Term::Op(Operator::Cond, vec![condition, then, otherwise])
+        },
+    );
+    Ok((input, case))
+}
+
+fn number(input: &str) -> Res<'_, Value> {
+    map_res(preceded(multispace0, recognize_float), |n: &str| {
+        match n.contains(['.', 'e', 'E']) {
+            true => n
+                .parse::<f64>()
+                .map(Value::float)
+                .map_err(|e| e.to_string()),
+            false => n.parse::<i64>().map(Value::int).map_err(|e| e.to_string()),
+        }
+    })
+    .parse(input)
+}
+
+fn string(input: &str) -> Res<'_, String> {
+    map(
+        preceded(
+            multispace0,
+            alt((
+                delimited(char('\''), take_till(|c| c == '\''), char('\'')),
+                delimited(char('"'), take_till(|c| c == '"'), char('"')),
+            )),
+        ),
+        str::to_string,
+    )
+    .parse(input)
+}
+
+fn list(input: &str) -> Res<'_, Vec<Term>> {
+    delimited(
+        token("["),
+        separated_list0(token(","), expression),
+        token("]"),
+    )
+    .parse(input)
+}
+
+/// `UNWIND n.tags AS tag`
+fn unwind_clause(input: &str) -> Res<'_, (Term, String)> {
+    pair(
+        preceded(keyword("UNWIND"), expression),
+        preceded(keyword("AS"), name),
+    )
+    .parse(input)
+}
+
+fn return_items(input: &str) -> Res<'_, Vec<(Term, Option<String>)>> {
+    separated_list1(
+        token(","),
+        pair(expression, opt(preceded(keyword("AS"), name))),
+    )
+    .parse(input)
+}
+
+fn order(input: &str) -> Res<'_, Vec<(Term, bool)>> {
+    preceded(
+        (keyword("ORDER"), keyword("BY")),
+        separated_list1(
+            token(","),
+            map(
+                pair(expression, opt(alt((keyword("ASC"), keyword("DESC"))))),
+                |(term, direction)| {
+                    let descending = direction.is_some_and(|d| d.eq_ignore_ascii_case("DESC"));
+                    (term, descending)
+                },
+            ),
+        ),
+    )
+    .parse(input)
+}
+
+fn limit(input: &str) -> Res<'_, usize> {
+    map_res(
+        preceded(keyword("LIMIT"), preceded(multispace0, digit1)),
+        str::parse,
+    )
+    .parse(input)
+}
+
+/// Collects the variables the term refers to.
+fn variables<'a>(term: &'a Term, out: &mut Vec<&'a str>) {
+    match term {
+        Term::Literal(_) => {}
+        Term::Variable(v) | Term::Property(v, _) => out.push(v),
+        Term::Call(_, args) | Term::Op(_, args) => args.iter().for_each(|a| variables(a, out)),
+    }
+}
 
-    let (input, (alias, src, label)) = parse_pattern(input)?;
+fn parse_cypher_query(input: &str) -> IResult<&str, MatchQuery> {
+    let start = input;
+    let (input, _) = keyword("MATCH").parse(input)?;
+    let (input, mut pattern) = parse_pattern(input)?;
+    let (input, predicate) = opt(preceded(keyword("WHERE"), expression)).parse(input)?;
+    let (input, unwinds) = many0(unwind_clause).parse(input)?;
+    let (input, _) = keyword("RETURN").parse(input)?;
+    let (input, returns) = return_items(input)?;
+    let (input, order) = opt(order).parse(input)?;
+    let (input, limit) = opt(limit).parse(input)?;
 
-    let (input, _) = multispace1(input)?;
-    let (input, _) = tag("RETURN")(input)?;
-    let (input, _) = multispace1(input)?;
+    // the source is given as label, e.g. (n:$$source:Person)
+    let elements = match &mut pattern {
+        Pattern::Node(node) => vec![node],
+        Pattern::Relationship {
+            start,
+            relationship,
+            end,
+        } => vec![start, relationship, end],
+    };
+    let mut bound = vec![];
+    let mut src = None;
+    for element in elements {
+        element.labels.retain(|group| match group.as_slice() {
+            [label] if label.starts_with('$') => {
+                src.get_or_insert(label.clone());
+                false
+            }
+            _ => true,
+        });
+        bound.extend(element.variable.clone());
+    }
 
-    // Parses "n.age"
-    let (input, _) = tag(alias)(input)?;
-    let (input, _) = tag(".")(input)?;
-    let (input, return_field) = identifier(input)?;
+    // every variable needs to be bound by the pattern or an UNWIND, ORDER BY may also use aliases
+    let mut used = vec![];
+    predicate.iter().for_each(|p| variables(p, &mut used));
+    for (term, variable) in &unwinds {
+        variables(term, &mut used);
+        bound.push(variable.clone());
+    }
+    returns.iter().for_each(|(r, _)| variables(r, &mut used));
+    let aliases = returns
+        .iter()
+        .filter_map(|(_, a)| a.clone())
+        .collect::<Vec<_>>();
+    let mut ordered = vec![];
+    order
+        .iter()
+        .flatten()
+        .for_each(|(o, _)| variables(o, &mut ordered));
+    let unbound = used
+        .iter()
+        .find(|v| !bound.iter().any(|b| b == *v))
+        .or(ordered
+            .iter()
+            .find(|v| !bound.iter().chain(&aliases).any(|b| b == *v)));
+    if let Some(variable) = unbound {
+        // points to the unbound variable
+        let span = QueryError::locate(Language::Cypher, start, variable, "").span;
+        return Err(nom::Err::Failure(Error::new(
+            &start[span.start..],
+            ErrorKind::Verify,
+        )));
+    }
 
     Ok((
         input,
         MatchQuery {
-            src: src.to_string(),
-            label: label.to_string(),
-            alias: alias.to_string(),
-            return_field: return_field.to_string(),
+            src: src.unwrap_or("$$source".to_string()),
+            pattern,
+            predicate,
+            unwinds,
+            returns,
+            order: order.unwrap_or_default(),
+            limit,
         },
     ))
 }
 
-pub fn parse_cypher(input: &str) -> anyhow::Result<Algebra> {
-    let (_, query) = parse_cypher_query(input).map_err(|e| anyhow!(e.to_string()))?;
-    Ok(query.into())
+/// How a pattern variable is read from the matched record.
+enum Binding {
+    Record,
+    Start,
+    End,
+    /// a field of the records an `UNWIND` produced, `true` if it holds the id of an endpoint
+    Field(bool),
+}
+
+struct Bindings<'a> {
+    variables: HashMap<String, Binding>,
+    query: &'a str,
+    functions: &'a FunctionRegistry,
+}
+
+impl Bindings<'_> {
+    fn error(&self, needle: &str, message: String) -> QueryError {
+        QueryError::locate(Language::Cypher, self.query, needle, message)
+    }
+
+    fn expression(&self, term: &Term) -> Result<Expression, QueryError> {
+        let binding = |v: &String| {
+            self.variables
+                .get(v)
+                .ok_or_else(|| self.error(v, format!("Unknown variable {}", v)))
+        };
+        Ok(match term {
+            Term::Literal(value) => Expression::Literal(value.clone()),
+            Term::Variable(v) => match binding(v)? {
+                Binding::Record => Expression::Wildcard,
+                Binding::Start => Expression::call(Operator::StartNode, vec![Expression::Wildcard]),
+                Binding::End => Expression::call(Operator::EndNode, vec![Expression::Wildcard]),
+                Binding::Field(_) => Expression::field(v),
+            },
+            Term::Property(v, property) => match binding(v)? {
+                Binding::Record => Expression::call(
+                    Operator::Index,
+                    vec![
+                        Expression::Wildcard,
+                        Expression::Literal(Value::text(property)),
+                    ],
+                ),
+                Binding::Field(false) => Expression::call(
+                    Operator::Index,
+                    vec![
+                        Expression::field(v),
+                        Expression::Literal(Value::text(property)),
+                    ],
+                ),
+                _ => Err(self.error(
+                    v,
+                    format!("Properties of {} are not part of the relationship", v),
+                ))?,
+            },
+            Term::Call(name, args) if Operator::aggregate(name).is_none() => {
+                let Some(function) = Function::lookup(name) else {
+                    let udf = self
+                        .functions
+                        .lookup(name)
+                        .ok_or_else(|| self.error(name, format!("Unknown function {}", name)))?;
+                    udf.check(args.len()).map_err(|e| self.error(name, e))?;
+                    let args = args
+                        .iter()
+                        .map(|a| self.expression(a))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    return Ok(Expression::call(Operator::Udf(udf), args));
+                };
+                function
+                    .check(args.len())
+                    .map_err(|e| self.error(name, e))?;
+                let mut args = args
+                    .iter()
+                    .map(|a| self.expression(a))
+                    .collect::<Result<Vec<_>, _>>()?;
+                // substring starts at 0 in Cypher
+                if function == Function::Substring {
+                    args[1] = Expression::call(
+                        Operator::Add,
+                        vec![args[1].clone(), Expression::Literal(Value::int(1))],
+                    );
+                }
+                Expression::function(function, args)
+            }
+            Term::Call(function, args) => {
+                let operator = Operator::aggregate(function).unwrap();
+                match (&operator, args.as_slice()) {
+                    (Operator::Count, []) => Expression::call(operator, vec![]),
+                    (_, [arg]) => Expression::call(operator, vec![self.expression(arg)?]),
+                    _ => {
+                        Err(self.error(function, format!("{} takes a single argument", function)))?
+                    }
+                }
+            }
+            Term::Op(operator, args) => {
+                let mut args = args
+                    .iter()
+                    .map(|a| self.expression(a))
+                    .collect::<Result<Vec<_>, _>>()?;
+                match operator {
+                    Operator::Index => {
+                        let keys = args.split_off(1);
+                        Expression::path(args.remove(0), keys)
+                    }
+                    operator => Expression::call(operator.clone(), args),
+                }
+            }
+        })
+    }
+}
+
+/// Whether the record has all label groups, e.g. `:Person:Admin|Owner`.
+fn labels(groups: &[Vec<String>]) -> Vec<Expression> {
+    groups
+        .iter()
+        .map(|group| {
+            Expression::fold(
+                Operator::Or,
+                group
+                    .iter()
+                    .map(|label| {
+                        Expression::call(
+                            Operator::HasLabel,
+                            vec![
+                                Expression::Wildcard,
+                                Expression::Literal(Value::text(label)),
+                            ],
+                        )
+                    })
+                    .collect(),
+            )
+        })
+        .collect()
+}
+
+/// Lowers the parsed query, `query` is the text it was parsed from.
+fn lower(m: MatchQuery, query: &str, functions: &FunctionRegistry) -> Result<Algebra, QueryError> {
+    let mut bindings = HashMap::new();
+    let record = match m.pattern {
+        Pattern::Node(node) => node,
+        Pattern::Relationship {
+            start,
+            relationship,
+            end,
+        } => {
+            for (node, binding) in [(start, Binding::Start), (end, Binding::End)] {
+                if !node.labels.is_empty() {
+                    return Err(QueryError::locate(
+                        Language::Cypher,
+                        query,
+                        &node.labels[0][0],
+                        "Labels of relationship endpoints cannot be matched",
+                    ));
+                }
+                if let Some(variable) = node.variable {
+                    bindings.insert(variable, binding);
+                }
+            }
+            relationship
+        }
+    };
+    if let Some(variable) = &record.variable {
+        bindings.insert(variable.clone(), Binding::Record);
+    }
+    let mut bindings = Bindings {
+        variables: bindings,
+        query,
+        functions,
+    };
+
+    let mut node = Algebra::Scan(Scan {
+        source: m.src,
+        schema: Schema::Dynamic,
+    });
+
+    let mut predicates = labels(&record.labels);
+    if let Some(predicate) = &m.predicate {
+        predicates.push(bindings.expression(predicate)?);
+    }
+    if !predicates.is_empty() {
+        node = Algebra::Filter(Filter {
+            predicate: Expression::fold(Operator::And, predicates),
+            input: Box::new(node),
+        });
+    }
+
+    for (term, variable) in &m.unwinds {
+        node = unwind(node, term, variable, &mut bindings)?;
+    }
+
+    let mut expressions = IndexMap::new();
+    let mut returned = vec![];
+    for (k, (term, alias)) in m.returns.iter().enumerate() {
+        let mut name = match (alias, term) {
+            (Some(alias), _) => alias.clone(),
+            (None, Term::Property(_, property)) => property.clone(),
+            // `n.props.x` is named after its last key
+            (None, Term::Op(Operator::Index, keys)) => match keys.last() {
+                Some(Term::Literal(Value::Text(key))) => key.0.to_string(),
+                _ => format!("field{}", k),
+            },
+            (None, Term::Variable(variable)) => variable.clone(),
+            (None, _) => format!("field{}", k),
+        };
+        if expressions.contains_key(&name) {
+            name = format!("{}{}", name, k);
+        }
+        expressions.insert(name.clone(), bindings.expression(term)?);
+        returned.push((term, alias, name));
+    }
+
+    let keys = expressions
+        .values()
+        .filter(|e| e.aggregates().is_empty())
+        .cloned()
+        .collect::<Vec<_>>();
+    node = match keys.len() < expressions.len() {
+        true => Algebra::Aggregate(Aggregate {
+            keys,
+            expressions,
+            input: Box::new(node),
+        }),
+        false => Algebra::Project(Project {
+            expressions,
+            input: Box::new(node),
+        }),
+    };
+
+    // sorting happens after the projection, so only returned values can be used
+    if !m.order.is_empty() {
+        let keys = m
+            .order
+            .iter()
+            .map(|(term, descending)| {
+                let name = returned
+                    .iter()
+                    .find(|(t, alias, _)| {
+                        *t == term || matches!(term, Term::Variable(v) if alias.as_ref() == Some(v))
+                    })
+                    .map(|(_, _, name)| name)
+                    .ok_or_else(|| {
+                        let mut variables = vec![];
+                        super::cypher::variables(term, &mut variables);
+                        bindings.error(
+                            variables.first().copied().unwrap_or("ORDER"),
+                            "ORDER BY can only use returned values".to_string(),
+                        )
+                    })?;
+                Ok(SortKey {
+                    expression: Expression::field(name),
+                    descending: *descending,
+                })
+            })
+            .collect::<Result<_, QueryError>>()?;
+        node = Algebra::Sort(Sort {
+            keys,
+            input: Box::new(node),
+        });
+    }
+
+    if let Some(limit) = m.limit {
+        node = Algebra::Limit(Limit {
+            limit,
+            input: Box::new(node),
+        });
+    }
+    Ok(node)
+}
+
+/// Projects the bound variables and the list into fields, so each element of the list can
+/// replace it in a record of its own.
+fn unwind(
+    input: Algebra,
+    term: &Term,
+    variable: &str,
+    bindings: &mut Bindings,
+) -> Result<Algebra, QueryError> {
+    let mut variables = bindings.variables.keys().cloned().collect::<Vec<_>>();
+    variables.sort();
+
+    let mut expressions = IndexMap::new();
+    for v in &variables {
+        expressions.insert(v.clone(), bindings.expression(&Term::Variable(v.clone()))?);
+    }
+    expressions.insert(variable.to_string(), bindings.expression(term)?);
+
+    for binding in bindings.variables.values_mut() {
+        *binding = Binding::Field(matches!(
+            binding,
+            Binding::Start | Binding::End | Binding::Field(true)
+        ));
+    }
+    bindings
+        .variables
+        .insert(variable.to_string(), Binding::Field(false));
+
+    Ok(Algebra::Unwind(Unwind {
+        input: Box::new(Algebra::Project(Project {
+            expressions,
+            input: Box::new(input),
+        })),
+        key: variable.to_string(),
+        func: Operator::Explode,
+    }))
+}
+
+pub fn parse_cypher(input: &str) -> Result<Algebra, QueryError> {
+    parse_cypher_with(input, &FunctionRegistry::default())
+}
+
+/// Parses the query, calls of functions which are not built in are looked up in the registry.
+pub fn parse_cypher_with(input: &str, functions: &FunctionRegistry) -> Result<Algebra, QueryError> {
+    let offset = |rest: &str| input.len() - rest.len();
+    let (rest, query) = parse_cypher_query(input).map_err(|e| match e {
+        nom::Err::Failure(e) if e.code == ErrorKind::Verify => {
+            let variable = identifier(e.input).map(|(_, v)| v).unwrap_or_default();
+            QueryError::new(
+                Language::Cypher,
+                offset(e.input)..offset(e.input) + variable.len(),
+                format!("Unknown variable {}", variable),
+            )
+        }
+        nom::Err::Error(e) | nom::Err::Failure(e) => QueryError::new(
+            Language::Cypher,
+            offset(e.input)..input.len(),
+            format!("Unexpected input {}", e.input.trim()),
+        ),
+        nom::Err::Incomplete(_) => QueryError::new(
+            Language::Cypher,
+            input.len()..input.len(),
+            "Unexpected end of query",
+        ),
+    })?;
+    if !rest.trim().is_empty() {
+        let start = offset(rest.trim_start());
+        return Err(QueryError::new(
+            Language::Cypher,
+            start..input.len(),
+            format!("Unexpected input {}", rest.trim()),
+        ));
+    }
+    lower(query, input, functions)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{Aggregator, Program};
+    use value::edge::Edge;
+    use value::{Dict, Int};
 
     #[test]
     fn test_parse_valid_cypher() {
@@ -108,10 +853,18 @@ mod tests {
         assert_eq!(remaining.trim(), "");
 
         // Verify the struct fields
-        assert_eq!(parsed.alias, "n");
         assert_eq!(parsed.src, "$$source");
-        assert_eq!(parsed.label, "Person");
-        assert_eq!(parsed.return_field, "age");
+        assert_eq!(
+            parsed.pattern,
+            Pattern::Node(Element {
+                variable: Some("n".to_string()),
+                labels: vec![vec!["Person".to_string()]],
+            })
+        );
+        assert_eq!(
+            parsed.returns,
+            vec![(Term::Property("n".to_string(), "age".to_string()), None)]
+        );
     }
 
     #[test]
@@ -142,4 +895,271 @@ mod tests {
         let query = "MATCH (n:$$source:Person) RETURN n.age";
         let _result = parse_cypher(query).unwrap();
     }
+
+    fn person(id: i64, labels: &[&str], name: &str, age: i64) -> Value {
+        Value::node(
+            Int::new(id),
+            labels.iter().map(|l| (*l).into()).collect(),
+            Dict::from(vec![("name", Value::text(name)), ("age", Value::int(age))]),
+        )
+    }
+
+    fn run(query: &str, records: Vec<Value>) -> Vec<Value> {
+        let algebra = parse_cypher(query).unwrap();
+        let mut program = Program::try_from(&algebra).unwrap();
+        program
+            .set_resource("$$source", records.into_iter())
+            .unwrap();
+        program.collect()
+    }
+
+    #[test]
+    fn where_and_aliases() {
+        let results = run(
+            "match (n:Person) where n.age >= 18 and not n.name = 'b' return n.name as who, n.age",
+            vec![
+                person(1, &["Person"], "a", 20),
+                person(2, &["Person"], "b", 30),
+                person(3, &["Person"], "c", 10),
+                person(4, &["Robot"], "d", 40),
+            ],
+        );
+        assert_eq!(
+            results,
+            vec![Value::array([Value::text("a"), Value::int(20)])]
+        );
+    }
+
+    #[test]
+    fn label_alternatives() {
+        let results = run(
+            "MATCH (n:Person|Robot) WHERE n.missing IS NULL RETURN n.name",
+            vec![
+                person(1, &["Person"], "a", 20),
+                person(2, &["Animal"], "b", 30),
+                person(3, &["Robot"], "c", 10),
+            ],
+        );
+        assert_eq!(
+            results,
+            vec![
+                Value::array([Value::text("a")]),
+                Value::array([Value::text("c")])
+            ]
+        );
+    }
+
+    #[test]
+    fn relationship() {
+        let edge = |label: &str, start: u64, end: u64| {
+            Value::Edge(Box::new(Edge {
+                id: Int::new(1),
+                label: Some(label.into()),
+                start,
+                end,
+                properties: Dict::from(vec![("since", Value::int(2020))]),
+            }))
+        };
+        let query = parse_cypher_query("MATCH (b)<-[r:KNOWS]-(a) RETURN a, b, r.since")
+            .unwrap()
+            .1;
+        let Pattern::Relationship { start, end, .. } = &query.pattern else {
+            panic!("expected a relationship");
+        };
+        assert_eq!(start.variable.as_deref(), Some("a"));
+        assert_eq!(end.variable.as_deref(), Some("b"));
+
+        let results = run(
+            "MATCH (a)-[r:KNOWS]->(b) RETURN a, b, r.since",
+            vec![edge("KNOWS", 1, 2), edge("LIKES", 2, 3)],
+        );
+        assert_eq!(
+            results,
+            vec![Value::array([
+                Value::int(1),
+                Value::int(2),
+                Value::int(2020)
+            ])]
+        );
+
+        assert!(parse_cypher("MATCH (a:Person)-[r]->(b) RETURN a").is_err());
+        assert!(parse_cypher("MATCH (a)-[r]->(b) RETURN a.name").is_err());
+    }
+
+    #[test]
+    fn aggregation() {
+        let algebra = parse_cypher(
+            "MATCH (n:Person) RETURN n.name AS name, count(*) AS people, collect(n.age) AS ages",
+        )
+        .unwrap();
+        let Algebra::Aggregate(aggregate) = &algebra else {
+            panic!("expected an aggregation, got {:?}", algebra);
+        };
+        assert_eq!(aggregate.keys.len(), 1);
+        assert_eq!(
+            aggregate.expressions.keys().collect::<Vec<_>>(),
+            vec!["name", "people", "ages"]
+        );
+        assert!(matches!(aggregate.input.as_ref(), Algebra::Filter(_)));
+
+        let mut aggregator = Aggregator::new(&algebra, None).unwrap();
+        for node in [
+            person(1, &["Person"], "a", 20),
+            person(2, &["Person"], "b", 30),
+            person(3, &["Person"], "a", 40),
+            person(4, &["Robot"], "a", 50),
+        ] {
+            aggregator.push(0, node).unwrap();
+        }
+        let rows = aggregator
+            .flush()
+            .unwrap()
+            .into_iter()
+            .map(|(_, row)| row)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            vec![
+                Value::array([
+                    Value::text("a"),
+                    Value::int(2),
+                    Value::array([Value::int(20), Value::int(40)])
+                ]),
+                Value::array([
+                    Value::text("b"),
+                    Value::int(1),
+                    Value::array([Value::int(30)])
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_properties() {
+        let located = |id: i64, address: Value| {
+            Value::node(
+                Int::new(id),
+                vec!["Person".into()],
+                Dict::from(vec![("address", address)]),
+            )
+        };
+        let address =
+            |city: &str| Value::Dict(Box::new(Dict::from(vec![("city", Value::text(city))])));
+        let results = run(
+            "MATCH (n:Person) WHERE n.address.city = 'Basel' RETURN n.address.city, n.address.zip AS zip",
+            vec![
+                located(1, address("Basel")),
+                located(2, address("Bern")),
+                located(3, Value::text("unknown")),
+            ],
+        );
+        // a property which is not a document has no keys
+        assert_eq!(
+            results,
+            vec![Value::array([Value::text("Basel"), Value::null()])]
+        );
+
+        let algebra = parse_cypher("MATCH (n) RETURN n.address.city").unwrap();
+        assert!(algebra.schema().unwrap().field_type("city").is_ok());
+    }
+
+    #[test]
+    fn unwind() {
+        let tagged = |id: i64, name: &str, tags: Vec<Value>| {
+            Value::node(
+                Int::new(id),
+                vec!["Person".into()],
+                Dict::from(vec![
+                    ("name", Value::text(name)),
+                    ("tags", Value::array(tags)),
+                ]),
+            )
+        };
+        let results = run(
+            "MATCH (n:Person) UNWIND n.tags AS tag RETURN n.name, tag",
+            vec![
+                tagged(1, "a", vec![Value::text("x"), Value::text("y")]),
+                tagged(2, "b", vec![]),
+                person(3, &["Person"], "c", 10),
+                tagged(4, "d", vec![Value::text("z")]),
+            ],
+        );
+        assert_eq!(
+            results,
+            vec![
+                Value::array([Value::text("a"), Value::text("x")]),
+                Value::array([Value::text("a"), Value::text("y")]),
+                Value::array([Value::text("d"), Value::text("z")]),
+            ]
+        );
+
+        // the elements of unwound maps have properties as well
+        let friend =
+            |name: &str| Value::Dict(Box::new(Dict::from(vec![("name", Value::text(name))])));
+        let results = run(
+            "MATCH (n) UNWIND n.tags AS f RETURN f.name AS friend",
+            vec![tagged(1, "a", vec![friend("x"), friend("y")])],
+        );
+        assert_eq!(
+            results,
+            vec![
+                Value::array([Value::text("x")]),
+                Value::array([Value::text("y")])
+            ]
+        );
+
+        assert!(parse_cypher("MATCH (n) UNWIND m.tags AS tag RETURN tag").is_err());
+        assert!(parse_cypher("MATCH (n) UNWIND n.tags AS tag RETURN tag, t").is_err());
+    }
+
+    #[test]
+    fn functions() {
+        let results = run(
+            "MATCH (n:Person) WHERE n.name =~ '^[ab]' \
+             RETURN toUpper(substring(n.name, 1)) AS rest, n.age % 7 AS rem, \
+             CASE n.age WHEN 30 THEN 'thirty' ELSE toString(n.age / 4) END AS age",
+            vec![
+                person(1, &["Person"], "ann", 30),
+                person(2, &["Person"], "bob", 42),
+                person(3, &["Person"], "cid", 30),
+            ],
+        );
+        assert_eq!(
+            results,
+            vec![
+                Value::array([Value::text("NN"), Value::int(2), Value::text("thirty")]),
+                Value::array([Value::text("OB"), Value::int(0), Value::text("10.5")]),
+            ]
+        );
+
+        assert!(parse_cypher("MATCH (n) RETURN unknown(n.name)").is_err());
+        assert!(parse_cypher("MATCH (n) RETURN trim(n.name, n.age)").is_err());
+    }
+
+    #[test]
+    fn order_and_limit() {
+        let algebra =
+            parse_cypher("MATCH (n) RETURN n.name, n.age AS age ORDER BY age DESC, n.name LIMIT 3")
+                .unwrap();
+        let Algebra::Limit(limit) = &algebra else {
+            panic!("expected a limit, got {:?}", algebra);
+        };
+        assert_eq!(limit.limit, 3);
+        let Algebra::Sort(sort) = limit.input.as_ref() else {
+            panic!("expected a sort");
+        };
+        assert_eq!(
+            sort.keys
+                .iter()
+                .map(|k| (k.expression.clone(), k.descending))
+                .collect::<Vec<_>>(),
+            vec![
+                (Expression::field("age"), true),
+                (Expression::field("name"), false)
+            ]
+        );
+
+        assert!(parse_cypher("MATCH (n) RETURN n.name ORDER BY n.age").is_err());
+        assert!(parse_cypher("MATCH (n) RETURN n.name ORDER BY m").is_err());
+    }
 }
@@ -0,0 +1,204 @@
+use serde::Serialize;
+use sqlparser::tokenizer::{Location, Span};
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Language {
+    Sql,
+    Mql,
+    Cypher,
+}
+
+impl Display for Language {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Language::Sql => write!(f, "SQL"),
+            Language::Mql => write!(f, "MQL"),
+            Language::Cypher => write!(f, "Cypher"),
+        }
+    }
+}
+
+/// A query which cannot be parsed or is not supported, `span` is the byte range of the query it refers to.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct QueryError {
+    pub language: Language,
+    pub span: Range<usize>,
+    pub message: String,
+}
+
+impl QueryError {
+    pub fn new<S: Into<String>>(language: Language, span: Range<usize>, message: S) -> Self {
+        QueryError {
+            language,
+            span,
+            message: message.into(),
+        }
+    }
+
+    /// Points to the first whole-word occurrence of `needle`, or the whole query if there is none.
+    pub(crate) fn locate<S: Into<String>>(
+        language: Language,
+        query: &str,
+        needle: &str,
+        message: S,
+    ) -> Self {
+        let word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+        let span = query
+            .match_indices(needle)
+            .map(|(start, _)| start..start + needle.len())
+            .find(|span| {
+                !word(query[..span.start].chars().next_back())
+                    && !word(query[span.end..].chars().next())
+            })
+            .unwrap_or(0..query.len());
+        QueryError::new(language, span, message)
+    }
+
+    /// Errors of sqlparser refer to lines and columns of the query.
+    pub(crate) fn sql<S: Into<String>>(query: &str, span: Span, message: S) -> Self {
+        let start = offset(query, span.start);
+        let end = offset(query, span.end).max(start);
+        let span = match span == Span::empty() {
+            true => 0..query.len(),
+            false => start..end,
+        };
+        QueryError::new(Language::Sql, span, message)
+    }
+
+    /// The message with the referred part of the query underlined.
+    pub fn report(&self, query: &str) -> String {
+        let start = self.span.start.min(query.len());
+        let end = self.span.end.clamp(start, query.len());
+        let line_start = query[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = query[start..]
+            .find('\n')
+            .map(|i| start + i)
+            .unwrap_or(query.len());
+        let line = &query[line_start..line_end];
+        let indent = query[line_start..start].chars().count();
+        let width = query[start..end.min(line_end)].chars().count().max(1);
+        format!(
+            "{}\n  {}\n  {}{}",
+            self,
+            line,
+            " ".repeat(indent),
+            "^".repeat(width)
+        )
+    }
+}
+
+/// Byte offset of a 1-based line and column.
+fn offset(query: &str, location: Location) -> usize {
+    let line_start = query
+        .split_inclusive('\n')
+        .take(location.line.saturating_sub(1) as usize)
+        .map(str::len)
+        .sum::<usize>();
+    query[line_start..]
+        .char_indices()
+        .nth(location.column.saturating_sub(1) as usize)
+        .map(|(i, _)| line_start + i)
+        .unwrap_or(query.len())
+}
+
+impl Display for QueryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} error at {}..{}: {}",
+            self.language, self.span.start, self.span.end, self.message
+        )
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_cypher, parse_mql, parse_sql};
+
+    #[test]
+    fn report() {
+        let query = "SELECT a\nFROM $$source WHERE b = ";
+        let error = QueryError::sql(
+            query,
+            Span::new(Location::new(2, 15), Location::new(2, 20)),
+            "unsupported",
+        );
+        assert_eq!(error.span, 23..28);
+        assert_eq!(&query[error.span.clone()], "WHERE");
+        assert_eq!(
+            error.report(query),
+            "SQL error at 23..28: unsupported\n  FROM $$source WHERE b = \n                ^^^^^"
+        );
+
+        let error = QueryError::locate(Language::Cypher, "MATCH (n) RETURN m.age", "m", "");
+        assert_eq!(error.span, 17..18);
+    }
+
+    /// The part of the query the error points to.
+    fn pointed(query: &str, error: QueryError) -> &str {
+        &query[error.span]
+    }
+
+    #[test]
+    fn sql() {
+        let query = "SELECT name FROM $$source WHERE price & 2 = 0";
+        let error = parse_sql(query).unwrap_err();
+        assert_eq!(error.language, Language::Sql);
+        assert_eq!(pointed(query, error), "price & 2");
+
+        let query = "SELECT reverse(name) FROM $$source";
+        // sqlparser ends the span of a call at its last argument
+        assert_eq!(pointed(query, parse_sql(query).unwrap_err()), "reverse(name");
+
+        let query = "SELECT name FROM $$source WHERE";
+        let error = parse_sql(query).unwrap_err();
+        assert!(error.message.starts_with("Expected"), "{}", error.message);
+
+        assert!(parse_sql("DELETE FROM $$source").is_err());
+    }
+
+    #[test]
+    fn mql() {
+        let query = "db.$$source.aggregate([{$lookup: {from: \"b\"}}])";
+        let error = parse_mql(query).unwrap_err();
+        assert_eq!(error.language, Language::Mql);
+        assert_eq!(pointed(query, error), "$lookup");
+
+        let query = "db.$$source.aggregate([{$match: {age: {$regex: \"a\"}}}])";
+        assert_eq!(pointed(query, parse_mql(query).unwrap_err()), "$regex");
+
+        let query = "db.$$source.aggregate([{$match: }])";
+        let error = parse_mql(query).unwrap_err();
+        assert!(error.span.start >= "db.$$source.aggregate(".len());
+
+        assert!(parse_mql("db.$$source.find({})").is_err());
+
+        let query = "db.$$source.aggregate([{$sort: {price: \"asc\"}}])";
+        assert_eq!(pointed(query, parse_mql(query).unwrap_err()), "price");
+        let query = "db.$$source.aggregate([{$sort: {price: true}}])";
+        assert_eq!(pointed(query, parse_mql(query).unwrap_err()), "price");
+
+        let query = "db.$$source.aggregate([{$match: {$or: [{a: 1}, 2]}}])";
+        assert_eq!(pointed(query, parse_mql(query).unwrap_err()), "$or");
+    }
+
+    #[test]
+    fn cypher() {
+        let query = "MATCH (n:Person) RETURN m.age";
+        let error = parse_cypher(query).unwrap_err();
+        assert_eq!(error.language, Language::Cypher);
+        assert_eq!(error.message, "Unknown variable m");
+        assert_eq!(pointed(query, error), "m");
+
+        let query = "MATCH (n) RETURN reverse(n.name)";
+        assert_eq!(pointed(query, parse_cypher(query).unwrap_err()), "reverse");
+
+        let query = "MATCH (n) RETURN n.name LIMIT x";
+        assert_eq!(pointed(query, parse_cypher(query).unwrap_err()), "LIMIT x");
+    }
+}
@@ -1,7 +1,11 @@
 mod mql;
 mod sql;
 mod cypher;
+mod error;
+mod transpile;
 
 pub use mql::*;
 pub use sql::*;
 pub use cypher::*;
+pub use error::*;
+pub use transpile::*;
@@ -1,8 +1,11 @@
-use crate::expression::Expression;
-use crate::{Algebra, Project, Scan, Schema};
-use anyhow::anyhow;
+use crate::expression::{Expression, literal};
+use crate::language::{Language, QueryError};
+use crate::operator::Operator;
+use crate::udf::FunctionRegistry;
+use crate::{Aggregate, Algebra, Filter, Limit, Project, Scan, Schema, Sort, SortKey, Unwind};
+use indexmap::IndexMap;
 use mongodb::bson;
-use mongodb::bson::Array;
+use mongodb::bson::{Array, Bson, Document};
 use nom::bytes::complete::{tag, take, take_until};
 use nom::character::complete::char;
 use nom::{IResult, Input};
@@ -14,7 +17,7 @@ struct MongoCommand {
     payload: Array,
 }
 
-fn parse_db_call(input: &str) -> IResult<&str, MongoCommand, nom::error::Error<&str>> {
+fn parse_db_call(input: &str) -> IResult<&str, (&str, &str), nom::error::Error<&str>> {
     let (input, _) = tag("db.")(input)?;
 
     let (input, collection) = take_until(".")(input)?;
@@ -24,58 +27,657 @@ fn parse_db_call(input: &str) -> IResult<&str, MongoCommand, nom::error::Error<&
     let (input, payload) = take(content_len)(input)?;
     let (input, _) = char(')')(input)?;
 
-    let value: Value = json5::from_str(payload).unwrap();
-    let payload = bson::to_bson(&value).unwrap();
+    Ok((input, (collection, payload)))
+}
 
-    Ok((
-        input,
-        MongoCommand {
-            collection: collection.to_string(),
-            payload: payload.as_array().unwrap().clone(),
-        },
-    ))
+fn parse_call(query: &str) -> Result<MongoCommand, QueryError> {
+    let (_, (collection, payload)) = parse_db_call(query).map_err(|e| {
+        let offset = match &e {
+            nom::Err::Error(e) | nom::Err::Failure(e) => query.len() - e.input.len(),
+            nom::Err::Incomplete(_) => query.len(),
+        };
+        QueryError::new(
+            Language::Mql,
+            offset..query.len(),
+            "Expected db.<collection>.aggregate([...])",
+        )
+    })?;
+    // the payload is a slice of the query
+    let start = payload.as_ptr() as usize - query.as_ptr() as usize;
+
+    let value: Value = json5::from_str(payload).map_err(|e| {
+        let offset = e
+            .position()
+            .and_then(|p| {
+                let line = payload
+                    .split_inclusive('\n')
+                    .take(p.line)
+                    .map(str::len)
+                    .sum::<usize>();
+                payload[line..]
+                    .char_indices()
+                    .nth(p.column)
+                    .map(|(i, _)| line + i)
+            })
+            .unwrap_or(0);
+        QueryError::new(
+            Language::Mql,
+            start + offset..start + offset + 1,
+            e.to_string(),
+        )
+    })?;
+    let pipeline = match bson::to_bson(&value) {
+        Ok(Bson::Array(pipeline)) => pipeline,
+        _ => {
+            return Err(QueryError::new(
+                Language::Mql,
+                start..start + payload.len(),
+                "The pipeline needs to be an array of stages",
+            ));
+        }
+    };
+
+    Ok(MongoCommand {
+        collection: collection.to_string(),
+        payload: pipeline,
+    })
 }
 
-fn parse_call<S: AsRef<str>>(input: S) -> anyhow::Result<MongoCommand> {
-    parse_db_call(input.as_ref())
-        .map(|(_, command)| command)
-        .map_err(|e| anyhow!(e.to_string()))
+pub fn parse_mql<S: AsRef<str>>(input: S) -> Result<Algebra, QueryError> {
+    parse_mql_with(input, &FunctionRegistry::default())
 }
 
-pub fn parse_mql<S: AsRef<str>>(input: S) -> anyhow::Result<Algebra> {
-    Ok(parse_call(input.as_ref())?.into())
+/// Parses the query, operators like `$geohash` which are not built in call registered functions.
+pub fn parse_mql_with<S: AsRef<str>>(
+    input: S,
+    functions: &FunctionRegistry,
+) -> Result<Algebra, QueryError> {
+    let query = input.as_ref();
+    lower(parse_call(query)?, query, functions)
 }
 
-impl From<MongoCommand> for Algebra {
-    fn from(command: MongoCommand) -> Self {
-        let mut node = Algebra::Scan(Scan {
-            source: command.collection.clone(),
-            schema: Schema::Dynamic,
-        });
-        for value in command.payload {
-            let (key, value) = value.as_document().unwrap().into_iter().next().unwrap();
+fn lower(
+    command: MongoCommand,
+    query: &str,
+    functions: &FunctionRegistry,
+) -> Result<Algebra, QueryError> {
+    let error =
+        |needle: &str, message: String| QueryError::locate(Language::Mql, query, needle, message);
 
-            if key == "$project" {
-                let expressions = value
-                    .as_document()
-                    .unwrap()
+    let mut node = Algebra::Scan(Scan {
+        source: command.collection.clone(),
+        schema: Schema::Dynamic,
+    });
+    for stage in command.payload {
+        let Some((key, value)) = stage.as_document().and_then(|d| d.into_iter().next()) else {
+            return Err(error("", format!("Invalid stage {}", stage)));
+        };
+        let document = || {
+            value
+                .as_document()
+                .ok_or_else(|| error(key, format!("{} expects a document", key)))
+        };
+        let input = Box::new(node);
+
+        node = match key.as_str() {
+            "$project" => {
+                let fields = document()?;
+                let mut expressions = IndexMap::new();
+                for (k, v) in fields {
+                    expressions.insert(
+                        k.to_string(),
+                        Expression::from_mql_field(k, v, query, functions)?,
+                    );
+                }
+                // a projection which only removes fields keeps all others
+                if expressions
+                    .values()
+                    .all(|e| matches!(e, Expression::Exclude(_)))
+                {
+                    expressions.shift_insert(0, "*".to_string(), Expression::Wildcard);
+                }
+                Algebra::Project(Project { expressions, input })
+            }
+            "$addFields" | "$set" => {
+                let mut expressions = IndexMap::from([("*".to_string(), Expression::Wildcard)]);
+                for (k, v) in document()? {
+                    expressions.insert(k.to_string(), Expression::from_mql(v, query, functions)?);
+                }
+                Algebra::Project(Project { expressions, input })
+            }
+            "$unset" => {
+                let fields = match value {
+                    Bson::Array(a) => a.iter().filter_map(|f| f.as_str()).collect(),
+                    f => f.as_str().into_iter().collect::<Vec<_>>(),
+                };
+                let mut expressions = IndexMap::from([("*".to_string(), Expression::Wildcard)]);
+                expressions.extend(
+                    fields
+                        .into_iter()
+                        .map(|f| (f.to_string(), Expression::Exclude(f.to_string()))),
+                );
+                Algebra::Project(Project { expressions, input })
+            }
+            "$match" => Algebra::Filter(Filter {
+                predicate: predicate(document()?, query, functions)?,
+                input,
+            }),
+            "$unwind" => {
+                let path = match value {
+                    Bson::Document(d) => d.get_str("path").ok(),
+                    path => path.as_str(),
+                }
+                .ok_or_else(|| error(key, "$unwind expects a path".to_string()))?;
+                Algebra::Unwind(Unwind {
+                    input,
+                    key: path.trim_start_matches('$').to_string(),
+                    func: Operator::Explode,
+                })
+            }
+            "$group" => group(document()?, input, query, functions)?,
+            "$count" => Algebra::Aggregate(Aggregate {
+                keys: vec![],
+                expressions: IndexMap::from([(
+                    value
+                        .as_str()
+                        .ok_or_else(|| error(key, "$count expects a field name".to_string()))?
+                        .to_string(),
+                    Expression::call(Operator::Count, vec![]),
+                )]),
+                input,
+            }),
+            "$sort" => Algebra::Sort(Sort {
+                keys: document()?
                     .into_iter()
-                    .map(|(k, v)| (k.to_string(), Expression::from((k.as_str(), v))))
-                    .collect();
+                    .map(|(k, v)| match number(v) {
+                        Some(1) => Ok(SortKey {
+                            expression: Expression::dotted(k),
+                            descending: false,
+                        }),
+                        Some(-1) => Ok(SortKey {
+                            expression: Expression::dotted(k),
+                            descending: true,
+                        }),
+                        _ => Err(error(k, format!("$sort expects 1 or -1 for {}", k))),
+                    })
+                    .collect::<Result<_, _>>()?,
+                input,
+            }),
+            "$limit" => Algebra::Limit(Limit {
+                limit: number(value)
+                    .filter(|n| *n >= 0)
+                    .ok_or_else(|| error(key, "$limit expects a positive number".to_string()))?
+                    as usize,
+                input,
+            }),
+            stage => return Err(error(stage, format!("Unsupported stage {}", stage))),
+        };
+    }
+    Ok(node)
+}
+
+fn number(value: &Bson) -> Option<i64> {
+    match value {
+        Bson::Int32(i) => Some(*i as i64),
+        Bson::Int64(i) => Some(*i),
+        Bson::Double(d) => Some(*d as i64),
+        _ => None,
+    }
+}
 
-                node = Algebra::Project(Project {
-                    expressions,
-                    input: Box::new(node),
-                });
+/// `{_id: "$name", total: {$sum: "$price"}}`, each field of a document `_id` becomes a key.
+fn group(
+    group: &Document,
+    input: Box<Algebra>,
+    query: &str,
+    functions: &FunctionRegistry,
+) -> Result<Algebra, QueryError> {
+    let mut keys = vec![];
+    let mut expressions = IndexMap::new();
+    match group.get("_id") {
+        None | Some(Bson::Null) => {}
+        Some(Bson::Document(d)) if !d.keys().any(|k| k.starts_with('$')) => {
+            for (name, key) in d {
+                let key = Expression::from_mql(key, query, functions)?;
+                keys.push(key.clone());
+                expressions.insert(name.to_string(), key);
             }
         }
-        node
+        Some(key) => {
+            let key = Expression::from_mql(key, query, functions)?;
+            keys.push(key.clone());
+            expressions.insert("_id".to_string(), key);
+        }
+    }
+    for (name, accumulator) in group.iter().filter(|(k, _)| *k != "_id") {
+        expressions.insert(
+            name.to_string(),
+            Expression::from_mql(accumulator, query, functions)?,
+        );
     }
+    Ok(Algebra::Aggregate(Aggregate {
+        keys,
+        expressions,
+        input,
+    }))
+}
+
+/// A query filter like `{age: {$gt: 3}, name: "a"}` as predicate.
+fn predicate(
+    filter: &Document,
+    query: &str,
+    functions: &FunctionRegistry,
+) -> Result<Expression, QueryError> {
+    let mut terms = vec![];
+    for (key, value) in filter {
+        match key.as_str() {
+            "$and" | "$or" => {
+                let parts = value
+                    .as_array()
+                    .ok_or_else(|| {
+                        QueryError::locate(
+                            Language::Mql,
+                            query,
+                            key,
+                            format!("{} expects an array", key),
+                        )
+                    })?
+                    .iter()
+                    .map(|p| {
+                        let p = p.as_document().ok_or_else(|| {
+                            QueryError::locate(
+                                Language::Mql,
+                                query,
+                                key,
+                                format!("{} expects an array of documents", key),
+                            )
+                        })?;
+                        predicate(p, query, functions)
+                    })
+                    .collect::<Result<_, _>>()?;
+                let operator = match key.as_str() {
+                    "$and" => Operator::And,
+                    _ => Operator::Or,
+                };
+                terms.push(Expression::fold(operator, parts));
+            }
+            "$expr" => terms.push(Expression::from_mql(value, query, functions)?),
+            field => match value {
+                Bson::Document(d) if d.keys().all(|k| k.starts_with('$')) => {
+                    for (operator, value) in d {
+                        terms.push(comparison(field, operator, value, query)?);
+                    }
+                }
+                value => terms.push(comparison(field, "$eq", value, query)?),
+            },
+        }
+    }
+    Ok(match terms.is_empty() {
+        true => Expression::Literal(value::Value::bool(true)),
+        false => Expression::fold(Operator::And, terms),
+    })
+}
+
+fn comparison(
+    field: &str,
+    operator: &str,
+    value: &Bson,
+    query: &str,
+) -> Result<Expression, QueryError> {
+    let field = Expression::dotted(field);
+    let value = literal(value);
+    // null matches null and missing fields
+    if value == value::Value::null() && matches!(operator, "$eq" | "$ne") {
+        let is_null = Expression::call(Operator::IsNull, vec![field]);
+        return Ok(match operator {
+            "$eq" => is_null,
+            _ => Expression::call(Operator::Not, vec![is_null]),
+        });
+    }
+    let operator = match operator {
+        "$eq" => Operator::Equal,
+        "$ne" => Operator::NotEqual,
+        "$gt" => Operator::Gt,
+        "$gte" => Operator::Gte,
+        "$lt" => Operator::Lt,
+        "$lte" => Operator::Lte,
+        "$in" | "$nin" => {
+            let mut expressions = vec![field];
+            if let value::Value::Array(list) = value {
+                expressions.extend(list.values.into_iter().map(Expression::Literal));
+            }
+            let call = Expression::call(Operator::In, expressions);
+            return Ok(match operator {
+                "$in" => call,
+                _ => Expression::call(Operator::Not, vec![call]),
+            });
+        }
+        "$exists" => {
+            let missing = Expression::call(Operator::IsNull, vec![field]);
+            return Ok(match value.as_bool().map(|b| b.0) {
+                Ok(false) => missing,
+                _ => Expression::call(Operator::Not, vec![missing]),
+            });
+        }
+        operator => {
+            return Err(QueryError::locate(
+                Language::Mql,
+                query,
+                operator,
+                format!("Unsupported query operator {}", operator),
+            ));
+        }
+    };
+    Ok(Expression::call(
+        operator,
+        vec![field, Expression::Literal(value)],
+    ))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{Aggregator, Program};
+    use std::collections::HashMap;
+    use value::{Dict, ValType};
+
+    fn run(query: &str, records: Vec<value::Value>) -> Vec<value::Value> {
+        let mut algebra = parse_mql(query).unwrap();
+        algebra.set_schema(Schema::fixed([
+            ("auction".to_string(), ValType::Integer),
+            ("name".to_string(), ValType::Text),
+            ("price".to_string(), ValType::Float),
+        ]));
+        let mut program = Program::try_from(&algebra).unwrap();
+        program
+            .set_resource("$$source", records.into_iter())
+            .unwrap();
+        program.collect()
+    }
+
+    fn bid(auction: i64, name: &str, price: f64) -> value::Value {
+        value::Value::dict(HashMap::from([
+            ("auction".to_string(), value::Value::int(auction)),
+            ("name".to_string(), value::Value::text(name)),
+            ("price".to_string(), value::Value::float(price)),
+        ]))
+    }
+
+    #[test]
+    fn nexmark_q1() {
+        let rows = run(
+            "db.$$source.aggregate([{$project: {auction: 1, price: {$multiply: [\"$price\", 2]}}}])",
+            vec![bid(1, "a", 1.5)],
+        );
+        assert_eq!(
+            rows,
+            vec![value::Value::array([
+                value::Value::int(1),
+                value::Value::float(3.0)
+            ])]
+        );
+    }
+
+    #[test]
+    fn match_set_unset() {
+        let rows = run(
+            "db.$$source.aggregate([
+                {$match: {price: {$gt: 1}, name: {$in: [\"a\", \"b\"]}}},
+                {$set: {label: {$concat: [\"$name\", \"!\"]}, cheap: {$cond: [{$lt: [\"$price\", 5]}, true, false]}}},
+                {$unset: \"auction\"}
+            ])",
+            vec![bid(1, "a", 2.0), bid(2, "c", 2.0), bid(3, "b", 0.5), bid(4, "b", 7.0)],
+        );
+        assert_eq!(
+            rows,
+            vec![
+                value::Value::array([
+                    value::Value::text("a"),
+                    value::Value::float(2.0),
+                    value::Value::text("a!"),
+                    value::Value::bool(true),
+                ]),
+                value::Value::array([
+                    value::Value::text("b"),
+                    value::Value::float(7.0),
+                    value::Value::text("b!"),
+                    value::Value::bool(false),
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn group() {
+        let mut algebra = parse_mql(
+            "db.$$source.aggregate([{$group: {_id: \"$name\", total: {$sum: \"$price\"}, bids: {$sum: 1}}}])",
+        )
+        .unwrap();
+        algebra.set_schema(Schema::fixed([
+            ("name".to_string(), ValType::Text),
+            ("price".to_string(), ValType::Float),
+        ]));
+        let mut aggregator = Aggregator::new(&algebra, None).unwrap();
+        for (name, price) in [("a", 1.0), ("b", 2.0), ("a", 3.0)] {
+            aggregator
+                .push(
+                    0,
+                    value::Value::array([value::Value::text(name), value::Value::float(price)]),
+                )
+                .unwrap();
+        }
+        let rows = aggregator
+            .flush()
+            .unwrap()
+            .into_iter()
+            .map(|(_, row)| row)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            vec![
+                value::Value::array([
+                    value::Value::text("a"),
+                    value::Value::float(4.0),
+                    value::Value::int(2)
+                ]),
+                value::Value::array([
+                    value::Value::text("b"),
+                    value::Value::float(2.0),
+                    value::Value::int(1)
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn stages() {
+        let algebra = parse_mql(
+            "db.$$source.aggregate([{$unwind: \"$tags\"}, {$sort: {price: -1}}, {$limit: 10}, {$count: \"total\"}])",
+        )
+        .unwrap();
+        let Algebra::Aggregate(count) = algebra else {
+            panic!("expected $count to aggregate");
+        };
+        let Algebra::Limit(limit) = *count.input else {
+            panic!("expected a limit");
+        };
+        assert_eq!(limit.limit, 10);
+        let Algebra::Sort(sort) = *limit.input else {
+            panic!("expected a sort");
+        };
+        assert!(sort.keys[0].descending);
+        assert!(matches!(*sort.input, Algebra::Unwind(_)));
+    }
+    #[test]
+    fn unwind_documents() {
+        // documents compare in the order of their keys
+        let order = |id: i64, items: value::Value| {
+            value::Value::Dict(Box::new(Dict::from(vec![
+                ("id", value::Value::int(id)),
+                ("items", items),
+            ])))
+        };
+        let item = |sku: &str, sizes: Vec<i64>| {
+            value::Value::Dict(Box::new(Dict::from(vec![
+                ("sku", value::Value::text(sku)),
+                (
+                    "sizes",
+                    value::Value::array(
+                        sizes.into_iter().map(value::Value::int).collect::<Vec<_>>(),
+                    ),
+                ),
+            ])))
+        };
+
+        let mut algebra = parse_mql("db.$$source.aggregate([{$unwind: \"$items\"}])").unwrap();
+        algebra.set_schema(Schema::Dynamic);
+        let mut program = algebra.processing().unwrap();
+        program
+            .set_resource(
+                "$$source",
+                vec![
+                    order(
+                        1,
+                        value::Value::array([item("a", vec![1, 2]), item("b", vec![])]),
+                    ),
+                    order(2, value::Value::array(Vec::<value::Value>::new())),
+                    value::Value::Dict(Box::new(Dict::from(vec![("id", value::Value::int(3))]))),
+                    order(4, value::Value::array([item("c", vec![3])])),
+                ]
+                .into_iter(),
+            )
+            .unwrap();
+
+        // every item replaces the array in a copy of its order, orders without items are dropped
+        let rows = program.collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            vec![
+                value::Value::array([order(1, item("a", vec![1, 2]))]),
+                value::Value::array([order(1, item("b", vec![]))]),
+                value::Value::array([order(4, item("c", vec![3]))]),
+            ]
+        );
+    }
+
+    #[test]
+    fn functions() {
+        let rows = run(
+            "db.$$source.aggregate([{$project: {name: {$toUpper: {$substrCP: [\"$name\", 0, 2]}}, \
+             half: {$round: [{$divide: [\"$price\", 2]}, 1]}, \
+             size: {$switch: {branches: [{case: {$gt: [\"$price\", 10]}, then: \"big\"}], default: \"small\"}}}}])",
+            vec![bid(1, "alice", 12.5), bid(2, "bob", 3.0)],
+        );
+        assert_eq!(
+            rows,
+            vec![
+                value::Value::array([
+                    value::Value::text("AL"),
+                    value::Value::float(6.3),
+                    value::Value::text("big")
+                ]),
+                value::Value::array([
+                    value::Value::text("BO"),
+                    value::Value::float(1.5),
+                    value::Value::text("small")
+                ]),
+            ]
+        );
+
+        assert!(parse_mql("db.$$source.aggregate([{$project: {a: {$toLower: [1, 2]}}}])").is_err());
+    }
+
+    #[test]
+    fn nested_fields() {
+        let order = |id: i64, city: &str, prices: Vec<f64>| {
+            let items = prices
+                .into_iter()
+                .map(|p| {
+                    value::Value::Dict(Box::new(Dict::from(vec![(
+                        "price",
+                        value::Value::float(p),
+                    )])))
+                })
+                .collect::<Vec<_>>();
+            value::Value::Dict(Box::new(Dict::from(vec![
+                ("id", value::Value::int(id)),
+                (
+                    "address",
+                    value::Value::Dict(Box::new(Dict::from(vec![(
+                        "city",
+                        value::Value::text(city),
+                    )]))),
+                ),
+                ("items", value::Value::array(items)),
+            ])))
+        };
+        let mut algebra = parse_mql(
+            "db.$$source.aggregate([{$match: {\"address.city\": \"Basel\"}}, \
+             {$project: {id: 1, second: \"$items.1.price\", zip: \"$address.zip\"}}])",
+        )
+        .unwrap();
+        algebra.set_schema(Schema::Dynamic);
+        let mut program = algebra.processing().unwrap();
+        program
+            .set_resource(
+                "$$source",
+                vec![
+                    order(1, "Basel", vec![1.0, 2.0]),
+                    order(2, "Bern", vec![3.0]),
+                    order(3, "Basel", vec![]),
+                ]
+                .into_iter(),
+            )
+            .unwrap();
+
+        // a digit follows an array by index, missing keys are null
+        assert_eq!(
+            program.collect::<Vec<_>>(),
+            vec![
+                value::Value::array([
+                    value::Value::int(1),
+                    value::Value::float(2.0),
+                    value::Value::null()
+                ]),
+                value::Value::array([
+                    value::Value::int(3),
+                    value::Value::null(),
+                    value::Value::null()
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn literals_and_nulls() {
+        let unnamed = value::Value::dict(HashMap::from([
+            ("auction".to_string(), value::Value::int(2)),
+            ("name".to_string(), value::Value::null()),
+            ("price".to_string(), value::Value::float(1.0)),
+        ]));
+        let rows = run(
+            "db.$$source.aggregate([
+                {$match: {name: {$ne: null}}},
+                {$project: {
+                    tag: {$literal: \"$name\"},
+                    second: {$arrayElemAt: [[\"a\", \"b\"], 1]},
+                    name: {$getField: {field: \"name\"}},
+                    free: {$eq: [\"$price\", null]}
+                }}
+            ])",
+            vec![bid(1, "a", 1.5), unnamed],
+        );
+        assert_eq!(
+            rows,
+            vec![value::Value::array([
+                value::Value::text("$name"),
+                value::Value::text("b"),
+                value::Value::text("a"),
+                value::Value::bool(false),
+            ])]
+        );
+    }
+
     #[test]
     fn test_parse_db_call() {
         let input = "db.$$source.aggregate([{$project: {}}])";
@@ -1,15 +1,31 @@
 use crate::expression::Expression;
-use crate::{Algebra, Project, Scan, Schema};
+use crate::language::{Language, QueryError};
+use crate::operator::Operator;
+use crate::udf::FunctionRegistry;
+use crate::{
+    Aggregate, Algebra, Distinct, Filter, Join, Limit, Project, Scan, Schema, Sort, SortKey, Unwind,
+};
 use indexmap::IndexMap;
-use sqlparser::ast::{Select, SetExpr, Statement, TableFactor};
-use sqlparser::dialect::Dialect;
-use sqlparser::parser::Parser;
+use sqlparser::ast::{
+    Distinct as SqlDistinct, GroupByExpr, JoinConstraint, JoinOperator, LimitClause, OrderByKind,
+    Query, Select, SelectItem, SetExpr, Spanned, Statement, TableFactor,
+};
+use sqlparser::dialect::{Dialect, GenericDialect};
+use sqlparser::parser::{Parser, ParserError};
+use sqlparser::tokenizer::{Location, Span};
+use std::any::TypeId;
 use tracing::debug;
+use value::Value;
 
 #[derive(Debug)]
 pub struct StreamDialect {}
 
 impl Dialect for StreamDialect {
+    /// Parses what the generic dialect does, e.g. `UNNEST` in `FROM`.
+    fn dialect(&self) -> TypeId {
+        TypeId::of::<GenericDialect>()
+    }
+
     fn is_identifier_start(&self, ch: char) -> bool {
         ch.is_alphabetic() || ch == '_' || ch == '#' || ch == '@' || ch == '$'
     }
@@ -28,51 +44,488 @@ pub trait Sql {
     fn sql(&self) -> String;
 }
 
-fn parse_alg(statements: Vec<Statement>) -> Algebra {
-    for statement in statements {
-        if let Statement::Query(q) = statement
-            && let SetExpr::Select(s) = *q.body
-        {
-            let mut expressions = IndexMap::new();
+fn parse_alg(
+    statements: Vec<Statement>,
+    query: &str,
+    functions: &FunctionRegistry,
+) -> Result<Algebra, QueryError> {
+    let [statement] = statements.as_slice() else {
+        return Err(QueryError::new(
+            Language::Sql,
+            0..query.len(),
+            "Expected a single statement",
+        ));
+    };
+    let Statement::Query(q) = statement else {
+        return Err(QueryError::sql(
+            query,
+            statement.span(),
+            "Only SELECT statements are supported",
+        ));
+    };
+    let SetExpr::Select(s) = q.body.as_ref() else {
+        return Err(QueryError::sql(
+            query,
+            q.body.span(),
+            "Only SELECT statements are supported",
+        ));
+    };
 
-            for (k, item) in s.projection.iter().enumerate() {
-                expressions.insert(format!("field{}", k), Expression::from(item));
-            }
+    let mut node = handle_scan(s, query, functions)?;
+    let aliases = match &node {
+        Algebra::Join(j) => vec![j.left_alias.clone(), j.right_alias.clone()],
+        _ => vec![],
+    };
+
+    if let Some(selection) = &s.selection {
+        node = Algebra::Filter(Filter {
+            predicate: qualify(Expression::from_sql(selection, query, functions)?, &aliases),
+            input: Box::new(node),
+        });
+    }
+
+    // SELECT * keeps the record as it is
+    if !matches!(s.projection.as_slice(), [SelectItem::Wildcard(_)]) {
+        node = handle_projection(s, node, &aliases, query, functions)?;
+    }
+    handle_order(q, s, node, &aliases, query, functions)
+}
+
+/// Projects or aggregates the selected items.
+fn handle_projection(
+    s: &Select,
+    node: Algebra,
+    aliases: &[String],
+    query: &str,
+    functions: &FunctionRegistry,
+) -> Result<Algebra, QueryError> {
+    let mut expressions = IndexMap::new();
+
+    for (k, item) in s.projection.iter().enumerate() {
+        let name = match item {
+            SelectItem::ExprWithAlias { alias, .. } => alias.value.clone(),
+            _ => format!("field{}", k),
+        };
+        let expression = Expression::from_select_item(item, query, functions)?;
+        expressions.insert(name, qualify(expression, aliases));
+    }
+
+    let keys = match &s.group_by {
+        GroupByExpr::Expressions(keys, _) => keys
+            .iter()
+            .map(|k| Expression::from_sql(k, query, functions).map(|k| qualify(k, aliases)))
+            .collect::<Result<Vec<_>, _>>()?,
+        GroupByExpr::All(_) => {
+            return Err(QueryError::sql(
+                query,
+                s.group_by.span(),
+                "GROUP BY ALL is not supported",
+            ));
+        }
+    };
 
-            let scan = handle_scan(&s);
+    let having = s
+        .having
+        .as_ref()
+        .map(|h| Expression::from_sql(h, query, functions).map(|h| qualify(h, aliases)))
+        .transpose()?;
 
-            return Algebra::Project(Project {
+    let aggregated = !keys.is_empty()
+        || expressions.values().any(|e| !e.aggregates().is_empty())
+        || having.as_ref().is_some_and(|h| !h.aggregates().is_empty());
+    if aggregated {
+        let Some(having) = having else {
+            return Ok(Algebra::Aggregate(Aggregate {
+                keys,
                 expressions,
-                input: Box::new(scan),
+                input: Box::new(node),
+            }));
+        };
+        // groups and aggregates only HAVING reads are computed as hidden fields
+        let selected = expressions.keys().cloned().collect::<Vec<_>>();
+        let predicate = grouped(having, &keys, &mut expressions);
+        let hidden = expressions.len() > selected.len();
+        let mut node = Algebra::Filter(Filter {
+            predicate,
+            input: Box::new(Algebra::Aggregate(Aggregate {
+                keys,
+                expressions,
+                input: Box::new(node),
+            })),
+        });
+        if hidden {
+            node = Algebra::Project(Project {
+                expressions: selected
+                    .into_iter()
+                    .map(|name| (name.clone(), Expression::Field(name)))
+                    .collect(),
+                input: Box::new(node),
             });
         }
+        return Ok(node);
+    }
+    if let Some(having) = &s.having {
+        return Err(QueryError::sql(
+            query,
+            having.span(),
+            "HAVING needs GROUP BY or an aggregate function",
+        ));
+    }
+
+    Ok(Algebra::Project(Project {
+        expressions,
+        input: Box::new(node),
+    }))
+}
+
+/// Reads the selected expressions and group keys of the predicate from the fields of the
+/// aggregation, the ones which are not selected are added to it.
+fn grouped(
+    predicate: Expression,
+    keys: &[Expression],
+    expressions: &mut IndexMap<String, Expression>,
+) -> Expression {
+    if let Some((name, _)) = expressions.iter().find(|(_, e)| **e == predicate) {
+        return Expression::Field(name.clone());
+    }
+    match predicate {
+        Expression::Call { ref operator, .. } if operator.is_aggregate() => {
+            hide(predicate, expressions)
+        }
+        predicate if keys.contains(&predicate) => hide(predicate, expressions),
+        Expression::Call {
+            operator,
+            expressions: arguments,
+        } => Expression::Call {
+            operator,
+            expressions: arguments
+                .into_iter()
+                .map(|a| grouped(a, keys, expressions))
+                .collect(),
+        },
+        predicate => predicate,
     }
-    panic!("No answer")
 }
 
-pub fn parse_sql(query: &str) -> Algebra {
+fn hide(expression: Expression, expressions: &mut IndexMap<String, Expression>) -> Expression {
+    let name = (expressions.len()..)
+        .map(|i| format!("having{}", i))
+        .find(|name| !expressions.contains_key(name))
+        .unwrap();
+    expressions.insert(name.clone(), expression);
+    Expression::Field(name)
+}
+
+/// Applies `DISTINCT`, `ORDER BY` and `LIMIT` in this order to the selected records, sort keys
+/// which are selected read the selected field.
+fn handle_order(
+    q: &Query,
+    s: &Select,
+    mut node: Algebra,
+    aliases: &[String],
+    query: &str,
+    functions: &FunctionRegistry,
+) -> Result<Algebra, QueryError> {
+    let selected = match (&node, node.aggregation()) {
+        // without the fields HAVING hides
+        (Algebra::Project(p), Some(a)) => a
+            .expressions
+            .iter()
+            .filter(|(name, _)| p.expressions.contains_key(*name))
+            .map(|(name, e)| (name.clone(), e.clone()))
+            .collect(),
+        (Algebra::Project(p), None) => p.expressions.clone(),
+        (_, Some(a)) => a.expressions.clone(),
+        _ => IndexMap::new(),
+    };
+    match &s.distinct {
+        None | Some(SqlDistinct::All) => {}
+        Some(SqlDistinct::Distinct) => {
+            node = Algebra::Distinct(Distinct {
+                input: Box::new(node),
+            })
+        }
+        Some(SqlDistinct::On(on)) => {
+            return Err(QueryError::sql(
+                query,
+                Span::union_iter(on.iter().map(|e| e.span())),
+                "DISTINCT ON is not supported",
+            ));
+        }
+    }
+
+    if let Some(order) = &q.order_by {
+        let OrderByKind::Expressions(exprs) = &order.kind else {
+            return Err(QueryError::sql(
+                query,
+                q.span(),
+                "ORDER BY ALL is not supported",
+            ));
+        };
+        let keys = exprs
+            .iter()
+            .map(|e| {
+                let expression = qualify(Expression::from_sql(&e.expr, query, functions)?, aliases);
+                let expression = match selected.iter().find(|(_, s)| **s == expression) {
+                    Some((name, _)) => Expression::Field(name.clone()),
+                    None => expression,
+                };
+                Ok(SortKey {
+                    expression,
+                    descending: e.options.asc == Some(false),
+                })
+            })
+            .collect::<Result<Vec<_>, QueryError>>()?;
+        node = Algebra::Sort(Sort {
+            keys,
+            input: Box::new(node),
+        });
+    }
+
+    match &q.limit_clause {
+        Some(LimitClause::LimitOffset {
+            limit: Some(limit),
+            offset: None,
+            limit_by,
+        }) if limit_by.is_empty() => {
+            let count = match Expression::from_sql(limit, query, functions)? {
+                Expression::Literal(Value::Int(i)) if i.0 >= 0 => i.0 as usize,
+                _ => {
+                    return Err(QueryError::sql(
+                        query,
+                        limit.span(),
+                        "LIMIT expects a positive integer",
+                    ));
+                }
+            };
+            node = Algebra::Limit(Limit {
+                limit: count,
+                input: Box::new(node),
+            });
+        }
+        // LIMIT ALL keeps all records
+        None
+        | Some(LimitClause::LimitOffset {
+            limit: None,
+            offset: None,
+            ..
+        }) => {}
+        Some(clause) => {
+            return Err(QueryError::sql(
+                query,
+                clause.span(),
+                "Only LIMIT with a count is supported",
+            ));
+        }
+    }
+    Ok(node)
+}
+
+pub fn parse_sql(query: &str) -> Result<Algebra, QueryError> {
+    parse_sql_with(query, &FunctionRegistry::default())
+}
+
+/// Parses the query, function names which are not built in are looked up in the registry.
+pub fn parse_sql_with(query: &str, functions: &FunctionRegistry) -> Result<Algebra, QueryError> {
     let dialect = StreamDialect {};
 
-    let ast = Parser::parse_sql(&dialect, query).unwrap();
+    let ast = Parser::parse_sql(&dialect, query).map_err(|e| parser_error(query, e))?;
 
     debug!("{:?}", ast);
 
-    parse_alg(ast)
+    parse_alg(ast, query, functions)
 }
 
-fn handle_scan(s: &Select) -> Algebra {
-    if s.from.len() == 1 {
-        if let TableFactor::Table { name, .. } = &s.from[0].relation {
-            return Algebra::Scan(Scan {
-                source: name.to_string(),
-                schema: Schema::Dynamic,
-            });
+/// sqlparser reports the position as part of the message, e.g. `… at Line: 1, Column: 8`.
+fn parser_error(query: &str, error: ParserError) -> QueryError {
+    let message = match error {
+        ParserError::TokenizerError(m) | ParserError::ParserError(m) => m,
+        ParserError::RecursionLimitExceeded => "Query is nested too deeply".to_string(),
+    };
+    let position = message
+        .rsplit_once(" at Line: ")
+        .and_then(|(text, position)| {
+            let (line, column) = position.split_once(", Column: ")?;
+            let location = Location::new(line.trim().parse().ok()?, column.trim().parse().ok()?);
+            Some((text.to_string(), location))
+        });
+    match position {
+        Some((message, location)) => QueryError::sql(query, Span::new(location, location), message),
+        None => QueryError::new(Language::Sql, 0..query.len(), message),
+    }
+}
+
+fn handle_scan(
+    s: &Select,
+    query: &str,
+    functions: &FunctionRegistry,
+) -> Result<Algebra, QueryError> {
+    // `FROM $$source, UNNEST(tags) AS tag` is the same as a cross join with the UNNEST
+    let (from, unnests) = match s.from.as_slice() {
+        [from, rest @ ..]
+            if rest.iter().all(|r| {
+                r.joins.is_empty() && matches!(r.relation, TableFactor::UNNEST { .. })
+            }) =>
+        {
+            (from, rest.iter().map(|r| &r.relation))
         }
-        todo!()
-    } else {
-        for _ in &s.from {
-            todo!()
+        _ => {
+            return Err(QueryError::sql(
+                query,
+                s.span(),
+                "Expected a single source in FROM",
+            ));
         }
-        todo!()
     };
+    let mut node = handle_table(&from.relation, query)?;
+
+    let (unnested, joins): (Vec<_>, Vec<_>) = from.joins.iter().partition(|j| {
+        matches!(j.join_operator, JoinOperator::CrossJoin(_))
+            && matches!(j.relation, TableFactor::UNNEST { .. })
+    });
+    if joins.len() > 1 {
+        return Err(QueryError::sql(
+            query,
+            from.span(),
+            "Joining more than two sources is not supported",
+        ));
+    }
+    let unnests = unnested.iter().map(|j| &j.relation).chain(unnests);
+    if !joins.is_empty() && unnests.clone().next().is_some() {
+        return Err(QueryError::sql(
+            query,
+            from.span(),
+            "UNNEST cannot be combined with joins",
+        ));
+    }
+    for relation in unnests {
+        node = handle_unnest(node, relation, query, functions)?;
+    }
+
+    for join in joins {
+        let on = match &join.join_operator {
+            JoinOperator::Join(JoinConstraint::On(on))
+            | JoinOperator::Inner(JoinConstraint::On(on)) => on,
+            _ => {
+                return Err(QueryError::sql(
+                    query,
+                    join.span(),
+                    "Only inner joins with ON are supported",
+                ));
+            }
+        };
+        let aliases = [table_alias(&from.relation), table_alias(&join.relation)];
+        node = Algebra::Join(Join {
+            left: Box::new(node),
+            right: Box::new(handle_table(&join.relation, query)?),
+            on: qualify(Expression::from_sql(on, query, functions)?, &aliases),
+            left_alias: aliases[0].clone(),
+            right_alias: aliases[1].clone(),
+        });
+    }
+    Ok(node)
+}
+
+/// `UNNEST(tags) AS tag` turns each element of `tags` into a record of its own, which has
+/// all fields of the input and the element as `tag`.
+fn handle_unnest(
+    input: Algebra,
+    relation: &TableFactor,
+    query: &str,
+    functions: &FunctionRegistry,
+) -> Result<Algebra, QueryError> {
+    let error = |message: &str| QueryError::sql(query, relation.span(), message);
+    let TableFactor::UNNEST {
+        alias,
+        array_exprs,
+        with_offset,
+        with_ordinality,
+        ..
+    } = relation
+    else {
+        return Err(error("Expected UNNEST"));
+    };
+    let [array] = array_exprs.as_slice() else {
+        return Err(error("UNNEST expects a single array"));
+    };
+    if *with_offset || *with_ordinality {
+        return Err(error("UNNEST with offsets is not supported"));
+    }
+    // `AS t(tag)` names the element `tag`, `AS tag` names it like the relation
+    let name = match alias {
+        None => "unnest".to_string(),
+        Some(alias) => match alias.columns.as_slice() {
+            [] => alias.name.value.clone(),
+            [column] => column.name.value.clone(),
+            _ => return Err(error("UNNEST produces a single column")),
+        },
+    };
+
+    let expressions = IndexMap::from([
+        ("*".to_string(), Expression::Wildcard),
+        (name.clone(), Expression::from_sql(array, query, functions)?),
+    ]);
+    Ok(Algebra::Unwind(Unwind {
+        input: Box::new(Algebra::Project(Project {
+            expressions,
+            input: Box::new(input),
+        })),
+        key: name,
+        func: Operator::Explode,
+    }))
+}
+
+fn handle_table(relation: &TableFactor, query: &str) -> Result<Algebra, QueryError> {
+    if let TableFactor::Table { name, .. } = relation {
+        return Ok(Algebra::Scan(Scan {
+            source: name.to_string(),
+            schema: Schema::Dynamic,
+        }));
+    }
+    Err(QueryError::sql(
+        query,
+        relation.span(),
+        "Only definitions can be queried",
+    ))
+}
+
+/// `d.name` is the field `name` of the joined source `d`, not the key `name` in a field `d`.
+fn qualify(expression: Expression, aliases: &[String]) -> Expression {
+    let Expression::Call {
+        operator,
+        expressions,
+    } = expression
+    else {
+        return expression;
+    };
+    let mut expressions = expressions
+        .into_iter()
+        .map(|e| qualify(e, aliases))
+        .collect::<Vec<_>>();
+    match (&operator, expressions.as_slice()) {
+        (
+            Operator::Index,
+            [
+                Expression::Field(alias),
+                Expression::Literal(Value::Text(key)),
+                ..,
+            ],
+        ) if aliases.contains(alias) => {
+            let field = Expression::Field(format!("{}.{}", alias, key.0));
+            Expression::path(field, expressions.split_off(2))
+        }
+        _ => Expression::call(operator, expressions),
+    }
+}
+
+/// `$$devices d` is qualified by `d`, `$$devices` by `devices`.
+fn table_alias(relation: &TableFactor) -> String {
+    match relation {
+        TableFactor::Table {
+            alias: Some(alias), ..
+        } => alias.name.value.clone(),
+        TableFactor::Table { name, .. } => name.to_string().trim_start_matches('$').to_string(),
+        // other relations are rejected by handle_table
+        relation => relation.to_string(),
+    }
 }
@@ -0,0 +1,1554 @@
+use crate::expression::Expression;
+use crate::function::Function;
+use crate::language::{Language, Sql};
+use crate::operator::Operator;
+use crate::{Aggregate, Algebra, Project, Schema};
+use anyhow::{anyhow, bail};
+use indexmap::IndexMap;
+use serde_json::{Map, Value as Json, json};
+use std::fmt::{Display, Formatter};
+use value::{ValType, Value};
+
+/// Writes the algebra as query of the language, so the engine which holds the records evaluates
+/// it. Scans read the entity, e.g. a name of `Definition::entity_name`, instead of their source.
+pub fn transpile(algebra: &Algebra, language: Language, entity: &str) -> anyhow::Result<String> {
+    match language {
+        Language::Sql => to_sql(algebra, entity),
+        Language::Mql => to_mql(algebra, entity),
+        Language::Cypher => to_cypher(algebra, entity),
+    }
+}
+
+/// The algebra as PostgreSQL query over the table.
+pub fn to_sql(algebra: &Algebra, table: &str) -> anyhow::Result<String> {
+    Ok(select(algebra, Some(table), &mut 0)?.to_string())
+}
+
+/// The algebra as aggregation over the collection, `db.<collection>.aggregate([...])`.
+pub fn to_mql(algebra: &Algebra, collection: &str) -> anyhow::Result<String> {
+    Ok(format!(
+        "db.{}.aggregate({})",
+        collection,
+        Json::Array(to_pipeline(algebra)?)
+    ))
+}
+
+/// The stages of the aggregation pipeline which evaluates the algebra.
+pub fn to_pipeline(algebra: &Algebra) -> anyhow::Result<Vec<Json>> {
+    let mut pipeline = vec![];
+    stages(algebra, &mut pipeline)?;
+    Ok(pipeline)
+}
+
+/// The algebra as Cypher query over the nodes with the label.
+pub fn to_cypher(algebra: &Algebra, label: &str) -> anyhow::Result<String> {
+    let mut cypher = Cypher {
+        clauses: vec![],
+        bound: Bound::Node,
+    };
+    cypher.write(algebra, label)?;
+    Ok(cypher.finish())
+}
+
+/// The name as it is, if it needs no quotes.
+fn quote(name: &str, quote: char) -> String {
+    let mut chars = name.chars();
+    let plain = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+    match plain {
+        true => name.to_string(),
+        false => format!(
+            "{0}{1}{0}",
+            quote,
+            name.replace(quote, &format!("{0}{0}", quote))
+        ),
+    }
+}
+
+/// The fields of the input, which are needed to replace one of them.
+fn input_fields(input: &Algebra, what: &str) -> anyhow::Result<Vec<String>> {
+    match input.schema()? {
+        Schema::Fixed(fields) => Ok(fields.into_keys().collect()),
+        Schema::Dynamic => bail!("{} needs the fields of its input", what),
+    }
+}
+
+/// The expressions of the projection, the wildcard and excluded fields are resolved against a
+/// fixed input.
+fn resolved(project: &Project) -> IndexMap<String, Expression> {
+    project.resolve(&project.input.schema().unwrap_or(Schema::Dynamic))
+}
+
+fn text(value: &Value) -> Option<&str> {
+    match value {
+        Value::Text(t) => Some(t.0.as_str()),
+        _ => None,
+    }
+}
+
+/// The regular expression of a SQL pattern with `%` and `_`.
+fn like_regex(pattern: &str) -> String {
+    let mut regex = String::new();
+    for c in pattern.chars() {
+        match c {
+            '%' => regex.push_str(".*"),
+            '_' => regex.push('.'),
+            c if c.is_alphanumeric() || c == ' ' => regex.push(c),
+            c => {
+                regex.push('\\');
+                regex.push(c);
+            }
+        }
+    }
+    regex
+}
+
+/// A single SELECT, operators which do not fit into it select from it as subquery.
+struct Select {
+    /// all columns if empty
+    items: Vec<String>,
+    /// the fields of the records if they are not all columns of the table, e.g. besides row ids
+    fields: Vec<String>,
+    from: String,
+    filter: Vec<String>,
+    group: Vec<String>,
+    order: Vec<String>,
+    limit: Option<usize>,
+    distinct: bool,
+}
+
+impl Select {
+    fn new(from: String) -> Self {
+        Select {
+            items: vec![],
+            fields: vec![],
+            from,
+            filter: vec![],
+            group: vec![],
+            order: vec![],
+            limit: None,
+            distinct: false,
+        }
+    }
+
+    /// Whether the rows are still the ones of the source, so a filter or projection can be added.
+    fn plain(&self) -> bool {
+        self.items.is_empty()
+            && self.group.is_empty()
+            && self.order.is_empty()
+            && self.limit.is_none()
+            && !self.distinct
+    }
+
+    /// Selects from this query, subqueries are named t0, t1, …
+    fn nest(self, subqueries: &mut usize) -> (Select, String) {
+        let alias = format!("t{}", subqueries);
+        *subqueries += 1;
+        (Select::new(format!("({}) AS {}", self, alias)), alias)
+    }
+}
+
+impl Display for Select {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SELECT ")?;
+        if self.distinct {
+            write!(f, "DISTINCT ")?;
+        }
+        match (self.items.is_empty(), self.fields.is_empty()) {
+            (true, true) => write!(f, "* FROM {}", self.from)?,
+            (true, false) => write!(f, "{} FROM {}", self.fields.join(", "), self.from)?,
+            (false, _) => write!(f, "{} FROM {}", self.items.join(", "), self.from)?,
+        }
+        if !self.filter.is_empty() {
+            write!(f, " WHERE {}", self.filter.join(" AND "))?;
+        }
+        if !self.group.is_empty() {
+            write!(f, " GROUP BY {}", self.group.join(", "))?;
+        }
+        if !self.order.is_empty() {
+            write!(f, " ORDER BY {}", self.order.join(", "))?;
+        }
+        if let Some(limit) = self.limit {
+            write!(f, " LIMIT {}", limit)?;
+        }
+        Ok(())
+    }
+}
+
+impl Sql for Algebra {
+    /// The query over the sources of the scans, parts which cannot be written become a comment.
+    fn sql(&self) -> String {
+        match select(self, None, &mut 0) {
+            Ok(select) => select.to_string(),
+            Err(err) => format!("/* {} */", err),
+        }
+    }
+}
+
+/// Writes the algebra as SELECT, scans read the table or their own source without one.
+fn select(
+    algebra: &Algebra,
+    table: Option<&str>,
+    subqueries: &mut usize,
+) -> anyhow::Result<Select> {
+    // the input as query whose rows are not yet transformed
+    let plain = |input: &Algebra, subqueries: &mut usize| -> anyhow::Result<Select> {
+        let select = select(input, table, subqueries)?;
+        Ok(match select.plain() {
+            true => select,
+            false => select.nest(subqueries).0,
+        })
+    };
+    Ok(match algebra {
+        Algebra::Scan(scan) => {
+            let mut select = Select::new(quote(table.unwrap_or(&scan.source), '"'));
+            if table.is_some()
+                && let Schema::Fixed(fields) = &scan.schema
+            {
+                select.fields = fields.keys().map(|field| quote(field, '"')).collect();
+            }
+            select
+        }
+        Algebra::Filter(filter) => {
+            let mut select = plain(&filter.input, subqueries)?;
+            select.filter.push(sql(&filter.predicate)?);
+            select
+        }
+        Algebra::Project(project) => {
+            let mut select = plain(&project.input, subqueries)?;
+            for (name, expression) in resolved(project) {
+                select.items.push(match expression {
+                    Expression::Wildcard => "*".to_string(),
+                    Expression::Exclude(field) => {
+                        bail!("Excluding {} needs the fields of the input", field)
+                    }
+                    Expression::Field(field) if field == name => quote(&field, '"'),
+                    e => format!("{} AS {}", sql(&e)?, quote(&name, '"')),
+                });
+            }
+            select
+        }
+        Algebra::Aggregate(aggregate) => {
+            let mut select = plain(&aggregate.input, subqueries)?;
+            for (name, expression) in &aggregate.expressions {
+                select
+                    .items
+                    .push(format!("{} AS {}", sql(expression)?, quote(name, '"')));
+            }
+            select.group = aggregate
+                .keys
+                .iter()
+                .map(sql)
+                .collect::<anyhow::Result<_>>()?;
+            select
+        }
+        Algebra::Unwind(unwind) => {
+            let mut select = plain(&unwind.input, subqueries)?;
+            select.items = input_fields(&unwind.input, "UNNEST")?
+                .into_iter()
+                .map(|field| match field == unwind.key {
+                    true => format!("UNNEST({0}) AS {0}", quote(&field, '"')),
+                    false => quote(&field, '"'),
+                })
+                .collect();
+            select
+        }
+        Algebra::Sort(sort) => {
+            let mut select = select(&sort.input, table, subqueries)?;
+            if !select.order.is_empty() || select.limit.is_some() {
+                select = select.nest(subqueries).0;
+            }
+            for key in &sort.keys {
+                let expression = sql(&key.expression)?;
+                select.order.push(match key.descending {
+                    true => format!("{} DESC", expression),
+                    false => expression,
+                });
+            }
+            select
+        }
+        Algebra::Limit(limit) => {
+            let mut select = select(&limit.input, table, subqueries)?;
+            if select.limit.is_some() {
+                select = select.nest(subqueries).0;
+            }
+            select.limit = Some(limit.limit);
+            select
+        }
+        Algebra::Distinct(distinct) => {
+            // duplicates are removed before sorting and limiting
+            let mut select = select(&distinct.input, table, subqueries)?;
+            if select.distinct || !select.order.is_empty() || select.limit.is_some() {
+                select = select.nest(subqueries).0;
+            }
+            select.distinct = true;
+            select
+        }
+        Algebra::Collect(collect) => {
+            let (mut select, alias) = select(&collect.input, table, subqueries)?.nest(subqueries);
+            select
+                .items
+                .push(format!("ARRAY_AGG({}) AS \"values\"", alias));
+            select
+        }
+        Algebra::Join(join) => {
+            let right = join
+                .right_source()
+                .ok_or_else(|| anyhow!("The right side of a join needs to be a definition"))?;
+            let left = match join.left.as_ref() {
+                Algebra::Scan(scan) => quote(table.unwrap_or(&scan.source), '"'),
+                left => format!("({})", select(left, table, subqueries)?),
+            };
+            Select::new(format!(
+                "{} AS {} JOIN {} AS {} ON {}",
+                left,
+                quote(&join.left_alias, '"'),
+                quote(right, '"'),
+                quote(&join.right_alias, '"'),
+                sql(&join.on)?
+            ))
+        }
+        Algebra::Todo(todo) => bail!("{} cannot be written as SQL", todo),
+    })
+}
+
+fn sql(expression: &Expression) -> anyhow::Result<String> {
+    let (operator, expressions) = match expression {
+        // fields of a join are qualified by the alias of their side
+        Expression::Field(name) => {
+            return Ok(name
+                .split('.')
+                .map(|part| quote(part, '"'))
+                .collect::<Vec<_>>()
+                .join("."));
+        }
+        Expression::Literal(value) => return sql_literal(value),
+        Expression::Wildcard => return Ok("*".to_string()),
+        Expression::Exclude(field) => bail!("Excluding {} is no expression", field),
+        Expression::Call {
+            operator,
+            expressions,
+        } => (operator, expressions),
+    };
+    // the first key of a path from the whole record names a column
+    if let (
+        Operator::Index,
+        [
+            Expression::Wildcard,
+            Expression::Literal(Value::Text(key)),
+            ..,
+        ],
+    ) = (operator, expressions.as_slice())
+    {
+        return sql(&Expression::path(
+            Expression::field(&key.0),
+            expressions[2..].to_vec(),
+        ));
+    }
+
+    let args = expressions
+        .iter()
+        .map(sql)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let binary = |op: &str| format!("({} {} {})", args[0], op, args[1]);
+    Ok(match operator {
+        Operator::Add => binary("+"),
+        Operator::Minus => binary("-"),
+        Operator::Multiply => binary("*"),
+        // integers are not divided to integers
+        Operator::Divide => format!("(CAST({} AS DOUBLE PRECISION) / {})", args[0], args[1]),
+        Operator::Gt => binary(">"),
+        Operator::Gte => binary(">="),
+        Operator::Lt => binary("<"),
+        Operator::Lte => binary("<="),
+        Operator::Equal => binary("="),
+        Operator::NotEqual => binary("<>"),
+        Operator::And => binary("AND"),
+        Operator::Or => binary("OR"),
+        Operator::Not => format!("(NOT {})", args[0]),
+        Operator::IsNull => format!("({} IS NULL)", args[0]),
+        Operator::In => format!("({} IN ({}))", args[0], args[1..].join(", ")),
+        // nested values are stored serialized, so their parts cannot be read by the database
+        Operator::Index => bail!("{} is evaluated by DataTracks", expression.sql()),
+        Operator::Cond => format!(
+            "CASE WHEN {} THEN {} ELSE {} END",
+            args[0], args[1], args[2]
+        ),
+        Operator::Concat => format!("({})", args.join(" || ")),
+        Operator::Count => match args.as_slice() {
+            [] => "COUNT(*)".to_string(),
+            args => format!("COUNT({})", args[0]),
+        },
+        Operator::Sum => format!("SUM({})", args[0]),
+        Operator::Avg => format!("AVG({})", args[0]),
+        Operator::Min => format!("MIN({})", args[0]),
+        Operator::Max => format!("MAX({})", args[0]),
+        Operator::Collect => format!("ARRAY_AGG({})", args[0]),
+        Operator::Function(Function::Regex) => binary("~"),
+        Operator::Function(Function::Cast(t)) => {
+            let t = match t {
+                ValType::Integer => "BIGINT",
+                ValType::Float => "DOUBLE PRECISION",
+                ValType::Text => "TEXT",
+                ValType::Bool => "BOOLEAN",
+                ValType::Time => "TIMESTAMP",
+                ValType::Date => "DATE",
+                t => bail!("Cannot cast to {:?} in SQL", t),
+            };
+            format!("CAST({} AS {})", args[0], t)
+        }
+        // only numerics are rounded to digits
+        Operator::Function(Function::Round) if args.len() == 2 => {
+            format!("ROUND(CAST({} AS NUMERIC), {})", args[0], args[1])
+        }
+        Operator::Function(f) => f.sql(args),
+        // the engine needs to know the function under the same name
+        Operator::Udf(f) => f.sql(args),
+        Operator::Explode | Operator::HasLabel | Operator::StartNode | Operator::EndNode => {
+            bail!("{:?} cannot be written as SQL", operator)
+        }
+    })
+}
+
+fn sql_literal(value: &Value) -> anyhow::Result<String> {
+    Ok(match value {
+        Value::Null => "NULL".to_string(),
+        Value::Bool(b) => b.0.to_string().to_uppercase(),
+        Value::Int(i) => i.0.to_string(),
+        Value::Float(f) => format!("{:?}", f.0.0),
+        Value::Text(t) => format!("'{}'", t.0.replace('\'', "''")),
+        Value::Array(a) => format!(
+            "ARRAY[{}]",
+            a.values
+                .iter()
+                .map(sql_literal)
+                .collect::<anyhow::Result<Vec<_>>>()?
+                .join(", ")
+        ),
+        value => bail!("Cannot write {:?} as SQL", value.type_()),
+    })
+}
+
+fn stages(algebra: &Algebra, pipeline: &mut Vec<Json>) -> anyhow::Result<()> {
+    match algebra {
+        Algebra::Scan(_) => {}
+        Algebra::Filter(filter) => {
+            stages(&filter.input, pipeline)?;
+            pipeline.push(json!({"$match": query_filter(&filter.predicate)?}));
+        }
+        Algebra::Project(project) => {
+            stages(&project.input, pipeline)?;
+            project_stages(project, pipeline)?;
+        }
+        Algebra::Aggregate(aggregate) => {
+            stages(&aggregate.input, pipeline)?;
+            group_stages(aggregate, pipeline)?;
+        }
+        Algebra::Unwind(unwind) => {
+            stages(&unwind.input, pipeline)?;
+            pipeline.push(json!({"$unwind": format!("${}", unwind.key)}));
+        }
+        Algebra::Sort(sort) => {
+            stages(&sort.input, pipeline)?;
+            // computed keys are only added for sorting
+            let mut computed = Map::new();
+            let mut keys = Map::new();
+            for (i, key) in sort.keys.iter().enumerate() {
+                let name = match field_path(&key.expression) {
+                    Some(path) => path,
+                    None => {
+                        let name = format!("__sort{}", i);
+                        computed.insert(name.clone(), mql(&key.expression, &|_| None)?);
+                        name
+                    }
+                };
+                keys.insert(name, json!(if key.descending { -1 } else { 1 }));
+            }
+            let names = computed.keys().cloned().collect::<Vec<_>>();
+            if !computed.is_empty() {
+                pipeline.push(json!({"$addFields": computed}));
+            }
+            pipeline.push(json!({"$sort": keys}));
+            if !names.is_empty() {
+                pipeline.push(json!({"$unset": names}));
+            }
+        }
+        Algebra::Limit(limit) => {
+            stages(&limit.input, pipeline)?;
+            pipeline.push(json!({"$limit": limit.limit}));
+        }
+        Algebra::Distinct(distinct) => {
+            stages(&distinct.input, pipeline)?;
+            // the ids of the documents would make all of them distinct
+            pipeline.push(json!({"$unset": "_id"}));
+            pipeline.push(json!({"$group": {"_id": "$$ROOT"}}));
+            pipeline.push(json!({"$replaceRoot": {"newRoot": "$_id"}}));
+        }
+        Algebra::Collect(collect) => {
+            stages(&collect.input, pipeline)?;
+            pipeline.push(json!({"$group": {"_id": null, "values": {"$push": "$$ROOT"}}}));
+            pipeline.push(json!({"$project": {"_id": 0, "values": 1}}));
+        }
+        Algebra::Join(_) => bail!("Joins cannot be written as MQL"),
+        Algebra::Todo(todo) => bail!("{} cannot be written as MQL", todo),
+    }
+    Ok(())
+}
+
+/// A projection which keeps the whole record only sets and removes fields.
+fn project_stages(project: &Project, pipeline: &mut Vec<Json>) -> anyhow::Result<()> {
+    let keeps = project
+        .expressions
+        .values()
+        .any(|e| matches!(e, Expression::Wildcard));
+
+    let mut fields = Map::new();
+    let mut excluded = vec![];
+    for (name, expression) in &project.expressions {
+        match expression {
+            Expression::Wildcard => {}
+            Expression::Exclude(field) if keeps => excluded.push(json!(field)),
+            Expression::Exclude(field) => {
+                fields.insert(field.clone(), json!(0));
+            }
+            Expression::Field(field) if field == name && keeps => {}
+            Expression::Field(field) if field == name => {
+                fields.insert(name.clone(), json!(1));
+            }
+            // numbers and booleans would include or exclude fields
+            Expression::Literal(value) => {
+                fields.insert(name.clone(), json!({"$literal": json_literal(value)?}));
+            }
+            e => {
+                fields.insert(name.clone(), mql(e, &|_| None)?);
+            }
+        }
+    }
+    match keeps {
+        true => {
+            if !fields.is_empty() {
+                pipeline.push(json!({"$addFields": fields}));
+            }
+            if !excluded.is_empty() {
+                pipeline.push(json!({"$unset": excluded}));
+            }
+        }
+        false => pipeline.push(json!({"$project": fields})),
+    }
+    Ok(())
+}
+
+/// Groups by the keys and accumulates the aggregate calls, the expressions are evaluated over
+/// their results afterwards.
+fn group_stages(aggregate: &Aggregate, pipeline: &mut Vec<Json>) -> anyhow::Result<()> {
+    let keys = &aggregate.keys;
+    let calls = aggregate.calls();
+
+    let id = match keys.as_slice() {
+        [] => Json::Null,
+        [key] => mql(key, &|_| None)?,
+        keys => Json::Object(
+            keys.iter()
+                .enumerate()
+                .map(|(i, key)| Ok((format!("k{}", i), mql(key, &|_| None)?)))
+                .collect::<anyhow::Result<_>>()?,
+        ),
+    };
+    let mut group = Map::from_iter([("_id".to_string(), id)]);
+    for (i, call) in calls.iter().enumerate() {
+        group.insert(format!("a{}", i), accumulator(call)?);
+    }
+    pipeline.push(json!({"$group": group}));
+
+    let grouped = |e: &Expression| {
+        if let Some(i) = keys.iter().position(|k| k == e) {
+            return Some(match keys.len() {
+                1 => json!("$_id"),
+                _ => json!(format!("$_id.k{}", i)),
+            });
+        }
+        calls
+            .iter()
+            .position(|c| *c == e)
+            .map(|i| json!(format!("$a{}", i)))
+    };
+    let mut fields = Map::from_iter([("_id".to_string(), json!(0))]);
+    for (name, expression) in &aggregate.expressions {
+        let value = match expression {
+            Expression::Literal(value) => json!({"$literal": json_literal(value)?}),
+            e => mql(e, &grouped)?,
+        };
+        fields.insert(name.clone(), value);
+    }
+    pipeline.push(json!({"$project": fields}));
+    Ok(())
+}
+
+fn accumulator(call: &Expression) -> anyhow::Result<Json> {
+    let Expression::Call {
+        operator,
+        expressions,
+    } = call
+    else {
+        bail!("{} is no aggregate", call.sql())
+    };
+    let arg = || match expressions.first() {
+        Some(e) => mql(e, &|_| None),
+        None => bail!("{:?} needs an argument", operator),
+    };
+    Ok(match operator {
+        Operator::Count if expressions.is_empty() => json!({"$sum": 1}),
+        // nulls are not counted
+        Operator::Count => json!({"$sum": {"$cond": [missing(arg()?), 0, 1]}}),
+        Operator::Sum => json!({"$sum": arg()?}),
+        Operator::Avg => json!({"$avg": arg()?}),
+        Operator::Min => json!({"$min": arg()?}),
+        Operator::Max => json!({"$max": arg()?}),
+        Operator::Collect => json!({"$push": arg()?}),
+        operator => bail!("{:?} is no aggregate", operator),
+    })
+}
+
+/// Whether the value is null or missing, which aggregation expressions tell apart.
+fn missing(value: Json) -> Json {
+    json!({"$eq": [{"$ifNull": [value, null]}, null]})
+}
+
+/// The dotted path of a field like `address.city`, if the expression is one.
+fn field_path(expression: &Expression) -> Option<String> {
+    match expression {
+        Expression::Field(name) => Some(name.clone()),
+        Expression::Call {
+            operator: Operator::Index,
+            expressions,
+        } => {
+            let mut path = match &expressions[0] {
+                Expression::Wildcard => vec![],
+                container => vec![field_path(container)?],
+            };
+            for key in &expressions[1..] {
+                match key {
+                    Expression::Literal(key) => path.push(text(key)?.to_string()),
+                    _ => return None,
+                }
+            }
+            (!path.is_empty()).then(|| path.join("."))
+        }
+        _ => None,
+    }
+}
+
+/// The predicate as query filter, comparisons of fields with values become conditions on the
+/// fields, which can use indexes.
+fn query_filter(predicate: &Expression) -> anyhow::Result<Json> {
+    let mut conditions = predicate
+        .conjuncts()
+        .into_iter()
+        .map(condition)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(match conditions.len() {
+        1 => conditions.remove(0),
+        _ => json!({"$and": conditions}),
+    })
+}
+
+fn condition(conjunct: &Expression) -> anyhow::Result<Json> {
+    let expression = || Ok(json!({"$expr": mql(conjunct, &|_| None)?}));
+    let Expression::Call {
+        operator,
+        expressions,
+    } = conjunct
+    else {
+        return expression();
+    };
+    // queries match missing fields like null ones
+    if let (
+        Operator::Not,
+        [
+            Expression::Call {
+                operator: Operator::IsNull,
+                expressions,
+            },
+        ],
+    ) = (operator, expressions.as_slice())
+        && let Some(field) = field_path(&expressions[0])
+    {
+        return Ok(json!({field: {"$ne": null}}));
+    }
+    let Some(field) = expressions.first().and_then(field_path) else {
+        return expression();
+    };
+    let values = expressions[1..]
+        .iter()
+        .map(|e| match e {
+            Expression::Literal(Value::Null) => None,
+            Expression::Literal(value) => json_literal(value).ok(),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>();
+    let compared = match operator {
+        Operator::IsNull => return Ok(json!({field: null})),
+        Operator::In => match values {
+            Some(values) => return Ok(json!({field: {"$in": values}})),
+            None => return expression(),
+        },
+        Operator::Equal => "$eq",
+        Operator::NotEqual => "$ne",
+        Operator::Gt => "$gt",
+        Operator::Gte => "$gte",
+        Operator::Lt => "$lt",
+        Operator::Lte => "$lte",
+        _ => return expression(),
+    };
+    match values.as_deref() {
+        // missing and null fields are not unequal to anything, like in the tuple VM
+        Some([value]) if compared == "$ne" => Ok(json!({field: {
+            "$ne": value,
+            "$exists": true,
+            "$not": {"$type": "null"}
+        }})),
+        Some([value]) => Ok(json!({field: {compared: value}})),
+        _ => expression(),
+    }
+}
+
+/// The aggregation expression, `replace` gives the parts which earlier stages computed.
+fn mql(
+    expression: &Expression,
+    replace: &dyn Fn(&Expression) -> Option<Json>,
+) -> anyhow::Result<Json> {
+    if let Some(replaced) = replace(expression) {
+        return Ok(replaced);
+    }
+    if let Some(path) = field_path(expression) {
+        return Ok(json!(format!("${}", path)));
+    }
+    let (operator, expressions) = match expression {
+        Expression::Literal(value) => return mql_literal(value),
+        Expression::Wildcard => return Ok(json!("$$ROOT")),
+        Expression::Field(_) | Expression::Exclude(_) => {
+            bail!("{} is no expression", expression.sql())
+        }
+        Expression::Call {
+            operator,
+            expressions,
+        } => (operator, expressions),
+    };
+    let mut args = expressions
+        .iter()
+        .map(|e| mql(e, replace))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let call = |name: &str, args: Vec<Json>| json!({name: args});
+
+    Ok(match operator {
+        Operator::Add => call("$add", args),
+        Operator::Minus => call("$subtract", args),
+        Operator::Multiply => call("$multiply", args),
+        Operator::Divide => call("$divide", args),
+        Operator::Gt => call("$gt", args),
+        Operator::Gte => call("$gte", args),
+        Operator::Lt => call("$lt", args),
+        Operator::Lte => call("$lte", args),
+        Operator::Equal => call("$eq", args),
+        // comparing with null or a missing field is unknown, like in the tuple VM
+        Operator::NotEqual => {
+            let unknown = json!({"$or": [missing(args[0].clone()), missing(args[1].clone())]});
+            json!({"$cond": [unknown, null, call("$ne", args)]})
+        }
+        Operator::And => call("$and", args),
+        Operator::Or => call("$or", args),
+        Operator::Not => call("$not", args),
+        Operator::IsNull => missing(args.remove(0)),
+        Operator::In => {
+            let value = args.remove(0);
+            json!({"$in": [value, args]})
+        }
+        // keys which are no names index arrays
+        Operator::Index => expressions[1..].iter().zip(args[1..].iter()).fold(
+            args[0].clone(),
+            |container, (key, arg)| match key {
+                Expression::Literal(Value::Text(_)) => {
+                    json!({"$getField": {"field": arg, "input": container}})
+                }
+                _ => json!({"$arrayElemAt": [container, arg]}),
+            },
+        ),
+        Operator::Cond => call("$cond", args),
+        Operator::Concat => call("$concat", args),
+        Operator::Function(function) => mql_function(function, args)?,
+        Operator::Udf(f) => call(&format!("${}", f.name), args),
+        Operator::Count
+        | Operator::Sum
+        | Operator::Avg
+        | Operator::Min
+        | Operator::Max
+        | Operator::Collect => bail!("{:?} can only be evaluated per group", operator),
+        Operator::Explode | Operator::HasLabel | Operator::StartNode | Operator::EndNode => {
+            bail!("{:?} cannot be written as MQL", operator)
+        }
+    })
+}
+
+fn mql_function(function: &Function, mut args: Vec<Json>) -> anyhow::Result<Json> {
+    let single = |name: &str, args: Vec<Json>| json!({name: args[0]});
+    Ok(match function {
+        Function::Lower => single("$toLower", args),
+        Function::Upper => single("$toUpper", args),
+        Function::Trim => json!({"$trim": {"input": args[0]}}),
+        Function::Length => single("$strLenCP", args),
+        Function::Like => match args[1].as_str() {
+            Some(pattern) => {
+                json!({"$regexMatch": {"input": args[0], "regex": format!("^{}$", like_regex(pattern))}})
+            }
+            None => bail!("LIKE needs a literal pattern in MQL"),
+        },
+        Function::Regex => json!({"$regexMatch": {"input": args[0], "regex": args[1]}}),
+        Function::Abs => single("$abs", args),
+        Function::Round => json!({"$round": args}),
+        Function::Floor => single("$floor", args),
+        Function::Ceil => single("$ceil", args),
+        Function::Mod => json!({"$mod": args}),
+        Function::Pow => json!({"$pow": args}),
+        Function::Coalesce => json!({"$ifNull": args}),
+        Function::NullIf => json!({"$cond": [{"$eq": [args[0], args[1]]}, null, args[0]]}),
+        Function::Cast(t) => {
+            let name = match t {
+                ValType::Integer => "$toLong",
+                ValType::Float => "$toDouble",
+                ValType::Text => "$toString",
+                ValType::Bool => "$toBool",
+                ValType::Time => "$toDate",
+                t => bail!("Cannot cast to {:?} in MQL", t),
+            };
+            single(name, args)
+        }
+        Function::Year => single("$year", args),
+        Function::Month => single("$month", args),
+        Function::Day => single("$dayOfMonth", args),
+        Function::Hour => single("$hour", args),
+        Function::Minute => single("$minute", args),
+        Function::Second => single("$second", args),
+        // the start is 0-based and the length is needed
+        Function::Substring => {
+            let length = match args.len() {
+                3 => args.remove(2),
+                _ => json!({"$strLenCP": args[0]}),
+            };
+            json!({"$substrCP": [args[0], {"$subtract": [args[1], 1]}, length]})
+        }
+    })
+}
+
+/// The literal as it is written in aggregation expressions.
+fn mql_literal(value: &Value) -> anyhow::Result<Json> {
+    // texts like "$name" would be fields
+    fn escaped(value: &Value) -> bool {
+        match value {
+            Value::Text(t) => t.0.starts_with('$'),
+            Value::Array(a) => a.values.iter().any(escaped),
+            _ => false,
+        }
+    }
+    let json = json_literal(value)?;
+    Ok(match escaped(value) {
+        true => json!({"$literal": json}),
+        false => json,
+    })
+}
+
+fn json_literal(value: &Value) -> anyhow::Result<Json> {
+    Ok(match value {
+        Value::Null => Json::Null,
+        Value::Bool(b) => json!(b.0),
+        Value::Int(i) => json!(i.0),
+        Value::Float(f) => json!(f.0.0),
+        Value::Text(t) => json!(t.0.as_str()),
+        Value::Array(a) => Json::Array(
+            a.values
+                .iter()
+                .map(json_literal)
+                .collect::<anyhow::Result<_>>()?,
+        ),
+        value => bail!("Cannot write {:?} as MQL", value.type_()),
+    })
+}
+
+/// What the clauses so far bind, the matched node as `n` or the named fields of a projection.
+enum Bound {
+    Node,
+    Fields(Vec<String>),
+}
+
+/// A clause with the parts which may follow it, `ORDER BY` and `LIMIT` only follow `WITH`.
+struct Clause {
+    head: String,
+    order: Vec<String>,
+    limit: Option<usize>,
+    filter: Vec<String>,
+}
+
+impl Display for Clause {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.head)?;
+        if !self.order.is_empty() {
+            write!(f, " ORDER BY {}", self.order.join(", "))?;
+        }
+        if let Some(limit) = self.limit {
+            write!(f, " LIMIT {}", limit)?;
+        }
+        if !self.filter.is_empty() {
+            write!(f, " WHERE {}", self.filter.join(" AND "))?;
+        }
+        Ok(())
+    }
+}
+
+struct Cypher {
+    clauses: Vec<Clause>,
+    bound: Bound,
+}
+
+impl Cypher {
+    fn push(&mut self, head: String) {
+        self.clauses.push(Clause {
+            head,
+            order: vec![],
+            limit: None,
+            filter: vec![],
+        });
+    }
+
+    /// The bound values as items of a `WITH` or `RETURN`.
+    fn items(&self) -> String {
+        match &self.bound {
+            Bound::Node => "n".to_string(),
+            Bound::Fields(names) => names
+                .iter()
+                .map(|n| quote(n, '`'))
+                .collect::<Vec<_>>()
+                .join(", "),
+        }
+    }
+
+    /// The last clause if it is a `WITH` which can still be sorted or limited.
+    fn with(&mut self, sorts: bool) -> &mut Clause {
+        let open = self.clauses.last().is_some_and(|c| {
+            c.head.starts_with("WITH ")
+                && c.filter.is_empty()
+                && c.limit.is_none()
+                && (!sorts || c.order.is_empty())
+        });
+        if !open {
+            self.push(format!("WITH {}", self.items()));
+        }
+        self.clauses.last_mut().unwrap()
+    }
+
+    /// Projects the named items, which are bound as fields afterwards.
+    fn project(&mut self, items: IndexMap<String, String>) {
+        let head = items
+            .iter()
+            .map(|(name, item)| match quote(name, '`') == *item {
+                true => item.clone(),
+                false => format!("{} AS {}", item, quote(name, '`')),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.push(format!("WITH {}", head));
+        self.bound = Bound::Fields(items.into_keys().collect());
+    }
+
+    fn write(&mut self, algebra: &Algebra, label: &str) -> anyhow::Result<()> {
+        match algebra {
+            Algebra::Scan(_) => {
+                self.push(format!("MATCH (n:{})", quote(label, '`')));
+                self.bound = Bound::Node;
+            }
+            Algebra::Filter(filter) => {
+                self.write(&filter.input, label)?;
+                let predicate = self.expression(&filter.predicate)?;
+                if !self
+                    .clauses
+                    .last()
+                    .is_some_and(|c| c.head.starts_with("MATCH ") || c.head.starts_with("WITH "))
+                {
+                    self.push(format!("WITH {}", self.items()));
+                }
+                self.clauses.last_mut().unwrap().filter.push(predicate);
+            }
+            Algebra::Project(project) => {
+                self.write(&project.input, label)?;
+                let mut items = IndexMap::new();
+                let mut properties = vec![];
+                for (name, expression) in resolved(project) {
+                    match (expression, &self.bound) {
+                        // the node stays bound with the new properties
+                        (Expression::Wildcard, Bound::Node) => properties.push(".*".to_string()),
+                        (Expression::Wildcard, Bound::Fields(names)) => {
+                            items.extend(names.iter().map(|n| (n.clone(), quote(n, '`'))))
+                        }
+                        (Expression::Exclude(field), Bound::Node) => {
+                            bail!("Excluding {} needs the fields of the input", field)
+                        }
+                        (Expression::Exclude(field), Bound::Fields(_)) => {
+                            items.shift_remove(&field);
+                        }
+                        (e, _) => {
+                            items.insert(name, self.expression(&e)?);
+                        }
+                    }
+                }
+                match properties.is_empty() {
+                    true => self.project(items),
+                    false => {
+                        properties.extend(
+                            items
+                                .into_iter()
+                                .map(|(name, item)| format!("{}: {}", quote(&name, '`'), item)),
+                        );
+                        self.push(format!("WITH n {{{}}} AS n", properties.join(", ")));
+                    }
+                }
+            }
+            Algebra::Aggregate(aggregate) => {
+                self.write(&aggregate.input, label)?;
+                let mut items = IndexMap::new();
+                for (name, expression) in &aggregate.expressions {
+                    items.insert(name.clone(), self.expression(expression)?);
+                }
+                let names = items.keys().cloned().collect::<Vec<_>>();
+                // the records are grouped by all items without aggregates
+                let hidden = aggregate
+                    .keys
+                    .iter()
+                    .filter(|k| !aggregate.expressions.values().any(|e| e == *k))
+                    .map(|k| self.expression(k))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                for (i, key) in hidden.iter().enumerate() {
+                    items.insert(format!("__key{}", i), key.clone());
+                }
+                self.project(items);
+                if !hidden.is_empty() {
+                    self.bound = Bound::Fields(names);
+                    self.push(format!("WITH {}", self.items()));
+                }
+            }
+            Algebra::Unwind(unwind) => {
+                self.write(&unwind.input, label)?;
+                let list = self.expression(&Expression::field(&unwind.key))?;
+                self.push(format!("UNWIND {} AS __element", list));
+                match &self.bound {
+                    Bound::Node => self.push(format!(
+                        "WITH n {{.*, {}: __element}} AS n",
+                        quote(&unwind.key, '`')
+                    )),
+                    Bound::Fields(names) => {
+                        let items = names
+                            .iter()
+                            .map(|n| match *n == unwind.key {
+                                true => (n.clone(), "__element".to_string()),
+                                false => (n.clone(), quote(n, '`')),
+                            })
+                            .collect();
+                        self.project(items);
+                    }
+                }
+            }
+            Algebra::Sort(sort) => {
+                self.write(&sort.input, label)?;
+                let keys = sort
+                    .keys
+                    .iter()
+                    .map(|key| {
+                        let expression = self.expression(&key.expression)?;
+                        Ok(match key.descending {
+                            true => format!("{} DESC", expression),
+                            false => expression,
+                        })
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                self.with(true).order = keys;
+            }
+            Algebra::Limit(limit) => {
+                self.write(&limit.input, label)?;
+                self.with(false).limit = Some(limit.limit);
+            }
+            Algebra::Distinct(distinct) => {
+                self.write(&distinct.input, label)?;
+                self.push(format!("WITH DISTINCT {}", self.items()));
+            }
+            Algebra::Collect(collect) => {
+                self.write(&collect.input, label)?;
+                let values = self.expression(&Expression::Wildcard)?;
+                self.project(IndexMap::from([(
+                    "values".to_string(),
+                    format!("collect({})", values),
+                )]));
+            }
+            Algebra::Join(_) => bail!("Joins cannot be written as Cypher"),
+            Algebra::Todo(todo) => bail!("{} cannot be written as Cypher", todo),
+        }
+        Ok(())
+    }
+
+    /// The query with the last `WITH` as `RETURN`, or with a `RETURN` of the bound values.
+    fn finish(mut self) -> String {
+        match self.clauses.last_mut() {
+            Some(last) if last.head.starts_with("WITH ") && last.filter.is_empty() => {
+                last.head.replace_range(..4, "RETURN");
+            }
+            _ => {
+                let items = self.items();
+                self.push(format!("RETURN {}", items));
+            }
+        }
+        self.clauses
+            .iter()
+            .map(Clause::to_string)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn expression(&self, expression: &Expression) -> anyhow::Result<String> {
+        let (operator, expressions) = match expression {
+            Expression::Field(name) => {
+                return Ok(match self.bound {
+                    Bound::Node => format!("n.{}", quote(name, '`')),
+                    Bound::Fields(_) => quote(name, '`'),
+                });
+            }
+            Expression::Literal(value) => return cypher_literal(value),
+            Expression::Wildcard => {
+                return Ok(match &self.bound {
+                    Bound::Node => "n".to_string(),
+                    Bound::Fields(names) => format!(
+                        "{{{}}}",
+                        names
+                            .iter()
+                            .map(|n| format!("{0}: {0}", quote(n, '`')))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                });
+            }
+            Expression::Exclude(field) => bail!("Excluding {} is no expression", field),
+            Expression::Call {
+                operator,
+                expressions,
+            } => (operator, expressions),
+        };
+        match (operator, expressions.as_slice()) {
+            // the first key of a path from the whole record is a property or field
+            (
+                Operator::Index,
+                [
+                    Expression::Wildcard,
+                    Expression::Literal(Value::Text(key)),
+                    ..,
+                ],
+            ) => {
+                return self.expression(&Expression::path(
+                    Expression::field(&key.0),
+                    expressions[2..].to_vec(),
+                ));
+            }
+            (
+                Operator::HasLabel,
+                [
+                    Expression::Wildcard,
+                    Expression::Literal(Value::Text(label)),
+                ],
+            ) if matches!(self.bound, Bound::Node) => {
+                return Ok(format!("n:{}", quote(&label.0, '`')));
+            }
+            _ => {}
+        }
+
+        let args = expressions
+            .iter()
+            .map(|e| self.expression(e))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let binary = |op: &str| format!("({} {} {})", args[0], op, args[1]);
+        let call = |name: &str| format!("{}({})", name, args.join(", "));
+        Ok(match operator {
+            Operator::Add => binary("+"),
+            Operator::Minus => binary("-"),
+            Operator::Multiply => binary("*"),
+            // integers are not divided to integers
+            Operator::Divide => format!("(toFloat({}) / {})", args[0], args[1]),
+            Operator::Gt => binary(">"),
+            Operator::Gte => binary(">="),
+            Operator::Lt => binary("<"),
+            Operator::Lte => binary("<="),
+            Operator::Equal => binary("="),
+            Operator::NotEqual => binary("<>"),
+            Operator::And => binary("AND"),
+            Operator::Or => binary("OR"),
+            Operator::Not => format!("(NOT {})", args[0]),
+            Operator::IsNull => format!("({} IS NULL)", args[0]),
+            Operator::In => format!("({} IN [{}])", args[0], args[1..].join(", ")),
+            Operator::Index => expressions[1..].iter().zip(&args[1..]).fold(
+                args[0].clone(),
+                |container, (key, arg)| match key {
+                    Expression::Literal(Value::Text(key)) => {
+                        format!("{}.{}", container, quote(&key.0, '`'))
+                    }
+                    _ => format!("{}[{}]", container, arg),
+                },
+            ),
+            Operator::Cond => format!(
+                "CASE WHEN {} THEN {} ELSE {} END",
+                args[0], args[1], args[2]
+            ),
+            Operator::Concat => format!("({})", args.join(" + ")),
+            Operator::Count if args.is_empty() => "count(*)".to_string(),
+            Operator::Count => call("count"),
+            Operator::Sum => call("sum"),
+            Operator::Avg => call("avg"),
+            Operator::Min => call("min"),
+            Operator::Max => call("max"),
+            Operator::Collect => call("collect"),
+            Operator::Function(function) => match function {
+                Function::Lower => call("toLower"),
+                Function::Upper => call("toUpper"),
+                Function::Trim => call("trim"),
+                Function::Length => call("size"),
+                // the start is 0-based
+                Function::Substring => format!(
+                    "substring({}, {} - 1{})",
+                    args[0],
+                    args[1],
+                    args.get(2).map(|l| format!(", {}", l)).unwrap_or_default()
+                ),
+                Function::Like => match expressions.get(1) {
+                    Some(Expression::Literal(Value::Text(pattern))) => format!(
+                        "({} =~ {})",
+                        args[0],
+                        cypher_literal(&Value::text(like_regex(&pattern.0)))?
+                    ),
+                    _ => bail!("LIKE needs a literal pattern in Cypher"),
+                },
+                // =~ matches the whole text
+                Function::Regex => format!("({} =~ ('.*(?:' + {} + ').*'))", args[0], args[1]),
+                Function::Abs => call("abs"),
+                Function::Round => call("round"),
+                Function::Floor => call("floor"),
+                Function::Ceil => call("ceil"),
+                Function::Mod => binary("%"),
+                Function::Pow => binary("^"),
+                Function::Coalesce => call("coalesce"),
+                Function::NullIf => format!(
+                    "CASE WHEN {0} = {1} THEN null ELSE {0} END",
+                    args[0], args[1]
+                ),
+                Function::Cast(t) => match t {
+                    ValType::Integer => call("toInteger"),
+                    ValType::Float => call("toFloat"),
+                    ValType::Text => call("toString"),
+                    ValType::Bool => call("toBoolean"),
+                    ValType::Time => call("datetime"),
+                    ValType::Date => call("date"),
+                    t => bail!("Cannot cast to {:?} in Cypher", t),
+                },
+                Function::Year => format!("{}.year", args[0]),
+                Function::Month => format!("{}.month", args[0]),
+                Function::Day => format!("{}.day", args[0]),
+                Function::Hour => format!("{}.hour", args[0]),
+                Function::Minute => format!("{}.minute", args[0]),
+                Function::Second => format!("{}.second", args[0]),
+            },
+            // the engine needs to know the function under the same name
+            Operator::Udf(f) => call(&f.name),
+            Operator::Explode | Operator::HasLabel | Operator::StartNode | Operator::EndNode => {
+                bail!("{:?} cannot be written as Cypher", operator)
+            }
+        })
+    }
+}
+
+fn cypher_literal(value: &Value) -> anyhow::Result<String> {
+    Ok(match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(b) => b.0.to_string(),
+        Value::Int(i) => i.0.to_string(),
+        Value::Float(f) => format!("{:?}", f.0.0),
+        Value::Text(t) => format!("'{}'", t.0.replace('\\', "\\\\").replace('\'', "\\'")),
+        Value::Array(a) => format!(
+            "[{}]",
+            a.values
+                .iter()
+                .map(cypher_literal)
+                .collect::<anyhow::Result<Vec<_>>>()?
+                .join(", ")
+        ),
+        value => bail!("Cannot write {:?} as Cypher", value.type_()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::{parse_cypher, parse_mql, parse_sql};
+    use crate::{Filter, Join, Scan};
+    use value::Dict;
+
+    fn schema() -> Schema {
+        Schema::fixed([
+            ("name".to_string(), ValType::Text),
+            ("price".to_string(), ValType::Float),
+            ("tags".to_string(), ValType::Array),
+        ])
+    }
+
+    fn algebra(query: &str) -> Algebra {
+        let mut algebra = parse_sql(query).unwrap();
+        algebra.set_schema(schema());
+        algebra
+    }
+
+    fn run(algebra: &Algebra) -> Vec<Value> {
+        let bid = |name: Value, price: f64, tags: Vec<&str>| {
+            Value::Dict(Box::new(Dict::from(vec![
+                ("name", name),
+                ("price", Value::float(price)),
+                (
+                    "tags",
+                    Value::array(tags.into_iter().map(Value::text).collect::<Vec<_>>()),
+                ),
+            ])))
+        };
+        let mut program = algebra.processing().unwrap();
+        program
+            .set_resource(
+                "$$source",
+                vec![
+                    bid(Value::text("apple"), 2.5, vec!["red", "fruit"]),
+                    bid(Value::text("bean"), 0.5, vec![]),
+                    bid(Value::null(), 4.0, vec!["unknown"]),
+                    bid(Value::text("Avocado"), 7.0, vec!["green"]),
+                ]
+                .into_iter(),
+            )
+            .unwrap();
+        program.collect()
+    }
+
+    #[test]
+    fn sql_select() {
+        let sql = |query: &str| to_sql(&algebra(query), "bids_0").unwrap();
+        assert_eq!(
+            sql(
+                "SELECT name, price * 2 AS double FROM $$source WHERE price > 1 AND name IS NOT NULL"
+            ),
+            "SELECT name AS field0, (price * 2) AS double FROM bids_0 WHERE ((price > 1) AND (NOT (name IS NULL)))"
+        );
+        assert_eq!(
+            sql("SELECT name, COUNT(*), SUM(price) FROM $$source GROUP BY name"),
+            "SELECT name AS field0, COUNT(*) AS field1, SUM(price) AS field2 FROM bids_0 GROUP BY name"
+        );
+        assert_eq!(
+            sql("SELECT 'it''s', price / 2 FROM $$source WHERE name IN ('a', 'b')"),
+            "SELECT 'it''s' AS field0, (CAST(price AS DOUBLE PRECISION) / 2) AS field1 FROM bids_0 WHERE (name IN ('a', 'b'))"
+        );
+    }
+
+    #[test]
+    fn sql_subqueries() {
+        // the rows of the table hold more columns than the fields of the records
+        assert_eq!(
+            to_sql(&algebra("SELECT * FROM $$source LIMIT 2"), "bids_0").unwrap(),
+            "SELECT name, price, tags FROM bids_0 LIMIT 2"
+        );
+        assert_eq!(
+            to_sql(&algebra("SELECT DISTINCT * FROM $$source"), "bids_0").unwrap(),
+            "SELECT DISTINCT name, price, tags FROM bids_0"
+        );
+
+        // filtering groups selects from the grouping
+        let grouped = Algebra::Filter(Filter {
+            predicate: Expression::call(
+                Operator::Gt,
+                vec![
+                    Expression::field("field1"),
+                    Expression::Literal(Value::int(1)),
+                ],
+            ),
+            input: Box::new(algebra("SELECT name, COUNT(*) FROM $$source GROUP BY name")),
+        });
+        assert_eq!(
+            to_sql(&grouped, "bids_0").unwrap(),
+            "SELECT * FROM (SELECT name AS field0, COUNT(*) AS field1 FROM bids_0 GROUP BY name) AS t0 WHERE (field1 > 1)"
+        );
+        assert_eq!(
+            to_sql(&Algebra::collect(grouped), "bids_0").unwrap(),
+            "SELECT ARRAY_AGG(t1) AS \"values\" FROM (SELECT * FROM (SELECT name AS field0, COUNT(*) AS field1 FROM bids_0 GROUP BY name) AS t0 WHERE (field1 > 1)) AS t1"
+        );
+
+        // HAVING filters by a hidden aggregate
+        assert_eq!(
+            to_sql(
+                &algebra("SELECT name FROM $$source GROUP BY name HAVING COUNT(*) > 1"),
+                "bids_0"
+            )
+            .unwrap(),
+            "SELECT field0 FROM (SELECT name AS field0, COUNT(*) AS having1 FROM bids_0 GROUP BY name) AS t0 WHERE (having1 > 1)"
+        );
+
+        // without a table the scans read their source
+        assert_eq!(
+            parse_sql("SELECT name FROM $$source").unwrap().sql(),
+            "SELECT name AS field0 FROM $$source"
+        );
+    }
+
+    #[test]
+    fn mql_pipeline() {
+        let pipeline = to_pipeline(&algebra(
+            "SELECT name, COUNT(*), SUM(price) + 1 FROM $$source WHERE name = 'apple' OR price > 1 GROUP BY name",
+        ))
+        .unwrap();
+        assert_eq!(
+            pipeline,
+            vec![
+                json!({"$match": {"$expr": {"$or": [{"$eq": ["$name", "apple"]}, {"$gt": ["$price", 1]}]}}}),
+                json!({"$group": {"_id": "$name", "a0": {"$sum": 1}, "a1": {"$sum": "$price"}}}),
+                json!({"$project": {"_id": 0, "field0": "$_id", "field1": "$a0", "field2": {"$add": ["$a1", 1]}}}),
+            ]
+        );
+    }
+
+    #[test]
+    fn mql_not_equal() {
+        // documents without the field are no more unequal than in the tuple VM
+        let pipeline = to_pipeline(&algebra(
+            "SELECT name <> 'apple' FROM $$source WHERE name <> 'bean'",
+        ))
+        .unwrap();
+        let unknown = json!({"$or": [
+            {"$eq": [{"$ifNull": ["$name", null]}, null]},
+            {"$eq": [{"$ifNull": ["apple", null]}, null]}
+        ]});
+        assert_eq!(
+            pipeline,
+            vec![
+                json!({"$match": {"name": {"$ne": "bean", "$exists": true, "$not": {"$type": "null"}}}}),
+                json!({"$project": {"field0": {"$cond": [unknown, null, {"$ne": ["$name", "apple"]}]}}}),
+            ]
+        );
+    }
+
+    #[test]
+    fn mql_round_trip() {
+        for query in [
+            "SELECT name, price * 2 AS double FROM $$source WHERE price > 1 AND name IS NOT NULL",
+            "SELECT * FROM $$source WHERE name IN ('apple', 'bean') OR price / 2 > 3",
+            "SELECT name, '$literal', tags FROM $$source WHERE name IS NULL",
+            "SELECT LOWER(name), SUBSTRING(name, 2, 3), CASE WHEN price > 2 THEN 'high' ELSE 'low' END FROM $$source WHERE name LIKE 'a%'",
+            "SELECT name, tags FROM $$source, UNNEST(tags) AS tags",
+        ] {
+            let algebra = algebra(query);
+            let mql = to_mql(&algebra, "$$source").unwrap();
+            let mut parsed = parse_mql(&mql).unwrap();
+            parsed.set_schema(schema());
+            assert_eq!(run(&parsed), run(&algebra), "{}", mql);
+        }
+    }
+
+    #[test]
+    fn cypher_query() {
+        let cypher = |query: &str| to_cypher(&algebra(query), "db_bids_0").unwrap();
+        assert_eq!(
+            cypher(
+                "SELECT name, price * 2 AS double FROM $$source WHERE price > 1 AND name IS NOT NULL"
+            ),
+            "MATCH (n:db_bids_0) WHERE ((n.price > 1) AND (NOT (n.name IS NULL))) RETURN n.name AS field0, (n.price * 2) AS double"
+        );
+        assert_eq!(
+            cypher("SELECT COUNT(*) FROM $$source GROUP BY name"),
+            "MATCH (n:db_bids_0) WITH count(*) AS field0, n.name AS __key0 RETURN field0"
+        );
+        assert_eq!(
+            cypher("SELECT name, tags FROM $$source, UNNEST(tags) AS tags"),
+            "MATCH (n:db_bids_0) WITH n.name AS name, n.price AS price, n.tags AS tags UNWIND tags AS __element WITH name, price, __element AS tags RETURN name AS field0, tags AS field1"
+        );
+
+        // simple queries are read back the same
+        let mut algebra =
+            parse_sql("SELECT name, price * 2 FROM $$source WHERE price > 1").unwrap();
+        let cypher = to_cypher(&algebra, "$$source").unwrap();
+        let mut parsed = parse_cypher(&cypher).unwrap();
+        algebra.set_schema(Schema::Dynamic);
+        parsed.set_schema(Schema::Dynamic);
+        assert_eq!(run(&parsed), run(&algebra), "{}", cypher);
+    }
+
+    #[test]
+    fn distinct() {
+        let query = "SELECT DISTINCT name AS name FROM $$source ORDER BY name LIMIT 2";
+        assert_eq!(
+            to_sql(&algebra(query), "bids_0").unwrap(),
+            "SELECT DISTINCT name FROM bids_0 ORDER BY name LIMIT 2"
+        );
+        // duplicates of the limited rows are removed after limiting
+        let limited = Algebra::Distinct(crate::Distinct {
+            input: Box::new(algebra("SELECT name FROM $$source LIMIT 2")),
+        });
+        assert_eq!(
+            to_sql(&limited, "bids_0").unwrap(),
+            "SELECT DISTINCT * FROM (SELECT name AS field0 FROM bids_0 LIMIT 2) AS t0"
+        );
+
+        assert_eq!(
+            to_pipeline(&algebra("SELECT DISTINCT name FROM $$source")).unwrap(),
+            vec![
+                json!({"$project": {"field0": "$name"}}),
+                json!({"$unset": "_id"}),
+                json!({"$group": {"_id": "$$ROOT"}}),
+                json!({"$replaceRoot": {"newRoot": "$_id"}}),
+            ]
+        );
+
+        assert_eq!(
+            to_cypher(&algebra(query), "db_bids_0").unwrap(),
+            "MATCH (n:db_bids_0) WITH n.name AS name RETURN DISTINCT name ORDER BY name LIMIT 2"
+        );
+    }
+
+    #[test]
+    fn unsupported() {
+        let join = Algebra::Join(Join {
+            left: Box::new(Algebra::Scan(Scan {
+                source: "$$source".to_string(),
+                schema: Schema::Dynamic,
+            })),
+            right: Box::new(Algebra::Scan(Scan {
+                source: "$$other".to_string(),
+                schema: Schema::Dynamic,
+            })),
+            left_alias: "a".to_string(),
+            right_alias: "b".to_string(),
+            on: Expression::call(
+                Operator::Equal,
+                vec![Expression::field("a.id"), Expression::field("b.id")],
+            ),
+        });
+        assert_eq!(
+            to_sql(&join, "bids_0").unwrap(),
+            "SELECT * FROM bids_0 AS a JOIN $$other AS b ON (a.id = b.id)"
+        );
+        assert!(to_mql(&join, "bids_0").is_err());
+        assert!(to_cypher(&join, "bids_0").is_err());
+
+        // replacing the unwound field needs the other fields
+        let unwind = parse_sql("SELECT * FROM $$source, UNNEST(tags) AS tags").unwrap();
+        assert!(to_sql(&unwind, "bids_0").is_err());
+        assert!(unwind.sql().starts_with("/*"));
+        assert!(to_sql(&Algebra::Todo("MERGE".to_string()), "bids_0").is_err());
+
+        // nested values are stored serialized
+        let nested = parse_sql("SELECT address.city FROM $$source").unwrap();
+        assert!(to_sql(&nested, "bids_0").is_err());
+    }
+}
@@ -1,14 +1,36 @@
+mod aggregate;
 mod algebra;
+mod backend;
+mod bounded;
+mod explain;
 mod expression;
+mod function;
 mod instruction;
+mod jit;
+mod join;
 mod language;
 mod operator;
+mod optimizer;
+mod plan;
 mod simd;
+mod time;
 mod tuple;
-mod jit;
+mod udf;
+mod window;
 
+pub use aggregate::Aggregator;
 pub use algebra::*;
+pub use backend::Backend;
+pub use bounded::{Bounded, StateLimit};
+pub use jit::JitProgram;
+pub use join::Joiner;
+pub use plan::{PLAN_VERSION, Plan};
+pub use simd::{Bitmap, Column, ColumnarProgram, RecordBatch};
+pub use time::{EventTime, Lateness, Watermarks, duration};
+pub use udf::{FunctionRegistry, Udf, UdfFn};
 
 pub use language::*;
 
 pub use tuple::program::*;
+
+pub use window::*;
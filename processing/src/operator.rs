@@ -1,18 +1,49 @@
 use crate::algebra::Scope;
 use crate::expression::Expression;
+use crate::function::Function;
 use crate::language::Sql;
-use serde::Serialize;
+use crate::udf::Udf;
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
 use sqlparser::ast::BinaryOperator;
+use value::ValType;
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Operator {
     Add,
     Equal,
+    NotEqual,
     Minus,
     Multiply,
+    Divide,
     Gt,
+    Gte,
+    Lt,
+    Lte,
+    And,
+    Or,
+    Not,
+    IsNull,
+    In,
+    /// the value under the keys, which are followed from the first argument one after another
     Index,
     Explode,
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+    Collect,
+    /// if, then, else
+    Cond,
+    Concat,
+    /// whether a node has the label or an edge the type
+    HasLabel,
+    StartNode,
+    EndNode,
+    Function(Function),
+    /// a function registered from Rust
+    Udf(Udf),
 }
 
 impl Operator {
@@ -21,10 +52,62 @@ impl Operator {
             Operator::Add => format!("{} + {}", expressions[0].sql(), expressions[1].sql()),
             Operator::Minus => format!("{} - {}", expressions[0].sql(), expressions[1].sql()),
             Operator::Multiply => format!("{} * {}", expressions[0].sql(), expressions[1].sql()),
+            Operator::Divide => format!("{} / {}", expressions[0].sql(), expressions[1].sql()),
             Operator::Gt => format!("{} > {}", expressions[0].sql(), expressions[1].sql()),
-            Operator::Index => format!("{}[{}]", expressions[0].sql(), expressions[1].sql()),
+            Operator::Gte => format!("{} >= {}", expressions[0].sql(), expressions[1].sql()),
+            Operator::Lt => format!("{} < {}", expressions[0].sql(), expressions[1].sql()),
+            Operator::Lte => format!("{} <= {}", expressions[0].sql(), expressions[1].sql()),
+            Operator::Index => expressions[1..]
+                .iter()
+                .fold(expressions[0].sql(), |path, key| {
+                    format!("{}[{}]", path, key.sql())
+                }),
             Operator::Explode => format!("explode({})", expressions[0].sql()),
             Operator::Equal => format!("{} = {}", expressions[0].sql(), expressions[1].sql()),
+            Operator::NotEqual => format!("{} <> {}", expressions[0].sql(), expressions[1].sql()),
+            Operator::And => format!("({} AND {})", expressions[0].sql(), expressions[1].sql()),
+            Operator::Or => format!("({} OR {})", expressions[0].sql(), expressions[1].sql()),
+            Operator::Not => format!("NOT {}", expressions[0].sql()),
+            Operator::IsNull => format!("{} IS NULL", expressions[0].sql()),
+            Operator::In => format!(
+                "{} IN ({})",
+                expressions[0].sql(),
+                expressions[1..]
+                    .iter()
+                    .map(|e| e.sql())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Operator::Count if expressions.is_empty() => "COUNT(*)".to_string(),
+            Operator::Count => format!("COUNT({})", expressions[0].sql()),
+            Operator::Sum => format!("SUM({})", expressions[0].sql()),
+            Operator::Avg => format!("AVG({})", expressions[0].sql()),
+            Operator::Min => format!("MIN({})", expressions[0].sql()),
+            Operator::Max => format!("MAX({})", expressions[0].sql()),
+            Operator::Collect => format!("ARRAY_AGG({})", expressions[0].sql()),
+            Operator::HasLabel => format!(
+                "HAS_LABEL({}, {})",
+                expressions[0].sql(),
+                expressions[1].sql()
+            ),
+            Operator::StartNode => format!("START_NODE({})", expressions[0].sql()),
+            Operator::EndNode => format!("END_NODE({})", expressions[0].sql()),
+            Operator::Cond => format!(
+                "CASE WHEN {} THEN {} ELSE {} END",
+                expressions[0].sql(),
+                expressions[1].sql(),
+                expressions[2].sql()
+            ),
+            Operator::Concat => format!(
+                "CONCAT({})",
+                expressions
+                    .iter()
+                    .map(|e| e.sql())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Operator::Function(f) => f.sql(expressions.iter().map(|e| e.sql()).collect()),
+            Operator::Udf(f) => f.sql(expressions.iter().map(|e| e.sql()).collect()),
         }
     }
 
@@ -32,22 +115,198 @@ impl Operator {
         match self {
             Operator::Add => Scope::Tuple,
             Operator::Minus => Scope::Tuple,
-            Operator::Multiply => Scope::Tuple,
-            Operator::Gt => Scope::Tuple,
+            Operator::Multiply | Operator::Divide => Scope::Tuple,
+            Operator::Gt | Operator::Gte | Operator::Lt | Operator::Lte => Scope::Tuple,
             Operator::Index => Scope::Tuple,
             Operator::Explode => Scope::Tuple,
-            Operator::Equal => Scope::Tuple,
+            Operator::Equal | Operator::NotEqual => Scope::Tuple,
+            Operator::And | Operator::Or | Operator::Not => Scope::Tuple,
+            Operator::IsNull | Operator::In => Scope::Tuple,
+            Operator::Cond | Operator::Concat => Scope::Tuple,
+            Operator::HasLabel | Operator::StartNode | Operator::EndNode => Scope::Tuple,
+            Operator::Function(_) | Operator::Udf(_) => Scope::Tuple,
+            Operator::Count
+            | Operator::Sum
+            | Operator::Avg
+            | Operator::Min
+            | Operator::Max
+            | Operator::Collect => Scope::Multi,
         }
     }
+
+    /// Aggregate functions combine the values of many records into one.
+    pub(crate) fn is_aggregate(&self) -> bool {
+        self.scope() == Scope::Multi
+    }
+
+    /// The type of the result when applied to arguments of the given types.
+    pub(crate) fn output_type(&self, args: &[ValType]) -> anyhow::Result<ValType> {
+        let arg = |i: usize| {
+            args.get(i)
+                .cloned()
+                .ok_or(anyhow!("Missing argument {} of {:?}", i, self))
+        };
+        let mismatch = || {
+            anyhow!(
+                "Cannot apply {:?} to {}",
+                self,
+                args.iter()
+                    .map(|a| a.dump(""))
+                    .collect::<Vec<_>>()
+                    .join(" and ")
+            )
+        };
+
+        match self {
+            Operator::Add => match (arg(0)?, arg(1)?) {
+                (ValType::Any, _) | (_, ValType::Any) => Ok(ValType::Any),
+                (ValType::Null, _) | (_, ValType::Null) => Ok(ValType::Null),
+                (ValType::Array, _) => Ok(ValType::Array),
+                (l, r) if l == r && matches!(l, ValType::Text | ValType::Time | ValType::Date) => {
+                    Ok(l)
+                }
+                (l, r) => numeric(&l, &r).ok_or_else(mismatch),
+            },
+            Operator::Minus | Operator::Multiply => {
+                numeric(&arg(0)?, &arg(1)?).ok_or_else(mismatch)
+            }
+            // dividing integers does not truncate
+            Operator::Divide => match numeric(&arg(0)?, &arg(1)?) {
+                Some(ValType::Integer) => Ok(ValType::Float),
+                t => t.ok_or_else(mismatch),
+            },
+            Operator::Gt
+            | Operator::Gte
+            | Operator::Lt
+            | Operator::Lte
+            | Operator::Equal
+            | Operator::NotEqual => match comparable(&arg(0)?, &arg(1)?) {
+                true => Ok(ValType::Bool),
+                false => Err(mismatch()),
+            },
+            Operator::In => {
+                let first = arg(0)?;
+                match args[1..].iter().all(|a| comparable(&first, a)) {
+                    true => Ok(ValType::Bool),
+                    false => Err(mismatch()),
+                }
+            }
+            Operator::And | Operator::Or | Operator::Not => {
+                match args
+                    .iter()
+                    .all(|a| matches!(a, ValType::Bool | ValType::Any | ValType::Null))
+                {
+                    true => Ok(ValType::Bool),
+                    false => Err(mismatch()),
+                }
+            }
+            Operator::IsNull => Ok(ValType::Bool),
+            Operator::Index | Operator::Explode => match arg(0)? {
+                ValType::Array
+                | ValType::Dict
+                | ValType::Node
+                | ValType::Edge
+                | ValType::Any
+                | ValType::Null => Ok(ValType::Any),
+                _ => Err(mismatch()),
+            },
+            Operator::HasLabel => match arg(0)? {
+                ValType::Node | ValType::Edge | ValType::Any | ValType::Null => Ok(ValType::Bool),
+                _ => Err(mismatch()),
+            },
+            Operator::StartNode | Operator::EndNode => match arg(0)? {
+                ValType::Edge | ValType::Any | ValType::Null => Ok(ValType::Integer),
+                _ => Err(mismatch()),
+            },
+            Operator::Collect => Ok(ValType::Array),
+            Operator::Count => Ok(ValType::Integer),
+            Operator::Sum => {
+                let arg = arg(0)?;
+                numeric(&arg, &arg).ok_or_else(mismatch)
+            }
+            Operator::Avg => {
+                let arg = arg(0)?;
+                numeric(&arg, &arg)
+                    .map(|t| match t {
+                        ValType::Integer => ValType::Float,
+                        t => t,
+                    })
+                    .ok_or_else(mismatch)
+            }
+            Operator::Min | Operator::Max => arg(0),
+            Operator::Cond => {
+                if !matches!(arg(0)?, ValType::Bool | ValType::Any | ValType::Null) {
+                    return Err(mismatch());
+                }
+                match (arg(1)?, arg(2)?) {
+                    (ValType::Null, t) | (t, ValType::Null) => Ok(t),
+                    (l, r) if l == r => Ok(l),
+                    (l, r) => Ok(numeric(&l, &r).unwrap_or(ValType::Any)),
+                }
+            }
+            Operator::Concat => match args
+                .iter()
+                .all(|a| matches!(a, ValType::Text | ValType::Any | ValType::Null))
+            {
+                true => Ok(ValType::Text),
+                false => Err(mismatch()),
+            },
+            Operator::Function(f) => f.output_type(args).ok_or_else(mismatch),
+            Operator::Udf(f) => f.output_type(args).ok_or_else(mismatch),
+        }
+    }
+
+    pub(crate) fn aggregate(name: &str) -> Option<Operator> {
+        match name.to_uppercase().as_str() {
+            "COUNT" => Some(Operator::Count),
+            "SUM" => Some(Operator::Sum),
+            "AVG" => Some(Operator::Avg),
+            "MIN" => Some(Operator::Min),
+            "MAX" => Some(Operator::Max),
+            "COLLECT" | "ARRAY_AGG" => Some(Operator::Collect),
+            _ => None,
+        }
+    }
+}
+
+/// The result of arithmetic on two types, integers only stay integers among themselves.
+fn numeric(left: &ValType, right: &ValType) -> Option<ValType> {
+    match (left, right) {
+        (ValType::Any, _) | (_, ValType::Any) => Some(ValType::Any),
+        (ValType::Null, _) | (_, ValType::Null) => Some(ValType::Null),
+        (ValType::Integer, ValType::Integer) => Some(ValType::Integer),
+        (ValType::Integer | ValType::Float, ValType::Integer | ValType::Float) => {
+            Some(ValType::Float)
+        }
+        _ => None,
+    }
 }
 
-impl From<&BinaryOperator> for Operator {
-    fn from(value: &BinaryOperator) -> Self {
-        match value {
+fn comparable(left: &ValType, right: &ValType) -> bool {
+    left == right || numeric(left, right).is_some()
+}
+
+impl TryFrom<&BinaryOperator> for Operator {
+    type Error = String;
+
+    fn try_from(value: &BinaryOperator) -> Result<Self, Self::Error> {
+        Ok(match value {
             BinaryOperator::Plus => Operator::Add,
             BinaryOperator::Minus => Operator::Minus,
             BinaryOperator::Multiply => Operator::Multiply,
-            _ => todo!("unsupported binary operator"),
-        }
+            BinaryOperator::Divide => Operator::Divide,
+            BinaryOperator::Modulo => Operator::Function(Function::Mod),
+            BinaryOperator::StringConcat => Operator::Concat,
+            BinaryOperator::PGRegexMatch => Operator::Function(Function::Regex),
+            BinaryOperator::Gt => Operator::Gt,
+            BinaryOperator::GtEq => Operator::Gte,
+            BinaryOperator::Lt => Operator::Lt,
+            BinaryOperator::LtEq => Operator::Lte,
+            BinaryOperator::Eq => Operator::Equal,
+            BinaryOperator::NotEq => Operator::NotEqual,
+            BinaryOperator::And => Operator::And,
+            BinaryOperator::Or => Operator::Or,
+            op => return Err(format!("Unsupported binary operator {}", op)),
+        })
     }
 }
@@ -0,0 +1,497 @@
+use crate::algebra::Scope;
+use crate::expression::Expression;
+use crate::function::Function;
+use crate::operator::Operator;
+use crate::tuple::program::Program;
+use crate::{Algebra, Filter, Project, Schema};
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use value::Value;
+
+/// Rewrites a node into a cheaper but equivalent one, `None` if it does not apply.
+type Rule = fn(&Algebra) -> Option<Algebra>;
+
+const RULES: [Rule; 4] = [
+    remove_true_filter,
+    push_filter,
+    merge_projects,
+    remove_unused,
+];
+
+/// Rewrites the algebra bottom-up until no rule applies anymore.
+///
+/// Only the expressions of projections and filters are simplified, the ones of the
+/// operators which are evaluated per window are matched against their input as they are.
+pub(crate) fn optimize(algebra: Algebra) -> Algebra {
+    let node = match algebra {
+        Algebra::Project(p) => Algebra::Project(Project {
+            expressions: p
+                .expressions
+                .into_iter()
+                .map(|(k, e)| (k, simplify(e)))
+                .collect(),
+            input: Box::new(optimize(*p.input)),
+        }),
+        Algebra::Filter(f) => Algebra::Filter(Filter {
+            predicate: simplify(f.predicate),
+            input: Box::new(optimize(*f.input)),
+        }),
+        mut node => {
+            let inputs: Vec<&mut Box<Algebra>> = match &mut node {
+                Algebra::Collect(c) => vec![&mut c.input],
+                Algebra::Unwind(u) => vec![&mut u.input],
+                Algebra::Aggregate(a) => vec![&mut a.input],
+                Algebra::Sort(s) => vec![&mut s.input],
+                Algebra::Limit(l) => vec![&mut l.input],
+                Algebra::Distinct(d) => vec![&mut d.input],
+                Algebra::Join(j) => vec![&mut j.left, &mut j.right],
+                _ => vec![],
+            };
+            for input in inputs {
+                let child = std::mem::replace(input.as_mut(), Algebra::Todo(String::new()));
+                **input = optimize(child);
+            }
+            node
+        }
+    };
+
+    for rule in RULES {
+        if let Some(rewritten) = rule(&node) {
+            return optimize(rewritten);
+        }
+    }
+    node
+}
+
+/// Simplifies the arguments first, so constants and booleans bubble up.
+fn simplify(expression: Expression) -> Expression {
+    let Expression::Call {
+        operator,
+        expressions,
+    } = expression
+    else {
+        return expression;
+    };
+    let args = expressions.into_iter().map(simplify).collect::<Vec<_>>();
+
+    let call = match simplify_boolean(operator, args) {
+        Ok(simplified) => return simplified,
+        Err(call) => call,
+    };
+    fold(&call).unwrap_or(call)
+}
+
+/// Removes neutral operands of `AND`/`OR`, double negations and conditions which are known.
+///
+/// An operand is only returned on its own if it is a boolean anyway, as other values would
+/// otherwise be yielded in place of the boolean the call produces.
+fn simplify_boolean(
+    operator: Operator,
+    mut args: Vec<Expression>,
+) -> Result<Expression, Expression> {
+    let literal = |e: &Expression| match e {
+        Expression::Literal(Value::Bool(b)) => Some(b.0),
+        _ => None,
+    };
+
+    match (&operator, args.as_slice()) {
+        (Operator::And, [l, r]) | (Operator::Or, [l, r]) => {
+            // true is neutral to AND, false to OR
+            let neutral = operator == Operator::And;
+            match (literal(l), literal(r)) {
+                (Some(b), _) | (_, Some(b)) if b != neutral => {
+                    return Ok(Expression::Literal(Value::bool(b)));
+                }
+                (Some(_), _) if boolean(r) => return Ok(args.remove(1)),
+                (_, Some(_)) if boolean(l) => return Ok(args.remove(0)),
+                _ => {}
+            }
+        }
+        (
+            Operator::Not,
+            [
+                Expression::Call {
+                    operator: Operator::Not,
+                    expressions,
+                },
+            ],
+        ) if expressions.len() == 1 && boolean(&expressions[0]) => {
+            return Ok(expressions[0].clone());
+        }
+        (Operator::Cond, [condition, _, _]) => match condition {
+            Expression::Literal(Value::Bool(b)) if b.0 => return Ok(args.remove(1)),
+            // false and unknown pick the otherwise branch, other literals fail the record
+            Expression::Literal(Value::Bool(_) | Value::Null) => return Ok(args.remove(2)),
+            _ => {}
+        },
+        _ => {}
+    }
+    Err(Expression::call(operator, args))
+}
+
+/// Whether the expression always evaluates to a boolean.
+fn boolean(expression: &Expression) -> bool {
+    match expression {
+        Expression::Literal(Value::Bool(_)) => true,
+        Expression::Call { operator, .. } => matches!(
+            operator,
+            Operator::Equal
+                | Operator::NotEqual
+                | Operator::Gt
+                | Operator::Gte
+                | Operator::Lt
+                | Operator::Lte
+                | Operator::And
+                | Operator::Or
+                | Operator::Not
+                | Operator::IsNull
+                | Operator::In
+                | Operator::HasLabel
+                | Operator::Function(Function::Like | Function::Regex)
+        ),
+        _ => false,
+    }
+}
+
+/// Evaluates a call on literals with the tuple VM, so the result is the one of a record.
+fn fold(call: &Expression) -> Option<Expression> {
+    let Expression::Call {
+        operator,
+        expressions,
+    } = call
+    else {
+        return None;
+    };
+    if !expressions
+        .iter()
+        .all(|e| matches!(e, Expression::Literal(_)))
+    {
+        return None;
+    }
+    // user-defined functions might not return the same for the same arguments
+    if call.scope() != Scope::Tuple
+        || matches!(
+            operator,
+            Operator::Index | Operator::Explode | Operator::Udf(_)
+        )
+        || call.val_type(&Schema::Dynamic).is_err()
+    {
+        return None;
+    }
+
+    let row = Program::try_from(call).ok()?.next()?;
+    let value = row.as_array().ok()?.values.first()?.clone();
+    Some(Expression::Literal(value))
+}
+
+/// A filter which lets everything pass is not needed.
+fn remove_true_filter(algebra: &Algebra) -> Option<Algebra> {
+    match algebra {
+        Algebra::Filter(f) if f.predicate == Expression::Literal(Value::bool(true)) => {
+            Some(*f.input.clone())
+        }
+        _ => None,
+    }
+}
+
+/// Filters before projecting, so rejected records are not projected at all.
+fn push_filter(algebra: &Algebra) -> Option<Algebra> {
+    let Algebra::Filter(filter) = algebra else {
+        return None;
+    };
+    let Algebra::Project(project) = filter.input.as_ref() else {
+        return None;
+    };
+    let expressions = resolved(project)?;
+    let predicate = substitute(&filter.predicate, &expressions)?;
+
+    Some(Algebra::Project(Project {
+        expressions,
+        input: Box::new(Algebra::Filter(Filter {
+            predicate,
+            input: project.input.clone(),
+        })),
+    }))
+}
+
+/// Replaces two projections with one, as long as no computed field is needed twice.
+fn merge_projects(algebra: &Algebra) -> Option<Algebra> {
+    let Algebra::Project(outer) = algebra else {
+        return None;
+    };
+    let Algebra::Project(inner) = outer.input.as_ref() else {
+        return None;
+    };
+    let outer_expressions = resolved(outer)?;
+    let inner_expressions = resolved(inner)?;
+
+    let uses = usages(outer_expressions.values());
+    let duplicated = inner_expressions.iter().any(|(name, e)| {
+        !matches!(e, Expression::Field(_) | Expression::Literal(_))
+            && uses.get(name).is_some_and(|n| *n > 1)
+    });
+    if duplicated {
+        return None;
+    }
+
+    let expressions = outer_expressions
+        .into_iter()
+        .map(|(name, e)| Some((name, substitute(&e, &inner_expressions)?)))
+        .collect::<Option<IndexMap<_, _>>>()?;
+    Some(Algebra::Project(Project {
+        expressions,
+        input: inner.input.clone(),
+    }))
+}
+
+/// Drops the fields of a projection which the operator above never reads.
+fn remove_unused(algebra: &Algebra) -> Option<Algebra> {
+    let Algebra::Project(outer) = algebra else {
+        return None;
+    };
+    let Algebra::Project(inner) = outer.input.as_ref() else {
+        return None;
+    };
+    let read = resolved(outer)?;
+    let inner_expressions = resolved(inner)?;
+    let produced = inner_expressions.len();
+
+    let uses = usages(read.values());
+    let used = inner_expressions
+        .into_iter()
+        .filter(|(name, _)| uses.contains_key(name))
+        .collect::<IndexMap<_, _>>();
+    if used.len() == produced || used.is_empty() {
+        return None;
+    }
+
+    Some(Algebra::Project(Project {
+        expressions: read,
+        input: Box::new(Algebra::Project(Project {
+            expressions: used,
+            input: inner.input.clone(),
+        })),
+    }))
+}
+
+/// The expressions of a tuple-scope projection with its wildcard resolved, `None` if the
+/// fields of its input are not known.
+fn resolved(project: &Project) -> Option<IndexMap<String, Expression>> {
+    let input = project.input.schema().ok()?;
+    let expressions = project.resolve(&input);
+    let resolvable = expressions.values().all(|e| {
+        !matches!(e, Expression::Wildcard | Expression::Exclude(_)) && e.scope() == Scope::Tuple
+    });
+    resolvable.then_some(expressions)
+}
+
+/// How often each field is read by the expressions.
+fn usages<'a>(expressions: impl Iterator<Item = &'a Expression>) -> HashMap<String, usize> {
+    fn count(expression: &Expression, uses: &mut HashMap<String, usize>) {
+        match expression {
+            Expression::Field(name) => *uses.entry(name.clone()).or_default() += 1,
+            Expression::Call { expressions, .. } => expressions.iter().for_each(|e| count(e, uses)),
+            _ => {}
+        }
+    }
+    let mut uses = HashMap::new();
+    expressions.for_each(|e| count(e, &mut uses));
+    uses
+}
+
+/// Replaces the fields with the expressions that produce them, `None` if one is not produced.
+fn substitute(
+    expression: &Expression,
+    fields: &IndexMap<String, Expression>,
+) -> Option<Expression> {
+    match expression {
+        Expression::Field(name) => fields.get(name).cloned(),
+        Expression::Call {
+            operator,
+            expressions,
+        } => Some(Expression::call(
+            operator.clone(),
+            expressions
+                .iter()
+                .map(|e| substitute(e, fields))
+                .collect::<Option<Vec<_>>>()?,
+        )),
+        e => Some(e.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_mql, parse_sql};
+    use value::ValType;
+
+    fn records() -> Vec<Value> {
+        vec![
+            Value::array([Value::int(1), Value::text("x"), Value::float(3.3)]),
+            Value::array([Value::int(2), Value::text("y"), Value::float(5.2)]),
+            Value::array([Value::int(3), Value::text("x"), Value::float(2.1)]),
+            Value::array([Value::int(4), Value::null(), Value::float(7.0)]),
+        ]
+    }
+
+    fn run(algebra: &Algebra) -> Vec<Value> {
+        let mut program = Program::try_from(algebra).unwrap();
+        program
+            .set_resource("$$source", records().into_iter())
+            .unwrap();
+        program.collect()
+    }
+
+    /// Optimizes the algebra and checks that the tuple VM yields the same records for both.
+    fn optimized(mut algebra: Algebra) -> Algebra {
+        algebra.set_schema(Schema::fixed([
+            ("id".to_string(), ValType::Integer),
+            ("name".to_string(), ValType::Text),
+            ("price".to_string(), ValType::Float),
+        ]));
+        let optimized = optimize(algebra.clone());
+        assert_eq!(run(&algebra), run(&optimized), "{:?}", optimized);
+        optimized
+    }
+
+    fn sql(query: &str) -> Algebra {
+        optimized(parse_sql(query).unwrap())
+    }
+
+    fn mql(query: &str) -> Algebra {
+        optimized(parse_mql(query).unwrap())
+    }
+
+    fn project(algebra: &Algebra) -> &Project {
+        match algebra {
+            Algebra::Project(p) => p,
+            a => panic!("expected a projection, got {:?}", a),
+        }
+    }
+
+    #[test]
+    fn fold_constants() {
+        let algebra = sql("SELECT price * (2 + 3), 'a' = 'a', id FROM $$source WHERE id > 1 + 1");
+        let expressions = project(&algebra).expressions.values().collect::<Vec<_>>();
+        assert_eq!(
+            expressions[0],
+            &Expression::call(
+                Operator::Multiply,
+                vec![
+                    Expression::field("price"),
+                    Expression::Literal(Value::int(5))
+                ]
+            )
+        );
+        assert_eq!(expressions[1], &Expression::Literal(Value::bool(true)));
+
+        let Algebra::Filter(filter) = project(&algebra).input.as_ref() else {
+            panic!("expected a filter");
+        };
+        assert_eq!(
+            filter.predicate,
+            Expression::call(
+                Operator::Gt,
+                vec![Expression::field("id"), Expression::Literal(Value::int(2))]
+            )
+        );
+
+        // a null operand folds to null, like it evaluates for a record
+        let call = Expression::call(
+            Operator::Add,
+            vec![
+                Expression::Literal(Value::int(1)),
+                Expression::Literal(Value::null()),
+            ],
+        );
+        assert_eq!(fold(&call), Some(Expression::Literal(Value::null())));
+    }
+
+    #[test]
+    fn simplify_booleans() {
+        let algebra = sql("SELECT id FROM $$source WHERE 1 = 1 AND (price > 3 OR false)");
+        let Algebra::Filter(filter) = project(&algebra).input.as_ref() else {
+            panic!("expected a filter");
+        };
+        assert_eq!(filter.predicate.conjuncts().len(), 1);
+
+        let algebra = sql("SELECT id FROM $$source WHERE NOT NOT id = 1 OR 2 < 1");
+        let Algebra::Filter(filter) = project(&algebra).input.as_ref() else {
+            panic!("expected a filter");
+        };
+        assert!(matches!(
+            filter.predicate,
+            Expression::Call {
+                operator: Operator::Equal,
+                ..
+            }
+        ));
+
+        // the filter is gone altogether
+        let algebra = sql("SELECT id FROM $$source WHERE id = 1 OR true");
+        assert!(matches!(*project(&algebra).input, Algebra::Scan(_)));
+
+        // a field is no boolean, so `AND true` still turns it into one
+        let simplified = simplify(Expression::call(
+            Operator::And,
+            vec![
+                Expression::field("id"),
+                Expression::Literal(Value::bool(true)),
+            ],
+        ));
+        assert!(matches!(simplified, Expression::Call { .. }));
+
+        // only booleans and null pick a branch, others are left to fail the record
+        let cond = |condition: Value| {
+            simplify(Expression::call(
+                Operator::Cond,
+                vec![
+                    Expression::Literal(condition),
+                    Expression::Literal(Value::int(1)),
+                    Expression::Literal(Value::int(0)),
+                ],
+            ))
+        };
+        assert_eq!(cond(Value::null()), Expression::Literal(Value::int(0)));
+        assert!(matches!(cond(Value::text("x")), Expression::Call { .. }));
+    }
+
+    #[test]
+    fn merge_and_push() {
+        let algebra = mql("db.$$source.aggregate([
+                {$project: {id: 1, total: {$multiply: [\"$price\", 2]}}},
+                {$match: {total: {$gt: 5}}},
+                {$project: {total: 1}}
+            ])");
+        let project = project(&algebra);
+        assert_eq!(
+            project.expressions.keys().collect::<Vec<_>>(),
+            vec!["total"]
+        );
+        let Algebra::Filter(filter) = project.input.as_ref() else {
+            panic!("expected a filter below the projection");
+        };
+        assert!(matches!(*filter.input, Algebra::Scan(_)));
+    }
+
+    #[test]
+    fn remove_unused_fields() {
+        // the total is read twice, so the projections stay apart, but the name is not needed
+        let algebra = mql("db.$$source.aggregate([
+                {$project: {name: 1, total: {$multiply: [\"$price\", \"$id\"]}}},
+                {$project: {a: {$add: [\"$total\", 1]}, b: {$add: [\"$total\", 2]}}}
+            ])");
+        let inner = project(&project(&algebra).input);
+        assert_eq!(inner.expressions.keys().collect::<Vec<_>>(), vec!["total"]);
+    }
+
+    #[test]
+    fn unchanged() {
+        // the fields of a dynamic record are not known
+        let mut algebra =
+            parse_mql("db.$$source.aggregate([{$set: {a: 1}}, {$match: {a: {$gt: 0}}}])").unwrap();
+        algebra.set_schema(Schema::Dynamic);
+        assert!(matches!(optimize(algebra), Algebra::Filter(_)));
+    }
+}
@@ -0,0 +1,278 @@
+use crate::algebra::Algebra;
+use crate::expression::Expression;
+use crate::operator::Operator;
+use crate::udf::FunctionRegistry;
+use anyhow::{Context, bail};
+use mongodb::bson;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// The version of the plan format, plans of other versions are rejected.
+pub const PLAN_VERSION: u32 = 1;
+
+/// A stored query, built from the algebra directly instead of from query text.
+///
+/// Plans are written as JSON or in the compact binary form (BSON). Calls of user-defined
+/// functions only keep the name and signature and are bound to a registry when loaded.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Plan {
+    pub version: u32,
+    pub algebra: Algebra,
+}
+
+/// Read before the rest, so a plan of another version fails on its version and not on a field.
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+}
+
+impl Plan {
+    pub fn new(algebra: Algebra) -> Self {
+        Plan {
+            version: PLAN_VERSION,
+            algebra,
+        }
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let header: Header = serde_json::from_str(json).context("Invalid plan")?;
+        check(header.version)?;
+        serde_json::from_str(json).context("Invalid plan")
+    }
+
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(bson::to_vec(self)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let header: Header = bson::from_slice(bytes).context("Invalid plan")?;
+        check(header.version)?;
+        bson::from_slice(bytes).context("Invalid plan")
+    }
+
+    /// Loads the plan file, `.json` files are JSON and all others binary.
+    pub fn read<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Cannot read plan {}", path.display()))?;
+        if is_json(path) {
+            Self::from_json(str::from_utf8(&bytes)?)
+        } else {
+            Self::from_bytes(&bytes)
+        }
+    }
+
+    /// Stores the plan in the form its extension asks for, like [`Plan::read`].
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let bytes = if is_json(path) {
+            self.to_json()?.into_bytes()
+        } else {
+            self.to_bytes()?
+        };
+        std::fs::write(path, bytes).with_context(|| format!("Cannot write plan {}", path.display()))
+    }
+
+    /// The algebra with its user-defined functions bound to the registered ones.
+    pub fn bind(mut self, functions: &FunctionRegistry) -> anyhow::Result<Algebra> {
+        bind_algebra(&mut self.algebra, functions)?;
+        Ok(self.algebra)
+    }
+}
+
+fn check(version: u32) -> anyhow::Result<()> {
+    if version != PLAN_VERSION {
+        bail!(
+            "Plan has version {}, only version {} is supported",
+            version,
+            PLAN_VERSION
+        )
+    }
+    Ok(())
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("json"))
+}
+
+fn bind_algebra(algebra: &mut Algebra, functions: &FunctionRegistry) -> anyhow::Result<()> {
+    let (expressions, inputs): (Vec<&mut Expression>, Vec<&mut Algebra>) = match algebra {
+        Algebra::Scan(_) | Algebra::Todo(_) => return Ok(()),
+        Algebra::Project(p) => (p.expressions.values_mut().collect(), vec![&mut p.input]),
+        Algebra::Filter(f) => (vec![&mut f.predicate], vec![&mut f.input]),
+        Algebra::Collect(c) => (vec![], vec![&mut c.input]),
+        Algebra::Unwind(u) => {
+            bind_operator(&mut u.func, functions)?;
+            (vec![], vec![&mut u.input])
+        }
+        Algebra::Aggregate(a) => (
+            a.keys
+                .iter_mut()
+                .chain(a.expressions.values_mut())
+                .collect(),
+            vec![&mut a.input],
+        ),
+        Algebra::Join(j) => (vec![&mut j.on], vec![&mut j.left, &mut j.right]),
+        Algebra::Sort(s) => (
+            s.keys.iter_mut().map(|k| &mut k.expression).collect(),
+            vec![&mut s.input],
+        ),
+        Algebra::Limit(l) => (vec![], vec![&mut l.input]),
+        Algebra::Distinct(d) => (vec![], vec![&mut d.input]),
+    };
+    for expression in expressions {
+        bind_expression(expression, functions)?;
+    }
+    for input in inputs {
+        bind_algebra(input, functions)?;
+    }
+    Ok(())
+}
+
+fn bind_expression(
+    expression: &mut Expression,
+    functions: &FunctionRegistry,
+) -> anyhow::Result<()> {
+    if let Expression::Call {
+        operator,
+        expressions,
+    } = expression
+    {
+        bind_operator(operator, functions)?;
+        for e in expressions {
+            bind_expression(e, functions)?;
+        }
+    }
+    Ok(())
+}
+
+fn bind_operator(operator: &mut Operator, functions: &FunctionRegistry) -> anyhow::Result<()> {
+    if let Operator::Udf(f) = operator {
+        *f = functions.bind(f)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::plan::{PLAN_VERSION, Plan};
+    use crate::udf::FunctionRegistry;
+    use crate::{Algebra, Schema, parse_sql, parse_sql_with};
+    use value::{ValType, Value};
+
+    fn records() -> Vec<Value> {
+        vec![
+            Value::array([Value::int(1), Value::text("x")]),
+            Value::array([Value::int(2), Value::text("y")]),
+            Value::array([Value::int(3), Value::text("x")]),
+            Value::array([Value::int(4), Value::null()]),
+        ]
+    }
+
+    fn schema() -> Schema {
+        Schema::fixed([
+            ("id".to_string(), ValType::Integer),
+            ("name".to_string(), ValType::Text),
+        ])
+    }
+
+    fn run(algebra: &Algebra) -> Vec<Value> {
+        let mut program = algebra.processing().unwrap();
+        program
+            .set_resource("$$source", records().into_iter())
+            .unwrap();
+        program.collect()
+    }
+
+    fn algebra(query: &str) -> Algebra {
+        let mut algebra = parse_sql(query).unwrap();
+        algebra.set_schema(schema());
+        algebra
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let algebra = algebra(
+            "SELECT id, UPPER(name) AS name FROM $$source WHERE id > 1 AND name IS NOT NULL",
+        );
+        let json = Plan::new(algebra.clone()).to_json().unwrap();
+        let loaded = Plan::from_json(&json).unwrap();
+
+        assert_eq!(loaded.version, PLAN_VERSION);
+        assert_eq!(loaded.to_json().unwrap(), json);
+        let loaded = loaded.bind(&FunctionRegistry::default()).unwrap();
+        assert_eq!(run(&loaded), run(&algebra));
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let algebra = algebra("SELECT name, COUNT(*) AS amount FROM $$source GROUP BY name");
+        let bytes = Plan::new(algebra.clone()).to_bytes().unwrap();
+        let loaded = Plan::from_bytes(&bytes).unwrap();
+
+        assert_eq!(loaded.to_bytes().unwrap(), bytes);
+        let loaded = loaded.bind(&FunctionRegistry::default()).unwrap();
+        assert_eq!(run(&loaded), run(&algebra));
+    }
+
+    #[test]
+    fn files() {
+        let plan = Plan::new(algebra("SELECT id FROM $$source WHERE id < 3"));
+        let dir = std::env::temp_dir();
+        for name in ["plan_test.json", "plan_test.plan"] {
+            let path = dir.join(format!("{}_{}", std::process::id(), name));
+            plan.write(&path).unwrap();
+            let loaded = Plan::read(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(loaded.to_json().unwrap(), plan.to_json().unwrap());
+        }
+    }
+
+    #[test]
+    fn other_version() {
+        let mut plan = Plan::new(algebra("SELECT id FROM $$source"));
+        plan.version = PLAN_VERSION + 1;
+
+        let error = Plan::from_json(&plan.to_json().unwrap()).unwrap_err();
+        assert!(error.to_string().contains("version"), "{}", error);
+        let error = Plan::from_bytes(&plan.to_bytes().unwrap()).unwrap_err();
+        assert!(error.to_string().contains("version"), "{}", error);
+    }
+
+    #[test]
+    fn bind_functions() {
+        let functions = FunctionRegistry::new();
+        functions
+            .register("twice", vec![ValType::Integer], ValType::Integer, |args| {
+                Value::int(args[0].as_int().map(|i| i.0 * 2).unwrap_or_default())
+            })
+            .unwrap();
+        let mut algebra =
+            parse_sql_with("SELECT twice(id) AS id FROM $$source", &functions).unwrap();
+        algebra.set_schema(schema());
+        let json = Plan::new(algebra.clone()).to_json().unwrap();
+
+        let loaded = Plan::from_json(&json).unwrap().bind(&functions).unwrap();
+        assert_eq!(run(&loaded), run(&algebra));
+
+        let error = Plan::from_json(&json)
+            .unwrap()
+            .bind(&FunctionRegistry::default())
+            .unwrap_err();
+        assert!(error.to_string().contains("not registered"), "{}", error);
+
+        let other = FunctionRegistry::new();
+        other
+            .register("twice", vec![ValType::Text], ValType::Text, |args| {
+                args[0].clone()
+            })
+            .unwrap();
+        let error = Plan::from_json(&json).unwrap().bind(&other).unwrap_err();
+        assert!(error.to_string().contains("signature"), "{}", error);
+    }
+}
@@ -1,7 +1,89 @@
+use crate::Schema;
 use crate::simd::column::Column;
+use anyhow::bail;
+use value::Value;
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct RecordBatch {
     pub(crate) columns: Vec<Column>,
     pub(crate) num_of_rows: usize,
 }
+
+impl RecordBatch {
+    pub fn new(columns: Vec<Column>) -> Self {
+        let num_of_rows = columns.first().map(|c| c.len()).unwrap_or_default();
+        Self {
+            columns,
+            num_of_rows,
+        }
+    }
+
+    /// Turns records into columns of the fixed schema, records are arrays in field order or
+    /// documents with the fields, missing fields are null.
+    pub fn from_rows<'a>(
+        rows: impl IntoIterator<Item = &'a Value>,
+        schema: &Schema,
+    ) -> anyhow::Result<Self> {
+        let Schema::Fixed(fields) = schema else {
+            bail!("Columns need a fixed schema")
+        };
+        let null = Value::null();
+        let mut values = vec![vec![]; fields.len()];
+        for row in rows {
+            match row {
+                Value::Array(a) => {
+                    for (i, column) in values.iter_mut().enumerate() {
+                        column.push(a.values.get(i).unwrap_or(&null));
+                    }
+                }
+                Value::Dict(d) => {
+                    for (name, column) in fields.keys().zip(values.iter_mut()) {
+                        column.push(d.get(name).unwrap_or(&null));
+                    }
+                }
+                v => bail!("Cannot split {} into columns", v),
+            }
+        }
+
+        let num_of_rows = values.first().map(|c| c.len()).unwrap_or_default();
+        let columns = values
+            .into_iter()
+            .zip(fields.values())
+            .map(|(values, val_type)| Column::from_values(values, val_type))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self {
+            columns,
+            num_of_rows,
+        })
+    }
+
+    /// The records of the batch as arrays in column order.
+    pub fn rows(&self) -> Vec<Value> {
+        (0..self.num_of_rows)
+            .map(|row| {
+                Value::array(
+                    self.columns
+                        .iter()
+                        .map(|c| c.value(row))
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.num_of_rows
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num_of_rows == 0
+    }
+
+    /// The selected rows in their order.
+    pub(crate) fn take(&self, selection: &[usize]) -> RecordBatch {
+        RecordBatch {
+            columns: self.columns.iter().map(|c| c.take(selection)).collect(),
+            num_of_rows: selection.len(),
+        }
+    }
+}
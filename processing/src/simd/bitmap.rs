@@ -0,0 +1,84 @@
+/// One bit per row of a column, set for the rows which are null.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bitmap {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl Bitmap {
+    /// A bitmap of `len` rows without any set bit.
+    pub fn new(len: usize) -> Self {
+        Self {
+            words: vec![0; len.div_ceil(64)],
+            len,
+        }
+    }
+
+    /// A bitmap of `len` rows with all bits set.
+    pub fn full(len: usize) -> Self {
+        let mut bitmap = Self {
+            words: vec![u64::MAX; len.div_ceil(64)],
+            len,
+        };
+        bitmap.trim();
+        bitmap
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn set(&mut self, row: usize) {
+        self.words[row / 64] |= 1 << (row % 64);
+    }
+
+    pub fn get(&self, row: usize) -> bool {
+        self.words[row / 64] & (1 << (row % 64)) != 0
+    }
+
+    /// Whether no bit is set.
+    pub fn is_clear(&self) -> bool {
+        self.words.iter().all(|w| *w == 0)
+    }
+
+    /// Whether all bits are set.
+    pub fn is_full(&self) -> bool {
+        *self == Bitmap::full(self.len)
+    }
+
+    pub fn union(&self, other: &Bitmap) -> Bitmap {
+        Bitmap {
+            words: self
+                .words
+                .iter()
+                .zip(&other.words)
+                .map(|(a, b)| a | b)
+                .collect(),
+            len: self.len,
+        }
+    }
+
+    /// The bits of the selected rows in their order.
+    pub fn take(&self, selection: &[usize]) -> Bitmap {
+        let mut bitmap = Bitmap::new(selection.len());
+        for (i, row) in selection.iter().enumerate() {
+            if self.get(*row) {
+                bitmap.set(i);
+            }
+        }
+        bitmap
+    }
+
+    // clears the bits past the last row, so full words compare equal
+    fn trim(&mut self) {
+        if !self.len.is_multiple_of(64)
+            && let Some(last) = self.words.last_mut()
+        {
+            *last &= (1 << (self.len % 64)) - 1;
+        }
+    }
+}
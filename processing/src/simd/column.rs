@@ -1,26 +1,360 @@
-use value::{Float, Int, Text, Value};
+use crate::simd::bitmap::Bitmap;
+use anyhow::bail;
+use std::cmp::Ordering;
+use value::{Bool, Float, Int, Text, Time, ValType, Value};
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Column {
     Text(Vec<Text>),
     Int(Vec<Int>),
     Float(Vec<Float>),
+    Bool(Vec<Bool>),
+    Time(Vec<Time>),
+    /// a column with null rows, their values are placeholders and never read
+    Nullable(Box<Column>, Bitmap),
 }
 
 impl From<(Value, usize)> for Column {
     fn from(value: (Value, usize)) -> Self {
         let (value, size) = value;
         match value {
-            Value::Int(num) => {
-                Column::Int(vec![num; size])
+            Value::Int(num) => Column::Int(vec![num; size]),
+            Value::Float(num) => Column::Float(vec![num; size]),
+            Value::Text(text) => Column::Text(vec![text; size]),
+            Value::Bool(bool) => Column::Bool(vec![bool; size]),
+            Value::Time(time) => Column::Time(vec![time; size]),
+            // null fits anywhere, the placeholder type does not matter
+            Value::Null => Column::Int(vec![Int(0); size]).with_nulls(Some(Bitmap::full(size))),
+            // `Compiler::supports` keeps programs with other literals off the columnar VM
+            value => unreachable!("{:?} literals are not loaded into columns", value.type_()),
+        }
+    }
+}
+
+macro_rules! zip_map {
+    ($a:expr, $b:expr, |$x:ident, $y:ident| $body:expr) => {
+        $a.iter().zip($b.iter()).map(|($x, $y)| $body).collect()
+    };
+}
+
+impl Column {
+    /// Collects values of a type into a column, integers are widened for float columns.
+    pub fn from_values(values: Vec<&Value>, val_type: &ValType) -> anyhow::Result<Column> {
+        let mut nulls = Bitmap::new(values.len());
+        macro_rules! collect {
+            ($variant:ident, $default:expr, $($pattern:pat => $value:expr),+) => {{
+                let mut column = Vec::with_capacity(values.len());
+                for (row, value) in values.iter().enumerate() {
+                    column.push(match value {
+                        $($pattern => $value,)+
+                        Value::Null => {
+                            nulls.set(row);
+                            $default
+                        }
+                        v => bail!("Expected {} but got {}", val_type.dump(""), v),
+                    });
+                }
+                Column::$variant(column)
+            }};
+        }
+
+        let column = match val_type {
+            ValType::Integer => collect!(Int, Int(0), Value::Int(i) => *i),
+            ValType::Float => collect!(
+                Float,
+                Float::from(0.0),
+                Value::Float(f) => *f,
+                Value::Int(i) => Float::from(i.0 as f64)
+            ),
+            ValType::Text => collect!(Text, Text::from(""), Value::Text(t) => t.clone()),
+            ValType::Bool => collect!(Bool, Bool(false), Value::Bool(b) => b.clone()),
+            ValType::Time => collect!(Time, Time::new(0, 0), Value::Time(t) => *t),
+            t => bail!("Columns of type {} are not supported", t.dump("")),
+        };
+        Ok(column.with_nulls(Some(nulls)))
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Column::Text(v) => v.len(),
+            Column::Int(v) => v.len(),
+            Column::Float(v) => v.len(),
+            Column::Bool(v) => v.len(),
+            Column::Time(v) => v.len(),
+            Column::Nullable(c, _) => c.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn value(&self, row: usize) -> Value {
+        match self {
+            Column::Text(v) => Value::Text(v[row].clone()),
+            Column::Int(v) => Value::Int(v[row]),
+            Column::Float(v) => Value::Float(v[row]),
+            Column::Bool(v) => Value::Bool(v[row].clone()),
+            Column::Time(v) => Value::Time(v[row]),
+            Column::Nullable(_, nulls) if nulls.get(row) => Value::null(),
+            Column::Nullable(c, _) => c.value(row),
+        }
+    }
+
+    /// The selected rows in their order.
+    pub fn take(&self, selection: &[usize]) -> Column {
+        fn take<T: Clone>(values: &[T], selection: &[usize]) -> Vec<T> {
+            selection.iter().map(|row| values[*row].clone()).collect()
+        }
+        match self {
+            Column::Text(v) => Column::Text(take(v, selection)),
+            Column::Int(v) => Column::Int(take(v, selection)),
+            Column::Float(v) => Column::Float(take(v, selection)),
+            Column::Bool(v) => Column::Bool(take(v, selection)),
+            Column::Time(v) => Column::Time(take(v, selection)),
+            Column::Nullable(c, nulls) => c.take(selection).with_nulls(Some(nulls.take(selection))),
+        }
+    }
+
+    /// The values without their null rows and which rows these are.
+    pub(crate) fn split(self) -> (Column, Option<Bitmap>) {
+        match self {
+            Column::Nullable(c, nulls) => (*c, Some(nulls)),
+            c => (c, None),
+        }
+    }
+
+    /// Marks the rows as null, a bitmap without set bits leaves the column as it is.
+    pub(crate) fn with_nulls(self, nulls: Option<Bitmap>) -> Column {
+        let (column, own) = self.split();
+        match union(own, nulls) {
+            Some(nulls) if !nulls.is_clear() => Column::Nullable(Box::new(column), nulls),
+            _ => column,
+        }
+    }
+
+    /// The rows which are null.
+    pub(crate) fn nulls(&self) -> Option<&Bitmap> {
+        match self {
+            Column::Nullable(_, nulls) => Some(nulls),
+            _ => None,
+        }
+    }
+
+    /// Adds, subtracts, multiplies or divides row by row, mixing integers and floats gives floats.
+    ///
+    /// Fails if integers overflow in a row which is not null, like the tuple VM does.
+    pub(crate) fn arithmetic(self, other: Column, op: Arithmetic) -> anyhow::Result<Column> {
+        let (l, l_nulls) = self.split();
+        let (r, r_nulls) = other.split();
+        let nulls = union(l_nulls, r_nulls);
+
+        let column = match (l, r, op) {
+            (Column::Int(a), Column::Int(b), Arithmetic::Divide) => {
+                Column::Float(zip_map!(a, b, |x, y| Float::from(x.0 as f64 / y.0 as f64)))
+            }
+            (Column::Int(a), Column::Int(b), op) => {
+                let apply = match op {
+                    Arithmetic::Add => i64::checked_add,
+                    Arithmetic::Minus => i64::checked_sub,
+                    _ => i64::checked_mul,
+                };
+                let mut ints = Vec::with_capacity(a.len());
+                for (row, (x, y)) in a.iter().zip(b.iter()).enumerate() {
+                    ints.push(Int(match apply(x.0, y.0) {
+                        Some(int) => int,
+                        // the placeholders of null rows may overflow, the rows stay null
+                        None if nulls.as_ref().is_some_and(|n| n.get(row)) => 0,
+                        None => bail!("Integer overflow in {:?} of {} and {}", op, x.0, y.0),
+                    }));
+                }
+                Column::Int(ints)
+            }
+            (
+                l @ (Column::Int(_) | Column::Float(_)),
+                r @ (Column::Int(_) | Column::Float(_)),
+                op,
+            ) => {
+                let (a, b) = (l.floats(), r.floats());
+                Column::Float(match op {
+                    Arithmetic::Add => zip_map!(a, b, |x, y| Float::from(x + y)),
+                    Arithmetic::Minus => zip_map!(a, b, |x, y| Float::from(x - y)),
+                    Arithmetic::Multiply => zip_map!(a, b, |x, y| Float::from(x * y)),
+                    Arithmetic::Divide => zip_map!(a, b, |x, y| Float::from(x / y)),
+                })
+            }
+            (l, r, op) => panic!("Cannot apply {:?} to {:?} and {:?}", op, l, r),
+        };
+        Ok(column.with_nulls(nulls))
+    }
+
+    /// Compares row by row, numbers by their numeric value regardless of their type.
+    pub(crate) fn compare(self, other: Column, matches: impl Fn(Ordering) -> bool) -> Column {
+        let (l, l_nulls) = self.split();
+        let (r, r_nulls) = other.split();
+        let nulls = union(l_nulls, r_nulls);
+
+        let bools: Vec<Bool> = match (l, r) {
+            (Column::Int(a), Column::Int(b)) => zip_map!(a, b, |x, y| Bool(matches(x.cmp(y)))),
+            (Column::Text(a), Column::Text(b)) => zip_map!(a, b, |x, y| Bool(matches(x.cmp(y)))),
+            (Column::Bool(a), Column::Bool(b)) => zip_map!(a, b, |x, y| Bool(matches(x.cmp(y)))),
+            (Column::Time(a), Column::Time(b)) => zip_map!(a, b, |x, y| Bool(matches(x.cmp(y)))),
+            (l @ (Column::Int(_) | Column::Float(_)), r @ (Column::Int(_) | Column::Float(_))) => {
+                let (a, b) = (l.floats(), r.floats());
+                zip_map!(a, b, |x, y| Bool(matches(
+                    Float::from(*x).cmp(&Float::from(*y))
+                )))
             }
-            Value::Float(num) => {
-                Column::Float(vec![num; size])
+            // different types never match
+            (l, _) => vec![Bool(false); l.len()],
+        };
+        Column::Bool(bools).with_nulls(nulls)
+    }
+
+    pub(crate) fn and(self, other: Column) -> Column {
+        self.logical(other, |a, b| a && b)
+    }
+
+    pub(crate) fn or(self, other: Column) -> Column {
+        self.logical(other, |a, b| a || b)
+    }
+
+    pub(crate) fn not(self) -> Column {
+        let (column, nulls) = self.split();
+        Column::Bool(column.bools().iter().map(|b| Bool(!b.0)).collect()).with_nulls(nulls)
+    }
+
+    pub(crate) fn is_null(&self) -> Column {
+        Column::Bool(match self.nulls() {
+            Some(nulls) => (0..self.len()).map(|row| Bool(nulls.get(row))).collect(),
+            None => vec![Bool(false); self.len()],
+        })
+    }
+
+    /// Picks the rows of `then` where the condition holds and the ones of `otherwise` elsewhere.
+    pub(crate) fn cond(self, then: Column, otherwise: Column) -> Column {
+        fn pick<T: Clone>(picked: &[bool], then: &[T], otherwise: &[T]) -> Vec<T> {
+            picked
+                .iter()
+                .zip(then.iter().zip(otherwise))
+                .map(|(p, (t, o))| if *p { t.clone() } else { o.clone() })
+                .collect()
+        }
+
+        let mut picked = vec![false; then.len()];
+        for row in self.selection() {
+            picked[row] = true;
+        }
+        let mut nulls = Bitmap::new(picked.len());
+        for (row, p) in picked.iter().enumerate() {
+            let source = if *p { &then } else { &otherwise };
+            if source.nulls().is_some_and(|n| n.get(row)) {
+                nulls.set(row);
             }
-            Value::Text(text) => {
-                Column::Text(vec![text; size])
+        }
+
+        let (then_null, otherwise_null) = (then.all_null(), otherwise.all_null());
+        let (t, _) = then.split();
+        let (o, _) = otherwise.split();
+        let column = match (&t, &o) {
+            (Column::Text(a), Column::Text(b)) => Column::Text(pick(&picked, a, b)),
+            (Column::Int(a), Column::Int(b)) => Column::Int(pick(&picked, a, b)),
+            (Column::Float(a), Column::Float(b)) => Column::Float(pick(&picked, a, b)),
+            (Column::Bool(a), Column::Bool(b)) => Column::Bool(pick(&picked, a, b)),
+            (Column::Time(a), Column::Time(b)) => Column::Time(pick(&picked, a, b)),
+            // mixed numbers are widened to floats, like the tuple VM does
+            (Column::Int(_) | Column::Float(_), Column::Int(_) | Column::Float(_)) => {
+                let floats = pick(&picked, &t.floats(), &o.floats());
+                Column::Float(floats.into_iter().map(Float::from).collect())
             }
-            _ => unimplemented!(),
+            // a branch of another type is null in all rows, e.g. a null literal
+            (c, _) if otherwise_null => c.clone(),
+            (_, c) if then_null => c.clone(),
+            (t, o) => panic!("Branches {:?} and {:?} have different types", t, o),
+        };
+        column.with_nulls(Some(nulls))
+    }
+
+    pub(crate) fn concat(columns: Vec<Column>) -> Column {
+        let len = columns.first().map(|c| c.len()).unwrap_or_default();
+        let mut nulls = None;
+        let mut texts = vec![String::new(); len];
+        for column in columns {
+            let (column, own) = column.split();
+            nulls = union(nulls, own);
+            for (row, text) in texts.iter_mut().enumerate() {
+                match column.value(row) {
+                    Value::Text(t) => text.push_str(&t.0),
+                    v => text.push_str(&v.to_string()),
+                }
+            }
+        }
+        Column::Text(texts.into_iter().map(|t| Text::from(t.as_str())).collect()).with_nulls(nulls)
+    }
+
+    /// Whether each row is true, null counts as false.
+    pub(crate) fn is_true(&self) -> Column {
+        let mut rows = vec![Bool(false); self.len()];
+        for row in self.selection() {
+            rows[row] = Bool(true);
         }
+        Column::Bool(rows)
+    }
+
+    /// The rows which are true, null counts as false.
+    pub(crate) fn selection(&self) -> Vec<usize> {
+        let (column, nulls) = match self {
+            Column::Nullable(c, nulls) => (c.as_ref(), Some(nulls)),
+            c => (c, None),
+        };
+        column
+            .bools()
+            .iter()
+            .enumerate()
+            .filter(|(row, b)| b.0 && !nulls.is_some_and(|n| n.get(*row)))
+            .map(|(row, _)| row)
+            .collect()
+    }
+
+    fn all_null(&self) -> bool {
+        self.nulls().is_some_and(|n| n.is_full())
+    }
+
+    fn logical(self, other: Column, op: impl Fn(bool, bool) -> bool) -> Column {
+        let (l, l_nulls) = self.split();
+        let (r, r_nulls) = other.split();
+        let (a, b) = (l.bools(), r.bools());
+        Column::Bool(zip_map!(a, b, |x, y| Bool(op(x.0, y.0)))).with_nulls(union(l_nulls, r_nulls))
+    }
+
+    fn bools(&self) -> &[Bool] {
+        match self {
+            Column::Bool(b) => b,
+            c => panic!("Expected a boolean column but got {:?}", c),
+        }
+    }
+
+    fn floats(&self) -> Vec<f64> {
+        match self {
+            Column::Int(v) => v.iter().map(|i| i.0 as f64).collect(),
+            Column::Float(v) => v.iter().map(|f| f.0.0).collect(),
+            c => panic!("Expected a numeric column but got {:?}", c),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Arithmetic {
+    Add,
+    Minus,
+    Multiply,
+    Divide,
+}
+
+fn union(a: Option<Bitmap>, b: Option<Bitmap>) -> Option<Bitmap> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.union(&b)),
+        (a, None) => a,
+        (None, b) => b,
     }
 }
@@ -1,10 +1,11 @@
+use crate::expression::Expression;
+use crate::function::Function;
 use crate::instruction::Instruction;
+use crate::operator::Operator;
 use crate::{Algebra, Scan, Schema};
 use std::collections::HashMap;
 use std::fmt::Debug;
 use value::{ValType, Value};
-use crate::expression::Expression;
-use crate::operator::Operator;
 
 #[derive(Clone, Debug)]
 pub struct Compiler {
@@ -51,10 +52,20 @@ impl Compiler {
                     self.compile_expr(e, out);
                 }
                 // Map the operators to the enum
-                out.push(Self::compile_op(operator))
+                out.push(match operator {
+                    Operator::In => Instruction::In(expressions.len() - 1),
+                    Operator::Index => Instruction::Path(expressions.len() - 1),
+                    Operator::Concat => Instruction::Concat(expressions.len()),
+                    Operator::Function(f) if *f != Function::Length => {
+                        Instruction::Call(f.clone(), expressions.len())
+                    }
+                    op => Self::compile_op(op),
+                })
             }
-            Expression::Exclude(_) => {
-                todo!()
+            // projections replace them with the fields of their fixed input before, and
+            // `Compiler::supports` keeps them anywhere else off the columnar VM
+            Expression::Exclude(_) | Expression::Wildcard => {
+                unreachable!("{:?} is not evaluated column-at-a-time", expr)
             }
         }
     }
@@ -63,17 +74,122 @@ impl Compiler {
         match op {
             Operator::Add => Instruction::Add,
             Operator::Gt => Instruction::Greater,
-            Operator::Index => Instruction::Index,
+            Operator::Gte => Instruction::GreaterEqual,
+            Operator::Lt => Instruction::Less,
+            Operator::Lte => Instruction::LessEqual,
+            Operator::NotEqual => Instruction::NotEqual,
+            Operator::And => Instruction::And,
+            Operator::Or => Instruction::Or,
+            Operator::Not => Instruction::Not,
+            Operator::IsNull => Instruction::IsNull,
+            Operator::In => Instruction::In(1),
+            Operator::Index => Instruction::Path(1),
             Operator::Minus => Instruction::Minus,
             Operator::Multiply => Instruction::Multiply,
-            Operator::Explode => Instruction::InitExplode(0),
+            Operator::Divide => Instruction::Divide,
+            Operator::Explode => panic!("Explode can only be evaluated by an Unwind"),
             Operator::Equal => Instruction::Equal,
+            Operator::Cond => Instruction::Cond,
+            Operator::Concat => Instruction::Concat(2),
+            Operator::HasLabel => Instruction::HasLabel,
+            Operator::StartNode => Instruction::StartNode,
+            Operator::EndNode => Instruction::EndNode,
+            Operator::Function(Function::Length) => Instruction::Length,
+            Operator::Function(f) => Instruction::Call(f.clone(), f.arity().0),
+            Operator::Udf(f) => panic!("{} cannot be evaluated on columns", f.name),
+            Operator::Count
+            | Operator::Sum
+            | Operator::Avg
+            | Operator::Min
+            | Operator::Max
+            | Operator::Collect => {
+                panic!("Aggregate {:?} can only be evaluated per group", op)
+            }
         }
     }
 
+    /// Whether the columnar VM can evaluate the algebra, which are projections and filters over
+    /// a fixed schema of flat types with arithmetic over numbers only.
+    pub fn supports(algebra: &Algebra) -> bool {
+        fn expression(e: &Expression, schema: &Schema) -> bool {
+            match e {
+                Expression::Field(_) => true,
+                Expression::Wildcard | Expression::Exclude(_) => false,
+                Expression::Literal(v) => matches!(
+                    v,
+                    Value::Int(_)
+                        | Value::Float(_)
+                        | Value::Text(_)
+                        | Value::Bool(_)
+                        | Value::Time(_)
+                        | Value::Null
+                ),
+                Expression::Call {
+                    operator:
+                        Operator::Add | Operator::Minus | Operator::Multiply | Operator::Divide,
+                    expressions,
+                } => expressions.iter().all(|e| {
+                    matches!(e.val_type(schema), Ok(ValType::Integer | ValType::Float))
+                        && expression(e, schema)
+                }),
+                Expression::Call {
+                    operator,
+                    expressions,
+                } => {
+                    matches!(
+                        operator,
+                        Operator::Equal
+                            | Operator::NotEqual
+                            | Operator::Gt
+                            | Operator::Gte
+                            | Operator::Lt
+                            | Operator::Lte
+                            | Operator::And
+                            | Operator::Or
+                            | Operator::Not
+                            | Operator::IsNull
+                            | Operator::In
+                            | Operator::Cond
+                            | Operator::Concat
+                    ) && expressions.iter().all(|e| expression(e, schema))
+                }
+            }
+        }
+
+        algebra.schema().is_ok()
+            && match algebra {
+                Algebra::Scan(Scan {
+                    schema: Schema::Fixed(fields),
+                    ..
+                }) => fields.values().all(|t| {
+                    matches!(
+                        t,
+                        ValType::Integer
+                            | ValType::Float
+                            | ValType::Text
+                            | ValType::Bool
+                            | ValType::Time
+                    )
+                }),
+                Algebra::Project(p) => {
+                    p.input.schema().is_ok_and(|schema| {
+                        p.resolve(&schema).values().all(|e| expression(e, &schema))
+                    }) && Self::supports(&p.input)
+                }
+                Algebra::Filter(f) => {
+                    f.input
+                        .schema()
+                        .is_ok_and(|schema| expression(&f.predicate, &schema))
+                        && Self::supports(&f.input)
+                }
+                _ => false,
+            }
+    }
+
     pub fn compile_algebra(
         &mut self,
         algebra: &Algebra,
+        tuples: &mut usize,
         ops: &mut Vec<Instruction>,
     ) {
         match algebra {
@@ -87,30 +203,50 @@ impl Compiler {
                     .or_insert_with(|| slot);
 
                 self.current_schema = schema.clone();
+                *tuples = self.current_schema.len();
 
                 ops.push(Instruction::NextTuple { resource_id: slot }); // Start the loop
                 self.loop_stack.push(start_pc);
+            }
+            Algebra::Filter(filter) => {
+                self.compile_algebra(&filter.input, tuples, ops);
 
+                // each AND term narrows the batch to its rows, an empty batch goes to the start
+                let start_pc = *self.loop_stack.last().unwrap();
+                for conjunct in filter.predicate.conjuncts() {
+                    self.compile_expr(conjunct, ops);
+                    ops.push(Instruction::JumpIfFalse { target: start_pc });
+                }
             }
             Algebra::Project(project) => {
                 // 1. Compile input (e.g., Scan)
-                self.compile_algebra(&project.input, ops);
+                self.compile_algebra(&project.input, tuples, ops);
 
-                for (_, expr) in &project.expressions {
+                let expressions = project.resolve(&self.current_schema);
+                for expr in expressions.values() {
                     self.compile_expr(expr, ops);
                 }
 
+                // the projected columns become the batch, so operators above can load them
+                ops.push(Instruction::Replace(expressions.len()));
+                self.current_schema = Schema::Fixed(
+                    expressions
+                        .keys()
+                        .map(|k| (k.clone(), ValType::Any))
+                        .collect(),
+                );
+                *tuples = expressions.len();
             }
-            Algebra::Filter(_) => {}
-            Algebra::Collect(_) => {}
-            Algebra::Unwind(_) => {}
-            Algebra::Todo(_) => {}
+            _ => panic!("Only projections and filters are evaluated column-at-a-time"),
         }
     }
 
-    fn compile_field(&mut self, name: &String) -> Instruction {
+    fn compile_field(&mut self, name: &str) -> Instruction {
         let slot = if let Schema::Fixed(f) = &mut self.current_schema {
-            f.insert_full(name.to_string(), ValType::Any).0
+            match f.get_index_of(name) {
+                Some(slot) => slot,
+                None => f.insert_full(name.to_string(), ValType::Any).0,
+            }
         } else {
             Schema::fixed([(name.to_string(), ValType::Any)]);
             0
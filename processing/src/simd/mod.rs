@@ -1,5 +1,11 @@
 mod batch;
+mod bitmap;
 mod column;
 mod compiler;
 mod program;
 mod vm;
+
+pub use batch::RecordBatch;
+pub use bitmap::Bitmap;
+pub use column::Column;
+pub use program::Program as ColumnarProgram;
@@ -1,16 +1,21 @@
 use crate::expression::Expression;
 use crate::instruction::Instruction;
 use crate::simd::batch::RecordBatch;
-use crate::simd::column::Column;
+use crate::simd::column::{Arithmetic, Column};
 use crate::simd::compiler::Compiler;
 use crate::simd::vm::VM;
 use crate::Algebra;
 use anyhow::anyhow;
+use std::cmp::Ordering;
+use value::Bool;
 
+#[derive(Clone)]
 pub struct Program {
     vm: VM,
     instructions: Vec<Instruction>,
     compiler: Compiler,
+    /// why the evaluation stopped early, e.g. as an integer overflowed
+    error: Option<String>,
 }
 
 impl From<&Expression> for Program {
@@ -31,21 +36,25 @@ impl From<&Algebra> for Program {
         let mut compiler = Compiler::new();
         let mut instructions = vec![];
 
-        compiler.compile_algebra(algebra, &mut instructions);
-        instructions.push(Instruction::Yield(compiler.current_schema.len()));
+        let mut tuples = 1;
+        compiler.compile_algebra(algebra, &mut tuples, &mut instructions);
+        instructions.push(Instruction::Yield(tuples));
 
         // we go back to the iterator
         if let Some(parent_pc) = compiler.loop_stack.last() {
-            instructions.insert(0, Instruction::Jump { target: *parent_pc });
+            instructions.push(Instruction::Jump { target: *parent_pc });
         }
 
         Self::new(compiler, instructions)
     }
 }
 
-
 impl Program {
-    
+    /// Compiles the algebra, returns `None` if it cannot be evaluated column-at-a-time.
+    pub fn compile(algebra: &Algebra) -> Option<Program> {
+        Compiler::supports(algebra).then(|| Program::from(algebra))
+    }
+
     fn new(compiler: Compiler, instructions: Vec<Instruction>) -> Program {
         let vm = VM {
             stack: Vec::new(),
@@ -59,6 +68,7 @@ impl Program {
             vm,
             instructions,
             compiler,
+            error: None,
         }
     }
 
@@ -72,16 +82,51 @@ impl Program {
             .resource_map
             .get(name.as_ref())
             .ok_or(anyhow!("No named resource in compiler"))?;
-        self.vm.resources.insert(*index, Box::new(iter));
+        if *index < self.vm.resources.len() {
+            self.vm.resources[*index] = Box::new(iter);
+        } else {
+            self.vm.resources.insert(*index, Box::new(iter));
+        }
         Ok(())
     }
 
-    #[cfg(test)]
-    pub(crate) fn set_batch(&mut self, record_batch: RecordBatch) -> anyhow::Result<()> {
-        self.vm.current_batch = Some(record_batch);
-        Ok(())
+    pub fn reset(&mut self) {
+        self.vm.pc = 0;
+        self.vm.size = 0;
+        self.vm.stack.clear();
+        self.vm.current_batch = None;
+        self.error = None;
+    }
+
+    /// Why the batches stopped since the last reset, the batch in question then has to be
+    /// evaluated record by record.
+    pub fn take_error(&mut self) -> Option<String> {
+        self.error.take()
     }
 
+    fn pop(&mut self) -> Column {
+        self.vm.stack.pop().expect("Stack underflow")
+    }
+
+    /// Applies the arithmetic, a failure ends the program without evaluating further batches.
+    fn arithmetic(&mut self, op: Arithmetic) {
+        let r = self.pop();
+        let l = self.pop();
+        match l.arithmetic(r, op) {
+            Ok(column) => self.vm.stack.push(column),
+            Err(err) => {
+                self.error = Some(err.to_string());
+                self.vm.stack.clear();
+                self.vm.pc = self.instructions.len();
+            }
+        }
+    }
+
+    fn compare(&mut self, matches: impl Fn(Ordering) -> bool) {
+        let r = self.pop();
+        let l = self.pop();
+        self.vm.stack.push(l.compare(r, matches));
+    }
 }
 
 impl Iterator for Program {
@@ -96,29 +141,85 @@ impl Iterator for Program {
                     let col = self.vm.current_batch.as_ref().unwrap().columns[*idx].clone();
                     self.vm.stack.push(col);
                 }
-                Instruction::Add => {
-                    let r = self.vm.stack.pop().unwrap();
-                    let l = self.vm.stack.pop().unwrap();
-
-                    match (l, r) {
-                        (Column::Int(a), Column::Int(b)) => {
-                            // This loop is what the CPU turns into SIMD instructions
-                            let res = a.iter().zip(b.iter()).map(|(x, y)| *x + *y).collect();
-                            self.vm.stack.push(Column::Int(res));
-                        }
-                        // Handle other type combos...
-                        _ => panic!("Type mismatch"),
+                Instruction::Add => self.arithmetic(Arithmetic::Add),
+                Instruction::Minus => self.arithmetic(Arithmetic::Minus),
+                Instruction::Multiply => self.arithmetic(Arithmetic::Multiply),
+                Instruction::Divide => self.arithmetic(Arithmetic::Divide),
+                Instruction::Greater => self.compare(|o| o == Ordering::Greater),
+                Instruction::GreaterEqual => self.compare(|o| o != Ordering::Less),
+                Instruction::Less => self.compare(|o| o == Ordering::Less),
+                Instruction::LessEqual => self.compare(|o| o != Ordering::Greater),
+                Instruction::Equal => self.compare(|o| o == Ordering::Equal),
+                Instruction::NotEqual => self.compare(|o| o != Ordering::Equal),
+                Instruction::And => {
+                    let r = self.pop();
+                    let l = self.pop();
+                    self.vm.stack.push(l.and(r));
+                }
+                Instruction::Or => {
+                    let r = self.pop();
+                    let l = self.pop();
+                    self.vm.stack.push(l.or(r));
+                }
+                Instruction::Not => {
+                    let column = self.pop();
+                    self.vm.stack.push(column.not());
+                }
+                Instruction::IsNull => {
+                    let column = self.pop();
+                    self.vm.stack.push(column.is_null());
+                }
+                Instruction::In(amount) => {
+                    let list = self.vm.stack.split_off(self.vm.stack.len() - amount);
+                    let column = self.pop();
+                    let any = list
+                        .into_iter()
+                        .map(|item| column.clone().compare(item, |o| o == Ordering::Equal))
+                        .map(|equal| equal.is_true())
+                        .reduce(|l, r| l.or(r))
+                        .unwrap_or_else(|| Column::Bool(vec![Bool(false); self.vm.size]));
+                    self.vm.stack.push(any);
+                }
+                Instruction::Cond => {
+                    let otherwise = self.pop();
+                    let then = self.pop();
+                    let condition = self.pop();
+                    self.vm.stack.push(condition.cond(then, otherwise));
+                }
+                Instruction::Concat(amount) => {
+                    let parts = self.vm.stack.split_off(self.vm.stack.len() - amount);
+                    self.vm.stack.push(Column::concat(parts));
+                }
+                Instruction::Replace(amount) => {
+                    let columns = self.vm.stack.split_off(self.vm.stack.len() - amount);
+                    self.vm.current_batch.as_mut().unwrap().columns = columns;
+                }
+                Instruction::JumpIfFalse { target } => {
+                    // the rows which pass stay in the batch, if none does we go to the next one
+                    let target = *target;
+                    let selection = self.pop().selection();
+                    let batch = self.vm.current_batch.as_mut().unwrap();
+                    if selection.is_empty() {
+                        self.vm.stack.clear();
+                        self.vm.pc = target;
+                        continue;
+                    }
+                    if selection.len() < batch.num_of_rows {
+                        *batch = batch.take(&selection);
+                        self.vm.size = batch.num_of_rows;
                     }
                 }
                 Instruction::Yield(amount) => {
-                    // Instead of a single Value, we assemble the stack into a result batch
-                    let mut result_cols = vec![];
-                    for _ in 0..*amount {
-                        result_cols.insert(0, self.vm.stack.pop().unwrap());
+                    self.vm.pc += 1; // Move past Yield for the next call
+                    let batch = self.vm.current_batch.as_ref().unwrap();
+                    if self.vm.stack.is_empty() {
+                        return Some(batch.clone());
                     }
+                    // Instead of a single Value, we assemble the stack into a result batch
+                    let columns = self.vm.stack.split_off(self.vm.stack.len() - amount);
                     return Some(RecordBatch {
-                        num_of_rows: self.vm.current_batch.as_ref().unwrap().num_of_rows,
-                        columns: result_cols,
+                        num_of_rows: batch.num_of_rows,
+                        columns,
                     });
                 }
                 Instruction::PushConst(id) => {
@@ -126,15 +227,15 @@ impl Iterator for Program {
                     self.vm.stack.push(column);
                 }
                 Instruction::NextTuple { resource_id } => {
-                    let batch = self.vm.resources[resource_id].next()?; // Pull a batch
+                    let batch = self.vm.resources[*resource_id].next()?; // Pull a batch
                     self.vm.size = batch.num_of_rows;
                     self.vm.current_batch = Some(batch);
                 }
                 Instruction::Jump { target } => {
                     self.vm.pc = *target;
+                    continue;
                 }
-                // ... other ops
-                op => todo!("{:?}", op)
+                op => panic!("{:?} is not evaluated column-at-a-time", op),
             }
             self.vm.pc += 1;
         }
@@ -149,9 +250,9 @@ mod test {
     use crate::simd::batch::RecordBatch;
     use crate::simd::column::Column;
     use crate::simd::program::Program;
-    use crate::{Algebra, Schema};
+    use crate::{Algebra, Schema, parse_sql};
     use std::time::Instant;
-    use value::{Int, ValType};
+    use value::{Int, ValType, Value};
 
     #[test]
     fn test_add() {
@@ -196,10 +297,10 @@ mod test {
     fn test_add_algebra() {
         let count = 10_000;
 
-        let a = (0..count).into_iter().map(|i| Int((i * 10).into())).collect::<Vec<_>>();
-        let b = (0..count).into_iter().map(|i| Int(i.into())).collect::<Vec<_>>();
+        let a = (0..count).map(|i| Int((i * 10).into())).collect::<Vec<_>>();
+        let b = (0..count).map(|i| Int(i.into())).collect::<Vec<_>>();
 
-        let calc = (0..count).into_iter().map(|i| Int((i * 10 + i).into())).collect::<Vec<_>>();
+        let calc = (0..count).map(|i| Int((i * 10 + i).into())).collect::<Vec<_>>();
 
         let col_a = Column::Int(a);
         let col_b = Column::Int(b);
@@ -246,4 +347,141 @@ mod test {
             )
         );
     }
+
+    fn schema() -> Schema {
+        Schema::fixed([
+            ("id".to_string(), ValType::Integer),
+            ("price".to_string(), ValType::Float),
+            ("name".to_string(), ValType::Text),
+            ("paid".to_string(), ValType::Bool),
+            ("at".to_string(), ValType::Time),
+        ])
+    }
+
+    fn rows() -> Vec<Value> {
+        (0..50)
+            .map(|i| {
+                Value::array(vec![
+                    Value::int(i),
+                    Value::float(i as f64 / 4.0),
+                    Value::text(format!("n{}", i % 7)),
+                    Value::bool(i % 3 == 0),
+                    Value::time(i * 1000, 0),
+                ])
+            })
+            .collect()
+    }
+
+    fn columnar(query: &str, rows: &[Value]) -> Vec<Value> {
+        let mut algebra = parse_sql(query).unwrap();
+        algebra.set_schema(schema());
+        let mut program = Program::compile(&algebra).unwrap();
+        let batches = rows
+            .chunks(16)
+            .map(|chunk| RecordBatch::from_rows(chunk, &schema()).unwrap())
+            .collect::<Vec<_>>();
+        program.set_resource("$$source", batches.into_iter()).unwrap();
+        program.flat_map(|batch| batch.rows()).collect()
+    }
+
+    #[test]
+    fn same_as_tuple_vm() {
+        for query in [
+            "SELECT * FROM $$source",
+            "SELECT id + 1 AS next, price * 2, id - price AS diff FROM $$source WHERE id > 4",
+            "SELECT name, at FROM $$source WHERE paid AND price <= 8.0 AND name <> 'n3'",
+            "SELECT id FROM $$source WHERE id IN (1, 2, 9, 40) OR NOT paid",
+            "SELECT name, id = 3 AS three, paid = TRUE FROM $$source WHERE id >= 45 OR id = 3",
+            "SELECT id FROM $$source WHERE id > 100",
+            "SELECT id * 2 AS double FROM $$source WHERE id < 10 AND id * 2 > 6",
+            "SELECT CASE WHEN id > 4 THEN price ELSE id END AS mixed FROM $$source",
+        ] {
+            let mut algebra = parse_sql(query).unwrap();
+            algebra.set_schema(schema());
+            let mut tuple = algebra.processing().unwrap();
+            tuple.set_resource("$$source", rows().into_iter()).unwrap();
+
+            assert_eq!(columnar(query, &rows()), tuple.collect::<Vec<_>>(), "{}", query);
+        }
+    }
+
+    #[test]
+    fn nulls() {
+        let rows = vec![
+            Value::array(vec![Value::int(1), Value::float(2.0)]),
+            Value::array(vec![Value::null(), Value::float(3.0)]),
+            Value::array(vec![Value::int(3), Value::null()]),
+        ];
+        let result = columnar(
+            "SELECT id + price AS sum, id IS NULL AS missing FROM $$source WHERE price > 1.0",
+            &rows,
+        );
+        assert_eq!(
+            result,
+            vec![
+                Value::array(vec![Value::float(3.0), Value::bool(false)]),
+                Value::array(vec![Value::null(), Value::bool(true)]),
+            ]
+        );
+
+        let batch = RecordBatch::from_rows(&rows, &schema()).unwrap();
+        assert_eq!(batch.len(), 3);
+        assert_eq!(
+            batch.rows()[1],
+            Value::array(vec![
+                Value::null(),
+                Value::float(3.0),
+                Value::null(),
+                Value::null(),
+                Value::null()
+            ])
+        );
+        let text = Value::array(vec![Value::text("a")]);
+        assert!(RecordBatch::from_rows(&[text], &schema()).is_err());
+    }
+
+    #[test]
+    fn overflow() {
+        let rows = vec![
+            Value::array(vec![Value::int(1)]),
+            Value::array(vec![Value::int(i64::MAX)]),
+        ];
+        let mut algebra = parse_sql("SELECT id + 1 AS next FROM $$source").unwrap();
+        algebra.set_schema(schema());
+        let mut program = Program::compile(&algebra).unwrap();
+        let batch = RecordBatch::from_rows(&rows, &schema()).unwrap();
+        program.set_resource("$$source", [batch].into_iter()).unwrap();
+
+        // the batch stops instead of wrapping around
+        assert!(program.next().is_none());
+        assert!(program.take_error().unwrap().contains("overflow"));
+
+        // null rows do not overflow
+        let rows = vec![Value::array(vec![Value::null()])];
+        assert_eq!(
+            columnar("SELECT id + 9223372036854775807 AS next FROM $$source", &rows),
+            vec![Value::array(vec![Value::null()])]
+        );
+    }
+
+    #[test]
+    fn not_supported() {
+        for query in [
+            "SELECT COUNT(*) AS c FROM $$source",
+            "SELECT id FROM $$source WHERE name + 1 > 3",
+            "SELECT name + name AS twice FROM $$source",
+        ] {
+            let mut algebra = parse_sql(query).unwrap();
+            algebra.set_schema(schema());
+            assert!(Program::compile(&algebra).is_none(), "{}", query);
+        }
+
+        // wildcards are only resolved as the fields of projections
+        let scan = Algebra::scan("$$source", schema());
+        let predicate = Expression::Call {
+            operator: Operator::IsNull,
+            expressions: vec![Expression::Wildcard],
+        };
+        assert!(Program::compile(&Algebra::filter(scan, predicate)).is_none());
+    }
 }
@@ -0,0 +1,164 @@
+use crate::function::cast;
+use crate::window::parse_duration;
+use anyhow::bail;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use value::{ValType, Value};
+
+/// Where a definition takes the event time of its records from and how long it waits for
+/// records which arrive out of order.
+///
+/// Declared with the mapping like `mapping.time = { field = "measured", delay = "30 SECONDS" }`,
+/// definitions without it process by the time their records were ingested.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventTime {
+    /// field of the mapping holding the event time, dotted for nested ones, else the one its
+    /// source carries is used
+    pub field: Option<String>,
+    /// how far records may arrive out of order, in ms
+    #[serde(default, with = "duration")]
+    pub delay: i64,
+    #[serde(default)]
+    pub late: Lateness,
+}
+
+/// What happens to records whose windows the watermark already closed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Lateness {
+    #[default]
+    Drop,
+    /// hands them to the side output of the definition
+    #[serde(alias = "side_output")]
+    Side,
+    /// keeps closed windows one more window size and emits them again when records are added
+    Update,
+}
+
+impl EventTime {
+    /// The event time in ms the field holds, numbers are ms since the epoch and texts RFC 3339.
+    /// A missing field holds none.
+    pub fn parse(value: &Value) -> anyhow::Result<Option<i64>> {
+        match (value, cast(value, &ValType::Time)) {
+            (Value::Null, _) => Ok(None),
+            (_, Some(Value::Time(time))) => Ok(Some(time.ms)),
+            (value, _) => bail!("Expected an event time, got {:?}", value),
+        }
+    }
+}
+
+/// Generates the watermarks of a definition with bounded out-of-orderness, it trails the latest
+/// event time by the delay. Clones share the latest event time.
+#[derive(Clone, Debug)]
+pub struct Watermarks {
+    latest: Arc<AtomicI64>,
+    delay: i64,
+}
+
+impl Watermarks {
+    pub fn new(delay: i64) -> Self {
+        Watermarks {
+            latest: Arc::new(AtomicI64::new(i64::MIN)),
+            delay,
+        }
+    }
+
+    /// Moves the watermark on with the event time and returns it.
+    pub fn observe(&self, event_time: i64) -> i64 {
+        let latest = self.latest.fetch_max(event_time, Ordering::Relaxed);
+        latest.max(event_time).saturating_sub(self.delay)
+    }
+
+    pub fn current(&self) -> i64 {
+        self.latest
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.delay)
+    }
+}
+
+impl Default for Watermarks {
+    fn default() -> Self {
+        Watermarks::new(0)
+    }
+}
+
+/// Delays are written like window durations, e.g. `5 SECONDS`.
+pub mod duration {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(delay: &i64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{} MILLISECONDS", delay))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+        let delay = String::deserialize(deserializer)?;
+        parse(&delay).map_err(serde::de::Error::custom)
+    }
+
+    pub(crate) fn parse(delay: &str) -> anyhow::Result<i64> {
+        let normalized = delay.to_uppercase();
+        let mut tokens = normalized.split_whitespace();
+        let delay = parse_duration(&mut tokens)?;
+        if let Some(token) = tokens.next() {
+            bail!("Unexpected token {} in delay", token)
+        }
+        Ok(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let time: EventTime =
+            serde_json::from_str(r#"{"field": "meta.at", "delay": "5 seconds", "late": "side"}"#)
+                .unwrap();
+        assert_eq!(
+            time,
+            EventTime {
+                field: Some("meta.at".to_string()),
+                delay: 5_000,
+                late: Lateness::Side,
+            }
+        );
+        let json = serde_json::to_string(&time).unwrap();
+        assert_eq!(serde_json::from_str::<EventTime>(&json).unwrap(), time);
+
+        let time: EventTime = serde_json::from_str("{}").unwrap();
+        assert_eq!(
+            (time.field, time.delay, time.late),
+            (None, 0, Lateness::Drop)
+        );
+
+        assert!(serde_json::from_str::<EventTime>(r#"{"delay": "5 weeks"}"#).is_err());
+        assert!(serde_json::from_str::<EventTime>(r#"{"late": "keep"}"#).is_err());
+    }
+
+    #[test]
+    fn parse_time() {
+        assert_eq!(EventTime::parse(&Value::int(1_500)).unwrap(), Some(1_500));
+        assert_eq!(
+            EventTime::parse(&Value::text("1970-01-01T00:00:02Z")).unwrap(),
+            Some(2_000)
+        );
+        assert_eq!(EventTime::parse(&Value::null()).unwrap(), None);
+        assert!(EventTime::parse(&Value::text("yesterday")).is_err());
+        assert!(EventTime::parse(&Value::array(vec![Value::int(3)])).is_err());
+    }
+
+    #[test]
+    fn watermarks() {
+        let watermarks = Watermarks::new(10);
+        assert_eq!(watermarks.current(), i64::MIN);
+        assert_eq!(watermarks.observe(100), 90);
+
+        // out of order records do not move it back
+        let shared = watermarks.clone();
+        assert_eq!(shared.observe(50), 90);
+        assert_eq!(watermarks.observe(120), 110);
+        assert_eq!(shared.current(), 110);
+    }
+}
@@ -1,7 +1,10 @@
 use crate::expression::Expression;
+use crate::function::Function;
 use crate::instruction::Instruction;
 use crate::operator::Operator;
+use crate::udf::Udf;
 use crate::{Algebra, Scan, Schema};
+use anyhow::bail;
 use std::collections::HashMap;
 use value::{ValType, Value};
 
@@ -10,8 +13,12 @@ pub struct Compiler {
     //pub field_map: HashMap<String, usize>,
     pub resource_map: HashMap<String, usize>,
     pub constants: Vec<Value>,
+    /// user-defined functions the program calls by their index
+    pub functions: Vec<Udf>,
     pub loop_stack: Vec<usize>,
     pub current_schema: Schema,
+    /// schema of the scanned records, which `Flatten` brings into field order
+    pub scan_schema: Schema,
 }
 
 impl Default for Compiler {
@@ -26,48 +33,97 @@ impl Compiler {
             //field_map: HashMap::new(),
             resource_map: HashMap::new(),
             constants: Vec::new(),
+            functions: Vec::new(),
             loop_stack: Vec::new(),
             current_schema: Schema::Dynamic,
+            scan_schema: Schema::Dynamic,
         }
     }
 
-    pub fn compile_expr(&mut self, expr: &Expression, out: &mut Vec<Instruction>) {
+    pub fn compile_expr(
+        &mut self,
+        expr: &Expression,
+        out: &mut Vec<Instruction>,
+    ) -> anyhow::Result<()> {
         match expr {
             Expression::Literal(value) => {
                 let idx = self.constants.len();
                 self.constants.push(value.clone());
                 out.push(Instruction::PushConst(idx));
             }
-            Expression::Field(name) => {
-                let op = self.compile_field(name);
-                out.push(op);
-            }
+            Expression::Field(name) => self.compile_field(name, out)?,
             Expression::Call {
                 operator,
                 expressions,
             } => {
                 for e in expressions {
-                    self.compile_expr(e, out);
+                    self.compile_expr(e, out)?;
                 }
                 // Map the operators to the enum
-                out.push(Self::compile_op(operator))
-            }
-            Expression::Exclude(_) => {
-                todo!()
+                out.push(match operator {
+                    Operator::In => Instruction::In(expressions.len() - 1),
+                    Operator::Index => Instruction::Path(expressions.len() - 1),
+                    Operator::Concat => Instruction::Concat(expressions.len()),
+                    Operator::Function(f) if *f != Function::Length => {
+                        Instruction::Call(f.clone(), expressions.len())
+                    }
+                    Operator::Udf(f) => {
+                        let idx = self.functions.len();
+                        self.functions.push(f.clone());
+                        Instruction::CallUdf(idx, expressions.len())
+                    }
+                    op => Self::compile_op(op)?,
+                })
             }
+            // a record without a fixed schema is a single document, otherwise its fields
+            Expression::Wildcard => match &self.current_schema {
+                Schema::Fixed(fields) => {
+                    out.extend((0..fields.len()).map(Instruction::LoadField));
+                    out.push(Instruction::Array(fields.len()));
+                }
+                Schema::Dynamic => out.push(Instruction::LoadField(0)),
+            },
+            Expression::Exclude(_) => bail!("Excluding fields is not supported by the tuple VM"),
         }
+        Ok(())
     }
 
-    pub fn compile_op(op: &Operator) -> Instruction {
-        match op {
+    pub fn compile_op(op: &Operator) -> anyhow::Result<Instruction> {
+        Ok(match op {
             Operator::Add => Instruction::Add,
             Operator::Gt => Instruction::Greater,
-            Operator::Index => Instruction::Index,
+            Operator::Gte => Instruction::GreaterEqual,
+            Operator::Lt => Instruction::Less,
+            Operator::Lte => Instruction::LessEqual,
+            Operator::NotEqual => Instruction::NotEqual,
+            Operator::And => Instruction::And,
+            Operator::Or => Instruction::Or,
+            Operator::Not => Instruction::Not,
+            Operator::IsNull => Instruction::IsNull,
+            Operator::In => Instruction::In(1),
+            Operator::Index => Instruction::Path(1),
             Operator::Minus => Instruction::Minus,
             Operator::Multiply => Instruction::Multiply,
-            Operator::Explode => Instruction::InitExplode(0),
+            Operator::Divide => Instruction::Divide,
+            Operator::Explode => bail!("Explode can only be evaluated by an Unwind"),
             Operator::Equal => Instruction::Equal,
-        }
+            Operator::Cond => Instruction::Cond,
+            Operator::Concat => Instruction::Concat(2),
+            Operator::HasLabel => Instruction::HasLabel,
+            Operator::StartNode => Instruction::StartNode,
+            Operator::EndNode => Instruction::EndNode,
+            Operator::Function(Function::Length) => Instruction::Length,
+            Operator::Function(f) => Instruction::Call(f.clone(), f.arity().0),
+            Operator::Udf(f) => bail!("{} is called by the program which compiles it", f.name),
+            Operator::Count
+            | Operator::Sum
+            | Operator::Avg
+            | Operator::Min
+            | Operator::Max
+            | Operator::Collect => {
+                bail!("Aggregate {:?} can only be evaluated per group", op)
+            }
+        })
     }
 
     pub fn compile_algebra(
@@ -75,8 +131,7 @@ impl Compiler {
         algebra: &Algebra,
         tuples: &mut usize,
         ops: &mut Vec<Instruction>,
-        ends: &mut Vec<Instruction>,
-    ) {
+    ) -> anyhow::Result<()> {
         match algebra {
             Algebra::Scan(Scan { source, schema }) => {
                 let start_pc = ops.len();
@@ -88,6 +143,9 @@ impl Compiler {
                     .or_insert_with(|| slot);
 
                 self.current_schema = schema.clone();
+                self.scan_schema = schema.clone();
+                // without a projection, the whole record is yielded
+                *tuples = self.current_schema.len();
 
                 ops.push(Instruction::NextTuple { resource_id: slot }); // Start the loop
                 self.loop_stack.push(start_pc);
@@ -99,24 +157,34 @@ impl Compiler {
             }
             Algebra::Filter(filter) => {
                 // 1. First, compile the source (Scan)
-                self.compile_algebra(&filter.input, tuples, ops, ends);
+                self.compile_algebra(&filter.input, tuples, ops)?;
 
-                // 2. Compile the condition (e.g., x > 10)
-                self.compile_expr(&filter.predicate, ops);
-
-                // 3. Jump to the start if condition is false (skips Yield)
+                // 2. Compile each AND term of the condition (e.g., x > 10) and
+                // 3. jump to the start as soon as one is false (skips Yield)
                 let start_pc = *self.loop_stack.last().unwrap();
-                ops.push(Instruction::JumpIfFalse { target: start_pc });
+                for conjunct in filter.predicate.conjuncts() {
+                    self.compile_expr(conjunct, ops)?;
+                    ops.push(Instruction::JumpIfFalse { target: start_pc });
+                }
             }
             Algebra::Project(project) => {
                 // 1. Compile input (e.g., Scan)
-                self.compile_algebra(&project.input, tuples, ops, ends);
+                self.compile_algebra(&project.input, tuples, ops)?;
 
-                for (_, expr) in &project.expressions {
-                    self.compile_expr(expr, ops);
+                let expressions = project.resolve(&self.current_schema);
+                for expr in expressions.values() {
+                    self.compile_expr(expr, ops)?;
                 }
 
-                *tuples = project.expressions.len();
+                // the projected values become the record, so operators above can load them
+                ops.push(Instruction::Replace(expressions.len()));
+                self.current_schema = Schema::Fixed(
+                    expressions
+                        .keys()
+                        .map(|k| (k.clone(), ValType::Any))
+                        .collect(),
+                );
+                *tuples = expressions.len();
             }
             Algebra::Todo(_) => {
                 let start_pc = ops.len();
@@ -133,48 +201,83 @@ impl Compiler {
                 ops.push(Instruction::Yield(1));
             }
             Algebra::Unwind(unwind) => {
-                self.compile_algebra(&unwind.input, tuples, ops, ends);
-                // --- LOOP SETUP ---
-
-                ops.push(self.compile_field(&unwind.key));
-
-                // C. Instruction to move the array from Stack -> VM.explode_stack
-                let loop_start_pc = ops.len() + 1;
+                self.compile_algebra(&unwind.input, tuples, ops)?;
+                if unwind.func != Operator::Explode {
+                    bail!("Unwinding with {:?} is not supported", unwind.func)
+                }
+                // an exhausted array continues with the next record of the loop around it
+                let parent_pc = *self.loop_stack.last().unwrap();
 
-                ops.push(match unwind.func {
-                    Operator::Explode => Instruction::InitExplode(loop_start_pc),
-                    _ => panic!("Unwind algebra operator not yet implemented"),
-                });
+                self.compile_field(&unwind.key, ops)?;
+                ops.push(Instruction::InitExplode(parent_pc));
 
+                // filters and the end of the program come back here for the next element
+                let loop_start_pc = ops.len();
                 self.loop_stack.push(loop_start_pc);
+                ops.push(Instruction::NextOrPop);
 
-                // --- LOOP BODY ---
-                // D. Load current element of the latest explode onto the stack
-                ops.push(Instruction::LoadExplodeElement);
-
-                let idx = self.current_schema.get(&unwind.key).unwrap();
-                ops.push(Instruction::StoreField(idx));
+                match self.current_schema {
+                    Schema::Fixed(_) => {
+                        ops.push(Instruction::LoadExplodeElement);
+                        let idx = self.current_schema.get(&unwind.key).unwrap();
+                        ops.push(Instruction::StoreField(idx));
+                    }
+                    // the element replaces the array in the document
+                    Schema::Dynamic => {
+                        ops.push(Instruction::LoadField(0));
+                        self.compile_expr(&Expression::Literal(Value::text(&unwind.key)), ops)?;
+                        ops.push(Instruction::LoadExplodeElement);
+                        ops.push(Instruction::SetKey);
+                        ops.push(Instruction::StoreField(0));
+                    }
+                }
+            }
+            Algebra::Aggregate(aggregate) => {
+                self.compile_algebra(&aggregate.input, tuples, ops)?;
 
-                // G. Advance the explode loop
-                // If has next: jumps to loop_start_pc + 1 (LoadExplodeElement)
-                // If done: pops explode_stack and continues to next instruction
-                ends.push(Instruction::NextOrPop);
+                // each input row becomes its group keys followed by one argument per aggregate call,
+                // the accumulation itself happens outside the VM
+                for key in &aggregate.keys {
+                    self.compile_expr(key, ops)?;
+                }
+                let calls = aggregate.calls();
+                for call in &calls {
+                    match call {
+                        Expression::Call { expressions, .. } if !expressions.is_empty() => {
+                            self.compile_expr(&expressions[0], ops)?
+                        }
+                        // COUNT(*) counts every row
+                        _ => self.compile_expr(&Expression::Literal(Value::bool(true)), ops)?,
+                    }
+                }
 
-                self.loop_stack.pop(); // Done with this level
+                *tuples = aggregate.keys.len() + calls.len();
             }
-            Algebra::Collect(_) => todo!(),
+            Algebra::Join(_) => bail!("Joins are evaluated by the Joiner"),
+            Algebra::Sort(_) | Algebra::Limit(_) | Algebra::Distinct(_) => {
+                bail!("Sorting, limiting and distinct records are evaluated per window")
+            }
+            Algebra::Collect(_) => bail!("Collecting is evaluated per window"),
         }
+        Ok(())
     }
 
-    fn compile_field(&mut self, name: &str) -> Instruction {
-        let slot = if let Schema::Fixed(f) = &mut self.current_schema {
-            f.insert(name.to_string(), ValType::Any);
-            f.len() - 1
-        } else {
-            Schema::fixed([(name.to_string(), ValType::Any)]);
-            0
-        };
-
-        Instruction::LoadField(slot)
+    /// Loads the field from its slot, without a fixed schema it is a key of the record.
+    fn compile_field(&mut self, name: &str, out: &mut Vec<Instruction>) -> anyhow::Result<()> {
+        match &mut self.current_schema {
+            Schema::Fixed(f) => {
+                let slot = match f.get_index_of(name) {
+                    Some(slot) => slot,
+                    None => f.insert_full(name.to_string(), ValType::Any).0,
+                };
+                out.push(Instruction::LoadField(slot));
+            }
+            Schema::Dynamic => {
+                out.push(Instruction::LoadField(0));
+                self.compile_expr(&Expression::Literal(Value::text(name)), out)?;
+                out.push(Instruction::Path(1));
+            }
+        }
+        Ok(())
     }
 }
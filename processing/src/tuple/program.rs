@@ -1,10 +1,13 @@
 use crate::Schema;
 use crate::algebra::Algebra;
 use crate::expression::Expression;
+use crate::function::Function;
 use crate::instruction::Instruction;
 use crate::tuple::compiler::Compiler;
 use crate::tuple::vm::VM;
 use anyhow::anyhow;
+use std::cmp::Ordering;
+use std::panic::{self, AssertUnwindSafe};
 use value::Value;
 
 #[derive(Clone, Debug)]
@@ -43,7 +46,16 @@ pub enum Op {
 pub struct ExplodeState {
     pub array: Vec<Value>,
     pub index: usize,
-    pub loop_pc: usize, // Where to jump back to for the next element
+    pub loop_pc: usize,     // Where to continue once all elements are loaded
+    pub record: Vec<Value>, // the record the elements are stored into
+}
+
+/// A record the program failed to evaluate, the program goes on without it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordError {
+    /// position of the record in the source, counted from the last reset
+    pub record: usize,
+    pub message: String,
 }
 
 #[derive(Clone)]
@@ -51,39 +63,44 @@ pub struct Program {
     instructions: Vec<Instruction>,
     compiler: Compiler,
     vm: VM,
+    /// where the outermost loop takes the next record, a failed record continues there
+    restart: Option<usize>,
 }
 
-impl From<&Expression> for Program {
-    fn from(expression: &Expression) -> Self {
+impl TryFrom<&Expression> for Program {
+    type Error = anyhow::Error;
+
+    fn try_from(expression: &Expression) -> anyhow::Result<Self> {
         let mut compiler = Compiler::new();
+        // the fields of the expression get a slot each, in order of appearance
+        compiler.current_schema = Schema::Fixed(Default::default());
         let mut instructions = vec![];
 
-        compiler.compile_expr(&expression.clone(), &mut instructions);
+        compiler.compile_expr(&expression.clone(), &mut instructions)?;
 
         instructions.push(Instruction::Yield(1));
 
-        Self::new(compiler, instructions)
+        Ok(Self::new(compiler, instructions))
     }
 }
 
-impl From<&Algebra> for Program {
-    fn from(algebra: &Algebra) -> Self {
+impl TryFrom<&Algebra> for Program {
+    type Error = anyhow::Error;
+
+    fn try_from(algebra: &Algebra) -> anyhow::Result<Self> {
         let mut compiler = Compiler::new();
         let mut instructions = vec![];
-        let mut ends = vec![];
 
         let mut tuples = 1;
-        compiler.compile_algebra(algebra, &mut tuples, &mut instructions, &mut ends);
+        compiler.compile_algebra(algebra, &mut tuples, &mut instructions)?;
         instructions.push(Instruction::Yield(tuples));
 
-        let mut instructions = [instructions, ends].concat();
-
         // we go back to the iterator
         if let Some(parent_pc) = compiler.loop_stack.last() {
             instructions.push(Instruction::Jump { target: *parent_pc });
         }
 
-        Self::new(compiler, instructions)
+        Ok(Self::new(compiler, instructions))
     }
 }
 
@@ -94,14 +111,19 @@ impl Program {
             resources: vec![],
             current_record: vec![],
             constants: compiler.constants.clone(),
+            functions: compiler.functions.clone(),
             pc: 0,
             explode_stack: vec![],
+            read: 0,
+            errors: vec![],
         };
+        let restart = compiler.loop_stack.first().copied();
 
         Self {
             instructions,
             compiler,
             vm,
+            restart,
         }
     }
 
@@ -115,7 +137,11 @@ impl Program {
             .resource_map
             .get(name.as_ref())
             .ok_or(anyhow!("No named resource in compiler"))?;
-        self.vm.resources.insert(*index, Box::new(iter));
+        if *index < self.vm.resources.len() {
+            self.vm.resources[*index] = Box::new(iter);
+        } else {
+            self.vm.resources.insert(*index, Box::new(iter));
+        }
         Ok(())
     }
 
@@ -130,26 +156,104 @@ impl Program {
         Ok(())
     }
 
+    /// The instructions with the constants they push and the slots of the scanned fields.
+    pub fn listing(&self) -> String {
+        let slots = match &self.compiler.scan_schema {
+            Schema::Dynamic => "0 record".to_string(),
+            Schema::Fixed(fields) => fields
+                .keys()
+                .enumerate()
+                .map(|(i, name)| format!("{} {}", i, name))
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+        let mut resources = self.compiler.resource_map.iter().collect::<Vec<_>>();
+        resources.sort_by_key(|(_, slot)| **slot);
+
+        let mut listing = format!(
+            "  slots: {}\n  resources: {}\n",
+            slots,
+            resources
+                .iter()
+                .map(|(name, slot)| format!("{} {}", slot, name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        for (pc, instruction) in self.instructions.iter().enumerate() {
+            let line = format!("{:>6}  {:?}", pc, instruction);
+            listing.push_str(&match instruction {
+                Instruction::PushConst(idx) => {
+                    format!("{}  ; {}\n", line, self.compiler.constants[*idx])
+                }
+                Instruction::CallUdf(idx, _) => {
+                    format!("{}  ; {}\n", line, self.compiler.functions[*idx].name)
+                }
+                _ => format!("{}\n", line),
+            });
+        }
+        listing
+    }
+
     pub fn reset(&mut self) {
         self.vm.pc = 0;
         self.vm.stack.clear();
         self.vm.current_record.clear();
         self.vm.explode_stack.clear();
+        self.vm.read = 0;
+        self.vm.errors.clear();
+    }
+
+    /// The records which failed since the last call, the rows of the others were yielded.
+    pub fn take_errors(&mut self) -> Vec<RecordError> {
+        std::mem::take(&mut self.vm.errors)
     }
 }
 
-macro_rules! binary_op {
-    ($self:ident, $op:tt) => {{
-        let r = $self.vm.stack.pop().expect("Stack underflow");
-        let l = $self.vm.stack.pop().expect("Stack underflow");
-        $self.vm.stack.push(&l $op &r);
+/// Orders two values, comparing numbers by their numeric value regardless of their type.
+pub(crate) fn compare(l: &Value, r: &Value) -> Option<Ordering> {
+    match (l, r) {
+        (Value::Null, _) | (_, Value::Null) => None,
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+            Some(l.as_float().ok()?.cmp(&r.as_float().ok()?))
+        }
+        (l, r) if l.type_() == r.type_() => Some(l.cmp(r)),
+        _ => None,
+    }
+}
+
+/// Compares two values for equality, nothing is equal (or unequal) to null.
+fn equals(l: &Value, r: &Value) -> Option<bool> {
+    match (l, r) {
+        (Value::Null, _) | (_, Value::Null) => None,
+        (l, r) => Some(l == r),
+    }
+}
+
+macro_rules! compare_op {
+    ($self:ident, $($ord:pat_param)|+) => {{
+        let r = $self.vm.pop()?;
+        let l = $self.vm.pop()?;
+        let result = match (&l, &r) {
+            (Value::Null, _) | (_, Value::Null) => Value::Null,
+            (l, r) => Value::bool(matches!(compare(l, r), Some($($ord)|+))),
+        };
+        $self.vm.stack.push(result);
     }};
 }
 
-impl Iterator for Program {
-    type Item = Value;
+macro_rules! binary_op {
+    ($self:ident, $instr:expr) => {{
+        let r = $self.vm.pop()?;
+        let l = $self.vm.pop()?;
+        $self.vm.stack.push(arithmetic($instr, &l, &r)?);
+    }};
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
+impl Program {
+    /// Runs until the next row is yielded, fails on the first instruction the current record
+    /// cannot be evaluated with.
+    fn execute(&mut self) -> Result<Option<Value>, String> {
         while self.vm.pc < self.instructions.len() {
             let instr = &self.instructions[self.vm.pc];
 
@@ -158,135 +262,260 @@ impl Iterator for Program {
                     self.vm.stack.push(self.vm.constants[*idx].clone());
                 }
                 Instruction::LoadField(idx) => {
-                    self.vm.stack.push(self.vm.current_record[*idx].clone());
+                    let value = self
+                        .vm
+                        .current_record
+                        .get(*idx)
+                        .cloned()
+                        .ok_or_else(|| format!("Record has no field {}", idx))?;
+                    self.vm.stack.push(value);
                 }
-                Instruction::Add => binary_op!(self, +),
-                Instruction::Minus => binary_op!(self, -),
-                Instruction::Multiply => binary_op!(self, *),
-                Instruction::Divide => binary_op!(self, /),
+                Instruction::Add => binary_op!(self, &Instruction::Add),
+                Instruction::Minus => binary_op!(self, &Instruction::Minus),
+                Instruction::Multiply => binary_op!(self, &Instruction::Multiply),
+                Instruction::Divide => binary_op!(self, &Instruction::Divide),
                 Instruction::JumpIfFalse { target } => {
-                    if !self.vm.stack.pop().unwrap().as_bool().unwrap().0 {
-                        self.vm.pc = *target;
+                    // unknown is not true either
+                    let target = *target;
+                    if truth(self.vm.pop()?)? != Some(true) {
+                        self.vm.pc = target;
                         continue; // Skip the standard pc += 1
                     }
                 }
                 Instruction::Yield(amount) => {
-                    let mut row = Vec::with_capacity(*amount);
-                    if self.vm.stack.is_empty() {
-                        assert_eq!(&self.vm.current_record.len(), amount);
-                        for value in &self.vm.current_record {
-                            row.push(value.clone());
+                    let amount = *amount;
+                    let row = if self.vm.stack.is_empty() {
+                        if self.vm.current_record.len() != amount {
+                            return Err(format!(
+                                "Record has {} fields instead of {}",
+                                self.vm.current_record.len(),
+                                amount
+                            ));
                         }
+                        self.vm.current_record.clone()
                     } else {
-                        for _ in 0..*amount {
-                            row.push(self.vm.stack.pop().expect("Stack underflow at yield"));
-                        }
-                    }
-
-                    row.reverse();
+                        self.vm.pop_many(amount)?
+                    };
 
                     self.vm.pc += 1; // Move past Yield for the next call
-                    return Some(Value::array(row));
+                    return Ok(Some(Value::array(row)));
                 }
                 Instruction::Equal => {
-                    let r = self.vm.stack.pop().unwrap();
-                    let l = self.vm.stack.pop().unwrap();
-                    self.vm.stack.push(Value::bool(l == r));
+                    let r = self.vm.pop()?;
+                    let l = self.vm.pop()?;
+                    self.vm.stack.push(logical(equals(&l, &r)));
                 }
                 Instruction::NextTuple { resource_id } => {
                     if let Some(resource) = self.vm.resources.get_mut(*resource_id)
                         && let Some(value) = resource.next()
                     {
-                        self.vm.current_record = vec![value]
+                        self.vm.current_record = vec![value];
+                        if self.restart == Some(self.vm.pc) {
+                            self.vm.read += 1;
+                        }
                     } else {
                         // we end the iterator
-                        return None;
+                        return Ok(None);
                     }
                 }
                 Instruction::Jump { target } => {
                     self.vm.pc = *target;
                     continue; // Skip the standard pc += 1
                 }
-                Instruction::Greater => {
-                    let r = self.vm.stack.pop().unwrap();
-                    let l = self.vm.stack.pop().unwrap();
-                    self.vm.stack.push(Value::bool(l > r));
+                Instruction::Greater => compare_op!(self, Ordering::Greater),
+                Instruction::GreaterEqual => {
+                    compare_op!(self, Ordering::Greater | Ordering::Equal)
+                }
+                Instruction::Less => compare_op!(self, Ordering::Less),
+                Instruction::LessEqual => compare_op!(self, Ordering::Less | Ordering::Equal),
+                Instruction::NotEqual => {
+                    let r = self.vm.pop()?;
+                    let l = self.vm.pop()?;
+                    self.vm.stack.push(logical(equals(&l, &r).map(|eq| !eq)));
+                }
+                Instruction::And => {
+                    let r = truth(self.vm.pop()?)?;
+                    let l = truth(self.vm.pop()?)?;
+                    let result = match (l, r) {
+                        (Some(false), _) | (_, Some(false)) => Some(false),
+                        (Some(true), Some(true)) => Some(true),
+                        _ => None,
+                    };
+                    self.vm.stack.push(logical(result));
+                }
+                Instruction::Or => {
+                    let r = truth(self.vm.pop()?)?;
+                    let l = truth(self.vm.pop()?)?;
+                    let result = match (l, r) {
+                        (Some(true), _) | (_, Some(true)) => Some(true),
+                        (Some(false), Some(false)) => Some(false),
+                        _ => None,
+                    };
+                    self.vm.stack.push(logical(result));
+                }
+                Instruction::Not => {
+                    let val = truth(self.vm.pop()?)?;
+                    self.vm.stack.push(logical(val.map(|b| !b)));
+                }
+                Instruction::IsNull => {
+                    let val = self.vm.pop()?;
+                    self.vm.stack.push(Value::bool(matches!(val, Value::Null)));
+                }
+                Instruction::In(amount) => {
+                    let list = self.vm.pop_many(*amount)?;
+                    let val = self.vm.pop()?;
+                    // without a match, a null makes it unknown whether the value is in the list
+                    let result = if list.iter().any(|item| equals(&val, item) == Some(true)) {
+                        Some(true)
+                    } else if matches!(val, Value::Null)
+                        || list.iter().any(|item| matches!(item, Value::Null))
+                    {
+                        None
+                    } else {
+                        Some(false)
+                    };
+                    self.vm.stack.push(logical(result));
                 }
 
-                Instruction::Index => {
-                    let index = self
-                        .vm
-                        .stack
-                        .pop()
-                        .expect("Stack underflow")
-                        .as_int()
-                        .unwrap()
-                        .0 as usize;
-                    let array = self.vm.stack.pop().expect("Stack underflow");
-                    if let Value::Array(a) = array {
-                        self.vm.stack.push(a.values[index].clone());
-                    } else if let Value::Text(t) = array {
-                        self.vm.stack.push(Value::text(&t.0[index..index + 1]))
-                    }
+                Instruction::Path(amount) => {
+                    let keys = self.vm.pop_many(*amount)?;
+                    let container = self.vm.pop()?;
+                    let value = keys
+                        .iter()
+                        .try_fold(container, lookup)
+                        .unwrap_or(Value::Null);
+                    self.vm.stack.push(value);
+                }
+                Instruction::HasLabel => {
+                    let label = self.vm.pop()?;
+                    let element = self.vm.pop()?;
+                    let has = match (&element, label.as_text()) {
+                        (Value::Node(n), Ok(label)) => n.labels.contains(&label),
+                        (Value::Edge(e), Ok(label)) => e.label.as_ref() == Some(&label),
+                        _ => false,
+                    };
+                    self.vm.stack.push(Value::bool(has));
+                }
+                Instruction::StartNode | Instruction::EndNode => {
+                    let element = self.vm.pop()?;
+                    let id = match (&element, instr) {
+                        (Value::Edge(e), Instruction::StartNode) => Value::int(e.start as i64),
+                        (Value::Edge(e), _) => Value::int(e.end as i64),
+                        _ => Value::null(),
+                    };
+                    self.vm.stack.push(id);
+                }
+                Instruction::Call(function, amount) => {
+                    let args = self.vm.pop_many(*amount)?;
+                    self.vm.stack.push(function.evaluate(&args));
                 }
-                Instruction::Minus => {
-                    let r = self.vm.stack.pop().unwrap();
-                    let l = self.vm.stack.pop().unwrap();
-                    self.vm.stack.push(&l - &r);
+                Instruction::CallUdf(idx, amount) => {
+                    let idx = *idx;
+                    let args = self.vm.pop_many(*amount)?;
+                    let function = &self.vm.functions[idx];
+                    // a panicking function only fails the record it was called for
+                    let value = panic::catch_unwind(AssertUnwindSafe(|| function.call(&args)))
+                        .map_err(|_| format!("Function {} panicked", function.name))?;
+                    self.vm.stack.push(value);
                 }
                 Instruction::Length => {
-                    let val = self.vm.stack.pop().unwrap();
-
-                    match val {
-                        Value::Array(a) => {
-                            self.vm.stack.push(Value::int(a.values.len() as i64));
-                        }
-                        Value::Text(t) => self.vm.stack.push(Value::int(t.0.len() as i64)),
-                        _ => {}
+                    let val = self.vm.pop()?;
+                    self.vm.stack.push(Function::Length.evaluate(&[val]));
+                }
+                Instruction::NextOrPop => {
+                    let state = self.vm.explode_stack.last().ok_or("No array to explode")?;
+                    if state.index >= state.array.len() {
+                        // This array is done, continue with the loop around it
+                        self.vm.pc = state.loop_pc;
+                        self.vm.explode_stack.pop();
+                        continue;
                     }
+                    // operators above may have replaced the record of the previous element
+                    self.vm.current_record.clone_from(&state.record);
                 }
-                Instruction::Multiply => {
-                    let r = self.vm.stack.pop().unwrap();
-                    let l = self.vm.stack.pop().unwrap();
-                    self.vm.stack.push(&l * &r);
+                Instruction::LoadExplodeElement => {
+                    let state = self
+                        .vm
+                        .explode_stack
+                        .last_mut()
+                        .ok_or("No array to explode")?;
+                    self.vm.stack.push(state.array[state.index].clone());
+                    state.index += 1;
                 }
-                Instruction::NextOrPop => {
-                    if let Some(state) = self.vm.explode_stack.last_mut() {
-                        state.index += 1;
-                        if state.index < state.array.len() {
-                            // Keep looping this array
-                            self.vm.pc = state.loop_pc;
-                            continue;
-                        } else {
-                            // This array is done
-                            self.vm.explode_stack.pop();
+                Instruction::InitExplode(parent_pc) => {
+                    let parent_pc = *parent_pc;
+                    // anything else than an array or text has no elements
+                    let array = match self.vm.pop()? {
+                        Value::Array(arr) => arr.values,
+                        Value::Text(text) => {
+                            text.0.chars().map(|c| Value::text(c.to_string())).collect()
                         }
+                        _ => vec![],
+                    };
+                    self.vm.explode_stack.push(ExplodeState {
+                        array,
+                        index: 0,
+                        loop_pc: parent_pc,
+                        record: self.vm.current_record.clone(),
+                    });
+                }
+                Instruction::SetKey => {
+                    let value = self.vm.pop()?;
+                    let key = self.vm.pop()?;
+                    let container = self.vm.pop()?;
+                    match (container, key) {
+                        (Value::Dict(mut d), Value::Text(key)) => {
+                            match d.keys.iter().position(|k| k == key.0.as_str()) {
+                                Some(i) => d.values[i] = value,
+                                None => d.insert(key.0.to_string(), value),
+                            }
+                            self.vm.stack.push(Value::Dict(d));
+                        }
+                        (container, _) => self.vm.stack.push(container),
                     }
                 }
-                Instruction::LoadExplodeElement => {
-                    let state = self.vm.explode_stack.last().unwrap();
-                    let val = state.array[state.index].clone();
-                    self.vm.stack.push(val);
-                }
-                Instruction::InitExplode(start_pc) => {
-                    let array_val = self.vm.stack.pop().unwrap();
-                    if let Value::Array(arr) = array_val {
-                        self.vm.explode_stack.push(ExplodeState {
-                            array: arr.values,
-                            index: 0,
-                            loop_pc: *start_pc,
-                        });
-                    } else if let Value::Text(text) = array_val {
-                        self.vm.explode_stack.push(ExplodeState {
-                            array: text.0.chars().map(|c| Value::text(c.to_string())).collect(),
-                            index: 0,
-                            loop_pc: *start_pc,
-                        });
+                Instruction::Cond => {
+                    let otherwise = self.vm.pop()?;
+                    let then = self.vm.pop()?;
+                    // unknown picks the otherwise branch, anything but a bool fails the record
+                    match truth(self.vm.pop()?)? {
+                        Some(true) => self.vm.stack.push(then),
+                        _ => self.vm.stack.push(otherwise),
                     }
                 }
+                Instruction::Concat(amount) => {
+                    let parts = self.vm.pop_many(*amount)?;
+                    let value = match parts.iter().any(|p| matches!(p, Value::Null)) {
+                        true => Value::null(),
+                        false => Value::text(
+                            parts
+                                .iter()
+                                .map(|p| match p {
+                                    Value::Text(t) => t.0.to_string(),
+                                    p => p.to_string(),
+                                })
+                                .collect::<String>(),
+                        ),
+                    };
+                    self.vm.stack.push(value);
+                }
+                Instruction::Array(amount) => {
+                    let values = self.vm.pop_many(*amount)?;
+                    self.vm.stack.push(Value::array(values));
+                }
+                Instruction::Replace(amount) => {
+                    let values = self.vm.pop_many(*amount)?;
+                    self.vm.current_record = values;
+                }
                 Instruction::StoreField(idx) => {
-                    let value = self.vm.stack.last().unwrap();
-                    self.vm.current_record[*idx] = value.clone()
+                    let idx = *idx;
+                    let value = self.vm.pop()?;
+                    let field = self
+                        .vm
+                        .current_record
+                        .get_mut(idx)
+                        .ok_or_else(|| format!("Record has no field {}", idx))?;
+                    *field = value
                 }
                 Instruction::Flatten => {
                     let value = self.vm.current_record.pop();
@@ -297,26 +526,141 @@ impl Iterator for Program {
                                     self.vm.current_record.push(val);
                                 }
                             }
-                            Value::Dict(d) => match &self.compiler.current_schema {
+                            Value::Dict(d) => match &self.compiler.scan_schema {
                                 Schema::Dynamic => {
                                     for v in d.values {
                                         self.vm.current_record.push(v);
                                     }
                                 }
                                 Schema::Fixed(f) => {
+                                    // missing fields are null
                                     for (k, _) in f {
-                                        self.vm.current_record.push(d.get(k).unwrap().clone())
+                                        self.vm
+                                            .current_record
+                                            .push(d.get(k).cloned().unwrap_or(Value::Null))
                                     }
                                 }
                             },
-                            _ => panic!(),
+                            value => {
+                                return Err(format!(
+                                    "Cannot split a {:?} into fields",
+                                    value.type_()
+                                ));
+                            }
                         }
                     }
                 }
             }
             self.vm.pc += 1;
         }
-        None
+        Ok(None)
+    }
+}
+
+impl Iterator for Program {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.execute() {
+                Ok(row) => return row,
+                Err(message) => {
+                    self.vm.errors.push(RecordError {
+                        record: self.vm.read.saturating_sub(1),
+                        message,
+                    });
+                    // the record is dropped, the program goes on with the next one
+                    self.vm.stack.clear();
+                    self.vm.explode_stack.clear();
+                    match self.restart {
+                        Some(pc) => self.vm.pc = pc,
+                        None => {
+                            self.vm.pc = self.instructions.len();
+                            return None;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The truth of a condition, null is unknown.
+fn truth(value: Value) -> Result<Option<bool>, String> {
+    match value {
+        Value::Bool(b) => Ok(Some(b.0)),
+        Value::Null => Ok(None),
+        value => Err(format!("Expected a bool, got {:?}", value.type_())),
+    }
+}
+
+fn logical(value: Option<bool>) -> Value {
+    value.map_or(Value::Null, Value::bool)
+}
+
+/// Applies the arithmetic instruction, a null operand makes the result null.
+fn arithmetic(instruction: &Instruction, l: &Value, r: &Value) -> Result<Value, String> {
+    let defined = match (instruction, l, r) {
+        (_, Value::Null, _) | (_, _, Value::Null) => return Ok(Value::Null),
+        (_, Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => true,
+        (Instruction::Add, Value::Text(_), r) => r.as_text().is_ok(),
+        (Instruction::Add, Value::Time(_), r) => r.as_time().is_ok(),
+        (Instruction::Add, Value::Date(_), r) => r.as_date().is_ok(),
+        (Instruction::Add, Value::Array(_), _) => true,
+        (Instruction::Multiply, Value::Text(_), Value::Int(times)) => times.0 >= 0,
+        _ => false,
+    };
+    if !defined {
+        return Err(format!(
+            "Cannot apply {:?} to {:?} and {:?}",
+            instruction,
+            l.type_(),
+            r.type_()
+        ));
+    }
+    // integers which overflow fail the record instead of wrapping around
+    if let (Value::Int(a), Value::Int(b)) = (l, r) {
+        let result = match instruction {
+            Instruction::Add => a.0.checked_add(b.0),
+            Instruction::Minus => a.0.checked_sub(b.0),
+            Instruction::Multiply => a.0.checked_mul(b.0),
+            _ => return Ok(l / r),
+        };
+        return result.map(Value::int).ok_or_else(|| {
+            format!(
+                "Integer overflow in {:?} of {} and {}",
+                instruction, a.0, b.0
+            )
+        });
+    }
+    Ok(match instruction {
+        Instruction::Add => l + r,
+        Instruction::Minus => l - r,
+        Instruction::Multiply => l * r,
+        _ => l / r,
+    })
+}
+
+/// The value under the key, e.g. a property of a document or an element of an array.
+///
+/// Arrays also take keys of digits, like the `items.2` of a dotted path.
+fn lookup(container: Value, key: &Value) -> Option<Value> {
+    match (container, key) {
+        (Value::Dict(d), Value::Text(key)) => d.get(&key.0).cloned(),
+        (Value::Node(n), Value::Text(key)) => n.properties.get(&key.0).cloned(),
+        (Value::Edge(e), Value::Text(key)) => e.properties.get(&key.0).cloned(),
+        (Value::Array(a), Value::Int(index)) => {
+            a.values.get(usize::try_from(index.0).ok()?).cloned()
+        }
+        (Value::Array(a), Value::Text(index)) => {
+            a.values.get(index.0.parse::<usize>().ok()?).cloned()
+        }
+        (Value::Text(t), Value::Int(index)) => {
+            t.0.chars()
+                .nth(usize::try_from(index.0).ok()?)
+                .map(|c| Value::text(c.to_string()))
+        }
+        _ => None,
     }
 }
 
@@ -326,7 +670,7 @@ mod test {
     use crate::Schema;
     use crate::operator::Operator;
     use std::vec;
-    use value::ValType;
+    use value::{Dict, ValType};
 
     #[test]
     fn test_vm_execution_add() {
@@ -339,7 +683,7 @@ mod test {
             ],
         };
 
-        let mut program = Program::from(&expr);
+        let mut program = Program::try_from(&expr).unwrap();
         program.set_record("price", Value::int(100)).unwrap();
 
         let mut program = program.map(|v| v.as_array().unwrap().values[0].clone());
@@ -350,7 +694,7 @@ mod test {
     #[test]
     fn test_vm_execution_filter() {
         // Simulate: price + 10
-        let mut program = Program::from(&Algebra::filter(
+        let mut program = Program::try_from(&Algebra::filter(
             Algebra::scan("test", Schema::fixed([("name".to_string(), ValType::Text)])),
             Expression::Call {
                 operator: Operator::Equal,
@@ -359,7 +703,8 @@ mod test {
                     Expression::Literal(Value::int(10)),
                 ],
             },
-        ));
+        ))
+        .unwrap();
 
         program
             .set_resource(
@@ -381,7 +726,7 @@ mod test {
     #[test]
     fn test_vm_execution_multiple() {
         // Simulate: price + 10
-        let mut program = Program::from(&Algebra::project(
+        let mut program = Program::try_from(&Algebra::project(
             Algebra::scan("test", Schema::fixed([("name".to_string(), ValType::Text)])),
             [
                 (
@@ -396,7 +741,8 @@ mod test {
                 ),
                 ("name1".to_string(), Expression::Field("name".to_string())),
             ],
-        ));
+        ))
+        .unwrap();
 
         program
             .set_resource("test", [Value::array([Value::int(100)])].into_iter())
@@ -416,7 +762,7 @@ mod test {
             Value::array([Value::int(3), Value::text("David"), Value::float(5.2)]),
         ];
 
-        let mut program = Program::from(&Algebra::project(
+        let mut program = Program::try_from(&Algebra::project(
             Algebra::scan(
                 "$$source",
                 Schema::fixed([
@@ -435,7 +781,8 @@ mod test {
                     ],
                 },
             )],
-        ));
+        ))
+        .unwrap();
 
         program
             .set_resource("$$source", values.into_iter())
@@ -450,11 +797,12 @@ mod test {
     #[test]
     fn test_vm_execution_explode() {
         // Simulate: explode
-        let mut program = Program::from(&Algebra::unwind(
+        let mut program = Program::try_from(&Algebra::unwind(
             Algebra::scan("test", Schema::fixed([("name".to_string(), ValType::Text)])),
             "name",
             Operator::Explode,
-        ));
+        ))
+        .unwrap();
 
         program
             .set_resource("test", [Value::array([Value::text("David")])].into_iter())
@@ -472,7 +820,7 @@ mod test {
     #[test]
     fn test_vm_execution_explode_nested() {
         // Simulate: explode
-        let mut program = Program::from(&Algebra::project(
+        let mut program = Program::try_from(&Algebra::project(
             Algebra::unwind(
                 Algebra::scan("test", Schema::fixed([("name".to_string(), ValType::Text)])),
                 "name",
@@ -488,7 +836,8 @@ mod test {
                     ],
                 },
             )],
-        ));
+        ))
+        .unwrap();
 
         program
             .set_resource("test", [Value::array([Value::text("David")])].into_iter())
@@ -503,6 +852,396 @@ mod test {
         assert_eq!(program.next().unwrap(), Value::text("dtest"));
     }
 
+    fn relational_source() -> (Schema, Vec<Value>) {
+        let schema = Schema::fixed([
+            ("id".to_string(), ValType::Integer),
+            ("name".to_string(), ValType::Text),
+            ("price".to_string(), ValType::Float),
+        ]);
+        let values = vec![
+            Value::array([Value::int(1), Value::text("x"), Value::float(3.3)]),
+            Value::array([Value::int(2), Value::text("y"), Value::float(5.2)]),
+            Value::array([Value::int(3), Value::text("x"), Value::float(2.1)]),
+            Value::array([Value::int(4), Value::null(), Value::float(7.0)]),
+        ];
+        (schema, values)
+    }
+
+    fn run_sql(query: &str) -> Vec<Value> {
+        let (schema, values) = relational_source();
+        let mut algebra = crate::parse_sql(query).unwrap();
+        algebra.set_schema(schema);
+
+        let mut program = algebra.processing().unwrap();
+        program
+            .set_resource("$$source", values.into_iter())
+            .unwrap();
+        program.collect()
+    }
+
+    #[test]
+    fn test_vm_execution_where() {
+        let result = run_sql("SELECT id FROM $$source WHERE price > 3 AND name = 'x'");
+        assert_eq!(result, vec![Value::array([Value::int(1)])]);
+    }
+
+    #[test]
+    fn test_vm_execution_where_comparisons() {
+        let ids = |query: &str| {
+            run_sql(query)
+                .into_iter()
+                .map(|v| v.as_array().unwrap().values[0].clone())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            ids("SELECT id FROM $$source WHERE price <= 3.3"),
+            vec![Value::int(1), Value::int(3)]
+        );
+        assert_eq!(
+            ids("SELECT id FROM $$source WHERE id >= 2 AND id < 4"),
+            vec![Value::int(2), Value::int(3)]
+        );
+        assert_eq!(
+            ids("SELECT id FROM $$source WHERE name <> 'x' OR id = 1"),
+            vec![Value::int(1), Value::int(2)]
+        );
+        assert_eq!(
+            ids("SELECT id FROM $$source WHERE NOT (id IN (1, 2))"),
+            vec![Value::int(3), Value::int(4)]
+        );
+        assert_eq!(
+            ids("SELECT id FROM $$source WHERE name IS NULL"),
+            vec![Value::int(4)]
+        );
+        assert_eq!(
+            ids("SELECT id FROM $$source WHERE name IS NOT NULL AND id NOT IN (1)"),
+            vec![Value::int(2), Value::int(3)]
+        );
+    }
+
+    #[test]
+    fn test_vm_execution_nulls() {
+        assert_eq!(
+            run_sql("SELECT price + 1, name = 'x', name <> 'x' FROM $$source WHERE id = 4"),
+            vec![Value::array([
+                Value::float(8.0),
+                Value::null(),
+                Value::null()
+            ])]
+        );
+
+        // unknown conditions drop the record, unless the rest of the condition decides it
+        let ids = |query: &str| {
+            run_sql(query)
+                .into_iter()
+                .map(|v| v.as_array().unwrap().values[0].clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids("SELECT id FROM $$source WHERE NOT (name = 'x')"),
+            vec![Value::int(2)]
+        );
+        assert_eq!(
+            ids("SELECT id FROM $$source WHERE name = 'x' OR id = 4"),
+            vec![Value::int(1), Value::int(3), Value::int(4)]
+        );
+        assert_eq!(
+            ids("SELECT id FROM $$source WHERE NOT (name IN ('y', 'z'))"),
+            vec![Value::int(1), Value::int(3)]
+        );
+    }
+
+    #[test]
+    fn test_vm_execution_errors() {
+        let document = |pairs: Vec<(&str, Value)>| Value::Dict(Box::new(Dict::from(pairs)));
+        let records = vec![
+            document(vec![("price", Value::int(2))]),
+            document(vec![("price", Value::text("a lot"))]),
+            document(vec![]),
+            document(vec![("price", Value::float(1.5))]),
+        ];
+        let run = |query: &str| {
+            let mut algebra = crate::parse_sql(query).unwrap();
+            algebra.set_schema(Schema::Dynamic);
+            let mut program = algebra.processing().unwrap();
+            program
+                .set_resource("$$source", records.clone().into_iter())
+                .unwrap();
+            let rows = program.by_ref().collect::<Vec<_>>();
+            (rows, program.take_errors())
+        };
+
+        // the failed record is reported, the others go on
+        let (rows, errors) = run("SELECT price - 2 FROM $$source");
+        assert_eq!(
+            rows,
+            vec![
+                Value::array([Value::int(0)]),
+                Value::array([Value::null()]),
+                Value::array([Value::float(-0.5)]),
+            ]
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].record, 1);
+
+        let (rows, errors) = run("SELECT price FROM $$source WHERE price");
+        assert!(rows.is_empty());
+        assert_eq!(
+            errors.iter().map(|e| e.record).collect::<Vec<_>>(),
+            vec![0, 1, 3]
+        );
+
+        // a condition fails the same records in a CASE as in a WHERE
+        let (rows, errors) = run("SELECT CASE WHEN price THEN 1 ELSE 0 END FROM $$source");
+        assert_eq!(rows, vec![Value::array([Value::int(0)])]);
+        assert_eq!(
+            errors.iter().map(|e| e.record).collect::<Vec<_>>(),
+            vec![0, 1, 3]
+        );
+
+        // integers do not wrap around
+        let (rows, errors) = run("SELECT price + 9223372036854775807 FROM $$source");
+        assert_eq!(rows.len(), 3);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].record, 0);
+        assert!(errors[0].message.contains("overflow"));
+    }
+
+    #[test]
+    fn test_vm_compile_errors() {
+        // collecting is evaluated per window, not by the VM
+        let algebra = Algebra::collect(Algebra::scan("$$source", Schema::Dynamic));
+        assert!(Program::try_from(&algebra).is_err());
+    }
+
+    #[test]
+    fn test_vm_execution_paths() {
+        let document = |pairs: Vec<(&str, Value)>| Value::Dict(Box::new(Dict::from(pairs)));
+        let records = vec![
+            document(vec![
+                ("id", Value::int(1)),
+                (
+                    "properties",
+                    document(vec![
+                        ("test", Value::text("a")),
+                        ("n", document(vec![("deep", Value::int(1))])),
+                    ]),
+                ),
+                (
+                    "items",
+                    Value::array([
+                        document(vec![("price", Value::float(1.5))]),
+                        document(vec![("price", Value::float(2.5))]),
+                    ]),
+                ),
+            ]),
+            document(vec![
+                ("id", Value::int(2)),
+                ("properties", document(vec![])),
+                ("items", Value::array(Vec::<Value>::new())),
+            ]),
+        ];
+        let run = |query: &str| {
+            let mut algebra = crate::parse_sql(query).unwrap();
+            algebra.set_schema(Schema::Dynamic);
+            let mut program = algebra.processing().unwrap();
+            program
+                .set_resource("$$source", records.clone().into_iter())
+                .unwrap();
+            program.collect::<Vec<_>>()
+        };
+
+        // missing keys and indexes are null
+        assert_eq!(
+            run(
+                "SELECT id, properties.test, properties.n.deep, items[1].price, properties['test'] FROM $$source"
+            ),
+            vec![
+                Value::array([
+                    Value::int(1),
+                    Value::text("a"),
+                    Value::int(1),
+                    Value::float(2.5),
+                    Value::text("a"),
+                ]),
+                Value::array([
+                    Value::int(2),
+                    Value::null(),
+                    Value::null(),
+                    Value::null(),
+                    Value::null(),
+                ]),
+            ]
+        );
+        assert_eq!(
+            run("SELECT id FROM $$source WHERE properties.n.deep = 1 AND items[7] IS NULL"),
+            vec![Value::array([Value::int(1)])]
+        );
+    }
+
+    #[test]
+    fn test_vm_execution_functions() {
+        let column = |query: &str| {
+            run_sql(query)
+                .into_iter()
+                .map(|v| v.as_array().unwrap().values[0].clone())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            column(
+                "SELECT UPPER(name) || '-' || SUBSTRING(name FROM 1 FOR 3) FROM $$source WHERE id < 3"
+            ),
+            vec![Value::text("X-x"), Value::text("Y-y")]
+        );
+        assert_eq!(
+            column("SELECT COALESCE(name, 'none') FROM $$source WHERE id > 2"),
+            vec![Value::text("x"), Value::text("none")]
+        );
+        assert_eq!(
+            column("SELECT ROUND(price / 2, 1) FROM $$source WHERE price > 5"),
+            vec![Value::float(2.6), Value::float(3.5)]
+        );
+        assert_eq!(
+            column("SELECT id % 2 FROM $$source WHERE name LIKE 'x%'"),
+            vec![Value::int(1), Value::int(1)]
+        );
+        assert_eq!(
+            column(
+                "SELECT CASE WHEN price > 5 THEN 'high' WHEN price > 3 THEN 'mid' END FROM $$source"
+            ),
+            vec![
+                Value::text("mid"),
+                Value::text("high"),
+                Value::null(),
+                Value::text("high")
+            ]
+        );
+        assert_eq!(
+            column("SELECT CAST(price AS INT) + CAST('1' AS INTEGER) FROM $$source WHERE id = 1"),
+            vec![Value::int(4)]
+        );
+        assert_eq!(
+            column(
+                "SELECT EXTRACT(YEAR FROM CAST('2024-02-29' AS DATE)) FROM $$source WHERE id = 1"
+            ),
+            vec![Value::int(2024)]
+        );
+        assert!(crate::parse_sql("SELECT LOWER(name, id) FROM $$source").is_err());
+    }
+
+    #[test]
+    fn test_vm_execution_where_wildcard() {
+        let result = run_sql("SELECT * FROM $$source WHERE id = 2");
+        assert_eq!(
+            result,
+            vec![Value::array([
+                Value::int(2),
+                Value::text("y"),
+                Value::float(5.2)
+            ])]
+        );
+    }
+
+    fn run_unnest(query: &str) -> Vec<Value> {
+        let mut algebra = crate::parse_sql(query).unwrap();
+        algebra.set_schema(Schema::fixed([
+            ("id".to_string(), ValType::Integer),
+            ("tags".to_string(), ValType::Array),
+        ]));
+        assert!(algebra.schema().is_ok());
+
+        let mut program = algebra.processing().unwrap();
+        program
+            .set_resource(
+                "$$source",
+                [
+                    Value::array([
+                        Value::int(1),
+                        Value::array([Value::text("a"), Value::text("b")]),
+                    ]),
+                    Value::array([Value::int(2), Value::array(Vec::<Value>::new())]),
+                    Value::array([Value::int(3), Value::null()]),
+                    Value::array([Value::int(4), Value::array([Value::text("b")])]),
+                ]
+                .into_iter(),
+            )
+            .unwrap();
+        program.collect()
+    }
+
+    #[test]
+    fn test_vm_execution_unnest() {
+        let expected = vec![
+            Value::array([Value::int(1), Value::text("a")]),
+            Value::array([Value::int(1), Value::text("b")]),
+            Value::array([Value::int(4), Value::text("b")]),
+        ];
+        assert_eq!(
+            run_unnest("SELECT id, tag FROM $$source CROSS JOIN UNNEST(tags) AS t(tag)"),
+            expected
+        );
+        assert_eq!(
+            run_unnest("SELECT id, tag FROM $$source, UNNEST(tags) AS tag"),
+            expected
+        );
+
+        // the filter continues with the next element of the same record
+        assert_eq!(
+            run_unnest("SELECT id FROM $$source, UNNEST(tags) AS tag WHERE tag = 'b'"),
+            vec![Value::array([Value::int(1)]), Value::array([Value::int(4)])]
+        );
+        assert_eq!(
+            run_unnest("SELECT * FROM $$source, UNNEST(tags) AS tag WHERE id = 1").len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_vm_execution_unnest_nested() {
+        let mut algebra = crate::parse_sql(
+            "SELECT id, item FROM $$source, UNNEST(tags) AS tag, UNNEST(tag) AS item",
+        )
+        .unwrap();
+        algebra.set_schema(Schema::fixed([
+            ("id".to_string(), ValType::Integer),
+            ("tags".to_string(), ValType::Array),
+        ]));
+
+        let mut program = algebra.processing().unwrap();
+        program
+            .set_resource(
+                "$$source",
+                [
+                    Value::array([
+                        Value::int(1),
+                        Value::array([
+                            Value::array([Value::int(1), Value::int(2)]),
+                            Value::array(Vec::<Value>::new()),
+                            Value::array([Value::int(3)]),
+                        ]),
+                    ]),
+                    Value::array([Value::int(2), Value::array([Value::array([Value::int(4)])])]),
+                ]
+                .into_iter(),
+            )
+            .unwrap();
+
+        let items = program
+            .map(|v| v.as_array().unwrap().values.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            items,
+            vec![
+                vec![Value::int(1), Value::int(1)],
+                vec![Value::int(1), Value::int(2)],
+                vec![Value::int(1), Value::int(3)],
+                vec![Value::int(2), Value::int(4)],
+            ]
+        );
+    }
+
     #[test]
     fn test_vm_execution_array() {
         // Simulate: array[0] + array[1]
@@ -526,7 +1265,7 @@ mod test {
             ],
         };
 
-        let mut program = Program::from(&expr);
+        let mut program = Program::try_from(&expr).unwrap();
         program.set_record("array", Value::text("text")).unwrap();
 
         let mut program = program.map(|v| v.as_array().unwrap().values[0].clone());
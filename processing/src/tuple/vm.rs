@@ -1,13 +1,18 @@
 use crate::ExplodeState;
+use crate::tuple::program::RecordError;
+use crate::udf::Udf;
 use value::Value;
 
 pub struct VM {
     pub(crate) stack: Vec<Value>,
     pub(crate) current_record: Vec<Value>,
     pub(crate) constants: Vec<Value>, // The "Pool" for literals
-    pub pc: usize,                    // Program Counter
+    pub(crate) functions: Vec<Udf>,
+    pub pc: usize, // Program Counter
     pub(crate) explode_stack: Vec<ExplodeState>,
     pub resources: Vec<Box<dyn Iterator<Item = Value> + Send + Sync>>,
+    pub(crate) read: usize, // how many records the outermost loop took from its resource
+    pub(crate) errors: Vec<RecordError>,
 }
 
 impl Clone for VM {
@@ -16,9 +21,27 @@ impl Clone for VM {
             stack: self.stack.clone(),
             current_record: self.current_record.clone(),
             constants: self.constants.clone(),
+            functions: self.functions.clone(),
             pc: self.pc,
             explode_stack: self.explode_stack.clone(),
             resources: vec![],
+            read: self.read,
+            errors: self.errors.clone(),
+        }
+    }
+}
+
+impl VM {
+    pub(crate) fn pop(&mut self) -> Result<Value, String> {
+        self.stack
+            .pop()
+            .ok_or_else(|| format!("Stack underflow at {}", self.pc))
+    }
+
+    pub(crate) fn pop_many(&mut self, amount: usize) -> Result<Vec<Value>, String> {
+        match self.stack.len().checked_sub(amount) {
+            Some(at) => Ok(self.stack.split_off(at)),
+            None => Err(format!("Stack underflow at {}", self.pc)),
         }
     }
 }
@@ -0,0 +1,328 @@
+use crate::function::Function;
+use crate::operator::Operator;
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, RwLock};
+use value::{ValType, Value};
+
+/// The Rust closure behind a user-defined function.
+pub type UdfFn = Arc<dyn Fn(&[Value]) -> Value + Send + Sync>;
+
+/// A function registered from Rust, queries call it by its name.
+///
+/// Unlike the built-in functions it also gets null arguments, so the closure decides what a null
+/// becomes.
+///
+/// Plans only store the name and signature, a loaded one is bound to the closures of a registry.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Udf {
+    pub id: usize,
+    pub name: String,
+    /// the types of the arguments, `ValType::Any` accepts all
+    pub args: Vec<ValType>,
+    pub output: ValType,
+    #[serde(skip, default = "unbound")]
+    function: UdfFn,
+}
+
+fn unbound() -> UdfFn {
+    Arc::new(|_| panic!("function of a loaded plan is not bound"))
+}
+
+impl Udf {
+    pub(crate) fn call(&self, args: &[Value]) -> Value {
+        (self.function)(args)
+    }
+
+    /// The declared result if the arguments fit the signature, integers are accepted as floats.
+    pub(crate) fn output_type(&self, args: &[ValType]) -> Option<ValType> {
+        let fits = args.len() == self.args.len()
+            && args.iter().zip(&self.args).all(|(arg, declared)| {
+                arg == declared
+                    || matches!(arg, ValType::Any | ValType::Null)
+                    || matches!(declared, ValType::Any)
+                    || matches!((arg, declared), (ValType::Integer, ValType::Float))
+            });
+        fits.then(|| self.output.clone())
+    }
+
+    /// Checks the number of arguments against the signature.
+    pub(crate) fn check(&self, args: usize) -> Result<(), String> {
+        if args != self.args.len() {
+            return Err(format!(
+                "{} does not take {} argument{}",
+                self.name,
+                args,
+                if args == 1 { "" } else { "s" }
+            ));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn sql(&self, args: Vec<String>) -> String {
+        format!("{}({})", self.name, args.join(", "))
+    }
+}
+
+impl PartialEq for Udf {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && Arc::ptr_eq(&self.function, &other.function)
+    }
+}
+
+impl Debug for Udf {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Udf")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("args", &self.args)
+            .field("output", &self.output)
+            .finish()
+    }
+}
+
+/// The user-defined functions queries may call, clones share their registrations.
+///
+/// Functions are registered before the queries which call them are parsed, a parsed query
+/// keeps the functions it calls.
+#[derive(Clone, Default)]
+pub struct FunctionRegistry {
+    functions: Arc<RwLock<Vec<Udf>>>,
+}
+
+impl FunctionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the closure under the name, which may neither be taken by a built-in function
+    /// nor by another registered one. Names are case-insensitive like the built-in ones.
+    pub fn register<S, F>(
+        &self,
+        name: S,
+        args: Vec<ValType>,
+        output: ValType,
+        function: F,
+    ) -> anyhow::Result<()>
+    where
+        S: AsRef<str>,
+        F: Fn(&[Value]) -> Value + Send + Sync + 'static,
+    {
+        let name = name.as_ref();
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            bail!("Invalid function name {:?}", name)
+        }
+        if Function::lookup(name).is_some() || Operator::aggregate(name).is_some() {
+            bail!("{} is a built-in function", name)
+        }
+
+        let mut functions = self.functions.write().unwrap();
+        if functions.iter().any(|f| f.name.eq_ignore_ascii_case(name)) {
+            bail!("Function {} is already registered", name)
+        }
+        let id = functions.len();
+        functions.push(Udf {
+            id,
+            name: name.to_string(),
+            args,
+            output,
+            function: Arc::new(function),
+        });
+        Ok(())
+    }
+
+    /// The function registered under the name, regardless of its case.
+    pub(crate) fn lookup(&self, name: &str) -> Option<Udf> {
+        self.functions
+            .read()
+            .unwrap()
+            .iter()
+            .find(|f| f.name.eq_ignore_ascii_case(name))
+            .cloned()
+    }
+
+    /// The registered function a loaded one stands for, it needs the same name and signature.
+    pub(crate) fn bind(&self, udf: &Udf) -> anyhow::Result<Udf> {
+        let Some(registered) = self.lookup(&udf.name) else {
+            bail!("Function {} is not registered", udf.name)
+        };
+        if registered.args != udf.args || registered.output != udf.output {
+            bail!("Function {} is registered with another signature", udf.name)
+        }
+        Ok(registered)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.functions
+            .read()
+            .unwrap()
+            .iter()
+            .map(|f| f.name.clone())
+            .collect()
+    }
+}
+
+impl Debug for FunctionRegistry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::udf::FunctionRegistry;
+    use crate::{Schema, parse_cypher_with, parse_mql_with, parse_sql, parse_sql_with};
+    use value::{ValType, Value};
+
+    /// Kilometres from miles and a greeting which handles nulls itself.
+    fn registry() -> FunctionRegistry {
+        let functions = FunctionRegistry::new();
+        functions
+            .register(
+                "km",
+                vec![ValType::Float],
+                ValType::Float,
+                |args| match args[0].as_float() {
+                    Ok(miles) => Value::float(miles.0.0 * 1.609344),
+                    Err(_) => Value::null(),
+                },
+            )
+            .unwrap();
+        functions
+            .register(
+                "greet",
+                vec![ValType::Text],
+                ValType::Text,
+                |args| match &args[0] {
+                    Value::Text(name) => Value::text(format!("hi {}", name.0)),
+                    _ => Value::text("hi stranger"),
+                },
+            )
+            .unwrap();
+        functions
+    }
+
+    fn run(mut algebra: crate::Algebra, schema: Schema, records: Vec<Value>) -> Vec<Value> {
+        algebra.set_schema(schema);
+        let mut program = algebra.processing().unwrap();
+        program
+            .set_resource("$$source", records.into_iter())
+            .unwrap();
+        program.collect()
+    }
+
+    #[test]
+    fn register() {
+        let functions = registry();
+        assert!(
+            functions
+                .register("KM", vec![], ValType::Float, |_| Value::null())
+                .is_err()
+        );
+        assert!(
+            functions
+                .register("lower", vec![], ValType::Text, |_| Value::null())
+                .is_err()
+        );
+        assert_eq!(functions.names(), vec!["km", "greet"]);
+
+        // clones see later registrations
+        let clone = functions.clone();
+        functions
+            .register("twice", vec![ValType::Integer], ValType::Integer, |args| {
+                &args[0] * &args[0]
+            })
+            .unwrap();
+        assert!(clone.lookup("TWICE").is_some());
+    }
+
+    #[test]
+    fn call() {
+        let functions = registry();
+        let schema = Schema::fixed([
+            ("name".to_string(), ValType::Text),
+            ("miles".to_string(), ValType::Float),
+        ]);
+        let records = vec![
+            Value::array([Value::text("a"), Value::float(10.0)]),
+            Value::array([Value::null(), Value::float(0.5)]),
+        ];
+
+        let sql = parse_sql_with(
+            "SELECT GREET(name) AS hi, km(miles) AS km FROM $$source WHERE km(miles) > 1",
+            &functions,
+        )
+        .unwrap();
+        assert_eq!(
+            run(sql, schema.clone(), records.clone()),
+            vec![Value::array([Value::text("hi a"), Value::float(16.09344)])]
+        );
+
+        let mql = parse_mql_with(
+            "db.$$source.aggregate([{$project: {hi: {$greet: \"$name\"}}}])",
+            &functions,
+        )
+        .unwrap();
+        assert_eq!(
+            run(mql, schema, records),
+            vec![
+                Value::array([Value::text("hi a")]),
+                Value::array([Value::text("hi stranger")]),
+            ]
+        );
+
+        let cypher = parse_cypher_with("MATCH (n) RETURN greet(n.name) AS hi", &functions).unwrap();
+        assert_eq!(
+            cypher.schema().unwrap().field_type("hi").unwrap(),
+            ValType::Text
+        );
+    }
+
+    #[test]
+    fn panicking() {
+        let functions = registry();
+        functions
+            .register(
+                "root",
+                vec![ValType::Integer],
+                ValType::Integer,
+                |args| match args[0].as_int() {
+                    Ok(i) if i.0 >= 0 => Value::int(i.0.isqrt()),
+                    _ => panic!("no root of {:?}", args[0]),
+                },
+            )
+            .unwrap();
+        let mut algebra = parse_sql_with("SELECT root(n) FROM $$source", &functions).unwrap();
+        algebra.set_schema(Schema::fixed([("n".to_string(), ValType::Integer)]));
+        let mut program = algebra.processing().unwrap();
+        program
+            .set_resource(
+                "$$source",
+                [9, -1, 16]
+                    .map(|n| Value::array([Value::int(n)]))
+                    .into_iter(),
+            )
+            .unwrap();
+
+        // only the record the function panicked for fails
+        assert_eq!(
+            program.by_ref().collect::<Vec<_>>(),
+            vec![Value::array([Value::int(3)]), Value::array([Value::int(4)])]
+        );
+        let errors = program.take_errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].record, 1);
+    }
+
+    #[test]
+    fn unknown_and_mismatched() {
+        let functions = registry();
+        assert!(parse_sql("SELECT km(miles) FROM $$source").is_err());
+        assert!(parse_sql_with("SELECT km(miles, 2) FROM $$source", &functions).is_err());
+
+        let mut algebra = parse_sql_with("SELECT km(name) FROM $$source", &functions).unwrap();
+        algebra.set_schema(Schema::fixed([("name".to_string(), ValType::Text)]));
+        assert!(algebra.schema().is_err());
+    }
+}
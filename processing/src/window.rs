@@ -0,0 +1,249 @@
+use anyhow::{anyhow, bail};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use value::timeunit::TimeUnit;
+
+/// Groups records by their timestamp (in ms) for Multi-scope definitions.
+///
+/// Written like `TUMBLING (SIZE 1 MINUTE)`, `HOPPING (SIZE 30 SECONDS, ADVANCE BY 10 SECONDS)`
+/// or `SLIDING (SIZE 30 SECONDS)`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Window {
+    Tumbling {
+        size: i64,
+    },
+    Hopping {
+        size: i64,
+        advance: i64,
+    },
+    /// one window per record, reaching `size` back from its timestamp
+    Sliding {
+        size: i64,
+    },
+}
+
+/// Start (inclusive) and end (exclusive) of a window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bounds {
+    pub start: i64,
+    pub end: i64,
+}
+
+impl Bounds {
+    pub fn contains(&self, timestamp: i64) -> bool {
+        self.start <= timestamp && timestamp < self.end
+    }
+}
+
+impl Window {
+    pub fn size(&self) -> i64 {
+        match self {
+            Window::Tumbling { size } | Window::Hopping { size, .. } | Window::Sliding { size } => {
+                *size
+            }
+        }
+    }
+
+    /// All windows the timestamp falls into, ordered by their start.
+    pub fn assign(&self, timestamp: i64) -> Vec<Bounds> {
+        match self {
+            Window::Tumbling { size } => {
+                let start = timestamp - timestamp.rem_euclid(*size);
+                vec![Bounds {
+                    start,
+                    end: start + size,
+                }]
+            }
+            Window::Hopping { size, advance } => {
+                let mut start = timestamp - timestamp.rem_euclid(*advance);
+                let mut bounds = vec![];
+                while start > timestamp - size {
+                    bounds.push(Bounds {
+                        start,
+                        end: start + size,
+                    });
+                    start -= advance;
+                }
+                bounds.reverse();
+                bounds
+            }
+            Window::Sliding { size } => vec![Bounds {
+                start: timestamp - size + 1,
+                end: timestamp + 1,
+            }],
+        }
+    }
+
+    /// End of the last window the timestamp falls into.
+    pub fn last_end(&self, timestamp: i64) -> i64 {
+        match self {
+            Window::Tumbling { size } => timestamp - timestamp.rem_euclid(*size) + size,
+            Window::Hopping { size, advance } => timestamp - timestamp.rem_euclid(*advance) + size,
+            Window::Sliding { size } => timestamp + size,
+        }
+    }
+
+    /// Whether two timestamps fall into a common window, for sliding windows
+    /// this means they are less than its size apart.
+    pub fn shares(&self, a: i64, b: i64) -> bool {
+        a.max(b) < self.last_end(a.min(b))
+    }
+}
+
+impl FromStr for Window {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s.to_uppercase().replace(['(', ')', ','], " ");
+        let mut tokens = normalized.split_whitespace().peekable();
+
+        let kind = tokens.next().ok_or(anyhow!("Empty window definition"))?;
+
+        let mut size = None;
+        let mut advance = None;
+        while let Some(token) = tokens.next() {
+            match token {
+                "SIZE" => size = Some(parse_duration(&mut tokens)?),
+                "ADVANCE" => {
+                    if tokens.peek() == Some(&"BY") {
+                        tokens.next();
+                    }
+                    advance = Some(parse_duration(&mut tokens)?)
+                }
+                t => bail!("Unexpected token {} in window {}", t, s),
+            }
+        }
+        let size = size.ok_or(anyhow!("Window {} has no SIZE", s))?;
+        if size == 0 || advance == Some(0) {
+            bail!("Window durations must be positive");
+        }
+
+        match (kind, advance) {
+            ("TUMBLING", None) => Ok(Window::Tumbling { size }),
+            ("HOPPING", Some(advance)) => Ok(Window::Hopping { size, advance }),
+            ("HOPPING", None) => bail!("Hopping window {} needs ADVANCE BY", s),
+            ("SLIDING", None) => Ok(Window::Sliding { size }),
+            (kind, _) => bail!("Unsupported window {}", kind),
+        }
+    }
+}
+
+/// A duration like `30 SECONDS` in ms.
+pub(crate) fn parse_duration<'a>(
+    tokens: &mut impl Iterator<Item = &'a str>,
+) -> anyhow::Result<i64> {
+    let amount = tokens
+        .next()
+        .ok_or(anyhow!("Missing duration"))?
+        .parse::<i64>()?;
+    let unit = match tokens.next() {
+        Some("MS" | "MILLISECOND" | "MILLISECONDS") => TimeUnit::Millis,
+        Some("SECOND" | "SECONDS") => TimeUnit::Seconds,
+        Some("MINUTE" | "MINUTES") => TimeUnit::Minutes,
+        Some("HOUR" | "HOURS") => TimeUnit::Hours,
+        Some("DAY" | "DAYS") => TimeUnit::Days,
+        unit => bail!("Unknown time unit {:?}", unit),
+    };
+    if amount < 0 {
+        bail!("Durations must not be negative");
+    }
+    Ok(amount * unit.as_ms())
+}
+
+impl TryFrom<String> for Window {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Window> for String {
+    fn from(value: Window) -> Self {
+        value.to_string()
+    }
+}
+
+impl Display for Window {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Window::Tumbling { size } => write!(f, "TUMBLING (SIZE {} MILLISECONDS)", size),
+            Window::Hopping { size, advance } => write!(
+                f,
+                "HOPPING (SIZE {} MILLISECONDS, ADVANCE BY {} MILLISECONDS)",
+                size, advance
+            ),
+            Window::Sliding { size } => write!(f, "SLIDING (SIZE {} MILLISECONDS)", size),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let window: Window = "HOPPING (SIZE 30 SECONDS, ADVANCE BY 10 SECONDS)"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            window,
+            Window::Hopping {
+                size: 30_000,
+                advance: 10_000
+            }
+        );
+
+        let window: Window = "tumbling (size 1 minute)".parse().unwrap();
+        assert_eq!(window, Window::Tumbling { size: 60_000 });
+
+        assert_eq!(window.to_string().parse::<Window>().unwrap(), window);
+        assert!("HOPPING (SIZE 30 SECONDS)".parse::<Window>().is_err());
+        assert!("TUMBLING (SIZE 30 WEEKS)".parse::<Window>().is_err());
+    }
+
+    #[test]
+    fn assign() {
+        let tumbling = Window::Tumbling { size: 10 };
+        assert_eq!(tumbling.assign(15), vec![Bounds { start: 10, end: 20 }]);
+        assert_eq!(tumbling.assign(-5), vec![Bounds { start: -10, end: 0 }]);
+
+        let hopping = Window::Hopping {
+            size: 30,
+            advance: 10,
+        };
+        assert_eq!(
+            hopping.assign(25),
+            vec![
+                Bounds { start: 0, end: 30 },
+                Bounds { start: 10, end: 40 },
+                Bounds { start: 20, end: 50 },
+            ]
+        );
+
+        let sliding = Window::Sliding { size: 10 };
+        assert_eq!(sliding.assign(25), vec![Bounds { start: 16, end: 26 }]);
+    }
+
+    #[test]
+    fn shares() {
+        let tumbling = Window::Tumbling { size: 10 };
+        assert!(tumbling.shares(11, 19));
+        assert!(!tumbling.shares(9, 11));
+
+        let hopping = Window::Hopping {
+            size: 20,
+            advance: 10,
+        };
+        assert!(hopping.shares(9, 19));
+        assert!(hopping.shares(19, 9));
+        assert!(!hopping.shares(9, 20));
+
+        let sliding = Window::Sliding { size: 10 };
+        assert!(sliding.shares(5, 14));
+        assert!(!sliding.shares(5, 15));
+    }
+}
@@ -65,6 +65,7 @@ impl KafkaSink {
                                     topics: SmallVec::from_vec(
                                         record.topics.into_iter().map(|t| t.into()).collect(),
                                     ),
+                                    event_time: record.timestamp,
                                 },
                             )) {
                                 Ok(_) => {}
@@ -92,6 +93,9 @@ impl KafkaSink {
 pub struct SinkRecord {
     topics: Vec<String>,
     value: String,
+    /// when the record happened in ms
+    #[serde(default)]
+    timestamp: Option<i64>,
 }
 
 impl From<SinkRecord> for Value {
@@ -140,6 +144,7 @@ impl Kafka {
         self.send(SinkRecord {
             topics: vec!["doc".to_string()],
             value: "Success2".to_string(),
+            timestamp: None,
         })
         .await?;
         Ok(())
@@ -149,6 +154,7 @@ impl Kafka {
         self.send(SinkRecord {
             topics: vec!["graph".to_string()],
             value: "Success2".to_string(),
+            timestamp: None,
         })
         .await?;
         Ok(())
@@ -158,6 +164,7 @@ impl Kafka {
         self.send(SinkRecord {
             topics: vec!["relational".to_string()],
             value: "Success2".to_string(),
+            timestamp: None,
         })
         .await?;
         Ok(())
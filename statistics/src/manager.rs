@@ -24,7 +24,8 @@ use util::{
 pub struct Statistics {
     engines: HashMap<EngineId, EngineStatistic>,
     engine_names: HashMap<EngineId, String>,
-    definitions: HashMap<DefinitionId, Definition>,
+    /// shared with the web server, which explains them
+    definitions: Arc<Mutex<HashMap<DefinitionId, Definition>>>,
     ids: IndexMap<u64, Instant>,
     delay: Delay,
 }
@@ -129,7 +130,10 @@ impl Statistics {
                     .unwrap_or(Duration::from_millis(0));
             }
             Event::Definition(definition_id, definition) => {
-                self.definitions.insert(definition_id, *definition);
+                self.definitions
+                    .lock()
+                    .unwrap()
+                    .insert(definition_id, *definition);
             }
             Event::Engine(engine_id, EngineEvent::Name(name)) => {
                 self.engine_names.insert(engine_id, name);
@@ -143,6 +147,8 @@ impl Statistics {
         let names = &self.engine_names;
         let definition_names = self
             .definitions
+            .lock()
+            .unwrap()
             .iter()
             .map(|(id, d)| (id.0, d.topic.clone()))
             .collect::<HashMap<_, _>>();
@@ -201,12 +207,12 @@ pub fn start(
             .unwrap();
 
         rt.block_on(async move {
+            let mut statistics = Statistics::new();
+            let definitions = statistics.definitions.clone();
 
             let stats_handle = tokio::spawn(async move {
                 log_channel(tx_clone.clone(), "Events", None).await;
 
-                let mut statistics = Statistics::new();
-
                 let mut timer = interval(Duration::from_secs(20));
                 timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
@@ -276,7 +282,7 @@ pub fn start(
                 }
                 error!("stopped here")
             });
-            web::start(bc_tx.clone(), output, last_shared_statistic.clone(), last_shared_tp.clone());
+            web::start(bc_tx.clone(), output, last_shared_statistic.clone(), last_shared_tp.clone(), definitions);
             tpc::start(bc_tx, last_shared_statistic, last_shared_tp);
 
             let statistic_tx = tx.clone();
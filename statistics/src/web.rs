@@ -2,19 +2,22 @@ use axum::Router;
 use axum::extract::ws::Message::Binary;
 use axum::extract::ws::{Message, WebSocket};
 use axum::extract::{Path, State, WebSocketUpgrade};
+use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::routing::get;
 use axum_embed::{FallbackBehavior, ServeEmbed};
 use rayon::iter::IntoParallelIterator;
 use rayon::iter::ParallelIterator;
 use rust_embed::RustEmbed;
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use tokio::net::TcpListener;
 use tokio::sync::broadcast;
 use tokio::sync::broadcast::Sender;
 use tower_http::cors::CorsLayer;
 use tracing::{error, info, warn};
-use util::{Batch, Event, StatisticEvent, TargetedRecord, ThroughputEvent};
+use util::definition::Definition;
+use util::{Batch, DefinitionId, Event, StatisticEvent, TargetedRecord, ThroughputEvent};
 use value::Text;
 
 #[derive(RustEmbed)]
@@ -28,12 +31,14 @@ struct EventState {
     output: Sender<Batch<TargetedRecord>>,
     last_statistic: Arc<Mutex<StatisticEvent>>,
     last_tp: Arc<Mutex<ThroughputEvent>>,
+    definitions: Arc<Mutex<HashMap<DefinitionId, Definition>>>,
 }
 pub fn start(
     tx: Sender<Event>,
     output: Sender<Batch<TargetedRecord>>,
     last_statistic: Arc<Mutex<StatisticEvent>>,
     last_tp: Arc<Mutex<ThroughputEvent>>,
+    definitions: Arc<Mutex<HashMap<DefinitionId, Definition>>>,
 ) {
     tokio::spawn(async move {
         let shared_state = EventState {
@@ -41,6 +46,7 @@ pub fn start(
             output,
             last_statistic,
             last_tp,
+            definitions,
         };
 
         let serve_assets = ServeEmbed::<Assets>::with_parameters(
@@ -55,6 +61,7 @@ pub fn start(
             .route("/statistics", get(ws_handler))
             .route("/channel/{topic}", get(ws_channel_handler))
             .route("/threads", get(ws_handler))
+            .route("/definitions/{id}/explain", get(explain_handler))
             .layer(CorsLayer::permissive())
             .with_state(shared_state)
             .fallback_service(serve_assets);
@@ -153,6 +160,16 @@ async fn handle_socket_logic(mut socket: WebSocket, state: EventState, path: Str
     }
 }
 
+async fn explain_handler(
+    Path(id): Path<u64>,
+    State(state): State<EventState>,
+) -> impl IntoResponse {
+    match state.definitions.lock().unwrap().get(&DefinitionId(id)) {
+        Some(definition) => (StatusCode::OK, definition.explain()),
+        None => (StatusCode::NOT_FOUND, format!("Unknown definition {}", id)),
+    }
+}
+
 async fn ws_channel_handler(
     Path(id): Path<String>, // Extracts the ":id" from the URL
     ws: WebSocketUpgrade,
@@ -1,4 +1,6 @@
+use crate::{TargetedMeta, TargetedRecord, target};
 use chrono::Utc;
+use processing::{RecordBatch, Schema};
 use smallvec::alloc;
 use speedy::{Readable, Writable};
 use std::ops::{Deref, DerefMut};
@@ -55,6 +57,22 @@ impl<T> Batch<T> {
     }
 }
 
+impl Batch<TargetedRecord> {
+    /// The values of the records column by column, fails if one does not fit the schema.
+    pub fn columnar(&self, schema: &Schema) -> anyhow::Result<RecordBatch> {
+        RecordBatch::from_rows(self.records.iter().map(|r| &r.value), schema)
+    }
+
+    /// One record per row of the columns, all with the same meta.
+    pub fn from_columnar(batch: &RecordBatch, meta: &TargetedMeta) -> Self {
+        batch
+            .rows()
+            .into_iter()
+            .map(|row| target!(row, meta.clone()))
+            .collect()
+    }
+}
+
 impl<T> Default for Batch<T> {
     fn default() -> Self {
         Batch::new(vec![])
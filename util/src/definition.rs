@@ -3,15 +3,26 @@ use crate::definition::DefinitionFilter::AllMatch;
 use crate::mappings::NativeMapping;
 use crate::partition::PartitionInfo;
 use crate::query::Query;
-use crate::{DefinitionId, EntityId, PartitionId, TargetedRecord, TimedMeta, log_channel};
+use crate::{
+    DefinitionId, EntityId, PartitionId, TargetedMeta, TargetedRecord, TimedMeta, log_channel,
+};
+use anyhow::{anyhow, bail};
 use flume::{Receiver, Sender, unbounded};
-use processing::{Algebra, Program};
+use processing::{
+    Algebra, Backend, Bounded, ColumnarProgram, EventTime, FunctionRegistry, Joiner, Schema, Scope,
+    StateLimit, Watermarks, Window,
+};
 use serde::{Deserialize, Serialize};
 use speedy::{Readable, Writable};
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::warn;
 use value::Value::Dict;
 use value::{Text, Value};
 
+/// Stored native records of a definition with the partition they went to.
+pub type Published = (PartitionId, Batch<TargetedRecord>);
+
 static ID_BUILDER: AtomicU64 = AtomicU64::new(0);
 
 /// Defines into which final entity an incoming value(primitive to complex) is stored
@@ -34,11 +45,34 @@ pub struct Definition {
         Sender<Batch<TargetedRecord>>,
         Receiver<Batch<TargetedRecord>>,
     ),
+    /// the stored native records for Multi and Join scope, not only their ids: windows and
+    /// watermarks need the timestamps of the metas, which the engines do not store
     #[serde(skip)]
-    pub process_full: (Sender<Vec<u64>>, Receiver<Vec<u64>>),
+    pub process_full: (
+        Sender<Batch<TargetedRecord>>,
+        Receiver<Batch<TargetedRecord>>,
+    ),
     pub mapping: NativeMapping,
     pub processing: Query,
     pub algebra: Algebra,
+    /// the user-defined functions the query may call, shared by all clones
+    #[serde(skip)]
+    pub functions: FunctionRegistry,
+    /// how Multi-scope processing groups records over time
+    pub window: Option<Window>,
+    /// event time of the records, ingestion time without
+    pub time: Option<EventTime>,
+    /// how many records `ORDER BY`, `LIMIT` and `DISTINCT` keep
+    pub state: StateLimit,
+    /// shared by all clones, so each record moves the same watermark
+    #[serde(skip)]
+    watermarks: Watermarks,
+    /// get the stored native records, e.g. to join them into other definitions
+    #[serde(skip)]
+    subscribers: Arc<Mutex<Vec<Sender<Published>>>>,
+    /// get the records which came too late for their windows
+    #[serde(skip)]
+    late: Arc<Mutex<Vec<Sender<Batch<TargetedRecord>>>>>,
     pub partition_info: PartitionInfo,
 }
 
@@ -58,36 +92,279 @@ impl Definition {
         }
     }
 
-    pub async fn new<S: AsRef<str>>(
+    /// Starts a definition of the topic, the other parts have defaults which the builder
+    /// overrides.
+    pub fn builder<S: AsRef<str>>(
         topic: S,
-        filter: DefinitionFilter,
         mapping: NativeMapping,
         processing: Query,
         model: Model,
-        entity: String,
-    ) -> Self {
+    ) -> DefinitionBuilder {
+        DefinitionBuilder {
+            topic: topic.as_ref().to_string(),
+            filter: DefinitionFilter::AllMatch,
+            mapping,
+            processing,
+            model,
+            entity: topic.as_ref().to_string(),
+            window: None,
+            time: None,
+            state: StateLimit::default(),
+            functions: FunctionRegistry::default(),
+        }
+    }
+
+    /// The schema of the processed records, fails on type errors in the query.
+    pub fn schema(&self) -> anyhow::Result<Schema> {
+        self.algebra.schema()
+    }
+
+    /// The schema of the records before processing, as the mapping stores them.
+    pub fn source_schema(&self) -> Schema {
+        self.mapping.schema()
+    }
+
+    /// The plans and instructions the query of this definition becomes.
+    pub fn explain(&self) -> String {
+        let mut algebra = self.algebra.clone();
+        algebra.set_schema(self.mapping.schema());
+        algebra.explain()
+    }
+
+    /// Compiles the query natively if the mapped schema allows it, for the tuple VM otherwise.
+    pub fn processing(&mut self) -> anyhow::Result<Backend> {
+        self.algebra.set_schema(self.mapping.schema());
+
+        Backend::new(&self.algebra)
+    }
+
+    /// Compiles the query for column-at-a-time processing next to the compiled backend.
+    ///
+    /// Tuple-scope queries are processed by the first which supports them: the JIT, the columnar
+    /// VM for relational definitions with projections and filters over flat fields, the tuple VM.
+    pub fn columnar(&mut self, backend: &Backend) -> Option<ColumnarProgram> {
+        if self.model != Model::Relational || matches!(backend, Backend::Jit(_)) {
+            return None;
+        }
+        self.algebra.set_schema(self.mapping.schema());
+
+        ColumnarProgram::compile(&self.algebra.optimized())
+    }
+
+    /// Evaluates the Multi-scope query per window, with the state it keeps bounded.
+    pub fn bounded(&mut self) -> anyhow::Result<Bounded> {
+        self.algebra.set_schema(self.mapping.schema());
+
+        let mut bounded = Bounded::new(&self.algebra, self.window.clone(), self.state.clone())?;
+        if let Some(time) = &self.time {
+            bounded.set_lateness(time.late)?;
+        }
+        Ok(bounded)
+    }
+
+    /// The name of the definition read by the join of the query, if there is one.
+    pub fn joined_name(&self) -> Option<&str> {
+        self.algebra
+            .join()
+            .and_then(|j| j.right_source())
+            .map(|source| source.trim_start_matches("$$"))
+    }
+
+    /// Types the records read from the joined definition, fails on type errors in the query.
+    pub fn set_joined(&mut self, joined: &Definition) -> anyhow::Result<()> {
+        self.algebra.set_joined_schema(joined.mapping.schema());
+        self.algebra
+            .schema()
+            .and_then(|_| self.joiner(joined))
+            .map_err(|e| anyhow!("{} in {}", e, self.processing.text()))?;
+        Ok(())
+    }
+
+    /// Joins the records of this definition with the ones of the joined definition.
+    pub fn joiner(&self, joined: &Definition) -> anyhow::Result<Joiner> {
+        Joiner::new(
+            &self.algebra,
+            self.mapping.schema(),
+            joined.mapping.schema(),
+            self.window.clone(),
+        )
+    }
+
+    pub fn subscribe(&self) -> Receiver<Published> {
+        let (tx, rx) = unbounded();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Hands the stored native records to all subscribers.
+    pub fn publish(&self, partition_id: PartitionId, records: &Batch<TargetedRecord>) {
+        for subscriber in self.subscribers.lock().unwrap().iter() {
+            let _ = subscriber.send((partition_id, records.clone()));
+        }
+    }
+
+    /// The side output, which gets the late records with [`Lateness::Side`](processing::Lateness).
+    pub fn subscribe_late(&self) -> Receiver<Batch<TargetedRecord>> {
+        let (tx, rx) = unbounded();
+        self.late.lock().unwrap().push(tx);
+        rx
+    }
+
+    pub fn publish_late(&self, records: &Batch<TargetedRecord>) {
+        for subscriber in self.late.lock().unwrap().iter() {
+            let _ = subscriber.send(records.clone());
+        }
+    }
+
+    /// The meta of a record of this definition, with its event time and the watermark it moves.
+    ///
+    /// The event time is read from the field of the record, else the one its source carries is
+    /// used and the ingestion time if there is none.
+    pub fn target(&self, meta: TimedMeta, value: &Value) -> TargetedMeta {
+        let Some(time) = &self.time else {
+            return TargetedMeta::new(meta, self.id);
+        };
+        let event_time = match self.event_time(time, value) {
+            Ok(event_time) => event_time,
+            Err(err) => {
+                warn!(
+                    "Record {} of {} has no event time: {:#}",
+                    meta.id, self.topic, err
+                );
+                None
+            }
+        };
+        let event_time = event_time.or(meta.event_time).unwrap_or(meta.timestamp);
+
+        let mut meta = TargetedMeta::new(meta, self.id);
+        meta.event_time = event_time;
+        meta.watermark = self.watermarks.observe(event_time);
+        meta
+    }
+
+    /// The event time the field of the mapping holds, if there is a field.
+    fn event_time(&self, time: &EventTime, value: &Value) -> anyhow::Result<Option<i64>> {
+        match &time.field {
+            None => Ok(None),
+            Some(field) => EventTime::parse(&self.mapping.field(value, field)?),
+        }
+    }
+
+    /// does our event match the defined definition
+    pub fn matches(&self, value: &Value, meta: &TimedMeta) -> bool {
+        match &self.filter {
+            AllMatch => true,
+            DefinitionFilter::Topic(n) => meta.topics.contains(n),
+            DefinitionFilter::KeyName(k, v) => match value {
+                Dict(d) => d.get(k) == Some(&Value::from(v.clone())),
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Collects the parts of a [`Definition`], which the query is checked against when it is built.
+pub struct DefinitionBuilder {
+    topic: String,
+    filter: DefinitionFilter,
+    mapping: NativeMapping,
+    processing: Query,
+    model: Model,
+    entity: String,
+    window: Option<Window>,
+    time: Option<EventTime>,
+    state: StateLimit,
+    functions: FunctionRegistry,
+}
+
+impl DefinitionBuilder {
+    pub fn filter(mut self, filter: DefinitionFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// The final entity, the topic without.
+    pub fn entity<S: AsRef<str>>(mut self, entity: S) -> Self {
+        self.entity = entity.as_ref().to_string();
+        self
+    }
+
+    pub fn window(mut self, window: Option<Window>) -> Self {
+        self.window = window;
+        self
+    }
+
+    pub fn time(mut self, time: Option<EventTime>) -> Self {
+        self.time = time;
+        self
+    }
+
+    pub fn state(mut self, state: StateLimit) -> Self {
+        self.state = state;
+        self
+    }
+
+    pub fn functions(mut self, functions: FunctionRegistry) -> Self {
+        self.functions = functions;
+        self
+    }
+
+    pub async fn build(self) -> anyhow::Result<Definition> {
+        let DefinitionBuilder {
+            topic,
+            filter,
+            mapping,
+            processing,
+            model,
+            entity,
+            window,
+            time,
+            state,
+            functions,
+        } = self;
+
+        // reject invalid queries before anything is started for them
+        let mut algebra = processing.parse(&functions)?;
+        algebra.set_schema(mapping.schema());
+        algebra
+            .schema()
+            .map_err(|e| anyhow!("{} in {}", e, processing.text()))?;
+
+        // reject mappings which cannot produce values
+        let _ = mapping.build()?;
+
+        // tuples have no fields besides the mapped ones
+        if let (NativeMapping::Relational(r), Some(field)) =
+            (&mapping, time.as_ref().and_then(|t| t.field.as_ref()))
+        {
+            let name = field.split('.').next().unwrap_or_default();
+            if r.position(name).is_none() {
+                bail!("Event time field {} is not mapped", name)
+            }
+        }
+
         let id = DefinitionId(ID_BUILDER.fetch_add(1, Ordering::Relaxed));
 
         let (native_tx, native_rx) = unbounded::<Batch<TargetedRecord>>();
-        let (process_tx_full, process_rx_full) = unbounded::<Vec<u64>>();
+        let (process_tx_full, process_rx_full) = unbounded::<Batch<TargetedRecord>>();
         let (process_tx_single, process_rx_single) = unbounded::<Batch<TargetedRecord>>();
 
         log_channel(
             native_tx.clone(),
-            format!("Native-{}-{}", id.0, topic.as_ref()),
+            format!("Native-{}-{}", id.0, topic),
             None,
         )
         .await;
 
         log_channel(
             process_tx_single.clone(),
-            format!("Native-{}-{}", id.0, topic.as_ref()),
+            format!("Native-{}-{}", id.0, topic),
             None,
         )
         .await;
 
-        Definition {
-            topic: topic.as_ref().to_string(),
+        let mut definition = Definition {
+            topic,
             id,
             filter,
             model,
@@ -96,28 +373,24 @@ impl Definition {
             process_full: (process_tx_full, process_rx_full),
             process_single: (process_tx_single, process_rx_single),
             mapping,
-            processing: processing.clone(),
-            algebra: processing.into(),
+            processing,
+            algebra,
+            functions,
+            window,
+            watermarks: Watermarks::new(time.as_ref().map_or(0, |t| t.delay)),
+            time,
+            state,
+            subscribers: Arc::new(Mutex::new(vec![])),
+            late: Arc::new(Mutex::new(vec![])),
             partition_info: PartitionInfo::new(),
+        };
+        // the operators of a window are checked before a worker starts them
+        if definition.algebra.scope() == Scope::Multi {
+            definition
+                .bounded()
+                .map_err(|e| anyhow!("{} in {}", e, definition.processing.text()))?;
         }
-    }
-
-    pub fn processing(&mut self) -> Program {
-        self.algebra.set_schema(self.mapping.schema());
-
-        self.algebra.processing()
-    }
-
-    /// does our event match the defined definition
-    pub fn matches(&self, value: &Value, meta: &TimedMeta) -> bool {
-        match &self.filter {
-            AllMatch => true,
-            DefinitionFilter::Topic(n) => meta.topics.contains(n),
-            DefinitionFilter::KeyName(k, v) => match value {
-                Dict(d) => d.get(k) == Some(&Value::from(v.clone())),
-                _ => false,
-            },
-        }
+        Ok(definition)
     }
 }
 
@@ -145,6 +418,8 @@ pub enum Model {
     Relational,
     #[serde(alias = "graph", alias = "GRAPH")]
     Graph,
+    #[serde(alias = "keyvalue", alias = "KEYVALUE", alias = "KV", alias = "kv")]
+    KeyValue,
 }
 
 static ENTITY_ID_BUILDER: AtomicU64 = AtomicU64::new(0);
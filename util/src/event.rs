@@ -29,6 +29,16 @@ pub enum Event {
     Statistics(StatisticEvent),
     Throughput(ThroughputEvent),
     Heartbeat(String),
+    /// a record the processing of a definition failed on, the rest of its batch went on
+    Error(ErrorEvent),
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ErrorEvent {
+    pub id: DefinitionId,
+    /// the id of the failed record
+    pub record: u64,
+    pub message: String,
 }
 
 #[derive(Serialize, Clone, Debug)]
@@ -1,4 +1,5 @@
 use crate::RelationalType;
+use anyhow::{anyhow, bail};
 use indexmap::IndexMap;
 use processing::Schema;
 use serde::{Deserialize, Deserializer, Serialize};
@@ -24,6 +25,7 @@ pub enum NativeMapping {
     #[serde(alias = "relational")]
     Relational(RelationalMapping),
     // can produce kv-pair
+    #[serde(alias = "keyvalue", alias = "kv")]
     KeyValue(KeyValueMapping),
 }
 
@@ -40,6 +42,56 @@ impl NativeMapping {
             _ => Schema::Dynamic,
         }
     }
+
+    /// The value of a field of a record before it is mapped, nested fields are dotted.
+    ///
+    /// Tuples name their fields like the mapping, graphs have the fields of their properties and
+    /// key-value pairs the ones of their value. Missing fields are null.
+    pub fn field(&self, value: &Value, path: &str) -> anyhow::Result<Value> {
+        let mut keys = path.split('.');
+        let mut field = match self {
+            NativeMapping::Relational(r) => {
+                let Array(a) = value else {
+                    bail!("Expected a tuple, got {:?}", value.type_())
+                };
+                let name = keys.next().unwrap_or_default();
+                let i = r
+                    .position(name)
+                    .ok_or_else(|| anyhow!("Field {} is not mapped", name))?;
+                a.values.get(i).cloned().unwrap_or_default()
+            }
+            NativeMapping::Document(DocumentMapping::Document(m)) => {
+                let mut sources = [&m.initial].into_iter().chain(&m.manual).chain(&m.auto);
+                sources
+                    .find_map(|source| Self::handle_doc_mapping(source)(value))
+                    .ok_or_else(|| anyhow!("Expected a document, got {:?}", value.type_()))?
+            }
+            NativeMapping::KeyValue(kv) => Self::handle_doc_mapping(&kv.value)(value)
+                .ok_or_else(|| anyhow!("Expected a pair, got {:?}", value.type_()))?,
+            NativeMapping::Graph(g) => {
+                let properties = match &g.initial {
+                    GraphMapping::Node(n) => &n.properties,
+                    GraphMapping::Edge(e) => &e.properties,
+                    GraphMapping::SubGraph(_) => bail!("Subgraphs have no fields"),
+                };
+                Self::handle_doc_mapping(properties)(value)
+                    .ok_or_else(|| anyhow!("Expected properties, got {:?}", value.type_()))?
+            }
+        };
+        for key in keys {
+            field = match field {
+                Value::Dict(dict) => dict.get(key).cloned().unwrap_or_default(),
+                Value::Null => Value::null(),
+                field => bail!(
+                    "Expected a document at {} of {}, got {:?}",
+                    key,
+                    path,
+                    field.type_()
+                ),
+            };
+        }
+        Ok(field)
+    }
 }
 
 fn deserialize_document<'de, D>(deserializer: D) -> Result<DocumentMapping, D::Error>
@@ -139,11 +191,21 @@ impl RelationalMapping {
             RelationalMapping::Tuple(types, _) => types.clone(),
         }
     }
+
+    /// The position of the field in the tuples.
+    pub fn position(&self, name: &str) -> Option<usize> {
+        match self {
+            RelationalMapping::Tuple(types, _) => types.iter().position(|(n, _)| n == name),
+        }
+    }
 }
 
+/// Extracts the key and the value of a pair, the native record is the array `[key, value]`.
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub enum KeyValueMapping {
-    KV(Mapping<MappingSource>),
+pub struct KeyValueMapping {
+    pub key: MappingSource,
+    #[serde(default)]
+    pub value: MappingSource,
 }
 
 impl NativeMapping {
@@ -180,7 +242,14 @@ impl NativeMapping {
         })
     }
 
-    pub fn build(&self) -> Box<dyn Fn(Value) -> Value + 'static + Send + Sync> {
+    pub fn doc_to_key_value<S: AsRef<str>>(key: S) -> Self {
+        NativeMapping::KeyValue(KeyValueMapping {
+            key: MappingSource::Document(DocumentSource::Key(key.as_ref().to_string())),
+            value: MappingSource::Document(DocumentSource::Whole),
+        })
+    }
+
+    pub fn build(&self) -> anyhow::Result<Box<dyn Fn(Value) -> Value + 'static + Send + Sync>> {
         let mut funcs: Vec<ValueProducer> = vec![];
         match self {
             // we build a node, edge or subgraph
@@ -199,19 +268,17 @@ impl NativeMapping {
                 );
                 funcs.append(&mut m.auto.iter().map(|m| Self::handle_doc_mapping(m)).collect());
             }
-            NativeMapping::Relational(r) => funcs.push(Self::handle_rel_mapping(r)),
-            NativeMapping::KeyValue(_) => {
-                todo!()
-            }
+            NativeMapping::Relational(r) => funcs.push(Self::handle_rel_mapping(r)?),
+            NativeMapping::KeyValue(kv) => funcs.push(Self::handle_kv_mapping(kv)),
         }
-        Box::new(move |value: Value| {
+        Ok(Box::new(move |value: Value| {
             for map in &funcs {
                 if let Some(next) = map(&value) {
                     return next;
                 }
             }
             Value::null()
-        })
+        }))
     }
 
     fn handle_doc_mapping(m: &MappingSource) -> ValueProducer {
@@ -234,6 +301,16 @@ impl NativeMapping {
                     None
                 }),
             },
+            // we get a single entry of a List
+            MappingSource::Index(i) => {
+                let i = *i;
+                Box::new(move |v: &Value| {
+                    if let Array(a) = v {
+                        return Some(a.values.get(i).cloned().unwrap_or_default());
+                    }
+                    None
+                })
+            }
             // we get the data as List
             MappingSource::List { keys } => {
                 let keys = keys.clone();
@@ -309,18 +386,29 @@ impl NativeMapping {
         }
     }
 
-    fn handle_rel_mapping(mapping: &RelationalMapping) -> ValueProducer {
+    fn handle_kv_mapping(mapping: &KeyValueMapping) -> ValueProducer {
+        let key = Self::handle_doc_mapping(&mapping.key);
+        let value = Self::handle_doc_mapping(&mapping.value);
+
+        Box::new(move |v: &Value| Some(Value::array(vec![key(v)?, value(v)?])))
+    }
+
+    fn handle_rel_mapping(mapping: &RelationalMapping) -> anyhow::Result<ValueProducer> {
         match mapping {
+            // tuples are only taken from lists so far
             RelationalMapping::Tuple(_, m) => match &m.initial {
-                MappingSource::Document(_) => {
-                    todo!()
+                source @ (MappingSource::Document(_) | MappingSource::Index(_)) => {
+                    bail!(
+                        "Relational mappings take their tuples from lists, not {:?}",
+                        source
+                    )
                 }
-                MappingSource::List { .. } => Box::new(move |v: &Value| {
+                MappingSource::List { .. } => Ok(Box::new(move |v: &Value| {
                     if let Array(a) = v {
                         return Some(Array(a.clone()));
                     }
                     None
-                }),
+                })),
             },
         }
     }
@@ -420,6 +508,7 @@ pub enum MappingSource {
     List {
         keys: Vec<String>,
     },
+    Index(usize),
 }
 
 impl Default for MappingSource {
@@ -434,3 +523,64 @@ pub enum DocumentSource {
     Key(String),
     Whole,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::definition::{Definition, Model};
+    use crate::query::Query;
+
+    #[test]
+    fn field() {
+        let relational = NativeMapping::tuple_to_relational(vec![
+            ("device".to_string(), RelationalType::Text),
+            ("measured".to_string(), RelationalType::Integer),
+        ]);
+        let tuple = Value::array(vec![Value::text("a"), Value::int(1_500)]);
+        assert_eq!(
+            relational.field(&tuple, "measured").unwrap(),
+            Value::int(1_500)
+        );
+        assert!(relational.field(&tuple, "reading").is_err());
+        assert!(relational.field(&tuple, "device.at").is_err());
+        assert!(relational.field(&Value::int(3), "measured").is_err());
+
+        let document = NativeMapping::document();
+        let doc = Value::from(Dict::from(vec![(
+            "meta",
+            Value::from(Dict::from(vec![("at", Value::int(2_000))])),
+        )]));
+        assert_eq!(document.field(&doc, "meta.at").unwrap(), Value::int(2_000));
+        assert_eq!(document.field(&doc, "meta.to.at").unwrap(), Value::null());
+        assert!(document.field(&tuple, "meta.at").is_err());
+
+        let pair = NativeMapping::doc_to_key_value("device");
+        assert_eq!(pair.field(&doc, "meta.at").unwrap(), Value::int(2_000));
+    }
+
+    #[tokio::test]
+    async fn relational_source() {
+        let types = vec![("device".to_string(), RelationalType::Text)];
+        let mapping = NativeMapping::Relational(RelationalMapping::Tuple(
+            types,
+            Mapping {
+                initial: MappingSource::Index(0),
+                manual: vec![],
+                auto: vec![],
+            },
+        ));
+        let err = mapping.build().err().unwrap();
+        assert!(err.to_string().contains("from lists"), "{}", err);
+
+        let definition = Definition::builder(
+            "sensors",
+            mapping,
+            Query::SQL("SELECT device FROM $$source".to_string()),
+            Model::Relational,
+        )
+        .build()
+        .await;
+        let err = definition.err().unwrap();
+        assert!(err.to_string().contains("from lists"), "{}", err);
+    }
+}
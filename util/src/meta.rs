@@ -9,6 +9,8 @@ use value::Text;
 #[derive(Clone, Debug, Writable, Readable)]
 pub struct InitialMeta {
     pub topics: SmallVec<[Text; 4]>,
+    /// when the record happened in ms, if its source knows it
+    pub event_time: Option<i64>,
 }
 
 impl InitialMeta {
@@ -20,6 +22,7 @@ impl InitialMeta {
                     .map(|t| Text(SmolStr::new(t)))
                     .collect::<Vec<_>>(),
             ),
+            event_time: None,
         }
     }
 }
@@ -27,7 +30,9 @@ impl InitialMeta {
 #[derive(Clone, Debug, Writable, Readable, Eq, PartialEq)]
 pub struct TimedMeta {
     pub id: u64,
+    /// when the record was ingested in ms
     pub timestamp: i64,
+    pub event_time: Option<i64>,
     pub topics: SmallVec<[Text; 4]>,
 }
 
@@ -36,6 +41,7 @@ impl TimedMeta {
         Self {
             id,
             timestamp: Utc::now().timestamp_millis(),
+            event_time: initial_meta.event_time,
             topics: initial_meta.topics,
         }
     }
@@ -45,15 +51,23 @@ impl TimedMeta {
 pub struct TargetedMeta {
     pub id: u64,
     pub timestamp: i64,
+    /// the time windows group the record by, its ingestion time without event time
+    pub event_time: i64,
+    /// no more records of the definition before it are expected
+    pub watermark: i64,
     pub definition: DefinitionId,
     pub topics: SmallVec<[Text; 4]>,
 }
 
 impl TargetedMeta {
+    /// Processes by ingestion time, see [`Definition::target`](crate::definition::Definition::target)
+    /// for event time.
     pub fn new(meta: TimedMeta, definition: DefinitionId) -> Self {
         Self {
             id: meta.id,
             timestamp: meta.timestamp,
+            event_time: meta.timestamp,
+            watermark: meta.timestamp,
             definition,
             topics: meta.topics,
         }
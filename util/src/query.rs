@@ -1,4 +1,7 @@
-use processing::{Algebra, parse_sql, parse_mql, parse_cypher};
+use anyhow::anyhow;
+use processing::{
+    Algebra, FunctionRegistry, Plan, parse_cypher_with, parse_mql_with, parse_sql_with,
+};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -9,14 +12,34 @@ pub enum Query {
     MQL(String),
     #[serde(alias = "cypher")]
     Cypher(String),
+    /// path of a stored plan, see [`Plan::read`]
+    #[serde(alias = "plan")]
+    Plan(String),
 }
 
-impl From<Query> for Algebra {
-    fn from(value: Query) -> Self {
-        match value {
-            Query::SQL(s) => parse_sql(&s),
-            Query::MQL(m) => parse_mql(&m).unwrap(),
-            Query::Cypher(c) => parse_cypher(&c).unwrap(),
+impl Query {
+    pub fn text(&self) -> &str {
+        match self {
+            Query::SQL(s) | Query::MQL(s) | Query::Cypher(s) | Query::Plan(s) => s,
         }
     }
+
+    /// Parses the query or loads the plan, both may call the functions of the registry.
+    pub fn parse(&self, functions: &FunctionRegistry) -> anyhow::Result<Algebra> {
+        let parsed = match self {
+            Query::SQL(s) => parse_sql_with(s, functions),
+            Query::MQL(m) => parse_mql_with(m, functions),
+            Query::Cypher(c) => parse_cypher_with(c, functions),
+            Query::Plan(path) => return Plan::read(path)?.bind(functions),
+        };
+        parsed.map_err(|e| anyhow!(e.report(self.text())))
+    }
+}
+
+impl TryFrom<Query> for Algebra {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Query) -> Result<Self, Self::Error> {
+        value.parse(&FunctionRegistry::default())
+    }
 }
@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
+use std::str::FromStr;
 use value::ValType;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -19,6 +20,9 @@ pub enum RelationalType {
     Bool,
     #[serde(alias = "string", alias = "text", alias = "TEXT")]
     Text,
+    /// Any value, stored serialized.
+    #[serde(alias = "blob", alias = "BLOB")]
+    Blob,
 }
 
 impl Display for RelationalType {
@@ -29,18 +33,43 @@ impl Display for RelationalType {
             RelationalType::Float => fmt.write_str("FLOAT"),
             RelationalType::Bool => fmt.write_str("BOOLEAN"),
             RelationalType::Text => fmt.write_str("TEXT"),
+            RelationalType::Blob => fmt.write_str("BLOB"),
         }
     }
 }
 
-impl From<RelationalType> for ValType{
-    fn from(relational_type: RelationalType) -> ValType{
+/// Parses the column types as they are declared, e.g. by the engines which create the tables.
+impl FromStr for RelationalType {
+    type Err = anyhow::Error;
+
+    fn from_str(declared: &str) -> Result<Self, Self::Err> {
+        let declared = declared.to_uppercase();
+        if let Some(length) = declared
+            .strip_prefix("VARCHAR(")
+            .and_then(|l| l.strip_suffix(')'))
+        {
+            return Ok(RelationalType::Varchar(length.trim().parse()?));
+        }
+        match declared.as_str() {
+            "INTEGER" | "INT" | "BIGINT" => Ok(RelationalType::Integer),
+            "FLOAT" | "REAL" => Ok(RelationalType::Float),
+            "BOOLEAN" | "BOOL" => Ok(RelationalType::Bool),
+            "TEXT" | "VARCHAR" => Ok(RelationalType::Text),
+            "BLOB" => Ok(RelationalType::Blob),
+            _ => anyhow::bail!("Unknown column type {}", declared),
+        }
+    }
+}
+
+impl From<RelationalType> for ValType {
+    fn from(relational_type: RelationalType) -> ValType {
         match relational_type {
             RelationalType::Varchar(_) => ValType::Text,
             RelationalType::Integer => ValType::Integer,
             RelationalType::Float => ValType::Float,
             RelationalType::Bool => ValType::Bool,
             RelationalType::Text => ValType::Text,
+            RelationalType::Blob => ValType::Any,
         }
     }
 }
@@ -52,7 +81,8 @@ impl From<&ValType> for RelationalType {
             ValType::Float => RelationalType::Float,
             ValType::Text => RelationalType::Text,
             ValType::Bool => RelationalType::Bool,
-            _ => todo!(),
+            // e.g. results of functions, mixed CASE branches or nested values
+            _ => RelationalType::Blob,
         }
     }
 }
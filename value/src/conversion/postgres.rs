@@ -3,7 +3,7 @@ use crate::{Text, bool};
 use bytes::{BufMut, BytesMut};
 use postgres::types::{IsNull, Type};
 use smol_str::SmolStr;
-use speedy::Writable;
+use speedy::{Readable, Writable};
 use std::error::Error;
 
 impl<'a> postgres::types::FromSql<'a> for Value {
@@ -14,13 +14,13 @@ impl<'a> postgres::types::FromSql<'a> for Value {
                 let result: &str = postgres::types::FromSql::from_sql(ty, raw)?;
                 Ok(Value::Text(Text(SmolStr::new(result))))
             }
-            Type::INT2 | Type::INT4 | Type::INT8 => {
-                let val: i64 = postgres::types::FromSql::from_sql(ty, raw)?;
-                Ok(Value::int(val))
-            }
-            Type::FLOAT4 | Type::FLOAT8 => {
-                Ok(Value::float(postgres::types::FromSql::from_sql(ty, raw)?))
-            }
+            // the narrower types are read with their own width
+            Type::INT2 => Ok(Value::int(i16::from_sql(ty, raw)? as i64)),
+            Type::INT4 => Ok(Value::int(i32::from_sql(ty, raw)? as i64)),
+            Type::INT8 => Ok(Value::int(i64::from_sql(ty, raw)?)),
+            Type::FLOAT4 => Ok(Value::float(f32::from_sql(ty, raw)? as f64)),
+            Type::FLOAT8 => Ok(Value::float(f64::from_sql(ty, raw)?)),
+            Type::BYTEA => Ok(Value::read_from_buffer(raw)?),
             _ => Err(format!("Unrecognized value type: {}", ty).into()),
         }
     }
@@ -36,15 +36,21 @@ impl<'a> postgres::types::FromSql<'a> for Value {
                 | Type::INT4
                 | Type::FLOAT4
                 | Type::FLOAT8
+                | Type::BYTEA
         )
     }
 }
 
 impl postgres::types::ToSql for Value {
-    fn to_sql(&self, _ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>>
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>>
     where
         Self: Sized,
     {
+        // blob columns hold any value, so they are stored serialized as a whole
+        if *ty == Type::BYTEA && !matches!(self, Value::Null) {
+            out.extend_from_slice(self.write_to_vec()?.as_slice());
+            return Ok(IsNull::No);
+        }
         match self {
             Value::Int(i) => out.put_i32(i.0 as i32),
             Value::Float(f) => out.put_f64(f.0.0),
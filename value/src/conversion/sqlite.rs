@@ -1,6 +1,6 @@
 use crate::value::Value;
 use rusqlite::types::{FromSqlResult, ToSqlOutput, ValueRef};
-use speedy::Writable;
+use speedy::{Readable, Writable};
 
 impl TryFrom<(&rusqlite::Row<'_>, usize)> for Value {
     type Error = rusqlite::Error;
@@ -27,7 +27,8 @@ impl rusqlite::types::FromSql for Value {
             rusqlite::types::Type::Integer => Ok(Value::int(value.as_i64()?)),
             rusqlite::types::Type::Real => Ok(Value::float(value.as_f64()?)),
             rusqlite::types::Type::Text => Ok(Value::text(value.as_str()?)),
-            rusqlite::types::Type::Blob => Err(rusqlite::types::FromSqlError::InvalidType),
+            rusqlite::types::Type::Blob => Value::read_from_buffer(value.as_blob()?)
+                .map_err(|err| rusqlite::types::FromSqlError::Other(Box::new(err))),
         }
     }
 }
@@ -40,12 +41,14 @@ impl rusqlite::types::ToSql for Value {
             Value::Bool(b) => Ok(ToSqlOutput::from(b.0)),
             Value::Text(t) => Ok(ToSqlOutput::from(t.0.to_string())),
             Value::Time(t) => Ok(ToSqlOutput::from(t.ms)),
-            Value::Array(_) => Err(rusqlite::Error::InvalidQuery),
-            Value::Dict(_) => Err(rusqlite::Error::InvalidQuery),
+            // nested values are stored as blobs, which are read as values again
+            Value::Array(_) | Value::Dict(_) | Value::Node(_) | Value::Edge(_) => {
+                Ok(ToSqlOutput::from(self.write_to_vec().map_err(|err| {
+                    rusqlite::Error::ToSqlConversionFailure(Box::new(err))
+                })?))
+            }
             Value::Null => Ok(ToSqlOutput::from(rusqlite::types::Null)),
             Value::Date(d) => Ok(ToSqlOutput::from(d.0)),
-            Value::Node(n) => Ok(ToSqlOutput::from(n.write_to_vec().unwrap())),
-            Value::Edge(e) => Ok(ToSqlOutput::from(e.write_to_vec().unwrap())),
         }
     }
 }
@@ -52,7 +52,8 @@ impl Sub for &Value {
                 let right = Value::float(b.0.0.neg());
                 right.add(self)
             }
-            (Value::Float(_), Value::Int(b)) => Value::int(-b.0).add(self),
+            (Value::Float(a), Value::Int(b)) => Value::float(a.0.0 - b.0 as f64),
+            (Value::Float(a), Value::Float(b)) => Value::float(a.0.0 - b.0.0),
             (lhs, rhs) => panic!("Cannot subtract {:?} from {:?}.", lhs, rhs),
         }
     }